@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE
+    releases
+ADD COLUMN
+    tag VARCHAR(64);
@@ -32,9 +32,13 @@ use itertools::Itertools;
 use tokio::sync::RwLock;
 use tokio_stream::StreamExt;
 use tracing::{debug, info, trace, warn};
+use typed_builder::TypedBuilder;
 use uuid::Uuid;
 
 use crate::config::*;
+use crate::endpoint::ContainerCleanup;
+use crate::error::ButidoError;
+use crate::error::ErrorRendering;
 use crate::filestore::path::StoreRoot;
 use crate::filestore::ReleaseStore;
 use crate::filestore::StagingStore;
@@ -45,13 +49,260 @@ use crate::package::condition::ConditionData;
 use crate::package::Dag;
 use crate::package::PackageName;
 use crate::package::PackageVersion;
+use crate::package::SerializedDag;
 use crate::package::Shebang;
 use crate::repository::Repository;
 use crate::schema;
 use crate::source::SourceCache;
+use crate::util::docker::resolve_image_name;
+use crate::util::docker::ImageName;
+use crate::util::docker::Platform;
+use crate::util::metrics::Counter;
+use crate::util::notify::BuildStatus;
+use crate::util::notify::Notification;
+use crate::util::parallelism::Parallelism;
 use crate::util::progress::ProgressBars;
+use crate::util::timings::Timings;
 use crate::util::EnvironmentVariableName;
 
+/// The parameters of a build
+#[derive(TypedBuilder)]
+pub struct BuildOptions {
+    package_name: PackageName,
+
+    /// Build the only package with `package_name` if not set
+    #[builder(default)]
+    package_version: Option<PackageVersion>,
+
+    image_name: ImageName,
+
+    #[builder(default)]
+    env: Vec<(EnvironmentVariableName, String)>,
+
+    /// Build exactly this package DAG instead of building the DAG from the repository
+    #[builder(default)]
+    tree: Option<SerializedDag>,
+
+    /// The commit of the repository the build is expected to run on, warns if HEAD differs
+    #[builder(default)]
+    expected_repo_hash: Option<String>,
+
+    shebang: Shebang,
+
+    #[builder(default)]
+    pinned_endpoint: Option<EndpointName>,
+
+    #[builder(default)]
+    skip_version_check: bool,
+
+    /// Use the policy from the configuration if not set
+    #[builder(default)]
+    image_pull_policy: Option<ImagePullPolicy>,
+
+    #[builder(default)]
+    platform: Option<Platform>,
+
+    /// Re-use this staging directory instead of creating a new one
+    #[builder(default)]
+    staging_dir: Option<PathBuf>,
+
+    /// Protect the staging directory from "staging gc", see `crate::commands::staging::keep()`
+    #[builder(default)]
+    staging_keep: bool,
+
+    #[builder(default)]
+    no_verification: bool,
+
+    #[builder(default)]
+    no_lint: bool,
+
+    #[builder(default)]
+    write_log_file: bool,
+
+    #[builder(default)]
+    image_tag_from_git: bool,
+
+    #[builder(default)]
+    container_cleanup: ContainerCleanup,
+
+    /// Use the command from the configuration if not set
+    #[builder(default)]
+    notify_command: Option<String>,
+
+    /// Use the webhook from the configuration if not set
+    #[builder(default)]
+    notify_webhook: Option<url::Url>,
+
+    /// Also write the package DAG to this file, see `SerializedDag::write_dump()`
+    #[builder(default)]
+    dump_dag: Option<PathBuf>,
+}
+
+impl BuildOptions {
+    fn from_matches(matches: &ArgMatches, config: &Configuration) -> Result<Self> {
+        let package_name = matches
+            .get_one::<String>("package_name")
+            .map(PackageName::try_new)
+            .unwrap()?; // safe by clap
+        let package_version = matches
+            .get_one::<String>("package_version")
+            .map(|s| PackageVersion::from(s.to_owned()));
+        BuildOptions::for_package(package_name, package_version, matches, config)
+    }
+
+    /// The options to build the package `package_name`, all other options are taken from the
+    /// command line
+    fn for_package(
+        package_name: PackageName,
+        package_version: Option<PackageVersion>,
+        matches: &ArgMatches,
+        config: &Configuration,
+    ) -> Result<Self> {
+        let image_name = matches
+            .get_one::<String>("image")
+            .map(|s| resolve_image_name(s, config.docker().images()))
+            .unwrap()?; // safe by clap
+
+        let env = matches
+            .get_many::<String>("env")
+            .unwrap_or_default()
+            .map(|s| crate::util::env::parse_to_env(s.as_ref()))
+            .collect::<Result<Vec<(EnvironmentVariableName, String)>>>()?;
+
+        BuildOptions::builder()
+            .package_name(package_name)
+            .package_version(package_version)
+            .image_name(image_name)
+            .env(env)
+            .shebang(Shebang::from(config.shebang().clone()))
+            .staging_dir(matches.get_one::<String>("staging_dir").map(PathBuf::from))
+            .build()
+            .with_flags(matches)
+    }
+
+    /// Set the options that do not change what is built from the command line
+    fn with_flags(mut self, matches: &ArgMatches) -> Result<Self> {
+        if let Some(shebang) = matches.get_one::<String>("shebang") {
+            self.shebang = Shebang::from(shebang.to_owned());
+        }
+
+        self.image_pull_policy = matches
+            .get_one::<String>("pull")
+            .map(|s| {
+                s.parse::<ImagePullPolicy>()
+                    .map_err(|_| anyhow!("Unknown image pull policy: {}", s))
+            })
+            .transpose()?;
+
+        self.platform = matches
+            .get_one::<String>("platform")
+            .map(|s| s.parse::<Platform>())
+            .transpose()?;
+
+        self.pinned_endpoint = matches
+            .get_one::<String>("endpoint")
+            .map(|s| EndpointName::from(s.to_owned()));
+        self.skip_version_check = matches.get_flag("skip_version_check");
+        self.no_verification = matches.get_flag("no_verification");
+        self.no_lint = matches.get_flag("no_lint");
+        self.write_log_file = matches.get_flag("write-log-file");
+        self.image_tag_from_git = matches.get_flag("image_tag_from_git");
+        self.container_cleanup = if matches.get_flag("no_cleanup") {
+            ContainerCleanup::Never
+        } else if matches.get_flag("cleanup_failed") {
+            ContainerCleanup::Always
+        } else {
+            ContainerCleanup::KeepFailed
+        };
+        self.notify_command = matches.get_one::<String>("notify_command").cloned();
+        self.notify_webhook = matches
+            .get_one::<String>("notify_webhook")
+            .map(|s| url::Url::parse(s))
+            .transpose()
+            .context("Parsing the notification webhook URL")?;
+        self.dump_dag = matches.get_one::<String>("dump_dag").map(PathBuf::from);
+        self.staging_keep = matches.get_flag("staging_keep");
+        Ok(self)
+    }
+}
+
+/// The parameters of a submit that are needed to repeat its build
+#[derive(Debug)]
+pub struct RebuildParameters {
+    package_name: PackageName,
+    package_version: PackageVersion,
+    image_name: ImageName,
+    env: Vec<(EnvironmentVariableName, String)>,
+    tree: SerializedDag,
+    repo_hash: String,
+}
+
+impl RebuildParameters {
+    /// Reconstruct the parameters from the database records of a submit
+    pub fn from_records(
+        submit: &crate::db::models::Submit,
+        package: &crate::db::models::Package,
+        image: &crate::db::models::Image,
+        githash: &crate::db::models::GitHash,
+        env: Vec<crate::db::models::EnvVar>,
+    ) -> Result<Self> {
+        let tree = submit
+            .tree
+            .clone()
+            .ok_or_else(|| {
+                anyhow!(
+                    "Submit {} has no stored package tree, it was created by an older version of butido",
+                    submit.uuid
+                )
+            })
+            .and_then(SerializedDag::from_json)
+            .with_context(|| anyhow!("Loading the package tree of submit {}", submit.uuid))?;
+
+        Ok(RebuildParameters {
+            package_name: PackageName::try_new(&package.name)?,
+            package_version: PackageVersion::from(package.version.clone()),
+            image_name: ImageName::from(image.name.clone()),
+            env: env_from_records(env),
+            tree,
+            repo_hash: githash.hash.clone(),
+        })
+    }
+
+    fn into_options(self, config: &Configuration) -> BuildOptions {
+        BuildOptions::builder()
+            .package_name(self.package_name)
+            .package_version(Some(self.package_version))
+            .image_name(self.image_name)
+            .env(self.env)
+            .tree(Some(self.tree))
+            .expected_repo_hash(Some(self.repo_hash))
+            .shebang(Shebang::from(config.shebang().clone()))
+            .build()
+    }
+}
+
+fn env_from_records(env: Vec<crate::db::models::EnvVar>) -> Vec<(EnvironmentVariableName, String)> {
+    let mut env = env
+        .into_iter()
+        .map(|ev| (EnvironmentVariableName::from(ev.name.as_str()), ev.value))
+        .collect::<Vec<_>>();
+    env.sort();
+    env
+}
+
+/// Merge the environment variables of a previous submit with `env`, `env` takes precedence
+fn merge_env(
+    submit_env: Vec<(EnvironmentVariableName, String)>,
+    env: Vec<(EnvironmentVariableName, String)>,
+) -> Vec<(EnvironmentVariableName, String)> {
+    let mut merged = submit_env
+        .into_iter()
+        .filter(|(name, _)| !env.iter().any(|(n, _)| n == name))
+        .collect::<Vec<_>>();
+    merged.extend(env);
+    merged
+}
+
 /// Implementation of the "build" subcommand
 #[allow(clippy::too_many_arguments)]
 pub async fn build(
@@ -62,86 +313,450 @@ pub async fn build(
     config: &Configuration,
     repo: Repository,
     repo_path: &Path,
+    parallelism: Parallelism,
+    timings: &Timings,
+) -> Result<()> {
+    if matches.contains_id("matching") {
+        return build_matching(
+            repo_root,
+            matches,
+            progressbars,
+            database_pool,
+            config,
+            &repo,
+            repo_path,
+            parallelism,
+            timings,
+        )
+        .await;
+    }
+
+    let retry_submit = matches
+        .get_one::<String>("retry_submit")
+        .map(|s| Uuid::parse_str(s))
+        .transpose()
+        .context("Parsing submit UUID")?;
+    let mut options = if let Some(submit_id) = retry_submit {
+        match retry_options(&database_pool, &submit_id, config)? {
+            Some(options) => options.with_flags(matches)?,
+            None => return Ok(()),
+        }
+    } else {
+        BuildOptions::from_matches(matches, config)?
+    };
+    if let Some(submit_id) = matches.get_one::<String>("env_from_submit") {
+        let submit_id = Uuid::parse_str(submit_id).context("Parsing submit UUID")?;
+        let submit_env = {
+            let mut conn = database_pool.get()?;
+            crate::db::models::Submit::with_id(&mut conn, &submit_id)
+                .with_context(|| anyhow!("Loading submit '{}' from DB", submit_id))?
+                .env(&mut conn)?
+        };
+        options.env = merge_env(env_from_records(submit_env), options.env);
+    }
+    build_with_options(
+        options,
+        repo_root,
+        progressbars,
+        database_pool,
+        config,
+        &repo,
+        repo_path,
+        parallelism,
+        timings,
+    )
+    .await
+}
+
+/// `build --matching` asks for confirmation before building more packages than this
+const BUILD_MATCHING_CONFIRMATION_THRESHOLD: usize = 10;
+
+/// Implementation of "build --matching": build every matching package in its own submit
+#[allow(clippy::too_many_arguments)]
+async fn build_matching(
+    repo_root: &Path,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+    database_pool: Pool<ConnectionManager<PgConnection>>,
+    config: &Configuration,
+    repo: &Repository,
+    repo_path: &Path,
+    parallelism: Parallelism,
+    timings: &Timings,
 ) -> Result<()> {
-    use crate::db::models::{EnvVar, GitHash, Image, Job, Package, Submit};
-    use crate::util::docker::resolve_image_name;
+    let filter = crate::commands::util::package_filter_from_matches(matches)?;
+    let packages = repo.packages_matching(&filter).collect::<Vec<_>>();
+    if packages.is_empty() {
+        return Err(ButidoError::PackageNotFound(filter.to_string()).into());
+    }
+
+    if packages.len() > BUILD_MATCHING_CONFIRMATION_THRESHOLD && !matches.get_flag("yes") {
+        let prompt = format!("Really build {} packages?", packages.len());
+        if !dialoguer::Confirm::new().with_prompt(prompt).interact()? {
+            return Ok(());
+        }
+    }
+
+    let keep_going = matches.get_flag("keep_going");
+    let mut failed = Vec::new();
+    for package in packages.iter() {
+        let name = format!("{} {}", package.name(), package.version());
+        info!("Building {}", name);
+        let options = BuildOptions::for_package(
+            package.name().clone(),
+            Some(package.version().clone()),
+            matches,
+            config,
+        )?;
+        let result = build_with_options(
+            options,
+            repo_root,
+            progressbars.clone(),
+            database_pool.clone(),
+            config,
+            repo,
+            repo_path,
+            parallelism,
+            timings,
+        )
+        .await
+        .with_context(|| anyhow!("Building {}", name));
+
+        match result {
+            Ok(()) => {}
+            Err(e) if keep_going => {
+                warn!("{:#}", e);
+                failed.push(name);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "The build of {} of {} packages failed: {}",
+            failed.len(),
+            packages.len(),
+            failed.join(", ")
+        ))
+    }
+}
+
+/// The package name, version and success of all jobs of a submit
+fn job_results(
+    database_pool: &Pool<ConnectionManager<PgConnection>>,
+    submit_id: &Uuid,
+) -> Result<Vec<(String, String, Option<bool>)>> {
+    use crate::db::models::{Job, Package};
+
+    schema::jobs::table
+        .inner_join(schema::submits::table)
+        .inner_join(schema::packages::table)
+        .filter(schema::submits::uuid.eq(submit_id))
+        .select((schema::jobs::all_columns, schema::packages::all_columns))
+        .load::<(Job, Package)>(&mut database_pool.get()?)
+        .with_context(|| anyhow!("Loading jobs for submit = {}", submit_id))?
+        .into_iter()
+        .map(|(job, package)| {
+            let success = crate::log::ParsedLog::from_str(&job.log_text)?
+                .is_successfull()
+                .to_bool();
+            Ok((package.name, package.version, success))
+        })
+        .collect()
+}
+
+fn load_rebuild_parameters(
+    database_pool: &Pool<ConnectionManager<PgConnection>>,
+    submit_id: &Uuid,
+) -> Result<RebuildParameters> {
+    use crate::db::models::{GitHash, Image, Package, Submit};
+
+    let mut conn = database_pool.get()?;
+    let submit = Submit::with_id(&mut conn, submit_id)
+        .with_context(|| anyhow!("Loading submit '{}' from DB", submit_id))?;
+    let package = schema::packages::table
+        .find(submit.requested_package_id)
+        .first::<Package>(&mut conn)
+        .with_context(|| anyhow!("Loading the package of submit '{}'", submit_id))?;
+    let image = schema::images::table
+        .find(submit.requested_image_id)
+        .first::<Image>(&mut conn)
+        .with_context(|| anyhow!("Loading the image of submit '{}'", submit_id))?;
+    let githash = GitHash::with_id(&mut conn, submit.repo_hash_id)
+        .with_context(|| anyhow!("Loading GitHash '{}' from DB", submit.repo_hash_id))?;
+    let env = submit.env(&mut conn)?;
+
+    RebuildParameters::from_records(&submit, &package, &image, &githash, env)
+}
+
+/// The options to retry the failed jobs of a submit, `None` if there is nothing to retry
+///
+/// The build uses the staging directory of the submit, so the artifacts of the successful jobs
+/// are reused and only the failed jobs and the jobs that depend on them run again.
+fn retry_options(
+    database_pool: &Pool<ConnectionManager<PgConnection>>,
+    submit_id: &Uuid,
+    config: &Configuration,
+) -> Result<Option<BuildOptions>> {
+    let parameters = load_rebuild_parameters(database_pool, submit_id)?;
+    let jobs = job_results(database_pool, submit_id)?;
+    let to_rebuild = parameters.tree.packages_to_rebuild(|name, version| {
+        jobs.iter().any(|(n, v, success)| {
+            n == name.as_ref() && v == version.as_ref() && *success == Some(true)
+        })
+    });
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    if to_rebuild.is_empty() {
+        writeln!(
+            outlock,
+            "All jobs of submit {submit_id} succeeded, nothing to retry"
+        )?;
+        return Ok(None);
+    }
+    writeln!(outlock, "Retrying submit {submit_id}, building again:")?;
+    for (name, version) in to_rebuild {
+        writeln!(outlock, "  {} {}", name.to_string().yellow(), version)?;
+    }
+
+    let staging_dir = config.staging_dir_for(submit_id)?;
+    if !staging_dir.is_dir() {
+        warn!(
+            "Staging directory {} of the submit does not exist, all packages are built again",
+            staging_dir.display()
+        );
+    }
+
+    let mut options = parameters.into_options(config);
+    options.staging_dir = Some(staging_dir);
+    Ok(Some(options))
+}
+
+/// Implementation of "db submit --rebuild": build a submit again with its stored parameters
+#[allow(clippy::too_many_arguments)]
+pub async fn rebuild(
+    submit_id: &Uuid,
+    progressbars: ProgressBars,
+    database_pool: Pool<ConnectionManager<PgConnection>>,
+    config: &Configuration,
+    repo: Repository,
+    repo_path: &Path,
+    parallelism: Parallelism,
+    timings: &Timings,
+) -> Result<()> {
+    let parameters = load_rebuild_parameters(&database_pool, submit_id)?;
+    info!("Rebuilding submit {}: {:?}", submit_id, parameters);
+
+    build_with_options(
+        parameters.into_options(config),
+        repo_path,
+        progressbars,
+        database_pool,
+        config,
+        &repo,
+        repo_path,
+        parallelism,
+        timings,
+    )
+    .await
+}
+
+/// What is known about a build when it finishes, for the notification
+#[derive(Debug, Default)]
+struct Submitted {
+    submit_id: Option<Uuid>,
+
+    /// The names and versions of all packages of the build
+    packages: Vec<(String, String)>,
+}
+
+/// Run the build, count it in the metrics and send the notification when it finished, no matter
+/// how
+#[allow(clippy::too_many_arguments)]
+async fn build_with_options(
+    mut options: BuildOptions,
+    repo_root: &Path,
+    progressbars: ProgressBars,
+    database_pool: Pool<ConnectionManager<PgConnection>>,
+    config: &Configuration,
+    repo: &Repository,
+    repo_path: &Path,
+    parallelism: Parallelism,
+    timings: &Timings,
+) -> Result<()> {
+    let notify_command = options
+        .notify_command
+        .take()
+        .or_else(|| config.notify_command().clone());
+    let notify_webhook = options
+        .notify_webhook
+        .take()
+        .or_else(|| config.notify_webhook().clone());
+
+    let mut submitted = Submitted::default();
+    let result = run_build(
+        options,
+        repo_root,
+        progressbars,
+        database_pool.clone(),
+        config,
+        repo,
+        repo_path,
+        parallelism,
+        timings,
+        &mut submitted,
+    )
+    .await;
+
+    // Every error counts as a failed build, also the ones before the jobs ran
+    timings.count(Counter::Builds, 1);
+    if result.is_err() {
+        timings.count(Counter::BuildFailures, 1);
+    }
+
+    if notify_command.is_none() && notify_webhook.is_none() {
+        return result;
+    }
+    // Without a submit, the build failed before it really started
+    let Some(submit_id) = submitted.submit_id else {
+        debug!("Not sending the notification, the build failed before the submit was created");
+        return result;
+    };
+    let status = if result.is_ok() {
+        BuildStatus::Success
+    } else {
+        BuildStatus::Failure
+    };
+    match job_results(&database_pool, &submit_id) {
+        Ok(jobs) => {
+            Notification::new(submit_id, status, submitted.packages, &jobs)
+                .send(notify_command.as_deref(), notify_webhook.as_ref())
+                .await
+        }
+        Err(e) => warn!("Not sending the notification: {:#}", e),
+    }
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_build(
+    options: BuildOptions,
+    repo_root: &Path,
+    progressbars: ProgressBars,
+    database_pool: Pool<ConnectionManager<PgConnection>>,
+    config: &Configuration,
+    repo: &Repository,
+    repo_path: &Path,
+    parallelism: Parallelism,
+    timings: &Timings,
+    submitted: &mut Submitted,
+) -> Result<()> {
+    use crate::db::models::{EnvVar, GitHash, Image, Job, Package, Submit, SubmitEnv};
 
     let git_repo = git2::Repository::open(repo_path)
         .with_context(|| anyhow!("Opening repository at {}", repo_path.display()))?;
 
     let now = chrono::offset::Local::now().naive_local();
 
-    let shebang = Shebang::from({
-        matches
-            .get_one::<String>("shebang")
-            .map(|s| s.to_owned())
-            .unwrap_or_else(|| config.shebang().clone())
-    });
-
-    let image_name = matches
-        .get_one::<String>("image")
-        .map(|s| resolve_image_name(s, config.docker().images()))
-        .unwrap()?; // safe by clap
+    let BuildOptions {
+        package_name: pname,
+        package_version: pvers,
+        image_name,
+        env: additional_env,
+        tree,
+        expected_repo_hash,
+        shebang,
+        pinned_endpoint,
+        skip_version_check,
+        image_pull_policy,
+        platform,
+        staging_dir,
+        staging_keep,
+        no_verification,
+        no_lint,
+        write_log_file,
+        image_tag_from_git,
+        container_cleanup,
+        notify_command: _,
+        notify_webhook: _,
+        dump_dag,
+    } = options;
 
     debug!("Getting repository HEAD");
     let hash_str = crate::util::git::get_repo_head_commit_hash(&git_repo)?;
     trace!("Repository HEAD = {}", hash_str);
+    if let Some(expected) = expected_repo_hash.filter(|expected| *expected != hash_str) {
+        warn!(
+            "The repository is at commit {}, but the build was submitted at commit {}",
+            hash_str, expected
+        );
+    }
     let phases = config.available_phases();
 
-    let mut endpoint_configurations = config
+    if let Some(endpoint) = pinned_endpoint.as_ref() {
+        if !config.docker().endpoints().contains_key(endpoint) {
+            return Err(anyhow!("Endpoint not found in configuration: {}", endpoint));
+        }
+    }
+
+    let check_versions = config.docker().check_versions() && !skip_version_check;
+    if !check_versions {
+        info!("Not checking the Docker versions of the endpoints");
+    }
+
+    let image_pull_policy =
+        image_pull_policy.unwrap_or_else(|| config.docker().image_pull_policy());
+
+    let endpoint_configurations = config
         .docker()
         .endpoints()
         .iter()
+        .filter(|(ep_name, _)| {
+            pinned_endpoint
+                .as_ref()
+                .map(|pinned| pinned == *ep_name)
+                .unwrap_or(true)
+        })
         .map(|(ep_name, ep_cfg)| {
             crate::endpoint::EndpointConfiguration::builder()
                 .endpoint_name(ep_name.clone())
                 .endpoint(ep_cfg.clone())
-                .required_images(
+                // Only the image of this build has to be present (or pulled)
+                .required_images(vec![image_name.clone()])
+                .required_docker_versions(
                     config
                         .docker()
-                        .images()
-                        .iter()
-                        .map(|img| img.name.clone())
-                        .collect::<Vec<_>>(),
+                        .docker_versions()
+                        .clone()
+                        .filter(|_| check_versions),
+                )
+                .required_docker_api_versions(
+                    config
+                        .docker()
+                        .docker_api_versions()
+                        .clone()
+                        .filter(|_| check_versions),
                 )
-                .required_docker_versions(config.docker().docker_versions().clone())
-                .required_docker_api_versions(config.docker().docker_api_versions().clone())
+                .api_retries(config.docker().docker_api_retries())
+                .image_pull_policy(image_pull_policy)
+                .registry_auth(config.docker().registry_auth().clone())
+                .platform(platform.clone())
                 .build()
         })
         .collect::<Vec<_>>();
-    {
-        // Because we're loading always sequencially, to have a bit more spread over the endpoints,
-        // shuffle the endpoints here. Not a perfect solution, but a working one.
-        use rand::seq::SliceRandom;
-        let mut rng = rand::thread_rng();
-        endpoint_configurations.shuffle(&mut rng);
-    }
     info!("Endpoint config build");
 
-    let pname = matches
-        .get_one::<String>("package_name")
-        .map(|s| s.to_owned())
-        .map(PackageName::from)
-        .unwrap(); // safe by clap
-
-    let pvers = matches
-        .get_one::<String>("package_version")
-        .map(|s| s.to_owned())
-        .map(PackageVersion::from);
     info!("We want {} ({:?})", pname, pvers);
 
-    let additional_env = matches
-        .get_many::<String>("env")
-        .unwrap_or_default()
-        .map(|s| crate::util::env::parse_to_env(s.as_ref()))
-        .collect::<Result<Vec<(EnvironmentVariableName, String)>>>()?;
-
-    let packages = if let Some(pvers) = pvers {
+    let packages = if let Some(pvers) = pvers.as_ref() {
         debug!(
             "Searching for package with version: '{}' '{}'",
             pname, pvers
         );
-        repo.find(&pname, &pvers)
+        repo.find(&pname, pvers)
     } else {
         debug!("Searching for package by name: '{}'", pname);
         repo.find_by_name(&pname)
@@ -156,9 +771,22 @@ pub async fn build(
             packages.len()
         ));
     }
-    let package = *packages
-        .first()
-        .ok_or_else(|| anyhow!("Found no package."))?;
+    let package = *packages.first().ok_or_else(|| {
+        let package = match pvers {
+            Some(pvers) => format!("{pname} {pvers}"),
+            None => pname.to_string(),
+        };
+        repo.package_not_found(&pname, &package)
+    })?;
+
+    // The overall progress of the build, the stages are weighted by their usual duration
+    let overall = progressbars.staged("Building")?;
+    let stage_releases = overall.add_stage(1);
+    let stage_staging = overall.add_stage(1);
+    let stage_dag = overall.add_stage(2);
+    let stage_verification = overall.add_stage(4);
+    let stage_lint = overall.add_stage(2);
+    let stage_jobs = overall.add_stage(40);
 
     let release_stores = config
         .release_stores()
@@ -166,7 +794,7 @@ pub async fn build(
         .map(|storename| {
             let bar_release_loading = progressbars.bar()?;
 
-            let p = config.releases_directory().join(storename);
+            let p = config.releases_dir_for(storename)?;
             let p_str = p.to_string_lossy();
             debug!("Loading release directory: {}", p_str);
             let r = ReleaseStore::load(StoreRoot::new(p.clone())?, &bar_release_loading);
@@ -180,38 +808,12 @@ pub async fn build(
             r.map(Arc::new)
         })
         .collect::<Result<Vec<_>>>()?;
+    stage_releases.finish();
 
     let (staging_store, staging_dir, submit_id) = {
         let bar_staging_loading = progressbars.bar()?;
 
-        let (submit_id, p) = if let Some(staging_dir) =
-            matches.get_one::<String>("staging_dir").map(PathBuf::from)
-        {
-            info!(
-                "Setting staging dir to {} for this run",
-                staging_dir.display()
-            );
-
-            let uuid = staging_dir
-                .file_name()
-                .ok_or_else(|| anyhow!("Seems not to be a directory: {}", staging_dir.display()))?
-                .to_owned()
-                .into_string()
-                .map_err(|_| anyhow!("Type conversion of staging dir name to UTF8 String"))
-                .context("Parsing staging dir name to UUID")?;
-            let uuid = Uuid::parse_str(&uuid)
-                .context("Parsing directory name as UUID")
-                .with_context(|| anyhow!("Seems not to be a submit UUID: {}", uuid))?;
-
-            (uuid, staging_dir)
-        } else {
-            let submit_id = uuid::Uuid::new_v4();
-            let staging_dir = config
-                .staging_directory()
-                .join(submit_id.hyphenated().to_string());
-
-            (submit_id, staging_dir)
-        };
+        let (submit_id, p) = submit_staging_dir(config, staging_dir)?;
 
         if !p.is_dir() {
             tokio::fs::create_dir_all(&p).await?;
@@ -228,39 +830,100 @@ pub async fn build(
             .map(Arc::new)
             .map(|store| (store, p, submit_id))?
     };
+    stage_staging.finish();
+    submitted.submit_id = Some(submit_id);
+    let staging_keep = staging_keep || *config.keep_staging();
+    if staging_keep {
+        crate::commands::staging::keep(&staging_dir)?;
+    }
 
     let dag = {
+        let start = std::time::Instant::now();
         let bar_tree_building = progressbars.bar()?;
         let condition_data = ConditionData {
             image_name: Some(&image_name),
             env: &additional_env,
         };
 
-        let dag = Dag::for_root_package(
-            package.clone(),
-            &repo,
-            Some(&bar_tree_building),
-            &condition_data,
-        )?;
+        let dag = match tree {
+            Some(tree) => tree
+                .into_dag(repo)
+                .context("Loading the stored package DAG")?,
+            None => Dag::for_root_package(
+                package.clone(),
+                repo,
+                Some(&bar_tree_building),
+                &condition_data,
+                *config.max_dependency_depth(),
+                *config.dependency_conflict_policy(),
+            )?,
+        };
         bar_tree_building.finish_with_message("Finished loading Dag");
+        timings.add_since("Building the DAG", start);
         dag
     };
+    submitted.packages = dag
+        .all_packages()
+        .into_iter()
+        .map(|p| (p.name().to_string(), p.version().to_string()))
+        .collect();
+    stage_dag.finish();
 
-    let source_cache = SourceCache::new(config.source_cache_root().clone());
+    if let Some(path) = dump_dag.as_ref() {
+        SerializedDag::write_dump(path, &[SerializedDag::from(&dag)], None)?;
+    }
+
+    let source_cache = SourceCache::from_config(config);
+
+    crate::commands::source::check_insecure_sources(
+        dag.all_packages().into_iter(),
+        *config.forbid_insecure_sources(),
+    )?;
+
+    // Fail early with a clear error instead of in the containers of the build
+    let missing_sources = dag
+        .all_packages()
+        .into_iter()
+        .filter(|p| !p.all_sources_present(&source_cache))
+        .map(|p| {
+            format!(
+                "Package {} {} is missing {} source(s)",
+                p.name(),
+                p.version(),
+                p.missing_sources(&source_cache).len()
+            )
+        })
+        .collect::<Vec<_>>();
+    if !missing_sources.is_empty() {
+        return Err(anyhow!(
+            "{}\nRun `butido source download` to download the missing sources",
+            missing_sources.join("\n")
+        ));
+    }
 
-    if matches.get_flag("no_verification") {
+    if no_verification {
         warn!("No hash verification will be performed");
     } else {
+        let start = std::time::Instant::now();
+        let opts = crate::commands::source::VerifyOptions::builder()
+            .jobs(parallelism.jobs())
+            .use_mmap(*config.hash_use_mmap())
+            .timings(Some(timings))
+            .stage(Some(&stage_verification))
+            .build();
         crate::commands::source::verify_impl(
             dag.all_packages().into_iter(),
             &source_cache,
             &progressbars,
+            &opts,
         )
         .await?;
+        timings.add_since("Verifying sources", start);
     }
+    stage_verification.finish();
 
     // linting the package scripts
-    if matches.get_flag("no_lint") {
+    if no_lint {
         warn!("No script linting will be performed!");
     } else if let Some(linter) = crate::ui::find_linter_command(repo_root, config)? {
         let all_packages = dag.all_packages();
@@ -269,10 +932,13 @@ pub async fn build(
         bar.set_message("Linting package scripts...");
 
         let iter = all_packages.into_iter();
-        crate::commands::util::lint_packages(iter, &linter, config, bar).await?;
+        crate::commands::util::lint_packages(iter, &linter, config, bar, Some(&stage_lint))
+            .await?
+            .check()?;
     } else {
         warn!("No linter set in configuration, no script linting will be performed!");
     } // linting
+    stage_lint.finish();
 
     dag.all_packages()
         .into_iter()
@@ -326,7 +992,8 @@ pub async fn build(
     let (db_package, db_githash, db_image, db_envs) =
         tokio::join!(db_package, db_githash, db_image, db_envs);
 
-    let (db_package, db_githash, db_image, _) = (db_package?, db_githash?, db_image?, db_envs?);
+    let (db_package, db_githash, db_image, db_envs) =
+        (db_package?, db_githash?, db_image?, db_envs?);
 
     trace!("Database jobs for Package, GitHash, Image finished successfully");
     trace!("Creating Submit in database");
@@ -337,11 +1004,16 @@ pub async fn build(
         &db_image,
         &db_package,
         &db_githash,
+        &SerializedDag::from(&dag),
     )?;
     trace!(
         "Creating Submit in database finished successfully: {:?}",
         submit
     );
+    for env in db_envs.iter() {
+        SubmitEnv::create(&mut database_pool.get().unwrap(), &submit, env)
+            .with_context(|| anyhow!("Recording environment variables of submit {}", submit_id))?;
+    }
 
     {
         let out = std::io::stdout();
@@ -379,7 +1051,7 @@ pub async fn build(
         .database(database_pool.clone())
         .source_cache(source_cache)
         .submit(submit)
-        .log_dir(if matches.get_flag("write-log-file") {
+        .log_dir(if write_log_file {
             Some(config.log_dir().clone())
         } else {
             None
@@ -387,13 +1059,24 @@ pub async fn build(
         .jobdag(jobdag)
         .config(config)
         .repository(git_repo)
+        .container_tag(if image_tag_from_git {
+            Some(crate::util::git::short_hash(&hash_str).to_string())
+        } else {
+            None
+        })
+        .container_cleanup(container_cleanup)
+        .timings(timings.clone())
+        .stage(Some(stage_jobs.clone()))
         .build()
         .setup()
         .await?;
 
     info!("Running orchestrator...");
     let mut artifacts = vec![];
+    let start = std::time::Instant::now();
     let errors = orch.run(&mut artifacts).await?;
+    timings.add_since("Running all jobs", start);
+    stage_jobs.finish();
     let out = std::io::stdout();
     let mut outlock = out.lock();
 
@@ -403,11 +1086,16 @@ pub async fn build(
     artifacts.into_iter().try_for_each(|artifact_path| {
         writeln!(outlock, "{}", staging_dir.join(artifact_path).display()).map_err(Error::from)
     })?;
+    if staging_keep {
+        writeln!(outlock, "Staging directory kept: {}", staging_dir.display())?;
+    }
 
+    let rendering = ErrorRendering::global();
+    let hint = rendering.hint(errors.values());
     let mut had_error = false;
     for (job_uuid, error) in errors {
         had_error = true;
-        for cause in error.chain() {
+        for cause in rendering.causes(&error) {
             writeln!(outlock, "{}: {}", "[ERROR]".red(), cause)?;
         }
 
@@ -478,6 +1166,11 @@ pub async fn build(
             )?;
         }
     }
+    if let Some(hint) = hint {
+        writeln!(outlock, "{hint}")?;
+    }
+
+    drop(outlock);
 
     if had_error {
         Err(anyhow!("One or multiple errors during build"))
@@ -485,3 +1178,222 @@ pub async fn build(
         Ok(())
     }
 }
+
+/// The submit id and the staging directory of a build
+///
+/// A new submit is staged in `Configuration::staging_dir_for()`. The `--staging-dir` overrides
+/// that, the name of the directory is the id of the submit then.
+fn submit_staging_dir(
+    config: &Configuration,
+    staging_dir: Option<PathBuf>,
+) -> Result<(Uuid, PathBuf)> {
+    let Some(staging_dir) = staging_dir else {
+        let submit_id = uuid::Uuid::new_v4();
+        return Ok((submit_id, config.staging_dir_for(&submit_id)?));
+    };
+
+    info!(
+        "Setting staging dir to {} for this run",
+        staging_dir.display()
+    );
+
+    let uuid = staging_dir
+        .file_name()
+        .ok_or_else(|| anyhow!("Seems not to be a directory: {}", staging_dir.display()))?
+        .to_owned()
+        .into_string()
+        .map_err(|_| anyhow!("Type conversion of staging dir name to UTF8 String"))
+        .context("Parsing staging dir name to UUID")?;
+    let uuid = Uuid::parse_str(&uuid)
+        .context("Parsing directory name as UUID")
+        .with_context(|| anyhow!("Seems not to be a submit UUID: {}", uuid))?;
+
+    Ok((uuid, staging_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::db::models;
+    use crate::package::tests::ab_repo;
+    use crate::package::tests::dag_of;
+    use crate::package::tests::package;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+    use crate::package::tests::temp_dir;
+
+    fn submit(tree: Option<serde_json::Value>) -> models::Submit {
+        models::Submit {
+            id: 1,
+            uuid: Uuid::new_v4(),
+            submit_time: chrono::NaiveDateTime::default(),
+            requested_image_id: 2,
+            requested_package_id: 3,
+            repo_hash_id: 4,
+            tree,
+        }
+    }
+
+    #[test]
+    fn test_matching_packages() {
+        let mut btree = BTreeMap::new();
+        for (name, version) in [
+            ("python-a", "1"),
+            ("python-a", "2"),
+            ("python-b", "1"),
+            ("a-python", "1"),
+            ("c", "1"),
+        ] {
+            let p = package(name, version, "https://example.com", "0");
+            btree.insert((pname(name), pversion(version)), p);
+        }
+        let repo = Repository::from(btree);
+
+        let selected = |args: &[&str]| {
+            let matches = crate::cli::cli()
+                .try_get_matches_from(
+                    ["butido", "build", "-I", "debian:bullseye"]
+                        .iter()
+                        .chain(args),
+                )
+                .unwrap();
+            let filter =
+                crate::commands::util::package_filter_from_matches(matches.subcommand().unwrap().1)
+                    .unwrap();
+            repo.packages_matching(&filter)
+                .map(|p| format!("{} {}", p.name(), p.version()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            selected(&["--matching", "^python-"]),
+            vec!["python-a 1", "python-a 2", "python-b 1"]
+        );
+        assert_eq!(
+            selected(&["--matching", "python"]),
+            vec!["a-python 1", "python-a 1", "python-a 2", "python-b 1"]
+        );
+        assert_eq!(
+            selected(&[
+                "--matching",
+                "python",
+                "--exclude",
+                "b$",
+                "--exclude",
+                "^a-"
+            ]),
+            vec!["python-a 1", "python-a 2"]
+        );
+        assert!(selected(&["--matching", "^d$"]).is_empty());
+    }
+
+    #[test]
+    fn test_rebuild_parameters_from_records() {
+        let (a, repo) = ab_repo("2");
+        let dag = dag_of(a, &repo);
+        let tree = SerializedDag::from(&dag);
+
+        let env = vec![
+            models::EnvVar {
+                id: 6,
+                name: String::from("B"),
+                value: String::from("2"),
+            },
+            models::EnvVar {
+                id: 5,
+                name: String::from("A"),
+                value: String::from("1"),
+            },
+        ];
+        let parameters = RebuildParameters::from_records(
+            &submit(Some(tree.to_json().unwrap())),
+            &models::Package {
+                id: 3,
+                name: String::from("a"),
+                version: String::from("1"),
+            },
+            &models::Image {
+                id: 2,
+                name: String::from("debian:bullseye"),
+            },
+            &models::GitHash {
+                id: 4,
+                hash: String::from("0123abcd"),
+            },
+            env,
+        )
+        .unwrap();
+
+        assert_eq!(parameters.package_name, pname("a"));
+        assert_eq!(parameters.package_version, pversion("1"));
+        assert_eq!(parameters.image_name, ImageName::from("debian:bullseye"));
+        assert_eq!(
+            parameters.env,
+            vec![
+                (EnvironmentVariableName::from("A"), String::from("1")),
+                (EnvironmentVariableName::from("B"), String::from("2")),
+            ]
+        );
+        assert_eq!(parameters.tree, tree);
+        assert_eq!(parameters.repo_hash, "0123abcd");
+    }
+
+    #[test]
+    fn test_merge_env_prefers_cli_values() {
+        let var =
+            |name: &str, value: &str| (EnvironmentVariableName::from(name), value.to_string());
+        let submit_env = vec![var("A", "submit"), var("B", "submit")];
+        let cli_env = vec![var("B", "cli"), var("C", "cli")];
+
+        assert_eq!(
+            merge_env(submit_env.clone(), cli_env.clone()),
+            vec![var("A", "submit"), var("B", "cli"), var("C", "cli")]
+        );
+        assert_eq!(merge_env(submit_env.clone(), vec![]), submit_env);
+        assert_eq!(merge_env(vec![], cli_env.clone()), cli_env);
+    }
+
+    #[test]
+    fn test_rebuild_parameters_need_a_tree() {
+        let err = RebuildParameters::from_records(
+            &submit(None),
+            &models::Package {
+                id: 3,
+                name: String::from("a"),
+                version: String::from("1"),
+            },
+            &models::Image {
+                id: 2,
+                name: String::from("debian:bullseye"),
+            },
+            &models::GitHash {
+                id: 4,
+                hash: String::from("0123abcd"),
+            },
+            vec![],
+        )
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("has no stored package tree"));
+    }
+
+    #[test]
+    fn test_submit_staging_dir() {
+        let dir = temp_dir();
+        let config = crate::config::tests::configuration_in(&dir);
+
+        let (submit_id, staging_dir) = submit_staging_dir(&config, None).unwrap();
+        assert_eq!(staging_dir, dir.join(submit_id.to_string()));
+
+        let other = std::env::temp_dir().join("f81d4fae-7dec-11d0-a765-00a0c91e6bf6");
+        let (submit_id, staging_dir) = submit_staging_dir(&config, Some(other.clone())).unwrap();
+        assert_eq!(
+            submit_id.to_string(),
+            "f81d4fae-7dec-11d0-a765-00a0c91e6bf6"
+        );
+        assert_eq!(staging_dir, other);
+
+        let not_a_submit = dir.join("not-a-uuid");
+        assert!(submit_staging_dir(&config, Some(not_a_submit)).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'config' subcommand
+
+use std::io::Write;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+
+/// Implementation of the "config" subcommand
+pub fn config(matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        Some(("schema", _)) => schema(),
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+fn schema() -> Result<()> {
+    let out = std::io::stdout();
+    let mut lock = out.lock();
+    serde_json::to_writer_pretty(&mut lock, &crate::config::configuration_schema())?;
+    writeln!(lock).map_err(Error::from)
+}
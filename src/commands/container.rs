@@ -0,0 +1,198 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'container' subcommand
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use clap::ArgMatches;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::config::Configuration;
+use crate::config::EndpointName;
+use crate::endpoint::ContainerStat;
+use crate::endpoint::LABEL_PACKAGE;
+use crate::endpoint::LABEL_SUBMIT;
+use crate::endpoint::LABEL_VERSION;
+
+/// Implementation of the "container" subcommand
+pub async fn container(matches: &ArgMatches, config: &Configuration) -> Result<()> {
+    match matches.subcommand() {
+        Some(("ls", matches)) => ls(matches, config).await,
+        Some(("rm", matches)) => rm(matches, config).await,
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+/// Whether the container is a build container of butido, of the submit if one is given
+///
+/// Build containers are recognized by their labels, so containers that were created with
+/// `container_labels = false` (or by older versions of butido) are not found.
+fn is_butido_container(stat: &ContainerStat, submit: Option<&Uuid>) -> bool {
+    match (stat.labels.get(LABEL_SUBMIT), submit) {
+        (Some(label), Some(submit)) => *label == submit.to_string(),
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+fn get_submit_filter(matches: &ArgMatches) -> Result<Option<Uuid>> {
+    matches
+        .get_one::<String>("submit_uuid")
+        .map(|s| Uuid::parse_str(s.as_ref()))
+        .transpose()
+        .context("Parsing the submit UUID")
+}
+
+/// The build containers of butido on all configured endpoints
+async fn butido_containers(
+    config: &Configuration,
+    submit: Option<&Uuid>,
+) -> Result<Vec<(std::sync::Arc<crate::endpoint::Endpoint>, ContainerStat)>> {
+    let endpoint_names = config
+        .docker()
+        .endpoints()
+        .keys()
+        .cloned()
+        .collect::<Vec<EndpointName>>();
+
+    crate::commands::endpoint::connect_to_endpoints(config, &endpoint_names)
+        .await?
+        .into_iter()
+        .map(|ep| async move {
+            ep.container_stats().await.map(|stats| {
+                stats
+                    .into_iter()
+                    .filter(|stat| is_butido_container(stat, submit))
+                    .map(|stat| (ep.clone(), stat))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<futures::stream::FuturesUnordered<_>>()
+        .collect::<Result<Vec<_>>>()
+        .await
+        .map(|containers| containers.into_iter().flatten().collect())
+}
+
+async fn ls(matches: &ArgMatches, config: &Configuration) -> Result<()> {
+    let csv = matches.get_flag("csv");
+    let submit = get_submit_filter(matches)?;
+    let hdr = crate::commands::util::mk_header(
+        [
+            "Endpoint",
+            "Container id",
+            "Submit",
+            "Package",
+            "Version",
+            "Created",
+            "Status",
+        ]
+        .to_vec(),
+    );
+
+    let label = |stat: &ContainerStat, name: &str| {
+        stat.labels
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| String::from("unknown"))
+    };
+
+    let data = butido_containers(config, submit.as_ref())
+        .await?
+        .into_iter()
+        .map(|(ep, stat)| {
+            vec![
+                ep.name().as_ref().to_owned(),
+                stat.id.clone(),
+                label(&stat, LABEL_SUBMIT),
+                label(&stat, LABEL_PACKAGE),
+                label(&stat, LABEL_VERSION),
+                stat.created.to_string(),
+                stat.status.clone(),
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+
+    crate::commands::util::display_data(hdr, data, csv)
+}
+
+async fn rm(matches: &ArgMatches, config: &Configuration) -> Result<()> {
+    // Either "--all" or "--submit" is set, safe by clap
+    let submit = get_submit_filter(matches)?;
+    let containers = butido_containers(config, submit.as_ref()).await?;
+
+    if containers.is_empty() {
+        println!("No butido containers found");
+        return Ok(());
+    }
+
+    let prompt = format!("Really remove {} butido containers?", containers.len());
+    if !dialoguer::Confirm::new().with_prompt(prompt).interact()? {
+        return Ok(());
+    }
+
+    containers
+        .into_iter()
+        .map(|(ep, stat)| async move {
+            ep.get_container_by_id(&stat.id)
+                .await?
+                .ok_or_else(|| anyhow!("Failed to find existing container {}", stat.id))?
+                .remove(shiplift::RmContainerOptions::builder().force(true).build())
+                .await
+                .with_context(|| anyhow!("Removing container {} on '{}'", stat.id, ep.name()))
+        })
+        .collect::<futures::stream::FuturesUnordered<_>>()
+        .collect::<Result<()>>()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn stat(labels: &[(&str, &str)]) -> ContainerStat {
+        ContainerStat {
+            created: chrono::Utc::now(),
+            id: String::from("0123456789ab"),
+            image: String::from("debian:bullseye"),
+            image_id: String::from("sha256:0123"),
+            state: String::from("exited"),
+            status: String::from("Exited (0)"),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn test_butido_containers_are_recognized_by_label() {
+        let submit = Uuid::parse_str("0c1b2a3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d").unwrap();
+        let other = Uuid::parse_str("6a4c2b5e-3f0d-4a8e-9d3c-1f2e3d4c5b6a").unwrap();
+
+        let foreign = stat(&[("com.example.owner", "someone")]);
+        let butido = stat(&[
+            (LABEL_SUBMIT, "0c1b2a3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d"),
+            (LABEL_PACKAGE, "a"),
+        ]);
+
+        assert!(!is_butido_container(&stat(&[]), None));
+        assert!(!is_butido_container(&foreign, None));
+        assert!(!is_butido_container(&foreign, Some(&submit)));
+        assert!(is_butido_container(&butido, None));
+        assert!(is_butido_container(&butido, Some(&submit)));
+        assert!(!is_butido_container(&butido, Some(&other)));
+    }
+}
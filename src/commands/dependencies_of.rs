@@ -20,7 +20,10 @@ use tracing::trace;
 
 use crate::commands::util::getbool;
 use crate::config::*;
+use crate::package::condition::ConditionData;
+use crate::package::Dag;
 use crate::package::PackageName;
+use crate::package::SerializedDag;
 use crate::repository::Repository;
 use crate::ui::*;
 
@@ -32,16 +35,12 @@ pub async fn dependencies_of(
 ) -> Result<()> {
     use filters::filter::Filter;
 
-    let package_filter = {
-        let name = matches
-            .get_one::<String>("package_name")
-            .map(|s| s.to_owned())
-            .map(PackageName::from)
-            .unwrap();
-        trace!("Checking for package with name = {}", name);
-
-        crate::util::filters::build_package_filter_by_name(name)
-    };
+    let name = matches
+        .get_one::<String>("package_name")
+        .map(PackageName::try_new)
+        .unwrap()?; // safe by clap
+    trace!("Checking for package with name = {}", name);
+    let package_filter = crate::util::filters::build_package_filter_by_name(&repo, name.clone());
 
     let format = config.package_print_format();
     let hb = crate::ui::handlebars_for_package_printing(format)?;
@@ -83,10 +82,43 @@ pub async fn dependencies_of(
         script_highlighting: false,
     };
 
-    let iter = repo
+    let packages = repo
         .packages()
         .filter(|package| package_filter.filter(package))
         .inspect(|pkg| trace!("Found package: {:?}", pkg))
+        .collect::<Vec<_>>();
+    if packages.is_empty() {
+        return Err(repo.package_not_found(&name, &name).into());
+    }
+
+    if let Some(path) = matches.get_one::<String>("dump_dag") {
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+        let dags = packages
+            .iter()
+            .map(|p| {
+                Dag::for_root_package(
+                    (*p).clone(),
+                    &repo,
+                    None,
+                    &condition_data,
+                    *config.max_dependency_depth(),
+                    *config.dependency_conflict_policy(),
+                )
+                .map(|dag| SerializedDag::from(&dag))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        SerializedDag::write_dump(path.as_ref(), &dags, None)?;
+    }
+
+    if matches.get_flag("count") {
+        return crate::commands::output::print_count(packages.len());
+    }
+
+    let iter = packages
+        .into_iter()
         .enumerate()
         .map(|(i, p)| p.prepare_print(config, &flags, &hb, i));
 
@@ -0,0 +1,231 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'doctor' subcommand
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use colored::Colorize;
+
+use crate::config::Configuration;
+use crate::db::DbConnectionConfig;
+
+/// The result of a single check of the environment
+struct Check {
+    name: String,
+
+    /// Whether butido cannot work properly if the check fails, otherwise it is only a warning
+    critical: bool,
+    result: Result<()>,
+
+    /// What the user can do if the check fails
+    hint: String,
+}
+
+impl Check {
+    fn new(
+        name: impl Into<String>,
+        critical: bool,
+        result: Result<()>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Check {
+            name: name.into(),
+            critical,
+            result,
+            hint: hint.into(),
+        }
+    }
+}
+
+/// Implementation of the "doctor" subcommand
+pub async fn doctor(
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+) -> Result<()> {
+    // The configuration was already validated, except for the directories which are checked here
+    let mut checks = vec![Check::new("Configuration", true, Ok(()), "")];
+    checks.extend(directory_checks(config));
+    checks.push(Check::new(
+        "Database connection",
+        true,
+        db_connection_config.establish_connection().map(|_| ()),
+        "Check the database_* settings and that the database server is running and reachable",
+    ));
+    checks.push(Check::new(
+        "Database CLI tool",
+        false,
+        check_any_tool(config.db_cli_tools()),
+        format!(
+            "Install one of {} (`db_cli_tools`) to use `butido db cli`",
+            config.db_cli_tools().join(", ")
+        ),
+    ));
+    checks.extend(endpoint_checks(config).await);
+
+    print_checks(&checks)?;
+
+    let failed = critical_failures(&checks);
+    if failed > 0 {
+        Err(anyhow!("{} critical checks failed", failed))
+    } else {
+        Ok(())
+    }
+}
+
+fn directory_checks(config: &Configuration) -> Vec<Check> {
+    let release_stores = config.release_stores().iter().map(|store| {
+        (
+            format!("release store '{store}'"),
+            config.releases_directory().join(store),
+        )
+    });
+
+    [
+        (String::from("log_dir"), config.log_dir().clone()),
+        (
+            String::from("releases_root"),
+            config.releases_directory().clone(),
+        ),
+        (String::from("staging"), config.staging_directory().clone()),
+        (
+            String::from("source_cache"),
+            config.source_cache_root().clone(),
+        ),
+    ]
+    .into_iter()
+    .chain(release_stores)
+    .map(|(name, path)| {
+        let hint = format!(
+            "Create the directory {} or change the configuration",
+            path.display()
+        );
+        Check::new(
+            format!("Directory {name}"),
+            true,
+            check_directory(&path),
+            hint,
+        )
+    })
+    .collect()
+}
+
+async fn endpoint_checks(config: &Configuration) -> Vec<Check> {
+    let mut checks = vec![];
+    for (ep_name, ep) in config.docker().endpoints().iter() {
+        let result = async {
+            crate::commands::endpoint::connect_to_endpoints(config, std::slice::from_ref(ep_name))
+                .await?
+                .first()
+                .ok_or_else(|| anyhow!("Endpoint not found"))?
+                .ping()
+                .await
+                .map(|_| ())
+        }
+        .await;
+
+        let hint = format!(
+            "Check that the Docker daemon is running and reachable at {} and has all configured images (see `butido endpoint ping` and `butido endpoint images verify-present`)",
+            ep.uri()
+        );
+        checks.push(Check::new(
+            format!("Endpoint '{ep_name}'"),
+            true,
+            result,
+            hint,
+        ));
+    }
+    checks
+}
+
+fn check_directory(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        Ok(())
+    } else if path.exists() {
+        Err(anyhow!("{} is not a directory", path.display()))
+    } else {
+        Err(anyhow!("{} does not exist", path.display()))
+    }
+}
+
+/// Check that at least one of the tools is in $PATH
+fn check_any_tool<S: AsRef<str>>(tools: &[S]) -> Result<()> {
+    if tools.iter().any(|tool| which::which(tool.as_ref()).is_ok()) {
+        Ok(())
+    } else {
+        let tools = tools.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        Err(anyhow!("None of {} found in $PATH", tools.join(", ")))
+    }
+}
+
+fn critical_failures(checks: &[Check]) -> usize {
+    checks
+        .iter()
+        .filter(|check| check.critical && check.result.is_err())
+        .count()
+}
+
+fn print_checks(checks: &[Check]) -> Result<()> {
+    let out = std::io::stdout();
+    let mut lock = out.lock();
+
+    for check in checks {
+        match (&check.result, check.critical) {
+            (Ok(()), _) => writeln!(lock, "{} {}", "✓".green(), check.name)?,
+            (Err(e), critical) => {
+                let mark = if critical { "✗".red() } else { "!".yellow() };
+                writeln!(lock, "{} {}: {:#}", mark, check.name, e)?;
+                writeln!(lock, "    {}", check.hint)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::tests::temp_dir;
+
+    #[test]
+    fn test_check_directory() {
+        let tmp = temp_dir();
+        let dir = tmp.join("dir");
+        assert!(check_directory(&dir).is_err());
+
+        std::fs::create_dir(&dir).unwrap();
+        let file = dir.join("file");
+        std::fs::write(&file, "").unwrap();
+        let (dir_result, file_result) = (check_directory(&dir), check_directory(&file));
+
+        assert!(dir_result.is_ok());
+        assert!(file_result.is_err());
+    }
+
+    #[test]
+    fn test_check_any_tool() {
+        assert!(check_any_tool(&["butido-missing-tool", "sh"]).is_ok());
+        assert!(check_any_tool(&["butido-missing-tool"]).is_err());
+    }
+
+    #[test]
+    fn test_only_critical_failures_count() {
+        let checks = vec![
+            Check::new("ok", true, Ok(()), ""),
+            Check::new("warning", false, Err(anyhow!("failed")), ""),
+            Check::new("critical", true, Err(anyhow!("failed")), ""),
+        ];
+        assert_eq!(critical_failures(&checks), 1);
+        assert_eq!(critical_failures(&checks[..2]), 0);
+    }
+}
@@ -28,6 +28,8 @@ use tracing::{debug, info, trace};
 use crate::config::Configuration;
 use crate::config::EndpointName;
 use crate::endpoint::Endpoint;
+use crate::endpoint::Image;
+use crate::util::docker::ImageName;
 use crate::util::progress::ProgressBars;
 
 pub async fn endpoint(
@@ -60,6 +62,7 @@ pub async fn endpoint(
         }
         Some(("containers", matches)) => containers(endpoint_names, matches, config).await,
         Some(("images", matches)) => images(endpoint_names, matches, config).await,
+        Some(("login-check", matches)) => login_check(endpoint_names, matches, config).await,
         Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
         None => Err(anyhow!("No subcommand")),
     }
@@ -82,13 +85,7 @@ async fn ping(
         .transpose()?
         .unwrap(); // safe by clap
     let endpoints = connect_to_endpoints(config, &endpoint_names).await?;
-    let multibar = Arc::new({
-        let mp = indicatif::MultiProgress::new();
-        if progress_generator.hide() {
-            mp.set_draw_target(indicatif::ProgressDrawTarget::hidden());
-        }
-        mp
-    });
+    let multibar = Arc::new(progress_generator.multi_progress());
 
     endpoints
         .iter()
@@ -506,6 +503,7 @@ async fn images(
     match matches.subcommand() {
         Some(("list", matches)) => images_list(endpoint_names, matches, config).await,
         Some(("verify-present", matches)) => images_present(endpoint_names, matches, config).await,
+        Some(("prune", matches)) => images_prune(endpoint_names, matches, config).await,
         Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
         None => Err(anyhow!("No subcommand")),
     }
@@ -595,6 +593,218 @@ async fn images_present(
         })
 }
 
+/// Check that the endpoints can log in to the registries with the configured credentials
+///
+/// The Docker API of the endpoints can only be used to log in as part of pulling an image, so for
+/// each registry one of the configured images from the registry is pulled. That is cheap if the
+/// image is already present on the endpoint.
+async fn login_check(
+    endpoint_names: Vec<EndpointName>,
+    matches: &ArgMatches,
+    config: &Configuration,
+) -> Result<()> {
+    let csv = matches.get_flag("csv");
+    let registry_auth = config.docker().registry_auth();
+    if registry_auth.is_empty() {
+        println!("No registry credentials configured");
+        return Ok(());
+    }
+
+    // The first configured image of each registry that has credentials
+    let registry_images = config
+        .docker()
+        .images()
+        .iter()
+        .filter_map(|img| {
+            crate::config::registry_auth_for(registry_auth, &img.name)
+                .map(|(registry, auth)| (registry, (&img.name, auth)))
+        })
+        .rev()
+        .collect::<std::collections::BTreeMap<_, _>>();
+
+    for registry in registry_auth.keys().sorted() {
+        if !registry_images.contains_key(registry.as_str()) {
+            info!(
+                "No configured image from registry '{}', not checked",
+                registry
+            );
+        }
+    }
+
+    let endpoints = connect_to_endpoints(config, &endpoint_names).await?;
+    let checks = endpoints
+        .iter()
+        .flat_map(|ep| {
+            registry_images
+                .iter()
+                .map(move |(registry, (img, auth))| async move {
+                    let result = match auth.docker_auth(registry) {
+                        Ok(auth) => ep.pull_image(img, Some(auth)).await,
+                        Err(e) => Err(e),
+                    };
+                    (ep.name().clone(), *registry, *img, result)
+                })
+        })
+        .collect::<futures::stream::FuturesUnordered<_>>()
+        .collect::<Vec<_>>()
+        .await;
+
+    let failed = checks.iter().filter(|(.., result)| result.is_err()).count();
+    let hdr =
+        crate::commands::util::mk_header(["Endpoint", "Registry", "Image", "Result"].to_vec());
+    let data = checks
+        .into_iter()
+        .sorted_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)))
+        .map(|(ep_name, registry, img, result)| {
+            vec![
+                ep_name.to_string(),
+                registry.to_string(),
+                img.to_string(),
+                match result {
+                    Ok(()) => String::from("ok"),
+                    Err(e) => format!("failed: {e:#}"),
+                },
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+    crate::commands::util::display_data(hdr, data, csv)?;
+
+    if failed > 0 {
+        Err(anyhow!("Logging in to a registry failed {} times", failed))
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether an image on an endpoint should be pruned
+///
+/// Only the images that are tagged exactly like one of the configured images are identified as
+/// butido images, other images on the endpoints (e.g. other tags of the same repository) are never
+/// pruned. The pruned images are pulled again by the next build that uses them.
+fn is_prunable_image(
+    image: &Image,
+    configured_images: &[ImageName],
+    older_than: Option<&chrono::DateTime<chrono::Local>>,
+) -> bool {
+    let is_configured = image
+        .tags()
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .any(|tag| configured_images.iter().any(|img| img.as_ref() == tag));
+    let is_old_enough = older_than
+        .map(|time| time > image.created())
+        .unwrap_or(true);
+
+    is_configured && is_old_enough
+}
+
+async fn images_prune(
+    endpoint_names: Vec<EndpointName>,
+    matches: &ArgMatches,
+    config: &Configuration,
+) -> Result<()> {
+    let older_than_filter = crate::commands::util::get_date_filter("older_than", matches)?;
+    let dry_run = matches.get_flag("dry_run");
+    let configured_images = config
+        .docker()
+        .images()
+        .iter()
+        .map(|img| img.name.clone())
+        .collect::<Vec<_>>();
+
+    let images = connect_to_endpoints(config, &endpoint_names)
+        .await?
+        .into_iter()
+        .map(|ep| {
+            let configured_images = &configured_images;
+            let older_than_filter = older_than_filter.as_ref();
+            async move {
+                let images = ep
+                    .images(None)
+                    .await?
+                    .filter(|img| is_prunable_image(img, configured_images, older_than_filter))
+                    .collect::<Vec<_>>();
+                Ok((ep, images))
+            }
+        })
+        .collect::<futures::stream::FuturesUnordered<_>>()
+        .collect::<Result<Vec<_>>>()
+        .await?;
+
+    let out = std::io::stdout();
+    let n_images = images.iter().map(|(_, images)| images.len()).sum::<usize>();
+    if n_images == 0 {
+        return writeln!(out.lock(), "No images to prune").map_err(Error::from);
+    }
+
+    if dry_run {
+        let mut lock = out.lock();
+        return images.iter().try_for_each(|(ep, images)| {
+            images.iter().try_for_each(|img| {
+                writeln!(
+                    lock,
+                    "Would remove from {ep}: {created} {tags} ({size})",
+                    ep = ep.name(),
+                    created = img.created(),
+                    tags = img.tags().as_deref().unwrap_or_default().join(", "),
+                    size = bytesize::ByteSize::b(*img.size()),
+                )
+                .map_err(Error::from)
+            })
+        });
+    }
+
+    let prompt = format!("Really remove {n_images} images?");
+    if !dialoguer::Confirm::new().with_prompt(prompt).interact()? {
+        return Ok(());
+    }
+
+    // An image that can't be removed (e.g. because a container uses it) doesn't stop the others
+    let results = images
+        .into_iter()
+        .map(|(ep, images)| async move {
+            let mut removed = 0;
+            let mut reclaimed = 0;
+            let mut errors = Vec::new();
+            for img in images.iter() {
+                match ep.remove_image(img.id()).await {
+                    Ok(()) => {
+                        removed += 1;
+                        reclaimed += img.size();
+                    }
+                    Err(e) => errors.push(e),
+                }
+            }
+            (ep, removed, reclaimed, errors)
+        })
+        .collect::<futures::stream::FuturesUnordered<_>>()
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut failed = 0;
+    let mut lock = out.lock();
+    for (ep, removed, reclaimed, errors) in results {
+        writeln!(
+            lock,
+            "{}: removed {} images, reclaimed up to {}",
+            ep.name(),
+            removed,
+            bytesize::ByteSize::b(reclaimed)
+        )?;
+        for e in errors.iter() {
+            writeln!(lock, "{}: {:#}", ep.name(), e)?;
+        }
+        failed += errors.len();
+    }
+
+    if failed > 0 {
+        Err(anyhow!("Removing {} images failed", failed))
+    } else {
+        Ok(())
+    }
+}
+
 /// Helper function to connect to all endpoints from the configuration, that appear (by name) in
 /// the `endpoint_names` list
 pub(super) async fn connect_to_endpoints(
@@ -610,16 +820,22 @@ pub(super) async fn connect_to_endpoints(
             crate::endpoint::EndpointConfiguration::builder()
                 .endpoint_name(ep_name.clone())
                 .endpoint(ep_cfg.clone())
-                .required_images(
+                // No required images, the endpoint commands have to work if images are missing
+                .required_docker_versions(
+                    config
+                        .docker()
+                        .docker_versions()
+                        .clone()
+                        .filter(|_| config.docker().check_versions()),
+                )
+                .required_docker_api_versions(
                     config
                         .docker()
-                        .images()
-                        .iter()
-                        .map(|img| img.name.clone())
-                        .collect::<Vec<_>>(),
+                        .docker_api_versions()
+                        .clone()
+                        .filter(|_| config.docker().check_versions()),
                 )
-                .required_docker_versions(config.docker().docker_versions().clone())
-                .required_docker_api_versions(config.docker().docker_api_versions().clone())
+                .api_retries(config.docker().docker_api_retries())
                 .build()
         })
         .collect::<Vec<_>>();
@@ -636,3 +852,40 @@ pub(super) async fn connect_to_endpoints(
 
     crate::endpoint::util::setup_endpoints(endpoint_configurations).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(age_days: i64, tags: &[&str]) -> Image {
+        let created = chrono::Utc::now() - chrono::Duration::days(age_days);
+        Image::new(created, "sha256:0123", tags)
+    }
+
+    #[test]
+    fn test_prunable_images() {
+        let configured = vec![ImageName::from("debian:bullseye")];
+        let prunable = |img: &Image| is_prunable_image(img, &configured, None);
+
+        assert!(prunable(&image(10, &["debian:bullseye"])));
+        assert!(prunable(&image(10, &["debian:buster", "debian:bullseye"])));
+
+        // Other tags of the configured repositories
+        assert!(!prunable(&image(10, &["debian:buster"])));
+        assert!(!prunable(&image(10, &["debian"])));
+        // Unrelated images
+        assert!(!prunable(&image(10, &["postgres:15"])));
+        assert!(!prunable(&image(10, &[])));
+    }
+
+    #[test]
+    fn test_prunable_images_older_than() {
+        let configured = vec![ImageName::from("debian:bullseye")];
+        let week_ago = chrono::Local::now() - chrono::Duration::days(7);
+
+        let old = image(10, &["debian:bullseye"]);
+        let new = image(1, &["debian:bullseye"]);
+        assert!(is_prunable_image(&old, &configured, Some(&week_ago)));
+        assert!(!is_prunable_image(&new, &configured, Some(&week_ago)));
+    }
+}
@@ -25,40 +25,43 @@ pub async fn env_of(matches: &ArgMatches, repo: Repository) -> Result<()> {
     use filters::filter::Filter;
     use std::io::Write;
 
-    let package_filter = {
-        let name = matches
-            .get_one::<String>("package_name")
-            .map(|s| s.to_owned())
-            .map(PackageName::from)
-            .unwrap();
-        let constraint = matches
-            .get_one::<String>("package_version_constraint")
-            .map(|s| s.to_owned())
-            .map(PackageVersionConstraint::try_from)
-            .unwrap()?;
-        trace!(
-            "Checking for package with name = {} and version = {:?}",
-            name,
-            constraint
-        );
+    let name = matches
+        .get_one::<String>("package_name")
+        .map(PackageName::try_new)
+        .unwrap()?; // safe by clap
+    let constraint = matches
+        .get_one::<String>("package_version_constraint")
+        .map(|s| s.to_owned())
+        .map(PackageVersionConstraint::try_from)
+        .unwrap()?;
+    trace!(
+        "Checking for package with name = {} and version = {:?}",
+        name,
+        constraint
+    );
+    let what = format!("{name} {constraint}");
+    let package_filter = crate::util::filters::build_package_filter_by_name(&repo, name.clone())
+        .and(crate::util::filters::build_package_filter_by_version_constraint(constraint));
 
-        crate::util::filters::build_package_filter_by_name(name)
-            .and(crate::util::filters::build_package_filter_by_version_constraint(constraint))
-    };
-
-    let mut stdout = std::io::stdout();
-    repo.packages()
+    let packages = repo
+        .packages()
         .filter(|package| package_filter.filter(package))
         .inspect(|pkg| trace!("Found package: {:?}", pkg))
-        .try_for_each(|pkg| {
-            if let Some(hm) = pkg.environment() {
-                for (key, value) in hm {
-                    writeln!(stdout, "{key} = '{value}'")?;
-                }
-            } else {
-                writeln!(stdout, "No environment")?;
+        .collect::<Vec<_>>();
+    if packages.is_empty() {
+        return Err(repo.package_not_found(&name, &what).into());
+    }
+
+    let mut stdout = std::io::stdout();
+    packages.into_iter().try_for_each(|pkg| {
+        if let Some(hm) = pkg.environment() {
+            for (key, value) in hm {
+                writeln!(stdout, "{key} = '{value}'")?;
             }
+        } else {
+            writeln!(stdout, "No environment")?;
+        }
 
-            Ok(())
-        })
+        Ok(())
+    })
 }
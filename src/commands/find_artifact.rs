@@ -80,7 +80,7 @@ pub async fn find_artifact(
         .map(|storename| {
             let bar_release_loading = progressbars.bar()?;
 
-            let p = config.releases_directory().join(storename);
+            let p = config.releases_dir_for(storename)?;
             let p_str = p.to_string_lossy();
             debug!("Loading release directory: {}", p_str);
             let r = ReleaseStore::load(StoreRoot::new(p.clone())?, &bar_release_loading);
@@ -20,10 +20,48 @@ use futures::stream::TryStreamExt;
 use tracing::trace;
 
 use crate::config::Configuration;
+use crate::package::Package;
 use crate::package::PackageVersionConstraint;
 use crate::repository::Repository;
 use crate::ui::*;
 
+/// The order of the found packages, see `--sort`
+#[derive(Clone, Copy, Debug)]
+enum SortOrder {
+    /// By name, the versions of a package in semantic order
+    Name,
+
+    /// By semantic version, packages with the same version by name
+    Version,
+}
+
+/// Sort the packages and keep at most `limit` of them
+///
+/// Returns the kept packages and the number of packages that were left out.
+fn sort_and_limit(
+    mut packages: Vec<&Package>,
+    sort: Option<SortOrder>,
+    limit: Option<usize>,
+) -> (Vec<&Package>, usize) {
+    match sort {
+        Some(SortOrder::Name) => packages.sort_by(|a, b| {
+            a.name()
+                .cmp(b.name())
+                .then_with(|| a.version().semantic_cmp(b.version()))
+        }),
+        Some(SortOrder::Version) => packages.sort_by(|a, b| {
+            a.version()
+                .semantic_cmp(b.version())
+                .then_with(|| a.name().cmp(b.name()))
+        }),
+        None => {}
+    }
+
+    let omitted = limit.map_or(0, |limit| packages.len().saturating_sub(limit));
+    packages.truncate(packages.len() - omitted);
+    (packages, omitted)
+}
+
 /// Implementation of the "find_pkg" subcommand
 pub async fn find_pkg(
     matches: &ArgMatches,
@@ -44,7 +82,18 @@ pub async fn find_pkg(
         .context("Parsing package version constraint")
         .context("A valid package version constraint looks like this: '=1.0.0'")?;
 
-    let iter = repo
+    let sort = matches
+        .get_one::<String>("sort")
+        .map(|sort| match sort.as_str() {
+            "version" => SortOrder::Version,
+            _ => SortOrder::Name,
+        });
+    let limit = matches
+        .get_one::<String>("limit")
+        .map(|s| s.parse::<usize>())
+        .transpose()?; // safe by clap
+
+    let packages = repo
         .packages()
         .filter(|p| package_name_regex.captures(p.name()).is_some())
         .filter(|p| {
@@ -53,15 +102,19 @@ pub async fn find_pkg(
                 .map(|v| v.matches(p.version()))
                 .unwrap_or(true)
         })
-        .inspect(|pkg| trace!("Found package: {:?}", pkg));
+        .inspect(|pkg| trace!("Found package: {:?}", pkg))
+        .collect::<Vec<_>>();
+    if matches.get_flag("count") {
+        return crate::commands::output::print_count(packages.len());
+    }
+    let (packages, omitted) = sort_and_limit(packages, sort, limit);
 
     let out = std::io::stdout();
     let mut outlock = out.lock();
     if matches.get_flag("terse") {
-        for p in iter {
+        for p in packages {
             writeln!(outlock, "{} {}", p.name(), p.version())?;
         }
-        Ok(())
     } else {
         let flags = crate::ui::PackagePrintFlags {
             print_all: matches.get_flag("show_all"),
@@ -92,7 +145,9 @@ pub async fn find_pkg(
         let hb = crate::ui::handlebars_for_package_printing(format)?;
 
         tokio_stream::iter({
-            iter.enumerate()
+            packages
+                .into_iter()
+                .enumerate()
                 .map(|(i, p)| p.prepare_print(config, &flags, &hb, i))
         })
         .map(|pp| pp.into_displayable())
@@ -100,6 +155,74 @@ pub async fn find_pkg(
             let r = writeln!(&mut outlock, "{p}").map_err(anyhow::Error::from);
             futures::future::ready(r)
         })
-        .await
+        .await?;
+    }
+
+    if omitted > 0 {
+        writeln!(outlock, "({omitted} more)")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::tests::package;
+
+    fn packages() -> Vec<Package> {
+        vec![
+            package("b", "1.10", "https://rust-lang.org", "1"),
+            package("a", "2", "https://rust-lang.org", "2"),
+            package("b", "1.9", "https://rust-lang.org", "3"),
+            package("a", "10", "https://rust-lang.org", "4"),
+            package("c", "1.9", "https://rust-lang.org", "5"),
+        ]
+    }
+
+    fn names(packages: &[&Package]) -> Vec<String> {
+        packages
+            .iter()
+            .map(|p| format!("{} {}", p.name(), p.version()))
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_by_version() {
+        let packages = packages();
+        let (sorted, omitted) =
+            sort_and_limit(packages.iter().collect(), Some(SortOrder::Version), None);
+        assert_eq!(
+            names(&sorted),
+            vec!["b 1.9", "c 1.9", "b 1.10", "a 2", "a 10"]
+        );
+        assert_eq!(omitted, 0);
+    }
+
+    #[test]
+    fn test_sort_by_name() {
+        let packages = packages();
+        let (sorted, _) = sort_and_limit(packages.iter().collect(), Some(SortOrder::Name), None);
+        assert_eq!(
+            names(&sorted),
+            vec!["a 2", "a 10", "b 1.9", "b 1.10", "c 1.9"]
+        );
+    }
+
+    #[test]
+    fn test_limit() {
+        let packages = packages();
+        let (limited, omitted) =
+            sort_and_limit(packages.iter().collect(), Some(SortOrder::Name), Some(2));
+        assert_eq!(names(&limited), vec!["a 2", "a 10"]);
+        assert_eq!(omitted, 3);
+
+        // Without sorting, the packages are kept in the order of the repository
+        let (limited, omitted) = sort_and_limit(packages.iter().collect(), None, Some(4));
+        assert_eq!(names(&limited), vec!["b 1.10", "a 2", "b 1.9", "a 10"]);
+        assert_eq!(omitted, 1);
+
+        let (limited, omitted) = sort_and_limit(packages.iter().collect(), None, Some(10));
+        assert_eq!(limited.len(), 5);
+        assert_eq!(omitted, 0);
     }
 }
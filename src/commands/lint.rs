@@ -35,8 +35,8 @@ pub async fn lint(
         .ok_or_else(|| anyhow!("No linter command found"))?;
     let pname = matches
         .get_one::<String>("package_name")
-        .map(|s| s.to_owned())
-        .map(PackageName::from);
+        .map(PackageName::try_new)
+        .transpose()?;
     let pvers = matches
         .get_one::<String>("package_version")
         .map(|s| s.to_owned())
@@ -48,7 +48,12 @@ pub async fn lint(
 
     let iter = repo
         .packages()
-        .filter(|p| pname.as_ref().map(|n| p.name() == n).unwrap_or(true))
+        .filter(|p| {
+            pname
+                .as_ref()
+                .map(|n| repo.names_match(p.name(), n))
+                .unwrap_or(true)
+        })
         .filter(|p| {
             pvers
                 .as_ref()
@@ -56,5 +61,7 @@ pub async fn lint(
                 .unwrap_or(true)
         });
 
-    crate::commands::util::lint_packages(iter, &linter, config, bar).await
+    let summary = crate::commands::util::lint_packages(iter, &linter, config, bar, None).await?;
+    summary.summary_line().print();
+    summary.check()
 }
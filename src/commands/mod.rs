@@ -10,10 +10,20 @@
 
 mod build;
 pub use build::build;
+pub use build::rebuild;
+
+mod config;
+pub use config::config;
+
+mod container;
+pub use container::container;
 
 mod db;
 pub use db::db;
 
+mod doctor;
+pub use doctor::doctor;
+
 mod endpoint;
 pub use endpoint::endpoint;
 pub(super) mod endpoint_container;
@@ -38,17 +48,32 @@ pub use what_depends::what_depends;
 
 mod release;
 pub use release::release;
+mod release_index;
+
+mod repo;
+pub use repo::repo;
+
+mod schema;
+pub use schema::schema;
 
 mod source;
 pub use source::source;
+pub(super) mod staging;
+pub use staging::staging;
+
+mod themes;
+pub use themes::themes;
 
 mod versions_of;
 pub use versions_of::versions_of;
 
 mod tree_of;
 pub use tree_of::tree_of;
+pub use tree_of::tree_of_dump;
 
 mod metrics;
 pub use metrics::metrics;
 
+pub(super) mod output;
+
 mod util;
@@ -0,0 +1,201 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Rendering of lists of records in the output formats that can be selected via `--format`
+
+use std::io::Write;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use itertools::Itertools;
+use serde::Serialize;
+
+/// The format a command prints its records in
+#[derive(parse_display::Display, parse_display::FromStr, Clone, Copy, Debug, Eq, PartialEq)]
+#[display(style = "lowercase")]
+pub enum OutputFormat {
+    Human,
+    Csv,
+    Json,
+    Yaml,
+}
+
+impl OutputFormat {
+    /// The values that are accepted by the `--format` flag
+    pub const VALUES: [&'static str; 4] = ["human", "csv", "json", "yaml"];
+
+    /// Get the format from the `--format` flag (or its `--csv` alias)
+    pub fn from_matches(matches: &ArgMatches) -> Result<Self> {
+        if matches.get_flag("csv") {
+            return Ok(OutputFormat::Csv);
+        }
+
+        matches
+            .get_one::<String>("format")
+            .map(|s| {
+                s.parse::<OutputFormat>()
+                    .map_err(|_| anyhow!("Unknown output format: {}", s))
+            })
+            .unwrap_or(Ok(OutputFormat::Human))
+    }
+}
+
+/// A record that can be printed in all output formats
+///
+/// The JSON and YAML formats use the `Serialize` implementation, the other formats print the
+/// `fields()` in the order of the `headers()`.
+pub trait Record: Serialize {
+    /// The column headers for the human readable and the CSV format
+    fn headers() -> Vec<&'static str>;
+
+    /// The fields of this record, in the order of `headers()`
+    fn fields(&self) -> Vec<String>;
+}
+
+/// Print the records in the requested format
+///
+/// The human readable format is printed as a table if stdout is a terminal.
+pub fn print_records<R: Record>(records: &[R], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Human => {
+            let hdr = crate::commands::util::mk_header(R::headers());
+            let data = records.iter().map(Record::fields).collect();
+            crate::commands::util::display_data(hdr, data, false)
+        }
+        _ => {
+            let out = std::io::stdout();
+            let mut lock = out.lock();
+            write!(lock, "{}", render_records(records, format)?).map_err(Error::from)
+        }
+    }
+}
+
+/// Print only the number of records, see the `--count` flag of the listing commands
+pub fn print_count(count: usize) -> Result<()> {
+    let out = std::io::stdout();
+    let mut lock = out.lock();
+    write!(lock, "{}", render_count(count)).map_err(Error::from)
+}
+
+/// Render the number of records as it is printed with `--count`
+pub fn render_count(count: usize) -> String {
+    format!("{count}\n")
+}
+
+/// Render the records in the requested format
+///
+/// The human readable format is rendered in the same way as if stdout is not a terminal.
+pub fn render_records<R: Record>(records: &[R], format: OutputFormat) -> Result<String> {
+    match format {
+        OutputFormat::Human => Ok(records.iter().map(|r| r.fields().join(" ") + "\n").join("")),
+        OutputFormat::Csv => {
+            crate::commands::util::to_csv(records.iter().map(Record::fields).collect())
+        }
+        OutputFormat::Json => serde_json::to_string_pretty(records)
+            .map(|json| json + "\n")
+            .map_err(Error::from),
+        OutputFormat::Yaml => serde_yaml::to_string(records).map_err(Error::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct TestRecord {
+        name: String,
+        url: String,
+    }
+
+    impl Record for TestRecord {
+        fn headers() -> Vec<&'static str> {
+            vec!["Name", "URL"]
+        }
+
+        fn fields(&self) -> Vec<String> {
+            vec![self.name.clone(), self.url.clone()]
+        }
+    }
+
+    fn records() -> Vec<TestRecord> {
+        vec![
+            TestRecord {
+                name: String::from("a"),
+                url: String::from("https://example.com/a.tar.gz"),
+            },
+            TestRecord {
+                name: String::from("b"),
+                url: String::from("https://example.com/b,c.tar.gz"),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_parse_output_format() {
+        for value in OutputFormat::VALUES {
+            let format = value.parse::<OutputFormat>().unwrap();
+            assert_eq!(format.to_string(), value);
+        }
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_render_human() {
+        assert_eq!(
+            render_records(&records(), OutputFormat::Human).unwrap(),
+            "a https://example.com/a.tar.gz\nb https://example.com/b,c.tar.gz\n"
+        );
+    }
+
+    #[test]
+    fn test_render_csv() {
+        assert_eq!(
+            render_records(&records(), OutputFormat::Csv).unwrap(),
+            "a,https://example.com/a.tar.gz\nb,\"https://example.com/b,c.tar.gz\"\n"
+        );
+    }
+
+    #[test]
+    fn test_render_json() {
+        let json = render_records(&records(), OutputFormat::Json).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            serde_json::json!([
+                { "name": "a", "url": "https://example.com/a.tar.gz" },
+                { "name": "b", "url": "https://example.com/b,c.tar.gz" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_render_count() {
+        let listing = render_records(&records(), OutputFormat::Human).unwrap();
+        assert_eq!(render_count(records().len()), "2\n");
+        assert_eq!(
+            render_count(records().len())
+                .trim()
+                .parse::<usize>()
+                .unwrap(),
+            listing.lines().count()
+        );
+        assert_eq!(render_count(0), "0\n");
+    }
+
+    #[test]
+    fn test_render_yaml() {
+        assert_eq!(
+            render_records(&records(), OutputFormat::Yaml).unwrap(),
+            "- name: a\n  url: https://example.com/a.tar.gz\n- name: b\n  url: https://example.com/b,c.tar.gz\n"
+        );
+    }
+}
@@ -23,6 +23,8 @@ use resiter::AndThen;
 use tokio_stream::StreamExt;
 use tracing::{debug, error, info, trace};
 
+use crate::commands::release_index::ContentIndex;
+use crate::commands::release_index::ReleaseOutcome;
 use crate::config::Configuration;
 use crate::db::models as dbmodels;
 use crate::db::DbConnectionConfig;
@@ -51,12 +53,7 @@ async fn new_release(
 ) -> Result<()> {
     let print_released_file_pathes = !matches.get_flag("quiet");
     let release_store_name = matches.get_one::<String>("release_store_name").unwrap(); // safe by clap
-    if !(config.releases_directory().exists() && config.releases_directory().is_dir()) {
-        return Err(anyhow!(
-            "Release directory does not exist or does not point to directory: {}",
-            config.releases_directory().display()
-        ));
-    }
+    let store_root = config.releases_dir_for(release_store_name)?;
 
     let pname = matches.get_one::<String>("package_name");
 
@@ -123,11 +120,7 @@ async fn new_release(
     debug!("Artifacts = {:?}", arts);
 
     arts.iter()
-        .filter_map(|art| {
-            art.path_buf()
-                .parent()
-                .map(|p| config.releases_directory().join(release_store_name).join(p))
-        })
+        .filter_map(|art| art.path_buf().parent().map(|p| store_root.join(p)))
         .map(|p| async {
             debug!("mkdir {:?}", p);
             tokio::fs::create_dir_all(p).await.map_err(Error::from)
@@ -136,13 +129,16 @@ async fn new_release(
         .collect::<Result<()>>()
         .await?;
 
-    let staging_base: &PathBuf = &config.staging_directory().join(submit.uuid.to_string());
+    let staging_base: &PathBuf = &config.staging_dir_for(&submit.uuid)?;
 
     let release_store =
         crate::db::models::ReleaseStore::create(&mut pool.get().unwrap(), release_store_name)?;
+    let tag = matches.get_one::<String>("tag").map(String::as_str);
     let do_update = matches.get_flag("package_do_update");
     let interactive = !matches.get_flag("noninteractive");
 
+    let content_index = tokio::sync::Mutex::new(ContentIndex::load(&store_root).await?);
+
     let now = chrono::offset::Local::now().naive_local();
     let any_err = arts
         .into_iter()
@@ -150,10 +146,7 @@ async fn new_release(
             #[allow(clippy::redundant_locals)]
             let art = art; // ensure it is moved
             let art_path = staging_base.join(&art.path);
-            let dest_path = config
-                .releases_directory()
-                .join(release_store_name)
-                .join(&art.path);
+            let dest_path = store_root.join(&art.path);
             debug!(
                 "Trying to release {} to {}",
                 art_path.display(),
@@ -167,7 +160,22 @@ async fn new_release(
                 );
                 Err(anyhow!("Not a file: {}", art_path.display()))
             } else {
-                if dest_path.exists() && !do_update {
+                // The lock is only held for accessing the index, not while files are hashed or
+                // copied
+                let hash = ContentIndex::hash(&art_path).await?;
+                let indexed = content_index.lock().await.get(&hash);
+                let identical = match indexed {
+                    Some(indexed) => indexed.verify(&hash).await?,
+                    None => None,
+                };
+                let already_released = identical.as_ref() == Some(&dest_path);
+
+                if already_released {
+                    debug!(
+                        "Identical content already released to {}",
+                        dest_path.display()
+                    );
+                } else if dest_path.exists() && !do_update {
                     return Err(anyhow!("Does already exist: {}", dest_path.display()));
                 } else if dest_path.exists() && do_update {
                     writeln!(
@@ -187,7 +195,7 @@ async fn new_release(
                     }
                 }
 
-                if dest_path.exists() {
+                if dest_path.exists() && !already_released {
                     debug!(
                         "Removing {} before writing new file to this path",
                         dest_path.display()
@@ -200,42 +208,50 @@ async fn new_release(
                     })?;
                 }
 
-                // else !dest_path.exists()
-                tokio::fs::copy(&art_path, &dest_path)
-                    .await
-                    .with_context(|| {
-                        anyhow!("Copying {} to {}", art_path.display(), dest_path.display())
-                    })
-                    .map_err(Error::from)
-                    .and_then(|_| {
-                        debug!("Updating {:?} to set released = true", art);
-                        let rel = crate::db::models::Release::create(
-                            &mut pool.get().unwrap(),
-                            &art,
-                            &now,
-                            &release_store,
-                        )?;
-                        debug!("Release object = {:?}", rel);
-                        Ok(dest_path)
-                    })
+                // else !dest_path.exists() or it is the identical file
+                let outcome =
+                    ContentIndex::release(&art_path, identical.as_deref(), &dest_path).await?;
+                content_index.lock().await.insert(hash, &dest_path)?;
+
+                debug!("Updating {:?} to set released = true", art);
+                let rel = crate::db::models::Release::create(
+                    &mut pool.get().unwrap(),
+                    &art,
+                    &now,
+                    &release_store,
+                    tag,
+                )?;
+                debug!("Release object = {:?}", rel);
+                Ok((dest_path, outcome))
             }
         })
         .collect::<futures::stream::FuturesUnordered<_>>()
         .collect::<Vec<Result<_>>>()
         .await
         .into_iter()
-        .and_then_ok(|dest_path| {
-            if print_released_file_pathes {
+        .and_then_ok(|(dest_path, outcome)| match outcome {
+            ReleaseOutcome::Copied if !print_released_file_pathes => Ok(()),
+            ReleaseOutcome::Copied => {
                 writeln!(std::io::stdout(), "{}", dest_path.display()).map_err(Error::from)
-            } else {
-                Ok(())
             }
+            ReleaseOutcome::Linked | ReleaseOutcome::Identical => writeln!(
+                std::io::stdout(),
+                "{}: already released (identical content)",
+                dest_path.display()
+            )
+            .map_err(Error::from),
         })
         .filter_map(Result::err)
         .inspect(|err| error!("Error: {}", err.to_string()))
         .last()
         .is_some(); // consume iterator completely, if not empty, there was an error
 
+    content_index
+        .into_inner()
+        .store()
+        .await
+        .context("Updating the content index of the release store")?;
+
     if any_err {
         Err(anyhow!("Releasing one or more artifacts failed"))
     } else {
@@ -249,18 +265,7 @@ pub async fn rm_release(
     matches: &ArgMatches,
 ) -> Result<()> {
     let release_store_name = matches.get_one::<String>("release_store_name").unwrap(); // safe by clap
-    if !(config.releases_directory().exists() && config.releases_directory().is_dir()) {
-        return Err(anyhow!(
-            "Release directory does not exist or does not point to directory: {}",
-            config.releases_directory().display()
-        ));
-    }
-    if !config.release_stores().contains(release_store_name) {
-        return Err(anyhow!(
-            "Unknown release store name: {}",
-            release_store_name
-        ));
-    }
+    let store_root = config.releases_dir_for(release_store_name)?;
 
     let pname = matches.get_one::<String>("package_name").unwrap(); // safe by clap
     let pvers = matches.get_one::<String>("package_version").unwrap(); // safe by clap
@@ -292,10 +297,7 @@ pub async fn rm_release(
             ))
             .first::<(crate::db::models::Release, crate::db::models::Artifact)>(&mut conn)?;
 
-    let artifact_path = config
-        .releases_directory()
-        .join(release_store_name)
-        .join(&artifact.path);
+    let artifact_path = store_root.join(&artifact.path);
     if !artifact_path.is_file() {
         return Err(anyhow!("Not a file: {}", artifact_path.display()));
     }
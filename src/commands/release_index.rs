@@ -0,0 +1,288 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! The content index of a release store
+//!
+//! The index maps the content hashes of the released files to their paths in the store, so that
+//! an artifact whose content was already released is not copied into the store again.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::debug;
+use tracing::warn;
+
+use crate::package::HashType;
+
+/// The name of the index file in the root directory of a release store
+const INDEX_FILE_NAME: &str = ".butido-content-index.json";
+
+/// How a file was released to the store
+#[derive(Debug, Eq, PartialEq)]
+pub enum ReleaseOutcome {
+    /// The file was copied into the store
+    Copied,
+
+    /// A file with identical content was already in the store and was hardlinked
+    Linked,
+
+    /// The file was already released to the destination path
+    Identical,
+}
+
+/// The content index of a single release store
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ContentIndex {
+    #[serde(skip)]
+    store_root: PathBuf,
+
+    /// Content hash (sha256) -> path of the file, relative to the store root
+    files: BTreeMap<String, PathBuf>,
+
+    /// The hashes of the files that were released while this index was loaded, their content
+    /// does not have to be verified again
+    #[serde(skip)]
+    verified: BTreeSet<String>,
+}
+
+/// A file of the index, as returned by `ContentIndex::get()`
+#[derive(Debug)]
+pub struct IndexedFile {
+    path: PathBuf,
+    verified: bool,
+}
+
+impl IndexedFile {
+    /// The file, if it still has the content hash `hash`
+    ///
+    /// The content of the file is verified, because files in the store may have been replaced or
+    /// removed since they were added to the index.
+    pub async fn verify(self, hash: &str) -> Result<Option<PathBuf>> {
+        if self.verified || (self.path.is_file() && ContentIndex::hash(&self.path).await? == hash) {
+            Ok(Some(self.path))
+        } else {
+            debug!(
+                "Outdated content index entry: {} -> {}",
+                hash,
+                self.path.display()
+            );
+            Ok(None)
+        }
+    }
+}
+
+impl ContentIndex {
+    /// Load the index of the store, an empty index is used if the store has none yet
+    pub async fn load(store_root: &Path) -> Result<Self> {
+        let path = store_root.join(INDEX_FILE_NAME);
+        let mut index = if path.exists() {
+            let content = tokio::fs::read_to_string(&path)
+                .await
+                .with_context(|| anyhow!("Reading {}", path.display()))?;
+            serde_json::from_str::<ContentIndex>(&content)
+                .with_context(|| anyhow!("Parsing {}", path.display()))?
+        } else {
+            ContentIndex::default()
+        };
+        index.store_root = store_root.to_path_buf();
+        Ok(index)
+    }
+
+    pub async fn store(&self) -> Result<()> {
+        let path = self.store_root.join(INDEX_FILE_NAME);
+        let content = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(&path, content)
+            .await
+            .with_context(|| anyhow!("Writing {}", path.display()))
+    }
+
+    /// The content hash of a file
+    pub async fn hash(path: &Path) -> Result<String> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| anyhow!("Opening {}", path.display()))?;
+        HashType::Sha256
+            .hash_from_reader(tokio::io::BufReader::new(file))
+            .await
+            .with_context(|| anyhow!("Hashing {}", path.display()))
+            .map(|hash| hash.to_string())
+    }
+
+    /// The file in the store that has the content hash according to the index
+    ///
+    /// This does not touch the file, use `IndexedFile::verify()` to check it.
+    pub fn get(&self, hash: &str) -> Option<IndexedFile> {
+        self.files.get(hash).map(|path| IndexedFile {
+            path: self.store_root.join(path),
+            verified: self.verified.contains(hash),
+        })
+    }
+
+    /// Record that the file `dest` in the store has the content hash `hash`
+    pub fn insert(&mut self, hash: String, dest: &Path) -> Result<()> {
+        let relative = dest
+            .strip_prefix(&self.store_root)
+            .with_context(|| {
+                anyhow!(
+                    "{} is not in the release store {}",
+                    dest.display(),
+                    self.store_root.display()
+                )
+            })?
+            .to_path_buf();
+
+        // The destination may have had another content before
+        self.files.retain(|_, path| *path != relative);
+        self.files.entry(hash.clone()).or_insert(relative);
+        self.verified.insert(hash);
+        Ok(())
+    }
+
+    /// Release the file `src` to `dest`
+    ///
+    /// `identical` is the verified file in the store with the same content. If it is set, the
+    /// file is hardlinked instead of copied. `dest` must not exist, unless it is the identical
+    /// file. The index is not updated, see `insert()`.
+    pub async fn release(
+        src: &Path,
+        identical: Option<&Path>,
+        dest: &Path,
+    ) -> Result<ReleaseOutcome> {
+        let outcome = match identical {
+            Some(identical) if identical == dest => ReleaseOutcome::Identical,
+            Some(identical) => match tokio::fs::hard_link(identical, dest).await {
+                Ok(()) => ReleaseOutcome::Linked,
+                Err(e) => {
+                    warn!(
+                        "Hardlinking {} to {} failed, copying instead: {}",
+                        identical.display(),
+                        dest.display(),
+                        e
+                    );
+                    copy(src, dest).await?
+                }
+            },
+            None => copy(src, dest).await?,
+        };
+        Ok(outcome)
+    }
+}
+
+async fn copy(src: &Path, dest: &Path) -> Result<ReleaseOutcome> {
+    tokio::fs::copy(src, dest)
+        .await
+        .with_context(|| anyhow!("Copying {} to {}", src.display(), dest.display()))
+        .map(|_| ReleaseOutcome::Copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use super::*;
+    use crate::package::tests::temp_dir;
+
+    async fn release(index: &mut ContentIndex, src: &Path, dest: &Path) -> ReleaseOutcome {
+        let hash = ContentIndex::hash(src).await.unwrap();
+        let identical = match index.get(&hash) {
+            Some(indexed) => indexed.verify(&hash).await.unwrap(),
+            None => None,
+        };
+        let outcome = ContentIndex::release(src, identical.as_deref(), dest)
+            .await
+            .unwrap();
+        index.insert(hash, dest).unwrap();
+        outcome
+    }
+
+    #[tokio::test]
+    async fn test_releasing_identical_content_stores_one_copy() {
+        let root = temp_dir();
+        let staging = root.join("staging");
+        let store = root.join("store");
+        std::fs::create_dir_all(&staging).unwrap();
+        std::fs::create_dir_all(&store).unwrap();
+
+        let src = staging.join("a-1.tar.gz");
+        std::fs::write(&src, "artifact").unwrap();
+        let dest = store.join("a-1.tar.gz");
+        let other_dest = store.join("a-1-rebuild.tar.gz");
+
+        let mut index = ContentIndex::load(&store).await.unwrap();
+        let first = release(&mut index, &src, &dest).await;
+        let again = release(&mut index, &src, &dest).await;
+        index.store().await.unwrap();
+
+        // A new index must know about the released file
+        let mut index = ContentIndex::load(&store).await.unwrap();
+        let other = release(&mut index, &src, &other_dest).await;
+
+        let inodes = (
+            std::fs::metadata(&dest).unwrap().ino(),
+            std::fs::metadata(&other_dest).unwrap().ino(),
+        );
+
+        assert_eq!(first, ReleaseOutcome::Copied);
+        assert_eq!(again, ReleaseOutcome::Identical);
+        assert_eq!(other, ReleaseOutcome::Linked);
+        assert_eq!(inodes.0, inodes.1, "Not the same stored copy");
+    }
+
+    #[tokio::test]
+    async fn test_outdated_entries_are_ignored() {
+        let root = temp_dir();
+        let store = root.join("store");
+        std::fs::create_dir_all(&store).unwrap();
+
+        let src = root.join("a-1.tar.gz");
+        std::fs::write(&src, "artifact").unwrap();
+        let dest = store.join("a-1.tar.gz");
+
+        let mut index = ContentIndex::load(&store).await.unwrap();
+        release(&mut index, &src, &dest).await;
+        index.store().await.unwrap();
+        let hash = ContentIndex::hash(&src).await.unwrap();
+
+        // The released file was replaced outside of butido
+        std::fs::write(&dest, "replaced").unwrap();
+        let index = ContentIndex::load(&store).await.unwrap();
+        let found = index.get(&hash).unwrap().verify(&hash).await.unwrap();
+
+        assert_eq!(found, None);
+    }
+
+    #[tokio::test]
+    async fn test_released_files_are_not_hashed_again() {
+        let root = temp_dir();
+        let store = root.join("store");
+        std::fs::create_dir_all(&store).unwrap();
+
+        let src = root.join("a-1.tar.gz");
+        std::fs::write(&src, "artifact").unwrap();
+        let dest = store.join("a-1.tar.gz");
+
+        let mut index = ContentIndex::load(&store).await.unwrap();
+        release(&mut index, &src, &dest).await;
+        let hash = ContentIndex::hash(&src).await.unwrap();
+
+        // The index trusts the file it released itself, so a changed content is not noticed
+        std::fs::write(&dest, "replaced").unwrap();
+        let found = index.get(&hash).unwrap().verify(&hash).await.unwrap();
+
+        assert_eq!(found, Some(dest));
+    }
+}
@@ -0,0 +1,287 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'repo' subcommand
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use clap::ArgMatches;
+use colored::Colorize;
+
+use crate::config::Configuration;
+use crate::config::LintSeverity;
+use crate::config::RepoLintRule;
+use crate::package::Package;
+use crate::package::PhaseName;
+use crate::repository::Repository;
+
+/// Implementation of the "repo" subcommand
+pub async fn repo(matches: &ArgMatches, config: &Configuration, repo: Repository) -> Result<()> {
+    match matches.subcommand() {
+        Some(("lint", _)) => lint(config, repo),
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+/// An issue that was found by `repo lint`
+#[derive(Debug)]
+struct LintIssue {
+    rule: RepoLintRule,
+    message: String,
+}
+
+impl LintIssue {
+    fn new(rule: RepoLintRule, message: String) -> Self {
+        LintIssue { rule, message }
+    }
+}
+
+/// Check the definition of a single package against all `repo lint` rules
+fn lint_package(package: &Package, available_phases: &[PhaseName]) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+
+    let mut source_names = package.sources().keys().collect::<Vec<_>>();
+    source_names.sort();
+    for name in source_names.iter() {
+        let source = &package.sources()[name.as_str()];
+        if source.hash().value().to_string().trim().is_empty() {
+            issues.push(LintIssue::new(
+                RepoLintRule::MissingHash,
+                format!("Source '{name}' has no hash"),
+            ));
+        }
+
+        if source.url().scheme() == "http" {
+            issues.push(LintIssue::new(
+                RepoLintRule::InsecureSource,
+                format!("Source '{}' uses plain http: {}", name, source.url()),
+            ));
+        }
+    }
+
+    let mut seen = HashMap::new();
+    for name in source_names {
+        if let Some(other) = seen.insert(name.to_lowercase(), name) {
+            issues.push(LintIssue::new(
+                RepoLintRule::DuplicateSourceName,
+                format!("Source names '{other}' and '{name}' only differ in case"),
+            ));
+        }
+    }
+
+    let mut phases = package
+        .phases()
+        .keys()
+        .filter(|phase| !available_phases.contains(phase))
+        .collect::<Vec<_>>();
+    phases.sort_by_key(|phase| phase.as_str());
+    for phase in phases {
+        issues.push(LintIssue::new(
+            RepoLintRule::UnknownPhase,
+            format!(
+                "Phase '{}' is not in the configured 'available_phases'",
+                phase.as_str()
+            ),
+        ));
+    }
+
+    let build = package.dependencies().build().iter().map(AsRef::as_ref);
+    let runtime = package.dependencies().runtime().iter().map(AsRef::as_ref);
+    for (kind, dependency) in std::iter::repeat("Build")
+        .zip(build)
+        .chain(std::iter::repeat("Runtime").zip(runtime))
+    {
+        if dependency.trim().is_empty() {
+            issues.push(LintIssue::new(
+                RepoLintRule::EmptyDependency,
+                format!("{kind} dependency is an empty string"),
+            ));
+        }
+    }
+
+    issues
+}
+
+fn lint(config: &Configuration, repo: Repository) -> Result<()> {
+    let severity_of = |rule: RepoLintRule| {
+        config
+            .repo_lint_severity()
+            .as_ref()
+            .and_then(|severities| severities.get(&rule).copied())
+            .unwrap_or_else(|| rule.default_severity())
+    };
+
+    // Group the issues by the file the package was loaded from
+    let mut issues_by_path = BTreeMap::<Option<PathBuf>, Vec<(&Package, LintIssue)>>::new();
+    for package in repo.packages() {
+        let issues = lint_package(package, config.available_phases());
+        if !issues.is_empty() {
+            issues_by_path
+                .entry(repo.path_of(package).map(PathBuf::from))
+                .or_default()
+                .extend(issues.into_iter().map(|issue| (package, issue)));
+        }
+    }
+
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    let mut errors = 0;
+    let mut warnings = 0;
+    for (path, issues) in issues_by_path {
+        let path = path
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| String::from("<unknown>"));
+        writeln!(outlock, "{}", path.bold())?;
+
+        for (package, issue) in issues {
+            let severity = match severity_of(issue.rule) {
+                LintSeverity::Error => {
+                    errors += 1;
+                    "error".red()
+                }
+                LintSeverity::Warn => {
+                    warnings += 1;
+                    "warn".yellow()
+                }
+            };
+
+            writeln!(
+                outlock,
+                "    [{}] {} {} ({}): {}",
+                severity,
+                package.name(),
+                package.version(),
+                issue.rule,
+                issue.message
+            )?;
+        }
+    }
+
+    writeln!(outlock, "{errors} errors, {warnings} warnings")?;
+    if errors > 0 {
+        Err(anyhow!("Linting the repository found {} errors", errors))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_toml(toml: &str) -> Vec<RepoLintRule> {
+        let package = toml::from_str::<Package>(toml).unwrap();
+        let available_phases = vec![PhaseName::from(String::from("build"))];
+        lint_package(&package, &available_phases)
+            .into_iter()
+            .map(|issue| issue.rule)
+            .collect()
+    }
+
+    fn package_toml(sources: &str, dependencies: &str, phases: &str) -> String {
+        format!(
+            r#"
+            name = "a"
+            version = "1"
+            version_is_semver = false
+            patches = []
+            {sources}
+            [dependencies]
+            {dependencies}
+            [phases]
+            {phases}
+            "#
+        )
+    }
+
+    fn source(name: &str, url: &str, hash: &str) -> String {
+        format!(
+            r#"
+            [sources.{name}]
+            url = "{url}"
+            download_manually = false
+            hash = {{ type = "sha1", hash = "{hash}" }}
+            "#
+        )
+    }
+
+    const DEPENDENCIES: &str = r#"build = ["b =1"]
+            runtime = ["c =1"]"#;
+    const PHASES: &str = r#"build.script = "true""#;
+
+    #[test]
+    fn test_good_package_has_no_issues() {
+        let toml = package_toml(
+            &source("src", "https://example.com/a.tar.gz", "abc"),
+            DEPENDENCIES,
+            PHASES,
+        );
+        assert_eq!(lint_toml(&toml), vec![]);
+    }
+
+    #[test]
+    fn test_missing_hash() {
+        let toml = package_toml(
+            &source("src", "https://example.com/a.tar.gz", ""),
+            DEPENDENCIES,
+            PHASES,
+        );
+        assert_eq!(lint_toml(&toml), vec![RepoLintRule::MissingHash]);
+    }
+
+    #[test]
+    fn test_insecure_source() {
+        let toml = package_toml(
+            &source("src", "http://example.com/a.tar.gz", "abc"),
+            DEPENDENCIES,
+            PHASES,
+        );
+        assert_eq!(lint_toml(&toml), vec![RepoLintRule::InsecureSource]);
+    }
+
+    #[test]
+    fn test_unknown_phase() {
+        let toml = package_toml(
+            &source("src", "https://example.com/a.tar.gz", "abc"),
+            DEPENDENCIES,
+            r#"build.script = "true"
+            biuld.script = "true""#,
+        );
+        assert_eq!(lint_toml(&toml), vec![RepoLintRule::UnknownPhase]);
+    }
+
+    #[test]
+    fn test_duplicate_source_name() {
+        let sources = source("src", "https://example.com/a.tar.gz", "abc")
+            + &source("SRC", "https://example.com/b.tar.gz", "def");
+        let toml = package_toml(&sources, DEPENDENCIES, PHASES);
+        assert_eq!(lint_toml(&toml), vec![RepoLintRule::DuplicateSourceName]);
+    }
+
+    #[test]
+    fn test_empty_dependency() {
+        let toml = package_toml(
+            &source("src", "https://example.com/a.tar.gz", "abc"),
+            r#"build = ["b =1", ""]
+            runtime = [" "]"#,
+            PHASES,
+        );
+        assert_eq!(
+            lint_toml(&toml),
+            vec![RepoLintRule::EmptyDependency, RepoLintRule::EmptyDependency]
+        );
+    }
+}
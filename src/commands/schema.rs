@@ -0,0 +1,99 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'schema' subcommand
+
+use std::io::Write;
+
+use anyhow::Error;
+use anyhow::Result;
+use schemars::schema::RootSchema;
+
+use crate::package::Package;
+
+/// Implementation of the "schema" subcommand
+pub fn schema() -> Result<()> {
+    let out = std::io::stdout();
+    let mut lock = out.lock();
+    serde_json::to_writer_pretty(&mut lock, &package_schema())?;
+    writeln!(lock).map_err(Error::from)
+}
+
+/// The JSON Schema of a `pkg.toml` file
+fn package_schema() -> RootSchema {
+    let mut schema = schemars::schema_for!(Package);
+
+    // The pkg.toml files are merged along the directory tree, so a single file does not have to
+    // contain all required fields; only the merged package does.
+    if let Some(object) = schema.schema.object.as_mut() {
+        object.required.clear();
+    }
+
+    let metadata = schema.schema.metadata();
+    metadata.title = Some(String::from("pkg.toml"));
+    metadata.description = Some(String::from(
+        "A butido package definition. Files are merged along the directory tree, \
+         the merged package must contain all fields.",
+    ));
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_json() -> serde_json::Value {
+        serde_json::to_value(package_schema()).unwrap()
+    }
+
+    #[test]
+    fn test_schema_is_valid_json_schema() {
+        let json = to_json();
+        assert_eq!(
+            json["$schema"], "http://json-schema.org/draft-07/schema#",
+            "Not a draft-07 JSON Schema"
+        );
+
+        // The schema must be readable as a JSON Schema again
+        let _: RootSchema = serde_json::from_value(json.clone()).unwrap();
+
+        assert_eq!(json["type"], "object");
+        assert_eq!(json["additionalProperties"], false);
+        assert!(json.get("required").is_none());
+        for field in ["name", "version", "sources", "dependencies", "phases"] {
+            assert!(
+                json["properties"].get(field).is_some(),
+                "Missing property {field}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_schema_covers_conditional_dependencies() {
+        let json = to_json();
+        let definitions = &json["definitions"];
+
+        for dependency in ["BuildDependency", "Dependency"] {
+            let variants = definitions[dependency]["anyOf"].as_array().unwrap();
+            assert_eq!(variants.len(), 2, "{dependency} has not two variants");
+            assert_eq!(variants[0]["type"], "string");
+            assert_eq!(variants[1]["type"], "object");
+            assert_eq!(
+                variants[1]["properties"]["condition"]["$ref"],
+                "#/definitions/Condition"
+            );
+        }
+
+        let condition = &definitions["Condition"]["properties"];
+        for field in ["has_env", "env_eq", "in_image"] {
+            assert!(condition.get(field).is_some(), "Missing condition {field}");
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::package::Package;
+use crate::repository::Repository;
+
+/// The host that is reported for URLs without a host (e.g. `file://` URLs)
+const NO_HOST: &str = "<none>";
+
+#[derive(Debug, Serialize)]
+struct HostEntry {
+    host: String,
+    protocol: String,
+    sources: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct InsecureSource {
+    package_name: String,
+    package_version: String,
+    source_name: String,
+    url: String,
+}
+
+/// A summary of the hosts and protocols all sources of a repository are fetched from
+#[derive(Debug, Default, Serialize)]
+struct SourceAudit {
+    hosts: BTreeMap<String, usize>,
+    protocols: BTreeMap<String, usize>,
+    host_protocols: Vec<HostEntry>,
+    insecure_sources: Vec<InsecureSource>,
+}
+
+impl SourceAudit {
+    fn of<'a>(packages: impl Iterator<Item = &'a Package>) -> Self {
+        let mut audit = SourceAudit::default();
+        let mut host_protocols = BTreeMap::<(String, String), usize>::new();
+
+        for package in packages {
+            for (source_name, source) in package.sources().iter() {
+                let url = source.url();
+                let host = url.host_str().unwrap_or(NO_HOST).to_lowercase();
+                let protocol = url.scheme().to_string();
+
+                *audit.hosts.entry(host.clone()).or_default() += 1;
+                *audit.protocols.entry(protocol.clone()).or_default() += 1;
+                *host_protocols.entry((host, protocol)).or_default() += 1;
+
+                if url.scheme() == "http" {
+                    audit.insecure_sources.push(InsecureSource {
+                        package_name: package.name().to_string(),
+                        package_version: package.version().to_string(),
+                        source_name: source_name.clone(),
+                        url: url.to_string(),
+                    });
+                }
+            }
+        }
+
+        audit.host_protocols = host_protocols
+            .into_iter()
+            .map(|((host, protocol), sources)| HostEntry {
+                host,
+                protocol,
+                sources,
+            })
+            .collect();
+        audit
+    }
+}
+
+/// Implementation of the "source audit" subcommand
+pub async fn audit(matches: &ArgMatches, repo: Repository) -> Result<()> {
+    let audit = SourceAudit::of(repo.packages());
+
+    if matches.get_flag("json") {
+        let out = std::io::stdout();
+        let mut lock = out.lock();
+        serde_json::to_writer_pretty(&mut lock, &audit)?;
+        return writeln!(lock).map_err(Error::from);
+    }
+
+    let csv = matches.get_flag("csv");
+    let hdr = crate::commands::util::mk_header(["Host", "Protocol", "Sources"].to_vec());
+    let data = audit
+        .host_protocols
+        .iter()
+        .map(|e| vec![e.host.clone(), e.protocol.clone(), e.sources.to_string()])
+        .collect::<Vec<_>>();
+    crate::commands::util::display_data(hdr, data, csv)?;
+
+    if csv {
+        return Ok(());
+    }
+
+    let out = std::io::stdout();
+    let mut lock = out.lock();
+    writeln!(lock)?;
+    writeln!(lock, "{} distinct hosts", audit.hosts.len())?;
+    for (protocol, count) in audit.protocols.iter() {
+        writeln!(lock, "{count} sources via {protocol}")?;
+    }
+
+    if !audit.insecure_sources.is_empty() {
+        writeln!(lock)?;
+        writeln!(
+            lock,
+            "{}",
+            format!(
+                "{} sources use insecure plain http:",
+                audit.insecure_sources.len()
+            )
+            .red()
+            .bold()
+        )?;
+        for s in audit.insecure_sources.iter() {
+            writeln!(
+                lock,
+                "{} {} -> {} = {}",
+                s.package_name,
+                s.package_version,
+                s.source_name,
+                s.url.red()
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::tests::package;
+
+    #[test]
+    fn test_audit_of_mixed_scheme_sources() {
+        let packages = [
+            package("a", "1", "https://example.com/a.tar.gz", "h"),
+            package("b", "1", "http://example.com/b.tar.gz", "h"),
+            package("c", "1", "https://mirror.example.org/c.tar.gz", "h"),
+            package("d", "1", "file:///srv/sources/d.tar.gz", "h"),
+            package("e", "1", "git+https://example.com/e.git", "h"),
+        ];
+
+        let audit = SourceAudit::of(packages.iter());
+
+        assert_eq!(audit.hosts.len(), 3);
+        assert_eq!(audit.hosts["example.com"], 3);
+        assert_eq!(audit.hosts["mirror.example.org"], 1);
+        assert_eq!(audit.hosts[NO_HOST], 1);
+
+        assert_eq!(audit.protocols["https"], 2);
+        assert_eq!(audit.protocols["http"], 1);
+        assert_eq!(audit.protocols["file"], 1);
+        assert_eq!(audit.protocols["git+https"], 1);
+
+        assert_eq!(audit.host_protocols.len(), 5);
+
+        assert_eq!(audit.insecure_sources.len(), 1);
+        assert_eq!(audit.insecure_sources[0].package_name, "b");
+        assert_eq!(audit.insecure_sources[0].url, "http://example.com/b.tar.gz");
+    }
+}
@@ -0,0 +1,328 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! The 'source cache' subcommands
+//!
+//! `source cache migrate` converts the source cache between the layouts of `CacheLayout`. Every
+//! step of the migration replaces a file atomically, so an interrupted migration can simply be
+//! run again.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use tracing::debug;
+use tracing::warn;
+
+use crate::config::Configuration;
+use crate::package::Package;
+use crate::repository::Repository;
+use crate::source::CacheLayout;
+use crate::source::HashLimits;
+use crate::source::SourceCache;
+use crate::source::SourceEntry;
+
+/// What the migration did with a source
+#[derive(Debug, Eq, PartialEq)]
+enum Migrated {
+    /// The source was moved to the target layout
+    Moved,
+
+    /// The source was replaced by a link to an identical stored source, this many bytes were
+    /// reclaimed
+    Deduplicated(u64),
+
+    /// The source is missing or in the target layout already
+    Unchanged,
+}
+
+/// The result of a migration
+#[derive(Debug, Default)]
+struct MigrationSummary {
+    moved: usize,
+    deduplicated: usize,
+    reclaimed_bytes: u64,
+    unchanged: usize,
+    errors: Vec<Error>,
+}
+
+impl std::fmt::Display for MigrationSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "moved: {}", self.moved)?;
+        writeln!(
+            f,
+            "deduplicated: {} ({} reclaimed)",
+            self.deduplicated,
+            indicatif::HumanBytes(self.reclaimed_bytes)
+        )?;
+        writeln!(f, "unchanged: {}", self.unchanged)?;
+        writeln!(f, "failed: {}", self.errors.len())
+    }
+}
+
+pub async fn cache(matches: &ArgMatches, config: &Configuration, repo: Repository) -> Result<()> {
+    match matches.subcommand() {
+        Some(("migrate", matches)) => migrate(matches, config, repo).await,
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+// Implementation of the 'source cache migrate' subcommand
+async fn migrate(matches: &ArgMatches, config: &Configuration, repo: Repository) -> Result<()> {
+    let to = match matches.get_one::<String>("to").map(String::as_str) {
+        Some("cas") => CacheLayout::Cas,
+        Some("package") => CacheLayout::Package,
+        other => return Err(anyhow!("Unknown cache layout: {:?}", other)), // safe by clap
+    };
+
+    let summary = migrate_cache(config.source_cache_root(), repo.packages(), to).await;
+    print!("{summary}");
+    if *config.source_cache_layout() != to {
+        warn!("The configured 'cache_layout' is not the layout the cache was migrated to");
+    }
+
+    if summary.errors.is_empty() {
+        Ok(())
+    } else {
+        for error in summary.errors.iter() {
+            eprintln!("{error:#}");
+        }
+        Err(anyhow!(
+            "Migrating {} source(s) failed",
+            summary.errors.len()
+        ))
+    }
+}
+
+/// Migrate the sources of the `packages` in the cache at `root` to the layout `to`
+///
+/// Sources that fail the hash verification are left where they are and reported as errors.
+async fn migrate_cache<'a>(
+    root: &Path,
+    packages: impl Iterator<Item = &'a Package>,
+    to: CacheLayout,
+) -> MigrationSummary {
+    let sc = SourceCache::new(root.to_path_buf()).with_layout(CacheLayout::Cas);
+    let mut summary = MigrationSummary::default();
+    for package in packages {
+        for source in sc.sources_for(package) {
+            let result = match to {
+                CacheLayout::Cas => to_cas(&source).await,
+                CacheLayout::Package => to_package(&source).await,
+            };
+            debug!("Migrating {}: {:?}", source.path().display(), result);
+            match result {
+                Ok(Migrated::Moved) => summary.moved += 1,
+                Ok(Migrated::Deduplicated(bytes)) => {
+                    summary.deduplicated += 1;
+                    summary.reclaimed_bytes += bytes;
+                }
+                Ok(Migrated::Unchanged) => summary.unchanged += 1,
+                Err(e) => summary
+                    .errors
+                    .push(e.context(anyhow!("Migrating {}", source.path().display()))),
+            }
+        }
+    }
+    summary
+}
+
+/// Move the file at `path()` of the (content-addressed) `source` into the store
+///
+/// The file is hard linked into the store first and then replaced by a symlink, so it is
+/// available at any time.
+async fn to_cas(source: &SourceEntry) -> Result<Migrated> {
+    let Some(cas_path) = source.cas_path() else {
+        return Ok(Migrated::Unchanged);
+    };
+    let p = source.path();
+    let meta = match tokio::fs::symlink_metadata(&p).await {
+        Ok(meta) => meta,
+        // An interrupted migration may have stored the file without linking it
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return if source.link_stored().await? {
+                Ok(Migrated::Moved)
+            } else {
+                Ok(Migrated::Unchanged)
+            };
+        }
+        Err(e) => return Err(e).with_context(|| anyhow!("Reading {}", p.display())),
+    };
+    if meta.is_symlink() {
+        return Ok(Migrated::Unchanged);
+    }
+
+    source
+        .verify_hash_detailed(vec![], false, &HashLimits::default())
+        .await?
+        .as_result()?;
+
+    let stored = match tokio::fs::metadata(&cas_path).await {
+        Ok(stored) => Some(stored),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| anyhow!("Reading {}", cas_path.display())),
+    };
+    let migrated = match stored {
+        // The hard link of an interrupted migration
+        Some(stored) if stored.dev() == meta.dev() && stored.ino() == meta.ino() => Migrated::Moved,
+        Some(_) => Migrated::Deduplicated(meta.len()),
+        None => {
+            if let Some(dir) = cas_path.parent() {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .with_context(|| anyhow!("Creating directory {}", dir.display()))?;
+            }
+            tokio::fs::hard_link(&p, &cas_path)
+                .await
+                .with_context(|| anyhow!("Linking {} to {}", cas_path.display(), p.display()))?;
+            Migrated::Moved
+        }
+    };
+
+    if source.link_stored().await? {
+        Ok(migrated)
+    } else {
+        Err(anyhow!("{} is not stored", cas_path.display()))
+    }
+}
+
+/// Replace the symlink at `path()` of the `source` by a copy of the stored file
+///
+/// The stored files are kept, as sources that are not in the repository may still link to them.
+async fn to_package(source: &SourceEntry) -> Result<Migrated> {
+    let p = source.path();
+    let is_symlink = match tokio::fs::symlink_metadata(&p).await {
+        Ok(meta) => meta.is_symlink(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e).with_context(|| anyhow!("Reading {}", p.display())),
+    };
+    if !is_symlink {
+        return Ok(Migrated::Unchanged);
+    }
+
+    source
+        .verify_hash_detailed(vec![], false, &HashLimits::default())
+        .await?
+        .as_result()?;
+
+    let tmp = source.tmp_path();
+    tokio::fs::copy(&p, &tmp)
+        .await
+        .with_context(|| anyhow!("Copying {} to {}", p.display(), tmp.display()))?;
+    tokio::fs::rename(&tmp, &p)
+        .await
+        .with_context(|| anyhow!("Moving {} to {}", tmp.display(), p.display()))?;
+    Ok(Migrated::Moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::tests::package;
+    use crate::package::tests::temp_dir;
+
+    // sha1 of "butido"
+    const FIXTURE_HASH: &str = "03f904e2a4708dfd115cf6665838244d0fda06c9";
+
+    async fn verified(sc: &SourceCache, package: &Package) -> bool {
+        sc.sources_for(package)[0]
+            .verify_hash_detailed(vec![], false, &HashLimits::default())
+            .await
+            .map(|v| v.matches())
+            .unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn test_migrate_package_layout_to_cas_and_back() {
+        let cache_root = temp_dir();
+        let packages = [
+            package("a", "1", "https://example.com/a-1.tar.gz", FIXTURE_HASH),
+            package("a", "2", "https://example.com/a-2.tar.gz", FIXTURE_HASH),
+            package("b", "1", "https://example.com/b-1.tar.gz", FIXTURE_HASH),
+            package("c", "1", "https://example.com/c-1.tar.gz", "ffff"),
+        ];
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        // "b" is corrupt, the source of "c" is missing
+        for (package, content) in packages.iter().zip(["butido", "butido", "corrupt"]) {
+            let path = sc.sources_for(package)[0].path();
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let summary = migrate_cache(&cache_root, packages.iter(), CacheLayout::Cas).await;
+        assert_eq!(summary.moved, 1);
+        assert_eq!(summary.deduplicated, 1);
+        assert_eq!(summary.reclaimed_bytes, 6);
+        assert_eq!(summary.unchanged, 1);
+        assert_eq!(summary.errors.len(), 1);
+
+        let cas = SourceCache::new(cache_root.to_path_buf()).with_layout(CacheLayout::Cas);
+        for package in &packages[..2] {
+            let source = &cas.sources_for(package)[0];
+            assert!(source.path().is_symlink());
+            assert!(verified(&cas, package).await);
+        }
+        let corrupt = &cas.sources_for(&packages[2])[0];
+        assert!(!corrupt.path().is_symlink());
+        assert_eq!(std::fs::read(corrupt.path()).unwrap(), b"corrupt");
+        assert!(!cas.sources_for(&packages[3])[0].exists());
+
+        // Migrating again changes nothing
+        let summary = migrate_cache(&cache_root, packages.iter(), CacheLayout::Cas).await;
+        assert_eq!((summary.moved, summary.deduplicated), (0, 0));
+        assert_eq!(summary.unchanged, 3);
+
+        let summary = migrate_cache(&cache_root, packages.iter(), CacheLayout::Package).await;
+        assert_eq!(summary.moved, 2);
+        assert_eq!(summary.unchanged, 2);
+        assert!(summary.errors.is_empty());
+        for package in &packages[..2] {
+            let source = &sc.sources_for(package)[0];
+            assert!(!source.path().is_symlink());
+            assert!(source.path().is_file());
+            assert!(verified(&sc, package).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migration_resumes_after_interruption() {
+        let cache_root = temp_dir();
+        let packages = [
+            package("a", "1", "https://example.com/a-1.tar.gz", FIXTURE_HASH),
+            package("a", "2", "https://example.com/a-2.tar.gz", FIXTURE_HASH),
+        ];
+        let cas = SourceCache::new(cache_root.to_path_buf()).with_layout(CacheLayout::Cas);
+        let first = cas.sources_for(&packages[0]).remove(0);
+        let second = cas.sources_for(&packages[1]).remove(0);
+        let cas_path = first.cas_path().unwrap();
+        std::fs::create_dir_all(first.path().parent().unwrap()).unwrap();
+        std::fs::create_dir_all(cas_path.parent().unwrap()).unwrap();
+
+        // Interrupted after storing the file of "a 1", but before linking it
+        std::fs::write(first.path(), "butido").unwrap();
+        std::fs::hard_link(first.path(), &cas_path).unwrap();
+        // Interrupted after the file of "a 2" was stored and its old file was removed
+        std::fs::create_dir_all(second.path().parent().unwrap()).unwrap();
+
+        let summary = migrate_cache(&cache_root, packages.iter(), CacheLayout::Cas).await;
+        assert_eq!(summary.moved, 2);
+        assert_eq!(summary.deduplicated, 0);
+        assert!(summary.errors.is_empty());
+        for package in packages.iter() {
+            assert!(cas.sources_for(package)[0].path().is_symlink());
+            assert!(verified(&cas, package).await);
+        }
+    }
+}
@@ -8,13 +8,12 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
-use std::convert::TryFrom;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::Write;
 use std::sync::Arc;
 
 use anyhow::anyhow;
 use anyhow::Context;
-use anyhow::Error;
 use anyhow::Result;
 use clap::ArgMatches;
 use tokio::io::AsyncWriteExt;
@@ -23,12 +22,18 @@ use tokio_stream::StreamExt;
 use tracing::{debug, info, trace, warn};
 
 use crate::config::*;
-use crate::package::PackageName;
-use crate::package::PackageVersionConstraint;
+use crate::error::ButidoError;
+use crate::package::Package;
 use crate::repository::Repository;
 use crate::source::*;
+use crate::util::metrics::Counter;
+use crate::util::parallelism::Parallelism;
 use crate::util::progress::ProgressBars;
+use crate::util::timings::Timings;
 
+/// The default number of concurrent downloads if the parallelism isn't set explicitly
+///
+/// Downloads are not CPU bound, therefore we don't fall back to the number of CPUs here.
 const NUMBER_OF_MAX_CONCURRENT_DOWNLOADS: usize = 100;
 
 /// A wrapper around the indicatif::ProgressBar
@@ -109,10 +114,50 @@ impl ProgressWrapper {
     }
 }
 
+/// The credentials that are used for downloading a source
+#[derive(Debug)]
+enum Credentials<'a> {
+    Config(&'a SourceAuth),
+    Netrc(&'a NetrcEntry),
+}
+
+impl Credentials<'_> {
+    fn authorize(&self, request: reqwest::RequestBuilder) -> Result<reqwest::RequestBuilder> {
+        match self {
+            Credentials::Config(auth) => auth.authorize(request),
+            Credentials::Netrc(entry) => Ok(entry.authorize(request)),
+        }
+    }
+}
+
+/// Find the credentials for the host of the URL
+///
+/// The `source_auth` from the configuration takes precedence over the netrc file.
+fn credentials_for<'a>(
+    source_auth: Option<&'a HashMap<String, SourceAuth>>,
+    netrc: Option<&'a Netrc>,
+    url: &url::Url,
+) -> Option<Credentials<'a>> {
+    let host = url.host_str()?;
+    source_auth
+        .and_then(|source_auth| {
+            source_auth
+                .iter()
+                .find(|(auth_host, _)| auth_host.eq_ignore_ascii_case(host))
+                .map(|(_, auth)| Credentials::Config(auth))
+        })
+        .or_else(|| {
+            netrc
+                .and_then(|netrc| netrc.credentials_for(host))
+                .map(Credentials::Netrc)
+        })
+}
+
 async fn perform_download(
     source: &SourceEntry,
     progress: Arc<Mutex<ProgressWrapper>>,
     timeout: Option<u64>,
+    credentials: Option<Credentials<'_>>,
 ) -> Result<()> {
     trace!("Downloading: {:?}", source);
 
@@ -129,8 +174,17 @@ async fn perform_download(
         .build()
         .context("Building HTTP client failed")?;
 
-    let request = client
-        .get(source.url().as_ref())
+    let request = client.get(source.url().as_ref());
+    let request = match credentials {
+        Some(credentials) => {
+            trace!("Using credentials for {}: {:?}", source.url(), credentials);
+            credentials.authorize(request).with_context(|| {
+                anyhow!("Adding credentials for {} failed", source.url().as_ref())
+            })?
+        }
+        None => request,
+    };
+    let request = request
         .build()
         .with_context(|| anyhow!("Building request for {} failed", source.url().as_ref()))?;
 
@@ -192,7 +246,82 @@ async fn perform_download(
         })?;
     }
 
-    file.flush().await.map_err(Error::from).map(|_| ())
+    file.flush().await?;
+    drop(file);
+    source.persist().await
+}
+
+/// Check the URLs of all sources of the packages against the configured source domains
+///
+/// Violations are errors if `strict` is set, warnings otherwise.
+fn check_source_domains(
+    packages: &[&Package],
+    policy: &SourceDomainPolicy,
+    strict: bool,
+) -> Result<()> {
+    let violations = packages
+        .iter()
+        .flat_map(|p| {
+            p.sources().values().filter_map(move |source| {
+                policy
+                    .check(source.url())
+                    .err()
+                    .map(|e| format!("{} {}: {}", p.name(), p.version(), e))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if strict && !violations.is_empty() {
+        return Err(anyhow!(
+            "Refusing to download, sources violate the configured source domains:\n{}",
+            violations.join("\n")
+        ));
+    }
+
+    for violation in violations {
+        warn!("{}", violation);
+    }
+    Ok(())
+}
+
+/// Split the `sources` into the ones that are downloaded and the missing ones that have to be
+/// downloaded manually
+///
+/// Sources that are marked for manual download and that exist already are neither. With
+/// `include_manual`, all sources are downloaded.
+fn split_manual_sources(
+    sources: Vec<SourceEntry>,
+    include_manual: bool,
+) -> (Vec<SourceEntry>, Vec<SourceEntry>) {
+    let (manual, download): (Vec<_>, Vec<_>) = sources
+        .into_iter()
+        .partition(|source| !include_manual && source.download_manually());
+    let missing = manual.into_iter().filter(|s| !s.exists()).collect();
+    (download, missing)
+}
+
+/// Write where the `sources` have to be downloaded from and where they have to be put
+fn write_manual_download_instructions(sources: &[SourceEntry], out: &mut impl Write) -> Result<()> {
+    writeln!(
+        out,
+        "{} source(s) have to be downloaded manually:",
+        sources.len()
+    )?;
+    for source in sources {
+        writeln!(
+            out,
+            "{} {} ({}):\n  download: {}\n  put at:   {}",
+            source.package_name(),
+            source.package_version(),
+            source.package_source_name(),
+            source.url(),
+            source.path().display()
+        )?;
+        if let Some(note) = source.package_source().manual_note() {
+            writeln!(out, "  note:     {note}")?;
+        }
+    }
+    Ok(())
 }
 
 // Implementation of the 'source download' subcommand
@@ -201,109 +330,111 @@ pub async fn download(
     config: &Configuration,
     repo: Repository,
     progressbars: ProgressBars,
+    parallelism: Parallelism,
+    timings: &Timings,
 ) -> Result<()> {
     let force = matches.get_flag("force");
+    let strict = matches.get_flag("strict");
+    let include_manual = matches.get_flag("include_manual");
+    let group_by_package = matches.get_flag("group_by_package");
     let timeout = matches
         .get_one::<String>("timeout")
         .map(|s| s.parse::<u64>())
         .transpose()
         .context("Parsing timeout argument to integer")?;
-    let cache = PathBuf::from(config.source_cache_root());
-    let sc = SourceCache::new(cache);
-    let pname = matches
-        .get_one::<String>("package_name")
-        .map(|s| s.to_owned())
-        .map(PackageName::from);
-    let pvers = matches
-        .get_one::<String>("package_version")
-        .map(|s| s.to_owned())
-        .map(PackageVersionConstraint::try_from)
-        .transpose()?;
-
-    let matching_regexp = matches
-        .get_one::<String>("matching")
-        .map(|s| crate::commands::util::mk_package_name_regex(s.as_ref()))
-        .transpose()?;
+    let sc = SourceCache::from_config(config);
+    let filter = crate::commands::util::package_filter_from_matches(matches)?;
+    let netrc = if *config.use_netrc() {
+        Netrc::load()?
+    } else {
+        None
+    };
 
     let progressbar = Arc::new(Mutex::new(ProgressWrapper::new(progressbars.bar()?)));
 
     let download_sema = Arc::new(tokio::sync::Semaphore::new(
-        NUMBER_OF_MAX_CONCURRENT_DOWNLOADS,
+        parallelism
+            .explicit(None)
+            .unwrap_or(NUMBER_OF_MAX_CONCURRENT_DOWNLOADS),
     ));
 
-    let mut r = repo.packages()
-        .filter(|p| {
-            match (pname.as_ref(), pvers.as_ref(), matching_regexp.as_ref()) {
-                (None, None, None)              => true,
-                (Some(pname), None, None)       => p.name() == pname,
-                (Some(pname), Some(vers), None) => p.name() == pname && vers.matches(p.version()),
-                (None, None, Some(regex))       => regex.is_match(p.name()),
-
-                (_, _, _) => {
-                    panic!("This should not be possible, either we select packages by name and (optionally) version, or by regex.")
-                },
-            }
-        }).peekable();
-
-    // check if the iterator is empty
-    if r.peek().is_none() {
-        let pname = matches.get_one::<String>("package_name");
-        let pvers = matches.get_one::<String>("package_version");
-        let matching_regexp = matches.get_one::<String>("matching");
+    let packages = repo.packages_matching(&filter).collect::<Vec<_>>();
+    if packages.is_empty() && !filter.is_empty() {
+        return Err(match filter.name() {
+            Some(name) => repo.package_not_found(name, &filter.to_string()),
+            None => ButidoError::PackageNotFound(filter.to_string()),
+        }
+        .into());
+    }
+    let packages = super::with_dependencies_if_recursive(matches, config, &repo, packages)?;
+    check_source_domains(&packages, &SourceDomainPolicy::from_config(config), strict)?;
+
+    let netrc = netrc.as_ref();
+    let download_source = |source: SourceEntry| {
+        let download_sema = download_sema.clone();
+        let progressbar = progressbar.clone();
+        async move {
+            let source_path_exists = source.path().exists();
+            if source_path_exists && !force {
+                Err(anyhow!("Source exists: {}", source.path().display()))
+            } else {
+                if source_path_exists
+                /* && force is implied by 'if' above*/
+                {
+                    source.remove_file().await?;
+                }
 
-        match (pname, pvers, matching_regexp) {
-            (Some(pname), None, None) => return Err(anyhow!("{} not found", pname)),
-            (Some(pname), Some(vers), None) => return Err(anyhow!("{} {} not found", pname, vers)),
-            (None, None, Some(regex)) => return Err(anyhow!("{} regex not found", regex)),
+                // A forced download replaces the stored file, which might be the corrupt one
+                if !force && source.link_stored().await? {
+                    info!(
+                        "Source {} is stored already, linked {}",
+                        source.url(),
+                        source.path().display()
+                    );
+                    return Ok(());
+                }
 
-            (_, _, _) => {
-                panic!("This should not be possible, either we select packages by name and (optionally) version, or by regex.")
+                progressbar.lock().await.inc_download_count().await;
+                {
+                    let permit = download_sema.acquire_owned().await?;
+                    let credentials =
+                        credentials_for(config.source_auth().as_ref(), netrc, source.url());
+                    perform_download(&source, progressbar.clone(), timeout, credentials).await?;
+                    drop(permit);
+                }
+                progressbar.lock().await.finish_one_download().await;
+                Ok(())
             }
         }
-    }
+    };
 
-    let r = r
-        .flat_map(|p| {
-            sc.sources_for(p).into_iter().map(|source| {
-                let download_sema = download_sema.clone();
-                let progressbar = progressbar.clone();
-                async move {
-                    let source_path_exists = source.path().exists();
-                    if !source_path_exists && source.download_manually() {
-                        return Err(anyhow!(
-                            "Cannot download source that is marked for manual download"
-                        ))
-                        .context(anyhow!("Creating source: {}", source.path().display()))
-                        .context(anyhow!("Downloading source: {}", source.url()))
-                        .map_err(Error::from);
-                    }
-
-                    if source_path_exists && !force {
-                        Err(anyhow!("Source exists: {}", source.path().display()))
-                    } else {
-                        if source_path_exists
-                        /* && force is implied by 'if' above*/
-                        {
-                            source.remove_file().await?;
-                        }
-
-                        progressbar.lock().await.inc_download_count().await;
-                        {
-                            let permit = download_sema.acquire_owned().await?;
-                            perform_download(&source, progressbar.clone(), timeout).await?;
-                            drop(permit);
-                        }
-                        progressbar.lock().await.finish_one_download().await;
-                        Ok(())
-                    }
-                }
-            })
-        })
-        .collect::<futures::stream::FuturesUnordered<_>>()
-        .collect::<Vec<Result<()>>>()
-        .await
-        .into_iter()
-        .collect::<Result<()>>();
+    let mut results = Vec::new();
+    let mut manual = Vec::new();
+    for group in super::source_groups(packages.into_iter(), &sc, group_by_package) {
+        let (group, missing_manual) = split_manual_sources(group, include_manual);
+        manual.extend(missing_manual);
+
+        if let Some(source) = group.first().filter(|_| group_by_package) {
+            info!(
+                "Downloading sources of {} {}",
+                source.package_name(),
+                source.package_version()
+            );
+        }
+
+        let group_results = group
+            .into_iter()
+            .map(download_source)
+            .collect::<futures::stream::FuturesUnordered<_>>()
+            .collect::<Vec<Result<()>>>()
+            .await;
+        results.extend(group_results);
+    }
+    let r = results.into_iter().collect::<Result<()>>();
+    timings.count(
+        Counter::SourceBytesDownloaded,
+        progressbar.lock().await.current_bytes as u64,
+    );
 
     if r.is_err() {
         progressbar.lock().await.error().await;
@@ -311,6 +442,204 @@ pub async fn download(
         progressbar.lock().await.success().await;
     }
 
+    if !manual.is_empty() {
+        warn!(
+            "Skipped {} source(s) that are marked for manual download, see --include-manual",
+            manual.len()
+        );
+        write_manual_download_instructions(&manual, &mut std::io::stdout().lock())?;
+    }
+
     debug!("r = {:?}", r);
     r
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::package::tests::temp_dir;
+
+    fn authorization_for(
+        source_auth: &HashMap<String, SourceAuth>,
+        netrc: Option<&Netrc>,
+        url: &str,
+    ) -> Option<String> {
+        let url = url::Url::parse(url).unwrap();
+        let request = reqwest::Client::new().get(url.as_ref());
+        let request = match credentials_for(Some(source_auth), netrc, &url) {
+            Some(credentials) => credentials.authorize(request).unwrap(),
+            None => request,
+        };
+        request
+            .build()
+            .unwrap()
+            .headers()
+            .get(reqwest::header::AUTHORIZATION)
+            .map(|value| {
+                assert!(value.is_sensitive());
+                value.to_str().unwrap().to_string()
+            })
+    }
+
+    #[test]
+    fn test_authorization_header_per_host() {
+        std::env::set_var("BUTIDO_TEST_DOWNLOAD_TOKEN", "secret");
+        let source_auth = HashMap::from([
+            (
+                String::from("basic.example.com"),
+                SourceAuth::Basic {
+                    username: String::from("user"),
+                    password: String::from("pass"),
+                },
+            ),
+            (
+                String::from("Bearer.example.com"),
+                SourceAuth::Bearer {
+                    token: String::from("${BUTIDO_TEST_DOWNLOAD_TOKEN}"),
+                },
+            ),
+        ]);
+
+        let auth = |url| authorization_for(&source_auth, None, url);
+        assert_eq!(
+            auth("https://basic.example.com/a.tar.gz").as_deref(),
+            Some("Basic dXNlcjpwYXNz")
+        );
+        assert_eq!(
+            auth("https://bearer.example.com/b.tar.gz").as_deref(),
+            Some("Bearer secret")
+        );
+        assert_eq!(auth("https://sub.basic.example.com/c.tar.gz"), None);
+        assert_eq!(auth("https://example.com/d.tar.gz"), None);
+    }
+
+    #[test]
+    fn test_source_auth_takes_precedence_over_netrc() {
+        let source_auth = HashMap::from([(
+            String::from("basic.example.com"),
+            SourceAuth::Basic {
+                username: String::from("user"),
+                password: String::from("pass"),
+            },
+        )]);
+        let netrc = Netrc::parse(indoc::indoc!(
+            r#"
+            machine basic.example.com login netrc password netrc
+            machine netrc.example.com login user password pass
+        "#
+        ))
+        .unwrap();
+
+        let auth = |url| authorization_for(&source_auth, Some(&netrc), url);
+        // "user:pass", from source_auth
+        assert_eq!(
+            auth("https://basic.example.com/a.tar.gz").as_deref(),
+            Some("Basic dXNlcjpwYXNz")
+        );
+        // "user:pass", from the netrc file
+        assert_eq!(
+            auth("https://netrc.example.com/b.tar.gz").as_deref(),
+            Some("Basic dXNlcjpwYXNz")
+        );
+        assert_eq!(auth("https://example.com/c.tar.gz"), None);
+    }
+
+    #[test]
+    fn test_manual_sources_are_reported() {
+        use crate::package::tests::package;
+        use crate::package::tests::pname;
+        use crate::package::tests::pversion;
+        use crate::package::Dependencies;
+
+        let cache_root = temp_dir();
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        let mut sources = package("a", "1", "https://example.com/a.tar.gz", "1")
+            .sources()
+            .clone();
+        let mut manual = sources["src"].clone();
+        manual.set_download_manually(true);
+        sources.insert(String::from("manual"), manual.clone());
+        sources.insert(String::from("present"), manual);
+        let pkg = Package::new(
+            pname("a"),
+            pversion("1"),
+            false,
+            sources,
+            Dependencies::empty(),
+        );
+
+        let entries = sc.sources_for(&pkg);
+        let present = entries
+            .iter()
+            .find(|s| s.package_source_name() == "present")
+            .unwrap()
+            .path();
+        std::fs::create_dir_all(present.parent().unwrap()).unwrap();
+        std::fs::write(&present, "").unwrap();
+
+        let names = |sources: &[SourceEntry]| {
+            let mut names = sources
+                .iter()
+                .map(|s| s.package_source_name().clone())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let (download, missing) = split_manual_sources(sc.sources_for(&pkg), false);
+        assert_eq!(names(&download), vec!["src"]);
+        assert_eq!(names(&missing), vec!["manual"]);
+
+        let mut out = Vec::new();
+        write_manual_download_instructions(&missing, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("1 source(s) have to be downloaded manually:\n"));
+        assert!(out.contains("a 1 (manual):"));
+        assert!(out.contains("download: https://example.com/a.tar.gz"));
+        assert!(out.contains(&format!("put at:   {}", missing[0].path().display())));
+
+        assert!(!out.contains("note:"));
+
+        let (download, missing) = split_manual_sources(sc.sources_for(&pkg), true);
+        assert_eq!(names(&download), vec!["manual", "present", "src"]);
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn test_manual_note_is_displayed() {
+        use crate::package::tests::pname;
+        use crate::package::tests::pversion;
+        use crate::package::Dependencies;
+
+        let source: crate::package::Source = toml::from_str(
+            r#"
+            url = "https://example.com/sdk.tar.gz"
+            download_manually = true
+            manual_note = "Accept the EULA at https://example.com/eula first"
+            hash = { type = "sha1", hash = "0000" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            source.manual_note().as_deref(),
+            Some("Accept the EULA at https://example.com/eula first")
+        );
+
+        let sources = HashMap::from([(String::from("src"), source)]);
+        let pkg = Package::new(
+            pname("sdk"),
+            pversion("1"),
+            false,
+            sources,
+            Dependencies::empty(),
+        );
+        let sc = SourceCache::new(PathBuf::from("/nonexistent/butido-sources"));
+        let (_, missing) = split_manual_sources(sc.sources_for(&pkg), false);
+
+        let mut out = Vec::new();
+        write_manual_download_instructions(&missing, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with("  note:     Accept the EULA at https://example.com/eula first\n"));
+    }
+}
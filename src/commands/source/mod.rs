@@ -12,6 +12,7 @@
 
 use std::convert::TryFrom;
 use std::io::Write;
+use std::path::Path;
 use std::path::PathBuf;
 
 use anyhow::anyhow;
@@ -20,17 +21,38 @@ use anyhow::Error;
 use anyhow::Result;
 use clap::ArgMatches;
 use colored::Colorize;
+use serde::Serialize;
 use tokio_stream::StreamExt;
-use tracing::{info, trace};
+use tracing::{debug, info, trace, warn};
 
+use crate::commands::output::print_records;
+use crate::commands::output::OutputFormat;
+use crate::commands::output::Record;
 use crate::config::*;
+use crate::error::ButidoError;
+use crate::error::ErrorRendering;
+use crate::package::condition::ConditionData;
+use crate::package::Dag;
+use crate::package::DependencyConflictPolicy;
+use crate::package::HashType;
+use crate::package::HashValue;
 use crate::package::Package;
 use crate::package::PackageName;
+use crate::package::PackageVersion;
 use crate::package::PackageVersionConstraint;
 use crate::repository::Repository;
 use crate::source::*;
+use crate::util::docker::resolve_image_name;
+use crate::util::metrics::Counter;
+use crate::util::parallelism::Parallelism;
 use crate::util::progress::ProgressBars;
+use crate::util::progress::StageProgress;
+use crate::util::summary::SummaryLine;
+use crate::util::timings::Timings;
+use crate::util::EnvironmentVariableName;
 
+mod audit;
+mod cache;
 mod download;
 
 /// Implementation of the "source" subcommand
@@ -39,15 +61,29 @@ pub async fn source(
     config: &Configuration,
     repo: Repository,
     progressbars: ProgressBars,
+    parallelism: Parallelism,
+    timings: &Timings,
 ) -> Result<()> {
     match matches.subcommand() {
-        Some(("verify", matches)) => verify(matches, config, repo, progressbars).await,
+        Some(("verify", matches)) => {
+            verify(matches, config, repo, progressbars, parallelism, timings).await
+        }
         Some(("list-missing", matches)) => list_missing(matches, config, repo).await,
         Some(("url", matches)) => url(matches, repo).await,
+        Some(("audit", matches)) => crate::commands::source::audit::audit(matches, repo).await,
         Some(("download", matches)) => {
-            crate::commands::source::download::download(matches, config, repo, progressbars).await
+            crate::commands::source::download::download(
+                matches,
+                config,
+                repo,
+                progressbars,
+                parallelism,
+                timings,
+            )
+            .await
         }
         Some(("of", matches)) => of(matches, config, repo).await,
+        Some(("cache", matches)) => cache::cache(matches, config, repo).await,
         Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
         None => Err(anyhow!("No subcommand")),
     }
@@ -58,104 +94,516 @@ pub async fn verify(
     config: &Configuration,
     repo: Repository,
     progressbars: ProgressBars,
+    parallelism: Parallelism,
+    timings: &Timings,
 ) -> Result<()> {
-    let sc = SourceCache::new(config.source_cache_root().clone());
-    let pname = matches
-        .get_one::<String>("package_name")
-        .map(|s| s.to_owned())
-        .map(PackageName::from);
-    let pvers = matches
-        .get_one::<String>("package_version")
-        .map(|s| s.to_owned())
-        .map(PackageVersionConstraint::try_from)
-        .transpose()?;
+    let sc = SourceCache::from_config(config);
+    let filter = crate::commands::util::package_filter_from_matches(matches)?;
+    let packages = repo
+        .packages_matching(&filter)
+        .inspect(|p| trace!("Found for verification: {} {}", p.name(), p.version()))
+        .collect::<Vec<_>>();
 
-    let matching_regexp = matches
-        .get_one::<String>("matching")
-        .map(|s| crate::commands::util::mk_package_name_regex(s.as_ref()))
-        .transpose()?;
+    let packages = with_dependencies_if_recursive(matches, config, &repo, packages)?;
+    check_insecure_sources(packages.iter().copied(), *config.forbid_insecure_sources())?;
 
-    let packages = repo
-        .packages()
-        .filter(|p| {
-            match (pname.as_ref(), pvers.as_ref(), matching_regexp.as_ref()) {
-                (None, None, None)              => true,
-                (Some(pname), None, None)       => p.name() == pname,
-                (Some(pname), Some(vers), None) => p.name() == pname && vers.matches(p.version()),
-                (None, None, Some(regex))       => regex.is_match(p.name()),
-
-                (_, _, _) => {
-                    panic!("This should not be possible, either we select packages by name and (optionally) version, or by regex.")
-                },
+    if matches.get_flag("list_orphans") {
+        list_orphans(packages.iter().copied(), &sc)?;
+    }
+
+    let manifest = matches.get_one::<String>("manifest").map(PathBuf::from);
+    let opts = VerifyOptions::builder()
+        .jobs(parallelism.jobs())
+        .show_progress_bytes(matches.get_flag("show_progress_bytes"))
+        .manifest(manifest.as_deref())
+        .remove_corrupt(matches.get_flag("remove_corrupt"))
+        .group_by_package(matches.get_flag("group_by_package"))
+        .use_mmap(*config.hash_use_mmap())
+        .read_concurrency(
+            matches
+                .get_one::<String>("read_concurrency")
+                .map(|s| s.parse::<usize>())
+                .transpose()?,
+        )
+        .hash_concurrency(
+            matches
+                .get_one::<String>("hash_concurrency")
+                .map(|s| s.parse::<usize>())
+                .transpose()?,
+        )
+        .timings(Some(timings))
+        .summary_only(matches.get_flag("summary_only"))
+        .build();
+    let results = verify_sources(packages.into_iter(), &sc, &progressbars, &opts).await?;
+    let reported = report_verification(&results, &opts);
+    VerificationSummary::from(&results[..])
+        .summary_line()
+        .print();
+    reported
+}
+
+/// The `packages` and all their (transitive) dependencies for the `condition_data`
+///
+/// Every package is only returned once, ordered by name and version. See
+/// `Dag::for_root_package()` for `max_depth` and `conflict_policy`.
+fn with_dependencies<'a>(
+    packages: &[&'a Package],
+    repo: &'a Repository,
+    condition_data: &ConditionData<'_>,
+    max_depth: usize,
+    conflict_policy: DependencyConflictPolicy,
+) -> Result<Vec<&'a Package>> {
+    let mut all = std::collections::BTreeMap::new();
+    for package in packages {
+        let dag = Dag::for_root_package(
+            (*package).clone(),
+            repo,
+            None,
+            condition_data,
+            max_depth,
+            conflict_policy,
+        )?;
+        for p in dag.all_packages() {
+            let key = (p.name().clone(), p.version().clone());
+            if let std::collections::btree_map::Entry::Vacant(entry) = all.entry(key) {
+                let in_repo = repo
+                    .find(p.name(), p.version())
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("{} {} not in the repository", p.name(), p.version()))?;
+                entry.insert(in_repo);
             }
+        }
+    }
+    Ok(all.into_values().collect())
+}
+
+/// The `packages` and, with `--recursive`, all their (transitive) dependencies
+///
+/// The dependencies are resolved for the `--image` and `--env` of the `matches`.
+fn with_dependencies_if_recursive<'a>(
+    matches: &ArgMatches,
+    config: &Configuration,
+    repo: &'a Repository,
+    packages: Vec<&'a Package>,
+) -> Result<Vec<&'a Package>> {
+    if !matches.get_flag("recursive") {
+        return Ok(packages);
+    }
+
+    let image_name = matches
+        .get_one::<String>("image")
+        .map(|s| resolve_image_name(s, config.docker().images()))
+        .transpose()?;
+    let additional_env = matches
+        .get_many::<String>("env")
+        .unwrap_or_default()
+        .map(AsRef::as_ref)
+        .map(crate::util::env::parse_to_env)
+        .collect::<Result<Vec<(EnvironmentVariableName, String)>>>()?;
+    let condition_data = ConditionData {
+        image_name: image_name.as_ref(),
+        env: &additional_env,
+    };
+    with_dependencies(
+        &packages,
+        repo,
+        &condition_data,
+        *config.max_dependency_depth(),
+        *config.dependency_conflict_policy(),
+    )
+}
+
+/// Warn about sources that are fetched via plain (insecure) http
+///
+/// If `forbid` is set, such sources are an error instead.
+pub(in crate::commands) fn check_insecure_sources<'a, I>(packages: I, forbid: bool) -> Result<()>
+where
+    I: Iterator<Item = &'a Package>,
+{
+    let insecure = packages
+        .flat_map(|p| {
+            p.sources()
+                .iter()
+                .filter(|(_, source)| source.url().scheme() == "http")
+                .map(move |(name, source)| {
+                    format!(
+                        "{} {} -> {} = {}",
+                        p.name(),
+                        p.version(),
+                        name,
+                        source.url()
+                    )
+                })
         })
-        .inspect(|p| trace!("Found for verification: {} {}", p.name(), p.version()));
+        .collect::<Vec<_>>();
+
+    if insecure.is_empty() {
+        return Ok(());
+    }
 
-    verify_impl(packages, &sc, &progressbars).await
+    if forbid {
+        return Err(anyhow!(
+            "Sources use insecure plain http, which is forbidden by 'forbid_insecure_sources':\n{}",
+            insecure.join("\n")
+        ));
+    }
+
+    for source in insecure {
+        warn!("Source uses insecure plain http: {}", source);
+    }
+    Ok(())
+}
+
+/// One entry of the manifest that `source verify --manifest` writes
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    package_name: PackageName,
+    package_version: PackageVersion,
+    source_name: String,
+    path: PathBuf,
+    hash_type: HashType,
+    expected_hash: HashValue,
+    computed_hash: Option<HashValue>,
+    passed: bool,
+    error: Option<String>,
+}
+
+impl ManifestEntry {
+    fn new(source: &SourceEntry, computed_hash: Option<HashValue>, result: &Result<()>) -> Self {
+        ManifestEntry {
+            package_name: source.package_name().clone(),
+            package_version: source.package_version().clone(),
+            source_name: source.package_source_name().clone(),
+            path: source.path(),
+            hash_type: source.package_source().hash().hashtype().clone(),
+            expected_hash: source.package_source().hash().value().clone(),
+            computed_hash,
+            passed: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        }
+    }
 }
 
+fn write_manifest(path: &Path, entries: &[ManifestEntry]) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| anyhow!("Creating manifest file: {}", path.display()))?;
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), entries)
+        .with_context(|| anyhow!("Writing manifest file: {}", path.display()))
+}
+
+/// Options for the verification of sources via `verify_impl()`
+#[derive(typed_builder::TypedBuilder)]
+pub(in crate::commands) struct VerifyOptions<'a> {
+    /// The number of sources that are verified concurrently
+    jobs: usize,
+
+    /// The number of source files that are read concurrently, unlimited if not set
+    #[builder(default)]
+    read_concurrency: Option<usize>,
+
+    /// The number of blocks that are hashed concurrently, unlimited if not set
+    #[builder(default)]
+    hash_concurrency: Option<usize>,
+
+    /// Whether the progress is shown in bytes instead of the number of sources
+    #[builder(default)]
+    show_progress_bytes: bool,
+
+    /// Write a JSON manifest of the verification results to this path
+    #[builder(default)]
+    manifest: Option<&'a Path>,
+
+    /// Whether source files with a hash mismatch are removed from the cache
+    #[builder(default)]
+    remove_corrupt: bool,
+
+    /// Whether the packages are verified one after another, see `source_groups()`
+    #[builder(default)]
+    group_by_package: bool,
+
+    /// Whether large source files are memory-mapped for hashing
+    #[builder(default)]
+    use_mmap: bool,
+
+    /// Counts the sources that failed the verification
+    #[builder(default)]
+    timings: Option<&'a Timings>,
+
+    /// Whether only the counts of the results are printed instead of the error of every source,
+    /// see `VerificationSummary`
+    #[builder(default)]
+    summary_only: bool,
+
+    /// Reports the fraction of the verified sources
+    #[builder(default)]
+    stage: Option<&'a StageProgress>,
+}
+
+/// Get the sources of the packages in the groups they are processed in
+///
+/// If `by_package` is set, every package gets its own group, in the order of the packages.
+/// Otherwise all sources are in a single group.
+fn source_groups<'a, I>(packages: I, sc: &SourceCache, by_package: bool) -> Vec<Vec<SourceEntry>>
+where
+    I: Iterator<Item = &'a Package>,
+{
+    let groups = packages.map(|p| sc.sources_for(p));
+    if by_package {
+        groups.filter(|group| !group.is_empty()).collect()
+    } else {
+        vec![groups.flatten().collect()]
+    }
+}
+
+/// The outcome of the verification of a single source
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(in crate::commands) enum VerificationStatus {
+    Verified,
+    Missing,
+
+    /// The source file has the wrong hash
+    Corrupt,
+
+    /// The source file could not be hashed, e.g. because it could not be read
+    Failed,
+}
+
+/// The result of the verification of a single source, see `verify_sources()`
+pub(in crate::commands) struct VerificationResult {
+    source: SourceEntry,
+    status: VerificationStatus,
+
+    /// The hash of the source file, if it could be hashed
+    computed_hash: Option<HashValue>,
+
+    /// Whether the source file was removed because its hash did not match, see
+    /// `VerifyOptions::remove_corrupt`
+    removed: bool,
+
+    result: Result<()>,
+}
+
+/// Verify the sources of the packages and report the results, see `verify_sources()` and
+/// `report_verification()`
 pub(in crate::commands) async fn verify_impl<'a, I>(
     packages: I,
     sc: &SourceCache,
     progressbars: &ProgressBars,
+    opts: &VerifyOptions<'_>,
 ) -> Result<()>
 where
     I: Iterator<Item = &'a Package> + 'a,
 {
-    let sources = packages
-        .flat_map(|p| sc.sources_for(p).into_iter())
-        .collect::<Vec<_>>();
+    let results = verify_sources(packages, sc, progressbars, opts).await?;
+    report_verification(&results, opts)
+}
+
+/// Verify the sources of the packages
+///
+/// Returns the result of every source, an error is only returned if the verification could not
+/// be run at all.
+pub(in crate::commands) async fn verify_sources<'a, I>(
+    packages: I,
+    sc: &SourceCache,
+    progressbars: &ProgressBars,
+    opts: &VerifyOptions<'_>,
+) -> Result<Vec<VerificationResult>>
+where
+    I: Iterator<Item = &'a Package> + 'a,
+{
+    let show_progress_bytes = opts.show_progress_bytes;
+    let limits = HashLimits::new(opts.read_concurrency, opts.hash_concurrency);
+    let groups = source_groups(packages, sc, opts.group_by_package);
+    let sources = groups.iter().flatten().collect::<Vec<_>>();
 
-    let bar = progressbars.bar()?;
+    let multibar = progressbars.multi_progress();
+
+    let bar = multibar.add(progressbars.bar()?);
     bar.set_message("Verifying sources");
-    bar.set_length(sources.len() as u64);
+    if show_progress_bytes {
+        let mut sum_bytes = 0;
+        for source in sources.iter().filter(|source| source.path().exists()) {
+            sum_bytes += source.size().await?;
+        }
+        bar.set_length(sum_bytes);
+    } else {
+        bar.set_length(sources.len() as u64);
+    }
 
-    let results = sources
-        .into_iter()
-        .map(|src| (bar.clone(), src))
-        .map(|(bar, source)| async move {
+    // Hash a single source, with a spinner that shows the progress in bytes if requested
+    async fn hash_source(
+        source: &SourceEntry,
+        bar: &indicatif::ProgressBar,
+        multibar: &indicatif::MultiProgress,
+        progressbars: &ProgressBars,
+        show_progress_bytes: bool,
+        use_mmap: bool,
+        limits: &HashLimits,
+    ) -> Result<HashVerification> {
+        if show_progress_bytes {
+            let spinner = multibar.add(progressbars.spinner()?);
+            spinner.set_message(format!(
+                "Hashing {} ({})",
+                source.path().display(),
+                bytesize::ByteSize::b(source.size().await?)
+            ));
+            let verification = source
+                .verify_hash_detailed(vec![bar.clone(), spinner.clone()], use_mmap, limits)
+                .await;
+            spinner.finish_and_clear();
+            verification
+        } else {
+            source.verify_hash_detailed(vec![], use_mmap, limits).await
+        }
+    }
+
+    let verify_source = |source: SourceEntry| {
+        let bar = bar.clone();
+        let multibar = &multibar;
+        let limits = &limits;
+        async move {
             trace!("Verifying: {}", source.path().display());
-            if source.path().exists() {
+            let mut status = VerificationStatus::Failed;
+            let (computed_hash, result) = if source.path().exists() {
                 trace!("Exists: {}", source.path().display());
-                source.verify_hash().await.with_context(|| {
+                let (computed_hash, result) = match hash_source(
+                    &source,
+                    &bar,
+                    multibar,
+                    progressbars,
+                    show_progress_bytes,
+                    opts.use_mmap,
+                    limits,
+                )
+                .await
+                {
+                    Ok(verification) => {
+                        status = if verification.matches() {
+                            VerificationStatus::Verified
+                        } else {
+                            VerificationStatus::Corrupt
+                        };
+                        (
+                            Some(verification.computed().clone()),
+                            verification.as_result(),
+                        )
+                    }
+                    Err(e) => (None, Err(e)),
+                };
+                let result = result.with_context(|| {
                     anyhow!("Hash verification failed for: {}", source.path().display())
-                })?;
+                });
 
-                trace!("Success verifying: {}", source.path().display());
-                bar.inc(1);
-                Ok(())
+                if result.is_ok() {
+                    trace!("Success verifying: {}", source.path().display());
+                }
+                (computed_hash, result)
             } else {
                 trace!("Failed verifying: {}", source.path().display());
+                status = VerificationStatus::Missing;
+                (None, Err(ButidoError::SourceMissing(source.path()).into()))
+            };
+
+            // Only remove files that exist but have the wrong hash, never because of other
+            // errors
+            let removed = if status == VerificationStatus::Corrupt && opts.remove_corrupt {
+                match source.remove_file().await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!(
+                            "Failed to remove corrupt source {}: {:#}",
+                            source.path().display(),
+                            e
+                        );
+                        false
+                    }
+                }
+            } else {
+                false
+            };
+
+            if !show_progress_bytes {
                 bar.inc(1);
-                Err(anyhow!("Source missing: {}", source.path().display()))
             }
-        })
-        .collect::<futures::stream::FuturesUnordered<_>>()
-        .collect::<Vec<Result<_>>>()
+            if let Some(stage) = opts.stage {
+                stage.follow(&bar);
+            }
+            VerificationResult {
+                source,
+                status,
+                computed_hash,
+                removed,
+                result,
+            }
+        }
+    };
+
+    let mut results = Vec::new();
+    for group in groups {
+        if let Some(source) = group.first().filter(|_| opts.group_by_package) {
+            info!(
+                "Verifying sources of {} {}",
+                source.package_name(),
+                source.package_version()
+            );
+        }
+
+        let group_results = {
+            use futures::stream::StreamExt;
+            futures::stream::iter(group.into_iter().map(verify_source)).buffer_unordered(opts.jobs)
+        }
+        .collect::<Vec<_>>()
         .await;
+        results.extend(group_results);
+    }
 
     info!("Verification processes finished");
 
-    if results.iter().any(Result::is_err) {
+    if results.iter().any(|r| r.result.is_err()) {
         bar.finish_with_message("Source verification failed");
     } else {
         bar.finish_with_message("Source verification successful");
     }
+    Ok(results)
+}
+
+/// Report the results of `verify_sources()`
+///
+/// Writes the manifest and prints the removed sources and the errors. Fails if any source failed
+/// the verification.
+pub(in crate::commands) fn report_verification(
+    results: &[VerificationResult],
+    opts: &VerifyOptions<'_>,
+) -> Result<()> {
+    if let Some(manifest) = opts.manifest {
+        let entries = results
+            .iter()
+            .map(|r| ManifestEntry::new(&r.source, r.computed_hash.clone(), &r.result))
+            .collect::<Vec<_>>();
+        write_manifest(manifest, &entries)?;
+        info!("Manifest written to {}", manifest.display());
+    }
+
+    let failures = results.iter().filter(|r| r.result.is_err()).count();
+    if let Some(timings) = opts.timings {
+        timings.count(Counter::SourceVerificationFailures, failures as u64);
+    }
 
     let out = std::io::stdout();
-    let mut any_error = false;
-    for result in results {
-        if let Err(e) = result {
-            let mut outlock = out.lock();
-            any_error = true;
-            for cause in e.chain() {
-                let _ = writeln!(outlock, "Error: {}", cause.to_string().red());
-            }
-            let _ = writeln!(outlock);
+    if opts.summary_only {
+        for r in results.iter().filter(|r| r.removed) {
+            debug!("Removed corrupt source: {}", r.source.path().display());
         }
+        for e in results.iter().filter_map(|r| r.result.as_ref().err()) {
+            debug!("{:#}", e);
+        }
+        let _ = write!(out.lock(), "{}", VerificationSummary::from(results));
+    } else {
+        print_verification_details(results, ErrorRendering::global(), &mut out.lock());
     }
 
-    if any_error {
+    if failures > 0 {
         Err(anyhow!(
             "At least one package failed with source verification"
         ))
@@ -164,8 +612,117 @@ where
     }
 }
 
+/// Print the removed sources and the error of every source that failed the verification
+fn print_verification_details(
+    results: &[VerificationResult],
+    rendering: ErrorRendering,
+    out: &mut impl Write,
+) {
+    for r in results.iter().filter(|r| r.removed) {
+        let _ = writeln!(
+            out,
+            "{}",
+            format!("Removed corrupt source: {}", r.source.path().display()).yellow()
+        );
+    }
+
+    let errors = results
+        .iter()
+        .filter_map(|r| r.result.as_ref().err())
+        .collect::<Vec<_>>();
+    for e in errors.iter() {
+        for cause in rendering.causes(e) {
+            let _ = writeln!(out, "Error: {}", cause.red());
+        }
+        let _ = writeln!(out);
+    }
+    if let Some(hint) = rendering.hint(errors) {
+        let _ = writeln!(out, "{hint}");
+    }
+}
+
+/// The counts of the verification results, printed with `source verify --summary-only`
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(in crate::commands) struct VerificationSummary {
+    total: usize,
+    verified: usize,
+    missing: usize,
+    corrupt: usize,
+    failed: usize,
+}
+
+impl VerificationSummary {
+    fn success(&self) -> bool {
+        self.verified == self.total
+    }
+
+    fn summary_line(&self) -> SummaryLine {
+        SummaryLine::new("verify")
+            .count("total", self.total)
+            .count("ok", self.verified)
+            .count("corrupt", self.corrupt)
+            .count("missing", self.missing)
+            .count("failed", self.failed)
+    }
+}
+
+impl From<&[VerificationResult]> for VerificationSummary {
+    fn from(results: &[VerificationResult]) -> Self {
+        let mut summary = VerificationSummary {
+            total: results.len(),
+            ..Default::default()
+        };
+        for r in results {
+            match r.status {
+                VerificationStatus::Verified => summary.verified += 1,
+                VerificationStatus::Missing => summary.missing += 1,
+                VerificationStatus::Corrupt => summary.corrupt += 1,
+                VerificationStatus::Failed => summary.failed += 1,
+            }
+        }
+        summary
+    }
+}
+
+impl std::fmt::Display for VerificationSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "total: {}", self.total)?;
+        writeln!(f, "verified: {}", self.verified)?;
+        writeln!(f, "missing: {}", self.missing)?;
+        writeln!(f, "corrupt: {}", self.corrupt)?;
+        writeln!(f, "failed: {}", self.failed)?;
+        let status = if self.success() { "success" } else { "failure" };
+        writeln!(f, "status: {status}")
+    }
+}
+
+/// Print the orphaned source files of the packages and of the content-addressed store
+fn list_orphans<'a, I>(packages: I, sc: &SourceCache) -> Result<()>
+where
+    I: Iterator<Item = &'a Package>,
+{
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+
+    for p in packages {
+        for orphan in sc.orphaned_files(p)? {
+            writeln!(
+                outlock,
+                "{} {} -> orphaned: {}",
+                p.name(),
+                p.version(),
+                orphan.display()
+            )?;
+        }
+    }
+    for orphan in sc.orphaned_stored_files()? {
+        writeln!(outlock, "cas -> orphaned: {}", orphan.display())?;
+    }
+    Ok(())
+}
+
 pub async fn list_missing(_: &ArgMatches, config: &Configuration, repo: Repository) -> Result<()> {
-    let sc = SourceCache::new(config.source_cache_root().clone());
+    let sc = SourceCache::from_config(config);
     let out = std::io::stdout();
     let mut outlock = out.lock();
 
@@ -192,74 +749,634 @@ pub async fn url(matches: &ArgMatches, repo: Repository) -> Result<()> {
 
     let pname = matches
         .get_one::<String>("package_name")
-        .map(|s| s.to_owned())
-        .map(PackageName::from);
+        .map(PackageName::try_new)
+        .transpose()?;
     let pvers = matches
         .get_one::<String>("package_version")
         .map(|s| s.to_owned())
         .map(PackageVersionConstraint::try_from)
         .transpose()?;
 
-    repo.packages()
-        .filter(|p| pname.as_ref().map(|n| p.name() == n).unwrap_or(true))
+    let mut packages = repo
+        .packages()
+        .filter(|p| {
+            pname
+                .as_ref()
+                .map(|n| repo.names_match(p.name(), n))
+                .unwrap_or(true)
+        })
         .filter(|p| {
             pvers
                 .as_ref()
                 .map(|v| v.matches(p.version()))
                 .unwrap_or(true)
+        });
+
+    // The human readable output is kept in its own format, so that scripts parsing it don't break
+    let format = OutputFormat::from_matches(matches)?;
+    if format != OutputFormat::Human {
+        return print_records(&url_records(packages), format);
+    }
+
+    packages.try_for_each(|p| {
+        p.sources().iter().try_for_each(|(source_name, source)| {
+            write!(
+                outlock,
+                "{} {} -> {} = {}",
+                p.name(),
+                p.version(),
+                source_name,
+                source.url()
+            )?;
+            match source.manual_note() {
+                Some(note) => writeln!(outlock, " (download manually: {note})"),
+                None => writeln!(outlock),
+            }
+            .map_err(Error::from)
         })
-        .try_for_each(|p| {
-            p.sources().iter().try_for_each(|(source_name, source)| {
-                writeln!(
-                    outlock,
-                    "{} {} -> {} = {}",
-                    p.name(),
-                    p.version(),
+    })
+}
+
+/// A record of the "source url" output
+#[derive(Serialize)]
+struct SourceUrlRecord<'a> {
+    name: &'a PackageName,
+    version: &'a PackageVersion,
+    source_name: &'a str,
+    url: &'a url::Url,
+    manual_note: Option<&'a str>,
+}
+
+impl Record for SourceUrlRecord<'_> {
+    fn headers() -> Vec<&'static str> {
+        vec!["Name", "Version", "Source", "URL", "Manual note"]
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.name.to_string(),
+            self.version.to_string(),
+            self.source_name.to_string(),
+            self.url.to_string(),
+            self.manual_note.unwrap_or_default().to_string(),
+        ]
+    }
+}
+
+fn url_records<'a>(packages: impl Iterator<Item = &'a Package>) -> Vec<SourceUrlRecord<'a>> {
+    packages
+        .flat_map(|p| {
+            p.sources()
+                .iter()
+                .map(move |(source_name, source)| SourceUrlRecord {
+                    name: p.name(),
+                    version: p.version(),
                     source_name,
-                    source.url()
-                )
-                .map_err(Error::from)
-            })
+                    url: source.url(),
+                    manual_note: source.manual_note().as_deref(),
+                })
         })
+        .collect()
 }
 
 async fn of(matches: &ArgMatches, config: &Configuration, repo: Repository) -> Result<()> {
-    let cache = PathBuf::from(config.source_cache_root());
-    let sc = SourceCache::new(cache);
-    let pname = matches
-        .get_one::<String>("package_name")
-        .map(|s| s.to_owned())
-        .map(PackageName::from);
-    let pvers = matches
-        .get_one::<String>("package_version")
-        .map(|s| s.to_owned())
-        .map(PackageVersionConstraint::try_from)
-        .transpose()?;
+    let sc = SourceCache::from_config(config);
+    let filter = crate::commands::util::package_filter_from_matches(matches)?;
+    let presence = if matches.get_flag("missing_only") {
+        Some(false)
+    } else if matches.get_flag("present_only") {
+        Some(true)
+    } else {
+        None
+    };
+    let sources = sources_of(repo.packages_matching(&filter), &sc, presence);
 
-    repo.packages()
-        .filter(|p| pname.as_ref().map(|n| p.name() == n).unwrap_or(true))
-        .filter(|p| {
-            pvers
-                .as_ref()
-                .map(|v| v.matches(p.version()))
-                .unwrap_or(true)
+    if matches.get_flag("csv") {
+        let hdr = crate::commands::util::mk_header(
+            ["Name", "Version", "Source", "Path", "Exists"].to_vec(),
+        );
+        return crate::commands::util::display_data(hdr, of_records(&sources), true);
+    }
+
+    sources
+        .iter()
+        .try_fold(std::io::stdout(), |mut out, (package, sources)| {
+            writeln!(out, "{} {}", package.name(), package.version())?;
+            for source in sources {
+                writeln!(out, "\t{}", source.path().display())?;
+            }
+
+            Ok(out)
         })
+        .map(|_| ())
+}
+
+/// The sources of the packages, for `source of`
+///
+/// If `presence` is set, only the sources whose file exists (`Some(true)`) or is missing
+/// (`Some(false)`) are returned, and packages without such sources are left out.
+fn sources_of<'a>(
+    packages: impl Iterator<Item = &'a Package>,
+    sc: &SourceCache,
+    presence: Option<bool>,
+) -> Vec<(&'a Package, Vec<SourceEntry>)> {
+    packages
         .map(|p| {
-            let pathes = sc
+            let sources = sc
                 .sources_for(p)
                 .into_iter()
-                .map(|source| source.path())
-                .collect::<Vec<PathBuf>>();
+                .filter(|source| presence.map(|e| source.exists() == e).unwrap_or(true))
+                .collect::<Vec<_>>();
+            (p, sources)
+        })
+        .filter(|(_, sources)| presence.is_none() || !sources.is_empty())
+        .collect()
+}
 
-            (p, pathes)
+/// The records for `source of --csv`: name, version, source name, path, whether the path exists
+fn of_records(sources: &[(&Package, Vec<SourceEntry>)]) -> Vec<Vec<String>> {
+    sources
+        .iter()
+        .flat_map(|(_, sources)| sources)
+        .map(|source| {
+            vec![
+                source.package_name().to_string(),
+                source.package_version().to_string(),
+                source.package_source_name().clone(),
+                source.path().display().to_string(),
+                source.exists().to_string(),
+            ]
         })
-        .try_fold(std::io::stdout(), |mut out, (package, pathes)| {
-            writeln!(out, "{} {}", package.name(), package.version())?;
-            for path in pathes {
-                writeln!(out, "\t{}", path.display())?;
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::commands::output::render_records;
+    use crate::package::tests::package;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+    use crate::package::tests::temp_dir;
+    use crate::package::Dependencies;
+    use crate::package::Dependency;
+    use crate::util::progress::ProgressMode;
+
+    /// A repository where "a" depends on "b", which depends on "c", "d" is not a dependency
+    fn abc_repo() -> Repository {
+        let mut btree = BTreeMap::new();
+        for (name, dependency) in [
+            ("a", Some("b =1")),
+            ("b", Some("c =1")),
+            ("c", None),
+            ("d", None),
+        ] {
+            let mut p = package(
+                name,
+                "1",
+                &format!("https://example.com/{name}.tar.gz"),
+                "0",
+            );
+            if let Some(dependency) = dependency {
+                p.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+                    String::from(dependency),
+                )));
             }
+            btree.insert((pname(name), pversion("1")), p);
+        }
+        Repository::from(btree)
+    }
 
-            Ok(out)
-        })
-        .map(|_| ())
+    #[test]
+    fn test_url_csv_quotes_commas() {
+        let packages = [package("a", "1", "https://example.com/a,b.tar.gz", "h")];
+        let csv = render_records(&url_records(packages.iter()), OutputFormat::Csv).unwrap();
+        assert_eq!(csv, "a,1,src,\"https://example.com/a,b.tar.gz\",\n");
+    }
+
+    #[test]
+    fn test_of_csv_quotes_commas() {
+        let packages = [package("a", "1", "https://example.com/a.tar.gz", "h")];
+        let sc = SourceCache::new(PathBuf::from("/tmp/sources,cache"));
+        let sources = sources_of(packages.iter(), &sc, None);
+        let csv = crate::commands::util::to_csv(of_records(&sources)).unwrap();
+        let path = sc.sources_for(&packages[0])[0].path();
+        assert_eq!(csv, format!("a,1,src,\"{}\",false\n", path.display()));
+    }
+
+    #[test]
+    fn test_of_filters_by_presence() {
+        let cache_root = temp_dir();
+        let packages = [
+            package("a", "1", "https://example.com/a.tar.gz", "h"),
+            package("b", "1", "https://example.com/b.tar.gz", "h"),
+            package("c", "1", "https://example.com/c.tar.gz", "h"),
+        ];
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        for p in [&packages[0], &packages[2]] {
+            let path = sc.sources_for(p)[0].path();
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "source").unwrap();
+        }
+
+        let names = |presence| {
+            sources_of(packages.iter(), &sc, presence)
+                .into_iter()
+                .map(|(p, sources)| (p.name().to_string(), sources.len()))
+                .collect::<Vec<_>>()
+        };
+        let all = names(None);
+        let present = names(Some(true));
+        let missing = names(Some(false));
+
+        let n = |name: &str, count| (String::from(name), count);
+        assert_eq!(all, vec![n("a", 1), n("b", 1), n("c", 1)]);
+        assert_eq!(present, vec![n("a", 1), n("c", 1)]);
+        assert_eq!(missing, vec![n("b", 1)]);
+    }
+
+    #[test]
+    fn test_insecure_sources_warn_by_default() {
+        let packages = [
+            package("a", "1", "https://example.com/a.tar.gz", "h"),
+            package("b", "1", "http://example.com/b.tar.gz", "h"),
+        ];
+
+        assert!(check_insecure_sources(packages.iter(), false).is_ok());
+    }
+
+    #[test]
+    fn test_insecure_sources_forbidden() {
+        let packages = [
+            package("a", "1", "https://example.com/a.tar.gz", "h"),
+            package("b", "1", "http://example.com/b.tar.gz", "h"),
+        ];
+
+        assert!(check_insecure_sources(packages[..1].iter(), true).is_ok());
+
+        let err = check_insecure_sources(packages.iter(), true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Sources use insecure plain http, which is forbidden by 'forbid_insecure_sources':\n\
+             b 1 -> src = http://example.com/b.tar.gz"
+        );
+    }
+
+    #[test]
+    fn test_source_groups_by_package() {
+        let a = package("a", "1", "https://example.com/a.tar.gz", "abc");
+        let b = {
+            let mut sources = a.sources().clone();
+            let second = sources.get("src").unwrap().clone();
+            sources.insert(String::from("second"), second);
+            Package::new(
+                PackageName::from(String::from("b")),
+                PackageVersion::from(String::from("2")),
+                false,
+                sources,
+                crate::package::Dependencies::empty(),
+            )
+        };
+        let c = package("c", "3", "https://example.com/c.tar.gz", "abc");
+        let sc = SourceCache::new(PathBuf::from("/tmp/butido-sources"));
+
+        let groups = source_groups([&b, &a, &c].into_iter(), &sc, true);
+        let owners = groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|source| source.package_name().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(owners, vec![vec!["b", "b"], vec!["a"], vec!["c"]]);
+
+        let groups = source_groups([&b, &a, &c].into_iter(), &sc, false);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_remove_corrupt_sources() {
+        let cache_root = temp_dir();
+
+        let content = b"butido";
+        let hash = {
+            use sha1::Digest;
+            format!("{:x}", sha1::Sha1::digest(content))
+        };
+        let good = package("a", "1", "https://example.com/a.tar.gz", &hash);
+        let corrupt = package("b", "1", "https://example.com/b.tar.gz", &hash);
+        let missing = package("c", "1", "https://example.com/c.tar.gz", &hash);
+
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        let good_path = sc.sources_for(&good)[0].path();
+        let corrupt_path = sc.sources_for(&corrupt)[0].path();
+        let missing_path = sc.sources_for(&missing)[0].path();
+        for (path, content) in [(&good_path, &content[..]), (&corrupt_path, b"corrupt")] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let progressbars = ProgressBars::setup(String::new(), String::new(), ProgressMode::Hidden);
+        let opts = VerifyOptions::builder()
+            .jobs(2)
+            .remove_corrupt(true)
+            .build();
+        let result = verify_impl(
+            [&good, &corrupt, &missing].into_iter(),
+            &sc,
+            &progressbars,
+            &opts,
+        )
+        .await;
+
+        let (good_exists, corrupt_exists, missing_exists) = (
+            good_path.exists(),
+            corrupt_path.exists(),
+            missing_path.exists(),
+        );
+
+        // The run is still reported as failed
+        assert!(result.is_err());
+        assert!(good_exists);
+        assert!(!corrupt_exists);
+        assert!(!missing_exists);
+    }
+
+    #[tokio::test]
+    async fn test_manifest_for_mixed_verification_run() {
+        let cache_root = temp_dir();
+
+        let content = b"butido";
+        let hash = {
+            use sha1::Digest;
+            format!("{:x}", sha1::Sha1::digest(content))
+        };
+        let good = package("a", "1", "https://example.com/a.tar.gz", &hash);
+        let bad = package("b", "1", "https://example.com/b.tar.gz", "0000");
+        let missing = package("c", "1", "https://example.com/c.tar.gz", "0000");
+
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        for source in sc
+            .sources_for(&good)
+            .into_iter()
+            .chain(sc.sources_for(&bad))
+        {
+            std::fs::create_dir_all(source.path().parent().unwrap()).unwrap();
+            std::fs::write(source.path(), content).unwrap();
+        }
+
+        let manifest = cache_root.join("manifest.json");
+        let progressbars = ProgressBars::setup(String::new(), String::new(), ProgressMode::Hidden);
+        let opts = VerifyOptions::builder()
+            .jobs(2)
+            .manifest(Some(&manifest))
+            .build();
+        let result = verify_impl(
+            [&good, &bad, &missing].into_iter(),
+            &sc,
+            &progressbars,
+            &opts,
+        )
+        .await;
+        assert!(result.is_err());
+
+        let mut entries: Vec<serde_json::Value> =
+            serde_json::from_reader(std::fs::File::open(&manifest).unwrap()).unwrap();
+        entries.sort_by_key(|e| e["package_name"].as_str().unwrap().to_string());
+
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0]["package_name"], "a");
+        assert_eq!(entries[0]["package_version"], "1");
+        assert_eq!(entries[0]["source_name"], "src");
+        assert_eq!(entries[0]["hash_type"], "sha1");
+        assert_eq!(entries[0]["expected_hash"], hash.as_str());
+        assert_eq!(entries[0]["computed_hash"], hash.as_str());
+        assert_eq!(entries[0]["passed"], true);
+        assert!(entries[0]["error"].is_null());
+
+        assert_eq!(entries[1]["package_name"], "b");
+        assert_eq!(entries[1]["expected_hash"], "0000");
+        assert_eq!(entries[1]["computed_hash"], hash.as_str());
+        assert_eq!(entries[1]["passed"], false);
+        assert!(entries[1]["error"]
+            .as_str()
+            .unwrap()
+            .contains("Hash mismatch"));
+
+        assert_eq!(entries[2]["package_name"], "c");
+        assert!(entries[2]["computed_hash"].is_null());
+        assert_eq!(entries[2]["passed"], false);
+        assert!(entries[2]["error"]
+            .as_str()
+            .unwrap()
+            .contains("Source missing"));
+    }
+
+    #[test]
+    fn test_with_dependencies_covers_the_dag() {
+        let repo = abc_repo();
+        let a = repo.find(&pname("a"), &pversion("1"))[0];
+        let b = repo.find(&pname("b"), &pversion("1"))[0];
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+        let sc = SourceCache::new(PathBuf::from("/cache"));
+        let sources = |packages: Vec<&Package>| {
+            source_groups(packages.into_iter(), &sc, false)
+                .into_iter()
+                .flatten()
+                .map(|source| source.path())
+                .collect::<BTreeSet<_>>()
+        };
+
+        let packages = with_dependencies(
+            &[a, b],
+            &repo,
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap();
+        let dag = Dag::for_root_package(
+            a.clone(),
+            &repo,
+            None,
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap();
+
+        assert_eq!(
+            packages
+                .iter()
+                .map(|p| p.name().to_string())
+                .collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(sources(packages), sources(dag.all_packages()));
+    }
+
+    #[tokio::test]
+    async fn test_recursive_verification_checks_the_dag() {
+        let cache_root = temp_dir();
+        let repo = abc_repo();
+        let a = repo.find(&pname("a"), &pversion("1"))[0];
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+        let packages = with_dependencies(
+            &[a],
+            &repo,
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap();
+
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        let manifest = cache_root.join("manifest.json");
+        let progressbars = ProgressBars::setup(String::new(), String::new(), ProgressMode::Hidden);
+        let opts = VerifyOptions::builder()
+            .jobs(2)
+            .manifest(Some(&manifest))
+            .build();
+        // All sources are missing, the manifest still lists every verified source
+        let result = verify_impl(packages.into_iter(), &sc, &progressbars, &opts).await;
+        let entries: Vec<serde_json::Value> =
+            serde_json::from_reader(std::fs::File::open(&manifest).unwrap()).unwrap();
+
+        assert!(result.is_err());
+        let verified = entries
+            .iter()
+            .map(|e| e["package_name"].as_str().unwrap().to_string())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            verified,
+            BTreeSet::from([String::from("a"), String::from("b"), String::from("c")])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_sources_results() {
+        let cache_root = temp_dir();
+
+        let content = b"butido";
+        let hash = {
+            use sha1::Digest;
+            format!("{:x}", sha1::Sha1::digest(content))
+        };
+        let good = package("a", "1", "https://example.com/a.tar.gz", &hash);
+        let bad = package("b", "1", "https://example.com/b.tar.gz", "0000");
+        let missing = package("c", "1", "https://example.com/c.tar.gz", "0000");
+
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        for source in sc
+            .sources_for(&good)
+            .into_iter()
+            .chain(sc.sources_for(&bad))
+        {
+            std::fs::create_dir_all(source.path().parent().unwrap()).unwrap();
+            std::fs::write(source.path(), content).unwrap();
+        }
+
+        let progressbars = ProgressBars::setup(String::new(), String::new(), ProgressMode::Hidden);
+        let opts = VerifyOptions::builder()
+            .jobs(2)
+            .remove_corrupt(true)
+            .build();
+        let mut results = verify_sources(
+            [&good, &bad, &missing].into_iter(),
+            &sc,
+            &progressbars,
+            &opts,
+        )
+        .await
+        .unwrap();
+        let bad_removed = !sc.sources_for(&bad)[0].exists();
+        results.sort_by_key(|r| r.source.package_name().clone());
+
+        let summary = results
+            .iter()
+            .map(|r| {
+                (
+                    r.source.package_name().to_string(),
+                    r.computed_hash.as_ref().map(ToString::to_string),
+                    r.removed,
+                    r.result.is_ok(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (String::from("a"), Some(hash.clone()), false, true),
+                (String::from("b"), Some(hash.clone()), true, false),
+                (String::from("c"), None, false, false),
+            ]
+        );
+        assert!(bad_removed);
+    }
+
+    #[tokio::test]
+    async fn test_verification_summary() {
+        let cache_root = temp_dir();
+
+        let content = b"butido";
+        let hash = {
+            use sha1::Digest;
+            format!("{:x}", sha1::Sha1::digest(content))
+        };
+        let packages = [
+            package("a", "1", "https://example.com/a.tar.gz", &hash),
+            package("b", "1", "https://example.com/b.tar.gz", &hash),
+            package("c", "1", "https://example.com/c.tar.gz", "0000"),
+            package("d", "1", "https://example.com/d.tar.gz", &hash),
+            package("e", "1", "https://example.com/e.tar.gz", "0000"),
+        ];
+
+        // "d" and "e" are missing
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        for source in packages[..3].iter().flat_map(|p| sc.sources_for(p)) {
+            std::fs::create_dir_all(source.path().parent().unwrap()).unwrap();
+            std::fs::write(source.path(), content).unwrap();
+        }
+
+        let progressbars = ProgressBars::setup(String::new(), String::new(), ProgressMode::Hidden);
+        let opts = VerifyOptions::builder().jobs(2).summary_only(true).build();
+        let results = verify_sources(packages.iter(), &sc, &progressbars, &opts)
+            .await
+            .unwrap();
+        let reported = report_verification(&results, &opts);
+
+        let summary = VerificationSummary::from(&results[..]);
+        assert_eq!(
+            summary,
+            VerificationSummary {
+                total: 5,
+                verified: 2,
+                missing: 2,
+                corrupt: 1,
+                failed: 0,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "total: 5\nverified: 2\nmissing: 2\ncorrupt: 1\nfailed: 0\nstatus: failure\n"
+        );
+        assert_eq!(
+            summary.summary_line().to_string(),
+            "butido: verify: total=5 ok=2 corrupt=1 missing=2 failed=0"
+        );
+        assert!(reported.is_err());
+    }
 }
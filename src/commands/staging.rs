@@ -0,0 +1,474 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'staging' subcommand
+//!
+//! The staging directories of the submits (see `Configuration::staging_dir_for()`) are never
+//! removed by a build. `staging gc` removes the old ones, except the ones that were kept with
+//! `build --staging-keep` (or the `keep_staging` setting). `staging rm` removes the ones of
+//! released submits.
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use clap::ArgMatches;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use itertools::Itertools;
+use serde::Serialize;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::commands::output::print_records;
+use crate::commands::output::OutputFormat;
+use crate::commands::output::Record;
+use crate::config::Configuration;
+use crate::db::DbConnectionConfig;
+use crate::schema;
+
+/// The file that marks a staging directory as kept, it is next to the directory because the
+/// staging directory itself must only contain artifacts
+pub fn keep_marker(staging_dir: &Path) -> PathBuf {
+    staging_dir.with_extension("keep")
+}
+
+/// Protect `staging_dir` from being removed by `staging gc`
+pub fn keep(staging_dir: &Path) -> Result<()> {
+    let marker = keep_marker(staging_dir);
+    std::fs::write(&marker, b"").with_context(|| anyhow!("Creating {}", marker.display()))
+}
+
+/// Implementation of the "staging" subcommand
+pub async fn staging(
+    db_connection_config: DbConnectionConfig<'_>,
+    matches: &ArgMatches,
+    config: &Configuration,
+) -> Result<()> {
+    match matches.subcommand() {
+        Some(("gc", matches)) => gc(db_connection_config, matches, config).await,
+        Some(("ls", matches)) => ls(db_connection_config, matches, config),
+        Some(("rm", matches)) => rm(db_connection_config, matches, config).await,
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+/// Implementation of the "staging gc" subcommand
+async fn gc(
+    db_connection_config: DbConnectionConfig<'_>,
+    matches: &ArgMatches,
+    config: &Configuration,
+) -> Result<()> {
+    let older_than = matches
+        .get_one::<String>("older_than")
+        .map(|s| humantime::parse_duration(s))
+        .unwrap() // safe by clap
+        .context("Parsing the --older-than duration")?;
+    let dry_run = matches.get_flag("dry_run");
+    let force = matches.get_flag("force");
+    let cutoff = SystemTime::now()
+        .checked_sub(older_than)
+        .ok_or_else(|| anyhow!("--older-than is too long"))?;
+
+    let released = released_submits(db_connection_config)?;
+    let dirs = staging_dirs(config.staging_directory())?;
+    let mut out = std::io::stdout().lock();
+    for dir in select_for_gc(dirs, cutoff, &released, force) {
+        if !dry_run {
+            remove_staging_dir(&dir).await?;
+        }
+        writeln!(out, "{}", dir.path.display())?;
+    }
+    Ok(())
+}
+
+/// Remove the staging directory and its `keep_marker()`
+async fn remove_staging_dir(dir: &StagingDir) -> Result<()> {
+    debug!("Removing {}", dir.path.display());
+    tokio::fs::remove_dir_all(&dir.path)
+        .await
+        .with_context(|| anyhow!("Removing {}", dir.path.display()))?;
+    let marker = keep_marker(&dir.path);
+    if dir.kept {
+        tokio::fs::remove_file(&marker)
+            .await
+            .with_context(|| anyhow!("Removing {}", marker.display()))?;
+    }
+    Ok(())
+}
+
+/// Implementation of the "staging ls" subcommand
+fn ls(
+    db_connection_config: DbConnectionConfig<'_>,
+    matches: &ArgMatches,
+    config: &Configuration,
+) -> Result<()> {
+    let format = OutputFormat::from_matches(matches)?;
+    let released = released_submits(db_connection_config)?;
+    let records = staging_dirs(config.staging_directory())?
+        .into_iter()
+        .sorted_by_key(|dir| dir.modified)
+        .map(|dir| {
+            Ok(StagingDirRecord {
+                submit: dir.submit.to_string(),
+                size: indicatif::HumanBytes(dir_size(&dir.path)?).to_string(),
+                modified: chrono::DateTime::<chrono::Local>::from(dir.modified)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                released: released.contains(&dir.submit),
+                kept: dir.kept,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    print_records(&records, format)
+}
+
+/// A record of the "staging ls" output
+#[derive(Serialize)]
+struct StagingDirRecord {
+    submit: String,
+    size: String,
+    modified: String,
+    released: bool,
+    kept: bool,
+}
+
+impl Record for StagingDirRecord {
+    fn headers() -> Vec<&'static str> {
+        vec!["Submit", "Size", "Modified", "Released", "Kept"]
+    }
+
+    fn fields(&self) -> Vec<String> {
+        let yes_no = |b: bool| String::from(if b { "yes" } else { "no" });
+        vec![
+            self.submit.clone(),
+            self.size.clone(),
+            self.modified.clone(),
+            yes_no(self.released),
+            yes_no(self.kept),
+        ]
+    }
+}
+
+/// Implementation of the "staging rm" subcommand
+async fn rm(
+    db_connection_config: DbConnectionConfig<'_>,
+    matches: &ArgMatches,
+    config: &Configuration,
+) -> Result<()> {
+    let submits = matches
+        .get_many::<String>("submit_uuid")
+        .unwrap_or_default()
+        .map(|s| Uuid::parse_str(s).with_context(|| anyhow!("Not a submit UUID: {}", s)))
+        .collect::<Result<Vec<_>>>()?;
+    let selection = if matches.get_flag("released") {
+        RmSelection::Released
+    } else {
+        RmSelection::Submits(submits)
+    };
+    let force = matches.get_flag("force");
+
+    let released = released_submits(db_connection_config)?;
+    let dirs = staging_dirs(config.staging_directory())?;
+    let mut out = std::io::stdout().lock();
+    for dir in select_for_rm(dirs, &released, selection, force)? {
+        remove_staging_dir(&dir).await?;
+        writeln!(out, "{}", dir.path.display())?;
+    }
+    Ok(())
+}
+
+/// The UUIDs of the submits of which at least one artifact was released
+fn released_submits(db_connection_config: DbConnectionConfig<'_>) -> Result<HashSet<Uuid>> {
+    let mut conn = db_connection_config.establish_connection()?;
+    schema::releases::table
+        .inner_join(
+            schema::artifacts::table
+                .inner_join(schema::jobs::table.inner_join(schema::submits::table)),
+        )
+        .select(schema::submits::uuid)
+        .distinct()
+        .load::<Uuid>(&mut conn)
+        .map(|uuids| uuids.into_iter().collect())
+        .context("Loading the released submits")
+}
+
+/// The staging directories "staging rm" removes
+#[derive(Debug)]
+enum RmSelection {
+    /// The directories of these submits
+    Submits(Vec<Uuid>),
+
+    /// The directories of all released submits
+    Released,
+}
+
+/// Why the staging directory must not be removed without `--force`, if it must not be removed
+fn removal_blocker(dir: &StagingDir, released: &HashSet<Uuid>) -> Option<&'static str> {
+    if dir.kept {
+        Some("it is kept (see build --staging-keep)")
+    } else if !released.contains(&dir.submit) {
+        Some("the submit was not released")
+    } else {
+        None
+    }
+}
+
+/// The staging directories to remove
+///
+/// With `RmSelection::Released` the directories that must not be removed are skipped, other
+/// selections fail if a directory must not be removed (and nothing is removed then), unless
+/// `force` is set.
+fn select_for_rm(
+    dirs: Vec<StagingDir>,
+    released: &HashSet<Uuid>,
+    selection: RmSelection,
+    force: bool,
+) -> Result<Vec<StagingDir>> {
+    match selection {
+        RmSelection::Released => Ok(dirs
+            .into_iter()
+            .filter(|dir| released.contains(&dir.submit))
+            .filter(|dir| force || removal_blocker(dir, released).is_none())
+            .sorted_by(|a, b| a.path.cmp(&b.path))
+            .collect()),
+        RmSelection::Submits(submits) => submits
+            .into_iter()
+            .map(|submit| {
+                let dir = dirs
+                    .iter()
+                    .find(|dir| dir.submit == submit)
+                    .ok_or_else(|| anyhow!("There is no staging directory of submit {}", submit))?;
+                match removal_blocker(dir, released) {
+                    Some(reason) if !force => Err(anyhow!(
+                        "Not removing the staging directory of submit {}, {} (use --force to remove it anyway)",
+                        submit,
+                        reason
+                    )),
+                    _ => Ok(dir.clone()),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// The size of all files in `path`
+fn dir_size(path: &Path) -> Result<u64> {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .map(|entry| {
+            let entry = entry?;
+            Ok(if entry.file_type().is_file() {
+                entry.metadata()?.len()
+            } else {
+                0
+            })
+        })
+        .sum::<Result<u64>>()
+        .with_context(|| anyhow!("Getting the size of {}", path.display()))
+}
+
+/// A staging directory of a submit
+#[derive(Clone, Debug)]
+struct StagingDir {
+    path: PathBuf,
+    submit: Uuid,
+    modified: SystemTime,
+
+    /// See `keep()`
+    kept: bool,
+}
+
+/// The staging directories of the submits in `root`, other files and directories are ignored
+fn staging_dirs(root: &Path) -> Result<Vec<StagingDir>> {
+    std::fs::read_dir(root)
+        .with_context(|| anyhow!("Reading {}", root.display()))?
+        .filter_map(|entry| {
+            entry
+                .and_then(|entry| {
+                    let path = entry.path();
+                    let submit = path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .and_then(|name| Uuid::parse_str(name).ok());
+                    let Some(submit) = submit.filter(|_| path.is_dir()) else {
+                        return Ok(None);
+                    };
+
+                    Ok(Some(StagingDir {
+                        submit,
+                        modified: entry.metadata()?.modified()?,
+                        kept: keep_marker(&path).exists(),
+                        path,
+                    }))
+                })
+                .transpose()
+        })
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| anyhow!("Reading {}", root.display()))
+}
+
+/// The staging directories that were last modified before `cutoff`
+///
+/// The directories that must not be removed (see `removal_blocker()`) are skipped, unless `force`
+/// is set.
+fn select_for_gc(
+    dirs: Vec<StagingDir>,
+    cutoff: SystemTime,
+    released: &HashSet<Uuid>,
+    force: bool,
+) -> Vec<StagingDir> {
+    let mut stale = dirs
+        .into_iter()
+        .filter(|dir| dir.modified < cutoff)
+        .filter(|dir| force || removal_blocker(dir, released).is_none())
+        .collect::<Vec<_>>();
+    stale.sort_by(|a, b| a.path.cmp(&b.path));
+    stale
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::package::tests::temp_dir;
+
+    #[test]
+    fn test_select_for_gc() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let dir = |name: &str, age_in_days: u32, kept| StagingDir {
+            path: PathBuf::from(name),
+            submit: Uuid::new_v4(),
+            modified: now - day * age_in_days,
+            kept,
+        };
+        let dirs = vec![
+            dir("new", 0, false),
+            dir("old", 10, false),
+            dir("old-but-kept", 10, true),
+            dir("older", 30, false),
+            dir("a-week", 7, false),
+            dir("old-unreleased", 10, false),
+        ];
+        let released = dirs
+            .iter()
+            .filter(|d| d.path != Path::new("old-unreleased"))
+            .map(|d| d.submit)
+            .collect::<HashSet<_>>();
+
+        let names = |cutoff, force| {
+            select_for_gc(dirs.clone(), cutoff, &released, force)
+                .into_iter()
+                .map(|d| d.path.display().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(now - day * 14, false), vec!["older"]);
+        assert_eq!(names(now - day * 5, false), vec!["a-week", "old", "older"]);
+        assert_eq!(
+            names(now + day, false),
+            vec!["a-week", "new", "old", "older"]
+        );
+        assert!(names(now - day * 60, false).is_empty());
+
+        // The unreleased and kept directories are only removed with --force
+        assert_eq!(
+            names(now - day * 5, true),
+            vec!["a-week", "old", "old-but-kept", "old-unreleased", "older"]
+        );
+    }
+
+    #[test]
+    fn test_select_for_rm() {
+        let dir = |kept| {
+            let submit = Uuid::new_v4();
+            StagingDir {
+                path: PathBuf::from(submit.to_string()),
+                submit,
+                modified: SystemTime::now(),
+                kept,
+            }
+        };
+        let (released, released_kept, unreleased) = (dir(false), dir(true), dir(false));
+        let dirs = vec![released.clone(), released_kept.clone(), unreleased.clone()];
+        let released_submits = HashSet::from([released.submit, released_kept.submit]);
+        let select = |selection, force| {
+            select_for_rm(dirs.clone(), &released_submits, selection, force).map(|dirs| {
+                dirs.into_iter()
+                    .map(|d| d.submit)
+                    .sorted()
+                    .collect::<Vec<_>>()
+            })
+        };
+        let sorted = |submits: &[Uuid]| submits.iter().copied().sorted().collect::<Vec<_>>();
+
+        // Only the released directories that aren't kept are safe to delete
+        assert_eq!(
+            select(RmSelection::Released, false).unwrap(),
+            vec![released.submit]
+        );
+        assert_eq!(
+            select(RmSelection::Released, true).unwrap(),
+            sorted(&[released.submit, released_kept.submit])
+        );
+        assert_eq!(
+            select(RmSelection::Submits(vec![released.submit]), false).unwrap(),
+            vec![released.submit]
+        );
+        for unsafe_to_delete in [&released_kept, &unreleased] {
+            let selection = || RmSelection::Submits(vec![released.submit, unsafe_to_delete.submit]);
+            assert!(select(selection(), false).is_err());
+            assert_eq!(
+                select(selection(), true).unwrap(),
+                sorted(&[released.submit, unsafe_to_delete.submit])
+            );
+        }
+        assert!(select(RmSelection::Submits(vec![Uuid::new_v4()]), true).is_err());
+    }
+
+    #[test]
+    fn test_staging_dirs() {
+        let root = temp_dir();
+        let submit = root.join(Uuid::new_v4().to_string());
+        let kept = root.join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&submit).unwrap();
+        std::fs::create_dir_all(&kept).unwrap();
+        std::fs::create_dir_all(root.join("not-a-submit")).unwrap();
+        std::fs::write(root.join(Uuid::new_v4().to_string()), b"").unwrap();
+        keep(&kept).unwrap();
+
+        let mut dirs = staging_dirs(&root).unwrap();
+        dirs.sort_by_key(|d| d.kept);
+        assert_eq!(dirs.len(), 2);
+        assert_eq!((&dirs[0].path, dirs[0].kept), (&submit, false));
+        assert_eq!(
+            dirs[0].submit.to_string(),
+            submit.file_name().unwrap().to_str().unwrap()
+        );
+        assert_eq!((&dirs[1].path, dirs[1].kept), (&kept, true));
+
+        let released = dirs.iter().map(|d| d.submit).collect();
+        let stale = select_for_gc(
+            dirs,
+            SystemTime::now() + Duration::from_secs(60),
+            &released,
+            false,
+        );
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].path, submit);
+    }
+}
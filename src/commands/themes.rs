@@ -0,0 +1,95 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'themes' subcommand
+
+use std::io::Write;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+
+use crate::package::Script;
+
+/// The script that is highlighted by `themes preview`
+const PREVIEW_SCRIPT: &str = r#"#!/bin/bash
+set -euo pipefail
+
+# Build the package
+VERSION="1.0.0"
+for patch in patches/*.patch; do
+    patch -p1 < "$patch"
+done
+
+./configure --prefix=/usr && make -j "$(nproc)"
+echo "Built version ${VERSION}" >&2
+"#;
+
+/// Implementation of the "themes" subcommand
+pub fn themes(matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        Some(("list", _)) => list(),
+        Some(("preview", matches)) => preview(matches),
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+fn list() -> Result<()> {
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    crate::package::highlight_theme_names()
+        .into_iter()
+        .try_for_each(|name| writeln!(outlock, "{name}").map_err(Error::from))
+}
+
+fn preview(matches: &ArgMatches) -> Result<()> {
+    let theme = matches.get_one::<String>("theme_name").unwrap(); // safe by clap
+    if !crate::package::highlight_theme_names().contains(theme) {
+        return Err(anyhow!(
+            "Theme not known: {}. Use 'themes list' to list the available themes",
+            theme
+        ));
+    }
+
+    // Highlighting is done via terminal escape sequences, so honor the color settings
+    let highlight = colored::control::SHOULD_COLORIZE.should_colorize();
+    let script = Script::from(String::from(PREVIEW_SCRIPT));
+    let printable = crate::ui::script_to_printable(&script, highlight, theme, true)?;
+
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    writeln!(outlock, "{printable}").map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::SCRIPT_HIGHLIGHT_THEMES;
+
+    #[test]
+    fn test_list_contains_all_allowed_themes() {
+        let names = crate::package::highlight_theme_names();
+        for theme in SCRIPT_HIGHLIGHT_THEMES {
+            assert!(
+                names.iter().any(|name| name == theme),
+                "Theme missing: {theme}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_preview_script_can_be_highlighted() {
+        let script = crate::package::Script::from(String::from(super::PREVIEW_SCRIPT));
+        for theme in SCRIPT_HIGHLIGHT_THEMES {
+            assert!(crate::ui::script_to_printable(&script, true, theme, true).is_ok());
+        }
+    }
+}
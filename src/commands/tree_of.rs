@@ -11,27 +11,56 @@
 //! Implementation of the 'tree-of' subcommand
 
 use std::convert::TryFrom;
+use std::path::Path;
 
 use anyhow::Error;
 use anyhow::Result;
 use clap::ArgMatches;
-use resiter::AndThen;
 
 use crate::config::Configuration;
 use crate::package::condition::ConditionData;
-use crate::package::Dag;
+use crate::package::DagCache;
+use crate::package::DisplayOptions;
+use crate::package::PackageHighlight;
 use crate::package::PackageName;
 use crate::package::PackageVersionConstraint;
+use crate::package::SerializedDag;
 use crate::repository::Repository;
 use crate::util::docker::resolve_image_name;
 use crate::util::EnvironmentVariableName;
 
+/// Implementation of the "tree_of" subcommand with "--load-dag", doesn't need the repository
+pub async fn tree_of_dump(path: &Path, matches: &ArgMatches, config: &Configuration) -> Result<()> {
+    let highlight = highlight_from_matches(matches)?;
+    let print_config = crate::ui::tree_print_config(ascii_from_matches(matches, config));
+    let stdout = std::io::stdout();
+    let mut outlock = stdout.lock();
+    SerializedDag::read_dump(path)?
+        .iter()
+        .try_for_each(|dag| {
+            let tree = dag.display(highlight.as_ref());
+            ptree::write_tree_with(&tree, &mut outlock, &print_config)
+        })
+        .map_err(Error::from)
+}
+
+fn ascii_from_matches(matches: &ArgMatches, config: &Configuration) -> bool {
+    matches.get_flag("ascii") || *config.ascii_trees()
+}
+
+fn highlight_from_matches(matches: &ArgMatches) -> Result<Option<PackageHighlight>> {
+    matches
+        .get_one::<String>("highlight")
+        .map(|s| PackageHighlight::parse(s))
+        .transpose()
+}
+
 /// Implementation of the "tree_of" subcommand
 pub async fn tree_of(matches: &ArgMatches, repo: Repository, config: &Configuration) -> Result<()> {
     let pname = matches
         .get_one::<String>("package_name")
-        .map(|s| s.to_owned())
-        .map(PackageName::from);
+        .map(PackageName::try_new)
+        .transpose()?;
     let pvers = matches
         .get_one::<String>("package_version")
         .map(|s| s.to_owned())
@@ -50,25 +79,74 @@ pub async fn tree_of(matches: &ArgMatches, repo: Repository, config: &Configurat
         .map(crate::util::env::parse_to_env)
         .collect::<Result<Vec<(EnvironmentVariableName, String)>>>()?;
 
+    let display_options = matches
+        .get_one::<String>("label")
+        .map(|label| DisplayOptions::with_label(label.to_owned()))
+        .transpose()?
+        .unwrap_or_default()
+        .with_max_width(crate::util::width::output_width())
+        .with_ascii(ascii_from_matches(matches, config));
+    let print_config = display_options.print_config();
+
+    let highlight = highlight_from_matches(matches)?;
+
     let condition_data = ConditionData {
         image_name: image_name.as_ref(),
         env: &additional_env,
     };
 
-    repo.packages()
-        .filter(|p| pname.as_ref().map(|n| p.name() == n).unwrap_or(true))
+    let packages = repo
+        .packages()
+        .filter(|p| {
+            pname
+                .as_ref()
+                .map(|n| repo.names_match(p.name(), n))
+                .unwrap_or(true)
+        })
         .filter(|p| {
             pvers
                 .as_ref()
                 .map(|v| v.matches(p.version()))
                 .unwrap_or(true)
         })
-        .map(|package| Dag::for_root_package(package.clone(), &repo, None, &condition_data))
-        .and_then_ok(|tree| {
-            let stdout = std::io::stdout();
-            let mut outlock = stdout.lock();
+        .collect::<Vec<_>>();
+    if let (Some(pname), true) = (pname.as_ref(), packages.is_empty()) {
+        let what = match pvers.as_ref() {
+            Some(pvers) => format!("{pname} {pvers}"),
+            None => pname.to_string(),
+        };
+        return Err(repo.package_not_found(pname, &what).into());
+    }
 
-            ptree::write_tree(&tree.display(), &mut outlock).map_err(Error::from)
-        })
-        .collect::<Result<()>>()
+    let mut cache = DagCache::new(&repo, config.dag_cache_dir().as_deref())?;
+    let mut dumped = Vec::new();
+    packages.into_iter().try_for_each(|package| {
+        let tree = cache.get_or_build(
+            package,
+            &repo,
+            &condition_data,
+            *config.max_dependency_depth(),
+            *config.dependency_conflict_policy(),
+        )?;
+        dumped.push(SerializedDag::from(tree));
+        let stdout = std::io::stdout();
+        let mut outlock = stdout.lock();
+
+        match highlight.as_ref() {
+            Some(highlight) => ptree::write_tree_with(
+                &tree.display_highlighted(&display_options, highlight),
+                &mut outlock,
+                &print_config,
+            ),
+            None => {
+                ptree::write_tree_with(&tree.display(&display_options), &mut outlock, &print_config)
+            }
+        }
+        .map_err(Error::from)
+    })?;
+
+    match matches.get_one::<String>("dump_dag") {
+        Some(path) => SerializedDag::write_dump(path.as_ref(), &dumped, highlight.as_ref()),
+        None => Ok(()),
+    }
 }
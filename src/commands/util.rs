@@ -10,8 +10,8 @@
 
 //! Utility module for subcommand implementation helpers
 
+use std::convert::TryFrom;
 use std::fmt::Display;
-use std::io::IsTerminal;
 use std::io::Write;
 use std::path::Path;
 
@@ -27,9 +27,14 @@ use tracing::{error, info, trace};
 
 use crate::config::*;
 use crate::package::Package;
+use crate::package::PackageName;
+use crate::package::PackageVersionConstraint;
 use crate::package::PhaseName;
 use crate::package::ScriptBuilder;
 use crate::package::Shebang;
+use crate::repository::PackageFilter;
+use crate::util::progress::StageProgress;
+use crate::util::summary::SummaryLine;
 
 /// Helper for getting a boolean value by name form the argument object
 pub fn getbool(m: &ArgMatches, name: &str, cmp: &str) -> bool {
@@ -43,7 +48,8 @@ pub async fn lint_packages<'a, I>(
     linter: &Path,
     config: &Configuration,
     bar: indicatif::ProgressBar,
-) -> Result<()>
+    stage: Option<&StageProgress>,
+) -> Result<LintSummary>
 where
     I: Iterator<Item = &'a Package> + 'a,
 {
@@ -67,6 +73,9 @@ where
 
                 let (status, stdout, stderr) = script.lint(cmd).await?;
                 bar.inc(1);
+                if let Some(stage) = stage {
+                    stage.follow(&bar);
+                }
                 Ok((pkg.name().clone(), pkg.version().clone(), status, stdout, stderr))
             }
         })
@@ -103,17 +112,43 @@ where
         })
         .collect::<Vec<_>>();
 
-    let lint_ok = lint_results.iter().all(|b| *b);
-
-    if !lint_ok {
+    let summary = LintSummary {
+        total: lint_results.len(),
+        failed: lint_results.iter().filter(|ok| !**ok).count(),
+    };
+    if summary.failed > 0 {
         bar.finish_with_message("Linting errored");
-        Err(anyhow!("Linting was not successful"))
     } else {
         bar.finish_with_message(format!(
             "Finished linting {} package scripts",
-            lint_results.len()
+            summary.total
         ));
-        Ok(())
+    }
+    Ok(summary)
+}
+
+/// The number of linted package scripts and of the ones the linter failed for
+#[derive(Debug, Eq, PartialEq)]
+pub struct LintSummary {
+    total: usize,
+    failed: usize,
+}
+
+impl LintSummary {
+    /// Fail if the linter failed for any script
+    pub fn check(&self) -> Result<()> {
+        if self.failed > 0 {
+            Err(anyhow!("Linting was not successful"))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn summary_line(&self) -> SummaryLine {
+        SummaryLine::new("lint")
+            .count("total", self.total)
+            .count("ok", self.total - self.failed)
+            .count("failed", self.failed)
     }
 }
 
@@ -162,6 +197,48 @@ pub fn mk_package_name_regex(regex: &str) -> Result<Regex> {
         .map_err(Error::from)
 }
 
+/// Get the package selection from the "package_name", "package_version", "matching" and
+/// "exclude" arguments
+///
+/// The arguments that are not defined for the subcommand are ignored.
+pub fn package_filter_from_matches(matches: &ArgMatches) -> Result<PackageFilter> {
+    let name = matches
+        .try_get_one::<String>("package_name")
+        .ok()
+        .flatten()
+        .map(PackageName::try_new)
+        .transpose()?;
+    let version = matches
+        .try_get_one::<String>("package_version")
+        .ok()
+        .flatten()
+        .map(|s| PackageVersionConstraint::try_from(s.to_owned()))
+        .transpose()?;
+    let regex = matches
+        .try_get_one::<String>("matching")
+        .ok()
+        .flatten()
+        .map(|s| mk_package_name_regex(s))
+        .transpose()?;
+
+    if regex.is_some() && (name.is_some() || version.is_some()) {
+        return Err(anyhow!(
+            "'--matching' cannot be combined with a package name or version"
+        ));
+    }
+
+    let exclude = matches
+        .try_get_many::<String>("exclude")
+        .ok()
+        .flatten()
+        .into_iter()
+        .flatten()
+        .map(|s| mk_package_name_regex(s))
+        .collect::<Result<Vec<_>>>()?;
+
+    PackageFilter::new(name, version, regex).map(|filter| filter.with_exclude(exclude))
+}
+
 /// Make a header column for the ascii_table crate
 pub fn mk_header(vec: Vec<&str>) -> Vec<ascii_table::Column> {
     vec.into_iter()
@@ -177,6 +254,7 @@ pub fn mk_header(vec: Vec<&str>) -> Vec<ascii_table::Column> {
 /// Display the passed data as nice ascii table,
 /// or, if stdout is a pipe, print it nicely parseable
 ///
+/// The table is fitted to the width of the terminal (or `--width`), too long cells are truncated.
 /// If `csv` is `true`, convert the data to CSV and print that instead.
 pub fn display_data<D: Display>(
     headers: Vec<ascii_table::Column>,
@@ -188,28 +266,13 @@ pub fn display_data<D: Display>(
     }
 
     if csv {
-        use csv::WriterBuilder;
-        let mut wtr = WriterBuilder::new().from_writer(vec![]);
-        for record in data.into_iter() {
-            let r: Vec<String> = record.into_iter().map(|e| e.to_string()).collect();
-
-            wtr.write_record(&r)?;
-        }
-
+        let text = to_csv(data)?;
         let out = std::io::stdout();
         let mut lock = out.lock();
-
-        wtr.into_inner()
-            .map_err(Error::from)
-            .and_then(|t| String::from_utf8(t).map_err(Error::from))
-            .and_then(|text| writeln!(lock, "{text}").map_err(Error::from))
-    } else if std::io::stdout().is_terminal() {
+        writeln!(lock, "{text}").map_err(Error::from)
+    } else if let Some(width) = crate::util::width::output_width() {
         let mut ascii_table = ascii_table::AsciiTable::default();
-        ascii_table.set_max_width(
-            terminal_size::terminal_size()
-                .map(|tpl| tpl.0 .0 as usize) // an ugly interface indeed!
-                .unwrap_or(80),
-        );
+        ascii_table.set_max_width(width);
 
         headers.into_iter().enumerate().for_each(|(i, c)| {
             *ascii_table.column(i) = c;
@@ -227,6 +290,21 @@ pub fn display_data<D: Display>(
     }
 }
 
+/// Convert the passed data to CSV, quoting and escaping fields where necessary
+pub fn to_csv<D: Display>(data: Vec<Vec<D>>) -> Result<String> {
+    use csv::WriterBuilder;
+    let mut wtr = WriterBuilder::new().from_writer(vec![]);
+    for record in data.into_iter() {
+        let r: Vec<String> = record.into_iter().map(|e| e.to_string()).collect();
+
+        wtr.write_record(&r)?;
+    }
+
+    wtr.into_inner()
+        .map_err(Error::from)
+        .and_then(|t| String::from_utf8(t).map_err(Error::from))
+}
+
 pub fn get_date_filter(
     name: &str,
     matches: &ArgMatches,
@@ -263,3 +341,80 @@ pub fn get_date_filter(
         })
         .transpose()
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Arg;
+    use clap::Command;
+
+    use super::*;
+
+    #[test]
+    fn test_lint_summary_line() {
+        let summary = LintSummary {
+            total: 12,
+            failed: 2,
+        };
+        assert_eq!(
+            summary.summary_line().to_string(),
+            "butido: lint: total=12 ok=10 failed=2"
+        );
+        assert!(summary.check().is_err());
+
+        let summary = LintSummary {
+            total: 3,
+            failed: 0,
+        };
+        assert_eq!(
+            summary.summary_line().to_string(),
+            "butido: lint: total=3 ok=3 failed=0"
+        );
+        assert!(summary.check().is_ok());
+    }
+
+    #[test]
+    fn test_package_filter_from_matches_with_name_and_regex_is_an_error() {
+        // A command that does not declare the arguments as conflicting
+        let matches = Command::new("test")
+            .arg(Arg::new("package_name").index(1))
+            .arg(Arg::new("package_version").index(2))
+            .arg(Arg::new("matching").long("matching"))
+            .get_matches_from(["test", "a", "=1", "--matching", "^a"]);
+
+        let err = package_filter_from_matches(&matches).unwrap_err();
+        assert!(err.to_string().contains("'--matching' cannot be combined"));
+    }
+
+    #[test]
+    fn test_package_filter_from_matches_ignores_unknown_arguments() {
+        let matches = Command::new("test")
+            .arg(Arg::new("package_name").index(1))
+            .get_matches_from(["test", "a"]);
+
+        let filter = package_filter_from_matches(&matches).unwrap();
+        assert_eq!(filter.to_string(), "a");
+    }
+
+    #[test]
+    fn test_package_filter_from_matches_with_exclude() {
+        let matches = Command::new("test")
+            .arg(Arg::new("matching").long("matching"))
+            .arg(
+                Arg::new("exclude")
+                    .long("exclude")
+                    .action(clap::ArgAction::Append),
+            )
+            .get_matches_from([
+                "test",
+                "--matching",
+                "^a",
+                "--exclude",
+                "b",
+                "--exclude",
+                "c$",
+            ]);
+
+        let filter = package_filter_from_matches(&matches).unwrap();
+        assert_eq!(filter.to_string(), "^a regex excluding b, c$");
+    }
+}
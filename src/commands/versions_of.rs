@@ -10,35 +10,178 @@
 
 //! Implementation of the 'versions_of' subcommand
 
+use std::collections::HashMap;
+use std::io::Write;
+
 use anyhow::Error;
 use anyhow::Result;
 use clap::ArgMatches;
+use serde::Serialize;
 use tracing::trace;
 
+use crate::commands::output::print_count;
+use crate::commands::output::print_records;
+use crate::commands::output::OutputFormat;
+use crate::commands::output::Record;
 use crate::package::PackageName;
+use crate::package::PackageVersion;
 use crate::repository::Repository;
 
+/// A record of the "versions_of" output
+#[derive(Serialize)]
+struct VersionRecord<'a> {
+    version: &'a PackageVersion,
+}
+
+impl Record for VersionRecord<'_> {
+    fn headers() -> Vec<&'static str> {
+        vec!["Version"]
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.version.to_string()]
+    }
+}
+
 /// Implementation of the "versions_of" subcommand
 pub async fn versions_of(matches: &ArgMatches, repo: Repository) -> Result<()> {
-    use filters::filter::Filter;
-    use std::io::Write;
+    let format = OutputFormat::from_matches(matches)?;
+    let versions = selected_versions(matches, &repo)?;
+    if matches.get_flag("count") {
+        return print_count(versions.len());
+    }
+
+    // The human readable output is kept as one version per line, so that scripts parsing it don't
+    // break
+    if format == OutputFormat::Human {
+        let mut stdout = std::io::stdout();
+        return versions
+            .into_iter()
+            .try_for_each(|version| writeln!(stdout, "{version}").map_err(Error::from));
+    }
 
-    let package_filter = {
-        let name = matches
-            .get_one::<String>("package_name")
-            .map(|s| s.to_owned())
-            .map(PackageName::from)
-            .unwrap();
-        trace!("Checking for package with name = {}", name);
+    let records = versions
+        .into_iter()
+        .map(|version| VersionRecord { version })
+        .collect::<Vec<_>>();
+    print_records(&records, format)
+}
+
+/// The versions of the package that are listed, in semantic order
+fn selected_versions<'a>(
+    matches: &ArgMatches,
+    repo: &'a Repository,
+) -> Result<Vec<&'a PackageVersion>> {
+    use filters::filter::Filter;
 
-        crate::util::filters::build_package_filter_by_name(name)
-    };
+    let name = matches
+        .get_one::<String>("package_name")
+        .map(PackageName::try_new)
+        .unwrap()?; // safe by clap
+    trace!("Checking for package with name = {}", name);
+    let package_filter = crate::util::filters::build_package_filter_by_name(repo, name.clone());
 
-    let mut stdout = std::io::stdout();
-    repo.packages()
+    let versions = repo
+        .packages()
         .filter(|package| package_filter.filter(package))
         .inspect(|pkg| trace!("Found package: {:?}", pkg))
-        .map(|pkg| writeln!(stdout, "{}", pkg.version()).map_err(Error::from))
-        .collect::<Result<Vec<_>>>()
-        .map(|_| ())
+        .map(|pkg| pkg.version())
+        .collect::<Vec<_>>();
+    if versions.is_empty() {
+        return Err(repo.package_not_found(&name, &name).into());
+    }
+
+    if matches.get_flag("latest") {
+        Ok(versions.into_iter().max().into_iter().collect())
+    } else if matches.get_flag("latest_per_minor") {
+        Ok(latest_per_minor(versions))
+    } else {
+        Ok(versions)
+    }
+}
+
+/// The newest version of each minor line (see `PackageVersion::major_minor()`), oldest first
+fn latest_per_minor(versions: Vec<&PackageVersion>) -> Vec<&PackageVersion> {
+    let mut latest = HashMap::<&str, &PackageVersion>::new();
+    for version in versions {
+        let entry = latest.entry(version.major_minor()).or_insert(version);
+        if version > *entry {
+            *entry = version;
+        }
+    }
+    let mut latest = latest.into_values().collect::<Vec<_>>();
+    latest.sort();
+    latest
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::commands::output::render_count;
+    use crate::commands::output::render_records;
+    use crate::package::tests::package;
+    use crate::package::tests::pversion;
+
+    #[test]
+    fn test_latest_per_minor() {
+        let versions = [
+            "1.10.0",
+            "1.2.0",
+            "1.9.3",
+            "2.0.0-rc1",
+            "1.2.10",
+            "1.9.12",
+            "1.2.9",
+            "1.10.1-beta",
+            "2.0.0",
+            "1.9.2",
+            "3",
+            "2.0.1+build7",
+        ]
+        .map(pversion);
+        let latest = latest_per_minor(versions.iter().collect())
+            .into_iter()
+            .map(|v| v.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            latest,
+            vec!["1.2.10", "1.9.12", "1.10.1-beta", "2.0.1+build7", "3"]
+        );
+
+        assert_eq!(versions.iter().max().map(|v| v.as_str()), Some("3"));
+        assert!(latest_per_minor(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_count() {
+        let mut map = BTreeMap::new();
+        for (name, version) in [("a", "1.0"), ("a", "1.1"), ("a", "1.1.1"), ("b", "2")] {
+            let p = package(name, version, "https://example.com/a.tar.gz", "h");
+            map.insert((p.name().clone(), p.version().clone()), p);
+        }
+        let repo = Repository::from(map);
+
+        for args in [vec![], vec!["--latest"], vec!["--latest-per-minor"]] {
+            let argv = ["butido", "versions-of", "a"].into_iter().chain(args);
+            let listing_matches = crate::cli::cli()
+                .try_get_matches_from(argv.clone())
+                .unwrap();
+            let count_matches = crate::cli::cli()
+                .try_get_matches_from(argv.chain(["--count"]))
+                .unwrap();
+            let (_, listing_matches) = listing_matches.subcommand().unwrap();
+            let (_, count_matches) = count_matches.subcommand().unwrap();
+
+            let records = selected_versions(listing_matches, &repo)
+                .unwrap()
+                .into_iter()
+                .map(|version| VersionRecord { version })
+                .collect::<Vec<_>>();
+            let listing = render_records(&records, OutputFormat::Human).unwrap();
+            let count = render_count(selected_versions(count_matches, &repo).unwrap().len());
+            assert_eq!(count, format!("{}\n", listing.lines().count()));
+        }
+    }
 }
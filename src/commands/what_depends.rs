@@ -10,18 +10,22 @@
 
 //! Implementation of the 'what_depends' subcommand
 
+use std::borrow::Cow;
 use std::io::Write;
 
 use anyhow::Result;
 use clap::ArgMatches;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
+use ptree::Style;
+use ptree::TreeItem;
 use resiter::Filter;
 use resiter::Map;
 use tracing::trace;
 
 use crate::commands::util::getbool;
 use crate::config::*;
+use crate::package::Package;
 use crate::package::PackageName;
 use crate::repository::Repository;
 use crate::ui::*;
@@ -32,8 +36,6 @@ pub async fn what_depends(
     config: &Configuration,
     repo: Repository,
 ) -> Result<()> {
-    use filters::failable::filter::FailableFilter;
-
     let print_runtime_deps = getbool(
         matches,
         "dependency_type",
@@ -45,19 +47,29 @@ pub async fn what_depends(
         crate::cli::IDENT_DEPENDENCY_TYPE_BUILD,
     );
 
-    let package_filter = {
-        let name = matches
-            .get_one::<String>("package_name")
-            .map(|s| s.to_owned())
-            .map(PackageName::from)
-            .unwrap();
+    let packages = dependents(
+        &repo,
+        &package_filter_name(matches)?,
+        print_build_deps,
+        print_runtime_deps,
+    )?;
+    if matches.get_flag("count") {
+        return crate::commands::output::print_count(packages.len());
+    }
 
-        crate::util::filters::build_package_filter_by_dependency_name(
-            &name,
+    if matches.get_one::<String>("format").map(String::as_str) == Some("tree") {
+        let tree = reverse_dependency_tree(
+            &repo,
+            package_filter_name(matches)?,
             print_build_deps,
             print_runtime_deps,
-        )
-    };
+        )?;
+        let stdout = std::io::stdout();
+        let mut outlock = stdout.lock();
+        let ascii = matches.get_flag("ascii") || *config.ascii_trees();
+        return ptree::write_tree_with(&tree, &mut outlock, &crate::ui::tree_print_config(ascii))
+            .map_err(anyhow::Error::from);
+    }
 
     let hb = crate::ui::handlebars_for_package_printing(config.package_print_format())?;
     let stdout = std::io::stdout();
@@ -80,24 +92,246 @@ pub async fn what_depends(
         script_highlighting: false,
     };
 
-    let mut i = 0;
-    let iter = repo
-        .packages()
-        .map(|package| package_filter.filter(package).map(|b| (b, package)))
-        .filter_ok(|(b, _)| *b)
-        .map_ok(|tpl| tpl.1)
-        .inspect(|pkg| trace!("Found package: {:?}", pkg))
-        .map_ok(|p| {
-            // poor mans enumerate_ok()
-            i += 1;
-            p.prepare_print(config, &flags, &hb, i)
-        });
+    let iter = packages
+        .into_iter()
+        .enumerate()
+        .map(|(i, p)| p.prepare_print(config, &flags, &hb, i + 1));
 
     tokio_stream::iter(iter)
-        .map(|pp| pp.and_then(|p| p.into_displayable()))
+        .map(|pp| pp.into_displayable())
         .try_for_each(|p| {
             let r = writeln!(&mut outlock, "{p}").map_err(anyhow::Error::from);
             futures::future::ready(r)
         })
         .await
 }
+
+/// The packages that depend on the package `name` via the checked dependency types
+fn dependents<'a>(
+    repo: &'a Repository,
+    name: &PackageName,
+    check_build_dep: bool,
+    check_runtime_dep: bool,
+) -> Result<Vec<&'a Package>> {
+    use filters::failable::filter::FailableFilter;
+
+    let package_filter = crate::util::filters::build_package_filter_by_dependency_name(
+        name,
+        check_build_dep,
+        check_runtime_dep,
+    );
+    repo.packages()
+        .map(|package| package_filter.filter(package).map(|b| (b, package)))
+        .filter_ok(|(b, _)| *b)
+        .map_ok(|tpl| tpl.1)
+        .inspect(|pkg| trace!("Found package: {:?}", pkg))
+        .collect()
+}
+
+fn package_filter_name(matches: &ArgMatches) -> Result<PackageName> {
+    matches
+        .get_one::<String>("package_name")
+        .map(PackageName::try_new)
+        .unwrap() // safe by clap
+}
+
+/// A tree of packages, rooted at a package name, with the packages that depend on it as children
+#[derive(Clone, Debug, PartialEq)]
+struct ReverseDependencyTree {
+    label: String,
+    children: Vec<ReverseDependencyTree>,
+}
+
+impl TreeItem for ReverseDependencyTree {
+    type Child = Self;
+
+    fn write_self<W: Write>(&self, f: &mut W, _: &Style) -> std::io::Result<()> {
+        write!(f, "{}", self.label)
+    }
+
+    fn children(&self) -> Cow<'_, [Self::Child]> {
+        Cow::from(&self.children[..])
+    }
+}
+
+/// Build the reverse dependency tree of the package `name`
+///
+/// Packages that depend on their parent only as build dependency are marked with a star, like in
+/// the output of `tree-of`.
+fn reverse_dependency_tree(
+    repo: &Repository,
+    name: PackageName,
+    check_build_dep: bool,
+    check_runtime_dep: bool,
+) -> Result<ReverseDependencyTree> {
+    fn dependents_of(
+        repo: &Repository,
+        name: &PackageName,
+        check_build_dep: bool,
+        check_runtime_dep: bool,
+        path: &mut Vec<PackageName>,
+    ) -> Result<Vec<ReverseDependencyTree>> {
+        use filters::failable::filter::FailableFilter;
+
+        let build_filter = crate::util::filters::build_package_filter_by_dependency_name(
+            name,
+            check_build_dep,
+            false,
+        );
+        let runtime_filter = crate::util::filters::build_package_filter_by_dependency_name(
+            name,
+            false,
+            check_runtime_dep,
+        );
+
+        let mut children = Vec::new();
+        for package in repo.packages() {
+            let is_build_dep = build_filter.filter(package)?;
+            let is_runtime_dep = runtime_filter.filter(package)?;
+            if !is_build_dep && !is_runtime_dep {
+                continue;
+            }
+
+            let marker = if is_runtime_dep { "" } else { "*" };
+            let label = format!("{}{} {}", marker, package.name(), package.version());
+            if path.contains(package.name()) {
+                children.push(ReverseDependencyTree {
+                    label: format!("{label} (cycle)"),
+                    children: Vec::new(),
+                });
+                continue;
+            }
+
+            path.push(package.name().clone());
+            let grandchildren = dependents_of(
+                repo,
+                package.name(),
+                check_build_dep,
+                check_runtime_dep,
+                path,
+            )?;
+            path.pop();
+
+            children.push(ReverseDependencyTree {
+                label,
+                children: grandchildren,
+            });
+        }
+        Ok(children)
+    }
+
+    let mut path = vec![name.clone()];
+    let children = dependents_of(repo, &name, check_build_dep, check_runtime_dep, &mut path)?;
+    Ok(ReverseDependencyTree {
+        label: name.to_string(),
+        children,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::package::tests::package;
+    use crate::package::BuildDependency;
+    use crate::package::Dependencies;
+    use crate::package::Dependency;
+
+    fn leaf(label: &str) -> ReverseDependencyTree {
+        ReverseDependencyTree {
+            label: String::from(label),
+            children: vec![],
+        }
+    }
+
+    // a <-runtime- b <-build- c
+    // a <-runtime- d
+    fn repo() -> Repository {
+        let a = package("a", "1", "https://example.com/a.tar.gz", "h");
+        let mut b = package("b", "1", "https://example.com/b.tar.gz", "h");
+        b.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+            String::from("a =1"),
+        )));
+        let mut c = package("c", "2", "https://example.com/c.tar.gz", "h");
+        c.set_dependencies(Dependencies::with_build_dependencies(vec![
+            BuildDependency::Simple(String::from("b =1")),
+        ]));
+        let mut d = package("d", "3", "https://example.com/d.tar.gz", "h");
+        d.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+            String::from("a =1"),
+        )));
+
+        let mut map = BTreeMap::new();
+        for p in [a, b, c, d] {
+            map.insert((p.name().clone(), p.version().clone()), p);
+        }
+        Repository::from(map)
+    }
+
+    #[test]
+    fn test_reverse_dependency_tree() {
+        let tree =
+            reverse_dependency_tree(&repo(), PackageName::from(String::from("a")), true, true)
+                .unwrap();
+
+        assert_eq!(
+            tree,
+            ReverseDependencyTree {
+                label: String::from("a"),
+                children: vec![
+                    ReverseDependencyTree {
+                        label: String::from("b 1"),
+                        children: vec![leaf("*c 2")],
+                    },
+                    leaf("d 3"),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_reverse_dependency_tree_ascii() {
+        let tree =
+            reverse_dependency_tree(&repo(), PackageName::from(String::from("a")), true, true)
+                .unwrap();
+        let mut out = Vec::new();
+        ptree::write_tree_with(&tree, &mut out, &crate::ui::tree_print_config(true)).unwrap();
+
+        assert!(out.is_ascii());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a\n+-- b 1\n|   `-- *c 2\n`-- d 3\n"
+        );
+    }
+
+    #[test]
+    fn test_dependents_count() {
+        let repo = repo();
+        let name = PackageName::from(String::from("a"));
+        for (build, runtime) in [(true, true), (false, true), (true, false)] {
+            let dependents = dependents(&repo, &name, build, runtime).unwrap();
+            let tree = reverse_dependency_tree(&repo, name.clone(), build, runtime).unwrap();
+            assert_eq!(
+                crate::commands::output::render_count(dependents.len()),
+                format!("{}\n", tree.children.len())
+            );
+        }
+        assert_eq!(dependents(&repo, &name, true, false).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_reverse_dependency_tree_runtime_only() {
+        let tree =
+            reverse_dependency_tree(&repo(), PackageName::from(String::from("a")), false, true)
+                .unwrap();
+
+        assert_eq!(
+            tree,
+            ReverseDependencyTree {
+                label: String::from("a"),
+                children: vec![leaf("b 1"), leaf("d 3")],
+            }
+        );
+    }
+}
@@ -9,6 +9,11 @@
 //
 
 use std::ops::Deref;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use uuid::Uuid;
 
 use crate::config::NotValidatedConfiguration;
 
@@ -25,3 +30,83 @@ impl Deref for Configuration {
         &self.inner
     }
 }
+
+impl Configuration {
+    /// The staging directory of a submit, i.e. `staging_directory/<submit-uuid>`
+    ///
+    /// Fails if the configured staging directory doesn't exist. The directory of the submit itself
+    /// may not exist (yet).
+    pub fn staging_dir_for(&self, submit: &Uuid) -> Result<PathBuf> {
+        if !self.staging_directory().is_dir() {
+            return Err(anyhow!(
+                "Staging directory does not exist or does not point to directory: {}",
+                self.staging_directory().display()
+            ));
+        }
+        Ok(self
+            .staging_directory()
+            .join(submit.hyphenated().to_string()))
+    }
+
+    /// The directory of the release store `store`, i.e. `releases_directory/<store>`
+    ///
+    /// Fails if the store is not one of the `release_stores` or if the configured releases
+    /// directory doesn't exist.
+    pub fn releases_dir_for(&self, store: &str) -> Result<PathBuf> {
+        if !self.release_stores().iter().any(|s| s == store) {
+            return Err(anyhow!("Unknown release store name: {}", store));
+        }
+        if !self.releases_directory().is_dir() {
+            return Err(anyhow!(
+                "Release directory does not exist or does not point to directory: {}",
+                self.releases_directory().display()
+            ));
+        }
+        Ok(self.releases_directory().join(store))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::package::tests::temp_dir;
+
+    /// The example configuration with all directories in `dir`
+    pub fn configuration_in(dir: &Path) -> Configuration {
+        let mut config = config::Config::default();
+        config
+            .merge(config::File::with_name("config.toml").required(true))
+            .unwrap();
+        for key in ["log_dir", "releases_root", "staging", "source_cache"] {
+            config.set(key, dir.display().to_string()).unwrap();
+        }
+        config
+            .try_into::<NotValidatedConfiguration>()
+            .unwrap()
+            .validate()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_staging_and_releases_dirs() {
+        let dir = temp_dir();
+        let config = configuration_in(&dir);
+
+        let submit = Uuid::parse_str("f81d4fae-7dec-11d0-a765-00a0c91e6bf6").unwrap();
+        assert_eq!(
+            config.staging_dir_for(&submit).unwrap(),
+            dir.join("f81d4fae-7dec-11d0-a765-00a0c91e6bf6")
+        );
+
+        let store = config.release_stores()[0].clone();
+        assert_eq!(config.releases_dir_for(&store).unwrap(), dir.join(&store));
+        assert!(config.releases_dir_for("no-such-store").is_err());
+
+        // The directories don't exist anymore
+        drop(dir);
+        assert!(config.staging_dir_for(&submit).is_err());
+        assert!(config.releases_dir_for(&store).is_err());
+    }
+}
@@ -10,12 +10,13 @@
 
 use getset::CopyGetters;
 use getset::Getters;
+use schemars::JsonSchema;
 use serde::Deserialize;
 
 use crate::util::EnvironmentVariableName;
 
 /// The configuration for the containers
-#[derive(Debug, CopyGetters, Getters, Deserialize)]
+#[derive(Debug, CopyGetters, Getters, Deserialize, JsonSchema)]
 pub struct ContainerConfig {
     /// check environment names whether they're allowed
     #[getset(get_copy = "pub")]
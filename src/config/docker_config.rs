@@ -11,14 +11,18 @@
 use std::collections::HashMap;
 
 use getset::{CopyGetters, Getters};
+use schemars::JsonSchema;
 use serde::Deserialize;
 
+use crate::config::util::default_check_versions;
+use crate::config::util::default_docker_api_retries;
 use crate::config::Endpoint;
 use crate::config::EndpointName;
+use crate::config::RegistryAuth;
 use crate::util::docker::ContainerImage;
 
 /// Configuration of the Docker daemon interfacing functionality
-#[derive(Debug, Getters, CopyGetters, Deserialize)]
+#[derive(Debug, Getters, CopyGetters, Deserialize, JsonSchema)]
 pub struct DockerConfig {
     /// The required Docker version
     ///
@@ -42,9 +46,88 @@ pub struct DockerConfig {
     #[getset(get = "pub")]
     docker_api_versions: Option<Vec<String>>,
 
+    /// Whether the versions of the endpoints are checked against `docker_versions` and
+    /// `docker_api_versions`
+    #[serde(default = "default_check_versions")]
+    #[getset(get_copy = "pub")]
+    check_versions: bool,
+
     #[getset(get = "pub")]
     images: Vec<ContainerImage>,
 
     #[getset(get = "pub")]
     endpoints: HashMap<EndpointName, Endpoint>,
+
+    /// How often idempotent Docker API calls (e.g. creating a container or listing images) are
+    /// retried if they fail with a temporary error
+    #[serde(default = "default_docker_api_retries")]
+    #[getset(get_copy = "pub")]
+    docker_api_retries: u32,
+
+    /// The credentials for pulling images, keyed by the registry host
+    #[serde(default)]
+    #[getset(get = "pub")]
+    registry_auth: HashMap<String, RegistryAuth>,
+
+    /// How the build jobs are distributed over the endpoints
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    endpoint_scheduling: EndpointScheduling,
+
+    /// Whether the images are pulled on the endpoints before a build
+    ///
+    /// Can be overridden via the `--pull` flag of the build command.
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    image_pull_policy: ImagePullPolicy,
+}
+
+/// When the images are pulled on the endpoints before a build
+#[derive(
+    parse_display::Display,
+    parse_display::FromStr,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    PartialEq,
+    JsonSchema,
+)]
+#[display(style = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ImagePullPolicy {
+    /// Always pull the images
+    Always,
+
+    /// Pull the images that are not present on an endpoint
+    Missing,
+
+    /// Never pull the images, missing images are an error
+    #[default]
+    Never,
+}
+
+impl ImagePullPolicy {
+    /// The values that are accepted by the `--pull` flag
+    pub const VALUES: [&'static str; 3] = ["always", "missing", "never"];
+}
+
+/// The strategy for selecting the endpoint a build job runs on
+///
+/// Only endpoints that run less than `maxjobs` jobs are considered.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointScheduling {
+    /// Use the endpoints one after another
+    RoundRobin,
+
+    /// Use the endpoint with the least running containers (including containers that were not
+    /// started by butido)
+    #[default]
+    LeastContainers,
+
+    /// Use the first endpoint (ordered by name)
+    FirstAvailable,
 }
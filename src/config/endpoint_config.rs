@@ -9,9 +9,10 @@
 //
 
 use getset::{CopyGetters, Getters};
+use schemars::JsonSchema;
 use serde::Deserialize;
 
-#[derive(Debug, Clone, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, JsonSchema)]
 #[serde(transparent)]
 pub struct EndpointName(String);
 
@@ -34,7 +35,7 @@ impl AsRef<str> for EndpointName {
 }
 
 /// Configuration of a single endpoint
-#[derive(Clone, Debug, Getters, CopyGetters, Deserialize)]
+#[derive(Clone, Debug, Getters, CopyGetters, Deserialize, JsonSchema)]
 pub struct Endpoint {
     /// The URI where the endpoint is reachable
     #[getset(get = "pub")]
@@ -48,6 +49,13 @@ pub struct Endpoint {
     #[getset(get_copy = "pub")]
     maxjobs: usize,
 
+    /// Maximum number of build containers which are allowed on this endpoint at the same time
+    ///
+    /// Jobs are only scheduled on the endpoint while a slot is free. Unlimited if not set, must
+    /// not be zero.
+    #[getset(get_copy = "pub")]
+    max_containers: Option<usize>,
+
     #[getset(get = "pub")]
     network_mode: Option<String>,
 
@@ -57,7 +65,7 @@ pub struct Endpoint {
 }
 
 /// The type of an endpoint
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, JsonSchema)]
 pub enum EndpointType {
     #[serde(rename = "socket")]
     Socket,
@@ -35,4 +35,13 @@ pub use endpoint_config::*;
 mod not_validated;
 pub use not_validated::*;
 
+mod registry_auth_config;
+pub use registry_auth_config::*;
+
+mod repo_lint_config;
+pub use repo_lint_config::*;
+
+mod source_auth_config;
+pub use source_auth_config::*;
+
 mod util;
@@ -12,22 +12,41 @@ use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use getset::Getters;
+use schemars::schema::RootSchema;
+use schemars::JsonSchema;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use crate::config::util::*;
 use crate::config::Configuration;
 use crate::config::ContainerConfig;
 use crate::config::DockerConfig;
+use crate::config::LintSeverity;
+use crate::config::RepoLintRule;
+use crate::config::SourceAuth;
+use crate::package::DependencyConflictPolicy;
 use crate::package::PhaseName;
+use crate::source::CacheLayout;
 
 // The configuration version must be increased each time breaking configuration changes are made
 // (that require users to update their configurations) and the required changes must be documented
 // in CHANGELOG.toml:
 const CONFIGURATION_VERSION: u16 = 1;
 
+/// The themes that are allowed for `script_highlight_theme` (from syntect)
+pub const SCRIPT_HIGHLIGHT_THEMES: [&str; 7] = [
+    "base16-ocean.dark",
+    "base16-eighties.dark",
+    "base16-mocha.dark",
+    "base16-ocean.light",
+    "InspiredGitHub",
+    "Solarized (dark)",
+    "Solarized (light)",
+];
+
 /// The configuration that is loaded from the filesystem
-#[derive(Debug, Getters, Deserialize)]
+#[derive(Debug, Getters, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct NotValidatedConfiguration {
     /// Compatibility setting to check if the butido configuration from the user is compatible with
@@ -37,6 +56,16 @@ pub struct NotValidatedConfiguration {
     #[getset(get = "pub")]
     compatibility: u16,
 
+    /// The profile that was applied, see `apply_profile()`
+    #[serde(default)]
+    #[getset(get = "pub")]
+    profile: Option<String>,
+
+    /// Named sets of settings that override the other settings if the profile is selected
+    #[serde(default)]
+    #[getset(get = "pub")]
+    profiles: HashMap<String, serde_json::Value>,
+
     /// The directory logs are written to, if logs are requested in plaintext files
     #[getset(get = "pub")]
     log_dir: PathBuf,
@@ -55,7 +84,6 @@ pub struct NotValidatedConfiguration {
     /// The format of the spinners in the CLI
     #[serde(default = "default_spinner_format")]
     #[getset(get = "pub")]
-    #[allow(unused)]
     spinner_format: String,
 
     /// The format used to print a package
@@ -70,6 +98,31 @@ pub struct NotValidatedConfiguration {
     #[getset(get = "pub")]
     build_error_lines: usize,
 
+    /// How deep the dependencies of a package may be nested, to stop on pathological dependency
+    /// graphs
+    #[serde(default = "default_max_dependency_depth")]
+    #[getset(get = "pub")]
+    max_dependency_depth: usize,
+
+    /// How several versions of the same package in the dependency DAG of a package are handled
+    #[serde(default)]
+    #[getset(get = "pub")]
+    dependency_conflict_policy: DependencyConflictPolicy,
+
+    /// A command that is run when a build finishes, see `--notify-command`
+    #[getset(get = "pub")]
+    notify_command: Option<String>,
+
+    /// A URL the result of a build is posted to when the build finishes, see `--notify-webhook`
+    #[getset(get = "pub")]
+    notify_webhook: Option<url::Url>,
+
+    /// The default number of jobs for everything that runs in parallel (e.g. hashing sources)
+    ///
+    /// Can be overridden via the global `--jobs` CLI flag. Defaults to the number of CPUs.
+    #[getset(get = "pub")]
+    parallelism: Option<usize>,
+
     /// The theme used to highlight scripts when printing them to the CLI
     #[getset(get = "pub")]
     script_highlight_theme: Option<String>,
@@ -100,11 +153,72 @@ pub struct NotValidatedConfiguration {
     #[getset(get = "pub")]
     staging_directory: PathBuf,
 
+    /// Whether the staging directories of all builds are protected from `staging gc`, see the
+    /// `--staging-keep` flag
+    #[serde(default)]
+    #[getset(get = "pub")]
+    keep_staging: bool,
+
     /// Where the sources are cached
     #[serde(rename = "source_cache")]
     #[getset(get = "pub")]
     source_cache_root: PathBuf,
 
+    /// How the sources are stored in the source cache
+    #[serde(default, rename = "cache_layout")]
+    #[getset(get = "pub")]
+    source_cache_layout: CacheLayout,
+
+    /// Whether large source files are memory-mapped for hashing instead of read with buffered reads
+    #[serde(default)]
+    #[getset(get = "pub")]
+    hash_use_mmap: bool,
+
+    /// If set, `tree-of` caches the package DAGs in this directory
+    #[getset(get = "pub")]
+    dag_cache_dir: Option<PathBuf>,
+
+    /// Whether symlinks are followed when loading the package repository
+    #[serde(default)]
+    #[getset(get = "pub")]
+    repo_follow_symlinks: bool,
+
+    /// Whether package names are looked up case-insensitively in the repository
+    #[serde(default)]
+    #[getset(get = "pub")]
+    case_insensitive_package_names: bool,
+
+    /// Whether trees are drawn with ASCII characters only, see the `--ascii` flags
+    #[serde(default)]
+    #[getset(get = "pub")]
+    ascii_trees: bool,
+
+    /// If set, sources may only be downloaded from these domains (or their subdomains)
+    #[getset(get = "pub")]
+    allowed_source_domains: Option<Vec<String>>,
+
+    /// Sources must not be downloaded from these domains (or their subdomains)
+    #[getset(get = "pub")]
+    denied_source_domains: Option<Vec<String>>,
+
+    /// Credentials for downloading sources, keyed by host
+    #[getset(get = "pub")]
+    source_auth: Option<HashMap<String, SourceAuth>>,
+
+    /// Whether the credentials for downloading sources are also looked up in the netrc file
+    #[serde(default)]
+    #[getset(get = "pub")]
+    use_netrc: bool,
+
+    /// Whether sources that are fetched via plain http are an error instead of a warning
+    #[serde(default)]
+    #[getset(get = "pub")]
+    forbid_insecure_sources: bool,
+
+    /// Overrides of the severities of the `repo lint` rules
+    #[getset(get = "pub")]
+    repo_lint_severity: Option<HashMap<RepoLintRule, LintSeverity>>,
+
     /// The hostname used to connect to the database
     #[getset(get = "pub")]
     #[serde(rename = "database_host")]
@@ -135,6 +249,19 @@ pub struct NotValidatedConfiguration {
     #[serde(rename = "database_connection_timeout")]
     database_connection_timeout: Option<u16>,
 
+    /// The user of a read-only role, used by `db cli --read-only`
+    #[getset(get = "pub")]
+    database_readonly_user: Option<String>,
+
+    /// The password of the read-only role
+    #[getset(get = "pub")]
+    database_readonly_password: Option<String>,
+
+    /// The programs `db cli` tries to use, in this order
+    #[serde(default = "default_db_cli_tools")]
+    #[getset(get = "pub")]
+    db_cli_tools: Vec<String>,
+
     #[getset(get = "pub")]
     docker: DockerConfig,
 
@@ -142,6 +269,11 @@ pub struct NotValidatedConfiguration {
     #[getset(get = "pub")]
     containers: ContainerConfig,
 
+    /// Whether the build containers are labeled with the package, version, submit, and job
+    #[serde(default = "default_container_labels")]
+    #[getset(get = "pub")]
+    container_labels: bool,
+
     /// The names of the phases which should be compiled into the packaging script
     #[getset(get = "pub")]
     available_phases: Vec<PhaseName>,
@@ -154,6 +286,72 @@ fn load_changelog() -> Result<std::collections::HashMap<String, String>> {
     toml::from_str(changelog_toml).context("Butido bug: Couldn't parse the embedded CHANGELOG.toml")
 }
 
+/// The JSON Schema of the butido configuration
+///
+/// The "compatibility" setting is restricted to the configuration version of this butido binary.
+pub fn configuration_schema() -> RootSchema {
+    let mut schema = schemars::schema_for!(NotValidatedConfiguration);
+    let metadata = schema.schema.metadata();
+    metadata.title = Some(String::from("butido configuration"));
+    metadata.description = Some(format!(
+        "The configuration of butido {} (configuration version {})",
+        env!("CARGO_PKG_VERSION"),
+        CONFIGURATION_VERSION
+    ));
+
+    let compatibility = schema
+        .schema
+        .object
+        .as_mut()
+        .and_then(|object| object.properties.get_mut("compatibility"));
+    if let Some(schemars::schema::Schema::Object(compatibility)) = compatibility {
+        compatibility.const_value = Some(serde_json::Value::from(CONFIGURATION_VERSION));
+    }
+    schema
+}
+
+/// Apply the settings of the profile `name` from the "profiles" table, before loading (type
+/// checking) the configuration
+///
+/// The profile settings override the settings that were merged into `config` so far, tables are
+/// merged (e.g. a profile can override a single docker endpoint).
+pub fn apply_profile(config: &mut config::Config, name: &str) -> Result<()> {
+    let mut profiles = match config.get_table("profiles") {
+        Ok(profiles) => profiles,
+        Err(config::ConfigError::NotFound(_)) => HashMap::new(),
+        Err(e) => return Err(e).context("Loading the \"profiles\" setting"),
+    };
+    let settings = match profiles.remove(name) {
+        Some(settings) => settings
+            .into_table()
+            .with_context(|| anyhow!("Loading the settings of profile '{}'", name))?,
+        None => {
+            let mut available = profiles.keys().map(String::as_str).collect::<Vec<_>>();
+            available.sort();
+            return Err(anyhow!(
+                "Unknown configuration profile '{}', available profiles: {}",
+                name,
+                if available.is_empty() {
+                    String::from("none")
+                } else {
+                    available.join(", ")
+                }
+            ));
+        }
+    };
+
+    let mut profile_config = config::Config::default();
+    for (key, value) in settings {
+        if ["compatibility", "profile", "profiles"].contains(&key.as_str()) {
+            return Err(anyhow!("Profile '{}' must not set \"{}\"", name, key));
+        }
+        profile_config.set(&key, value)?;
+    }
+    config.merge(profile_config)?;
+    config.set("profile", name)?;
+    Ok(())
+}
+
 // Helper function to check if the configuration should be compatible before loading (type checking) it:
 pub fn check_compatibility(config: &config::Config) -> Result<()> {
     // We don't use config.get_int() as it is petty lax and, e.g., converts `true` to `1`:
@@ -220,6 +418,14 @@ impl NotValidatedConfiguration {
     pub fn validate(self) -> Result<Configuration> {
         self.validate_config(false)
     }
+
+    /// Validate the configuration without checking that the configured directories exist
+    ///
+    /// Used by `doctor`, which checks the directories itself.
+    pub fn validate_without_filesystem_checks(self) -> Result<Configuration> {
+        self.validate_config(true)
+    }
+
     fn validate_config(self, skip_filesystem_checks: bool) -> Result<Configuration> {
         // A trivial helper to check if a directory is missing:
         let check_directory_exists = |path: &PathBuf, config_key_name: &str| -> Result<()> {
@@ -247,6 +453,12 @@ impl NotValidatedConfiguration {
         check_directory_exists(&self.staging_directory, "staging")?;
         check_directory_exists(&self.source_cache_root, "source_cache")?;
 
+        if let Some(profile) = self.profile.as_ref() {
+            if !self.profiles.contains_key(profile) {
+                return Err(anyhow!("Unknown configuration profile '{}'", profile));
+            }
+        }
+
         if self.release_stores.is_empty() {
             return Err(anyhow!(
                 "You need at least one release store in 'release_stores'"
@@ -258,20 +470,42 @@ impl NotValidatedConfiguration {
             return Err(anyhow!("No phases configured"));
         }
 
+        // A semaphore without permits would block all builds on the endpoint forever
+        if let Some(name) = self
+            .docker
+            .endpoints()
+            .iter()
+            .find(|(_, ep)| ep.max_containers() == Some(0))
+            .map(|(name, _)| name)
+        {
+            return Err(anyhow!(
+                "'max_containers' of endpoint {} must be greater than zero",
+                name
+            ));
+        }
+
+        if self.parallelism == Some(0) {
+            return Err(anyhow!("'parallelism' must be greater than zero"));
+        }
+
+        for (setting, domains) in [
+            ("allowed_source_domains", &self.allowed_source_domains),
+            ("denied_source_domains", &self.denied_source_domains),
+        ] {
+            if domains
+                .iter()
+                .flatten()
+                .any(|d| d.trim_start_matches('.').is_empty())
+            {
+                return Err(anyhow!("'{}' must not contain empty domains", setting));
+            }
+        }
+
         // Error if script highlighting theme is not valid
         if let Some(configured_theme) = self.script_highlight_theme.as_ref() {
-            let allowed_theme_present = [
-                // from syntect
-                "base16-ocean.dark",
-                "base16-eighties.dark",
-                "base16-mocha.dark",
-                "base16-ocean.light",
-                "InspiredGitHub",
-                "Solarized (dark)",
-                "Solarized (light)",
-            ]
-            .iter()
-            .any(|allowed_theme| configured_theme == *allowed_theme);
+            let allowed_theme_present = SCRIPT_HIGHLIGHT_THEMES
+                .iter()
+                .any(|allowed_theme| configured_theme == *allowed_theme);
 
             if !allowed_theme_present {
                 return Err(anyhow!("Theme not known: {}", configured_theme));
@@ -284,7 +518,9 @@ impl NotValidatedConfiguration {
 
 #[cfg(test)]
 mod tests {
+    use super::apply_profile;
     use super::check_compatibility;
+    use super::configuration_schema;
     use super::load_changelog;
     use super::NotValidatedConfiguration;
     use super::CONFIGURATION_VERSION;
@@ -300,6 +536,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_configuration_schema() {
+        let json = serde_json::to_value(configuration_schema()).unwrap();
+        assert_eq!(json["additionalProperties"], false);
+
+        let required = json["required"].as_array().unwrap();
+        for key in [
+            "compatibility",
+            "staging",
+            "releases_root",
+            "release_stores",
+            "source_cache",
+            "docker",
+            "containers",
+        ] {
+            assert!(required.contains(&key.into()), "Not required: {key}");
+        }
+        for key in ["parallelism", "source_auth", "use_netrc"] {
+            assert!(!required.contains(&key.into()), "Required: {key}");
+        }
+
+        assert_eq!(
+            json["properties"]["compatibility"]["const"],
+            CONFIGURATION_VERSION
+        );
+    }
+
     // A helper function to load and validate butido configuration files:
     fn test_loading_configuration_file(file_path: &str) {
         let mut config = config::Config::default();
@@ -313,6 +576,90 @@ mod tests {
         assert!(config.is_ok(), "Config validation failed: {config:?}");
     }
 
+    #[test]
+    fn test_profiles() {
+        let load = |profile: Option<&str>| {
+            let mut config = config::Config::default();
+            config
+                .merge(config::File::with_name("config.toml").required(true))
+                .unwrap();
+            config
+                .merge(config::File::from_str(
+                    indoc::indoc!(
+                        r#"
+                        [profiles.prod]
+                        database_host = "db.prod.example.com"
+                        docker.check_versions = false
+
+                        [profiles.dev]
+                        database_name = "butido_dev"
+                        "#
+                    ),
+                    config::FileFormat::Toml,
+                ))
+                .unwrap();
+            if let Some(profile) = profile {
+                apply_profile(&mut config, profile)?;
+            }
+            config
+                .try_into::<NotValidatedConfiguration>()
+                .map_err(anyhow::Error::from)?
+                .validate_config(true)
+        };
+
+        let base = load(None).unwrap();
+        assert_eq!(base.database_host(), "localhost");
+        assert_eq!(base.profile(), &None);
+
+        let prod = load(Some("prod")).unwrap();
+        assert_eq!(prod.database_host(), "db.prod.example.com");
+        assert_eq!(prod.database_name(), base.database_name());
+        assert_eq!(prod.profile().as_deref(), Some("prod"));
+        // Tables are merged
+        assert!(base.docker().check_versions());
+        assert!(!prod.docker().check_versions());
+        assert_eq!(
+            prod.docker().endpoints().len(),
+            base.docker().endpoints().len()
+        );
+
+        let dev = load(Some("dev")).unwrap();
+        assert_eq!(dev.database_host(), "localhost");
+        assert_eq!(dev.database_name(), "butido_dev");
+
+        let err = load(Some("qa")).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Unknown configuration profile 'qa', available profiles: dev, prod"
+        );
+    }
+
+    #[test]
+    fn test_max_containers_must_not_be_zero() {
+        let validate = |max_containers: usize| {
+            let mut config = config::Config::default();
+            config
+                .merge(config::File::with_name("config.toml").required(true))
+                .unwrap();
+            config
+                .set(
+                    "docker.endpoints.testhostname.max_containers",
+                    max_containers as i64,
+                )
+                .unwrap();
+            config
+                .try_into::<NotValidatedConfiguration>()
+                .unwrap()
+                .validate_config(true)
+        };
+
+        assert!(validate(1).is_ok());
+        assert_eq!(
+            validate(0).unwrap_err().to_string(),
+            "'max_containers' of endpoint testhostname must be greater than zero"
+        );
+    }
+
     #[test]
     // A test to ensure the example configuration file is up-to-date and valid
     fn test_loading_example_configuration_file() {
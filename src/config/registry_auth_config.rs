@@ -0,0 +1,140 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::config::source_auth_config::interpolate_env;
+use crate::util::docker::ImageName;
+
+/// The credentials that are used to pull images from a registry
+///
+/// All values may reference environment variables as `${NAME}`, which are resolved when the
+/// credentials are used.
+#[derive(Clone, Deserialize, Eq, PartialEq, JsonSchema)]
+#[serde(tag = "type")]
+pub enum RegistryAuth {
+    #[serde(rename = "basic")]
+    Basic { username: String, password: String },
+
+    #[serde(rename = "token")]
+    Token { token: String },
+}
+
+/// Never print the credentials
+impl std::fmt::Debug for RegistryAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryAuth::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"***")
+                .finish(),
+            RegistryAuth::Token { .. } => f.debug_struct("Token").field("token", &"***").finish(),
+        }
+    }
+}
+
+impl RegistryAuth {
+    /// The credentials in the form the Docker API expects them for logging in to `registry`
+    pub fn docker_auth(&self, registry: &str) -> Result<shiplift::RegistryAuth> {
+        match self {
+            RegistryAuth::Basic { username, password } => Ok(shiplift::RegistryAuth::builder()
+                .username(interpolate_env(username).context("Resolving the username")?)
+                .password(interpolate_env(password).context("Resolving the password")?)
+                .server_address(registry)
+                .build()),
+            RegistryAuth::Token { token } => Ok(shiplift::RegistryAuth::token(
+                interpolate_env(token).context("Resolving the registry token")?,
+            )),
+        }
+    }
+}
+
+/// Normalize a registry host, so that the different names of Docker Hub are matched
+fn normalize_registry(registry: &str) -> String {
+    match registry.to_lowercase().as_str() {
+        "index.docker.io" | "registry-1.docker.io" => String::from("docker.io"),
+        other => other.to_string(),
+    }
+}
+
+/// The configured credentials for the registry the image is pulled from
+///
+/// Returns the configured registry host along with the credentials.
+pub fn registry_auth_for<'a>(
+    registry_auth: &'a HashMap<String, RegistryAuth>,
+    image: &ImageName,
+) -> Option<(&'a str, &'a RegistryAuth)> {
+    let registry = normalize_registry(image.registry_host());
+    registry_auth
+        .iter()
+        .find(|(host, _)| normalize_registry(host) == registry)
+        .map(|(host, auth)| (host.as_str(), auth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(username: &str) -> RegistryAuth {
+        RegistryAuth::Basic {
+            username: String::from(username),
+            password: String::from("secret"),
+        }
+    }
+
+    #[test]
+    fn test_registry_host_matching() {
+        let registry_auth = HashMap::from([
+            (String::from("registry.example.com"), basic("example")),
+            (String::from("Registry.Example.com:5000"), basic("port")),
+            (String::from("localhost"), basic("local")),
+            (String::from("docker.io"), basic("hub")),
+        ]);
+        let username =
+            |image: &str| match registry_auth_for(&registry_auth, &ImageName::from(image)) {
+                Some((_, RegistryAuth::Basic { username, .. })) => Some(username.clone()),
+                _ => None,
+            };
+
+        assert_eq!(
+            username("registry.example.com/base/debian:bullseye"),
+            Some(String::from("example"))
+        );
+        assert_eq!(
+            username("registry.example.com:5000/debian"),
+            Some(String::from("port"))
+        );
+        assert_eq!(username("localhost/debian"), Some(String::from("local")));
+        assert_eq!(username("debian:bullseye"), Some(String::from("hub")));
+        assert_eq!(username("library/debian"), Some(String::from("hub")));
+        assert_eq!(
+            username("index.docker.io/library/debian"),
+            Some(String::from("hub"))
+        );
+        assert_eq!(username("other.example.com/debian"), None);
+        assert_eq!(username("sub.registry.example.com/debian"), None);
+    }
+
+    #[test]
+    fn test_debug_masks_credentials() {
+        let token = RegistryAuth::Token {
+            token: String::from("secret"),
+        };
+        for auth in [basic("butido"), token] {
+            assert!(!format!("{auth:?}").contains("secret"));
+        }
+    }
+}
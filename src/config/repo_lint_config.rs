@@ -0,0 +1,76 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// A rule that is checked by `repo lint`
+#[derive(
+    parse_display::Display, Clone, Copy, Debug, Deserialize, Eq, PartialEq, Hash, JsonSchema,
+)]
+pub enum RepoLintRule {
+    /// A source has an empty hash
+    #[serde(rename = "missing_hash")]
+    #[display("missing_hash")]
+    MissingHash,
+
+    /// A source is fetched via plain http
+    #[serde(rename = "insecure_source")]
+    #[display("insecure_source")]
+    InsecureSource,
+
+    /// A package defines a phase that is not in `available_phases`
+    #[serde(rename = "unknown_phase")]
+    #[display("unknown_phase")]
+    UnknownPhase,
+
+    /// Two sources of a package have names that only differ in case
+    #[serde(rename = "duplicate_source_name")]
+    #[display("duplicate_source_name")]
+    DuplicateSourceName,
+
+    /// A build or runtime dependency is an empty string
+    #[serde(rename = "empty_dependency")]
+    #[display("empty_dependency")]
+    EmptyDependency,
+}
+
+impl RepoLintRule {
+    /// The severity of the rule if it is not configured via `repo_lint_severity`
+    pub fn default_severity(&self) -> LintSeverity {
+        match self {
+            RepoLintRule::InsecureSource => LintSeverity::Warn,
+            _ => LintSeverity::Error,
+        }
+    }
+}
+
+/// The severity of a `repo lint` rule
+#[derive(
+    parse_display::Display,
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    JsonSchema,
+)]
+pub enum LintSeverity {
+    #[serde(rename = "warn")]
+    #[display("warn")]
+    Warn,
+
+    #[serde(rename = "error")]
+    #[display("error")]
+    Error,
+}
@@ -0,0 +1,145 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// The credentials that are used to download sources from a host
+///
+/// All values may reference environment variables as `${NAME}`, which are resolved when the
+/// credentials are used.
+#[derive(Clone, Deserialize, Eq, PartialEq, JsonSchema)]
+#[serde(tag = "type")]
+pub enum SourceAuth {
+    #[serde(rename = "basic")]
+    Basic { username: String, password: String },
+
+    #[serde(rename = "bearer")]
+    Bearer { token: String },
+}
+
+/// Never print the credentials
+impl std::fmt::Debug for SourceAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceAuth::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"***")
+                .finish(),
+            SourceAuth::Bearer { .. } => f.debug_struct("Bearer").field("token", &"***").finish(),
+        }
+    }
+}
+
+impl SourceAuth {
+    /// Add the credentials as "Authorization" header to the request
+    ///
+    /// The header is marked as sensitive, so it does not show up in debug output.
+    pub fn authorize(&self, request: reqwest::RequestBuilder) -> Result<reqwest::RequestBuilder> {
+        match self {
+            SourceAuth::Basic { username, password } => Ok(request.basic_auth(
+                interpolate_env(username).context("Resolving the username")?,
+                Some(interpolate_env(password).context("Resolving the password")?),
+            )),
+            SourceAuth::Bearer { token } => {
+                Ok(request
+                    .bearer_auth(interpolate_env(token).context("Resolving the bearer token")?))
+            }
+        }
+    }
+}
+
+/// Replace all `${NAME}` in `value` with the value of the environment variable `NAME`
+///
+/// The error messages never contain the value itself, because it is a credential.
+pub(crate) fn interpolate_env(value: &str) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| anyhow!("Missing '}}' after '${{'"))?;
+        let name = &rest[start + 2..end];
+        let var = std::env::var(name)
+            .with_context(|| anyhow!("Environment variable '{}' is not set", name))?;
+        result.push_str(&var);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_deserialize_source_auth() {
+        let toml = r#"
+            [source_auth."artifacts.example.com"]
+            type = "basic"
+            username = "butido"
+            password = "${PASSWORD}"
+
+            [source_auth."private.example.com"]
+            type = "bearer"
+            token = "secret"
+        "#;
+        let auth = toml::from_str::<HashMap<String, HashMap<String, SourceAuth>>>(toml).unwrap();
+        let auth = &auth["source_auth"];
+        assert_eq!(
+            auth["artifacts.example.com"],
+            SourceAuth::Basic {
+                username: String::from("butido"),
+                password: String::from("${PASSWORD}"),
+            }
+        );
+        assert_eq!(
+            auth["private.example.com"],
+            SourceAuth::Bearer {
+                token: String::from("secret"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_debug_masks_credentials() {
+        let basic = SourceAuth::Basic {
+            username: String::from("butido"),
+            password: String::from("secret"),
+        };
+        let bearer = SourceAuth::Bearer {
+            token: String::from("secret"),
+        };
+        for auth in [basic, bearer] {
+            assert!(!format!("{auth:?}").contains("secret"));
+        }
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        std::env::set_var("BUTIDO_TEST_SOURCE_AUTH_TOKEN", "secret");
+        assert_eq!(
+            interpolate_env("a-${BUTIDO_TEST_SOURCE_AUTH_TOKEN}-b").unwrap(),
+            "a-secret-b"
+        );
+        assert_eq!(interpolate_env("plain").unwrap(), "plain");
+        assert!(interpolate_env("${BUTIDO_TEST_SOURCE_AUTH_UNSET}").is_err());
+        assert!(interpolate_env("${BUTIDO_TEST_SOURCE_AUTH_TOKEN").is_err());
+    }
+}
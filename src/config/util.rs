@@ -115,3 +115,28 @@ pub fn default_script_shebang() -> String {
 pub fn default_build_error_lines() -> usize {
     10
 }
+
+/// The default value for how deep the dependencies of a package may be nested
+pub fn default_max_dependency_depth() -> usize {
+    100
+}
+
+/// The default programs for `db cli`
+pub fn default_db_cli_tools() -> Vec<String> {
+    vec![String::from("psql"), String::from("pgcli")]
+}
+
+/// The default value for whether the build containers should be labeled
+pub fn default_container_labels() -> bool {
+    true
+}
+
+/// The default value for how often Docker API calls are retried
+pub fn default_docker_api_retries() -> u32 {
+    3
+}
+
+/// The default value for whether the Docker versions of the endpoints are checked
+pub fn default_check_versions() -> bool {
+    true
+}
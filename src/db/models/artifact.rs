@@ -51,7 +51,7 @@ impl Artifact {
         release_store_name: &str,
     ) -> Result<crate::db::models::Release> {
         let rs = crate::db::models::ReleaseStore::create(database_connection, release_store_name)?;
-        crate::db::models::Release::create(database_connection, &self, release_date, &rs)
+        crate::db::models::Release::create(database_connection, &self, release_date, &rs, None)
     }
 
     pub fn get_release(&self, database_connection: &mut PgConnection) -> Result<Option<Release>> {
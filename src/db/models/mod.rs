@@ -40,3 +40,6 @@ pub use release_store::*;
 
 mod submit;
 pub use submit::*;
+
+mod submit_env;
+pub use submit_env::*;
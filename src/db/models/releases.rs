@@ -27,6 +27,7 @@ pub struct Release {
     pub artifact_id: i32,
     pub release_date: NaiveDateTime,
     pub release_store_id: i32,
+    pub tag: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -35,6 +36,7 @@ struct NewRelease<'a> {
     pub artifact_id: i32,
     pub release_date: &'a NaiveDateTime,
     pub release_store_id: i32,
+    pub tag: Option<&'a str>,
 }
 
 impl Release {
@@ -43,11 +45,13 @@ impl Release {
         art: &Artifact,
         date: &'a NaiveDateTime,
         store: &'a ReleaseStore,
+        release_tag: Option<&'a str>,
     ) -> Result<Release> {
         let new_rel = NewRelease {
             artifact_id: art.id,
             release_date: date,
             release_store_id: store.id,
+            tag: release_tag,
         };
 
         database_connection.transaction::<_, Error, _>(|conn| {
@@ -15,9 +15,11 @@ use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::PgConnection;
 
+use crate::db::models::EnvVar;
 use crate::db::models::GitHash;
 use crate::db::models::Image;
 use crate::db::models::Package;
+use crate::package::SerializedDag;
 use crate::schema::submits;
 use crate::schema::submits::*;
 
@@ -32,6 +34,9 @@ pub struct Submit {
     pub requested_image_id: i32,
     pub requested_package_id: i32,
     pub repo_hash_id: i32,
+
+    /// The package DAG of the submit, as `SerializedDag` (not set for submits of old versions)
+    pub tree: Option<serde_json::Value>,
 }
 
 #[derive(Insertable)]
@@ -42,6 +47,7 @@ struct NewSubmit<'a> {
     pub requested_image_id: i32,
     pub requested_package_id: i32,
     pub repo_hash_id: i32,
+    pub tree: serde_json::Value,
 }
 
 impl Submit {
@@ -52,6 +58,7 @@ impl Submit {
         requested_image: &Image,
         requested_package: &Package,
         repo_hash: &GitHash,
+        package_tree: &SerializedDag,
     ) -> Result<Submit> {
         let new_submit = NewSubmit {
             uuid: submit_id,
@@ -59,6 +66,7 @@ impl Submit {
             requested_image_id: requested_image.id,
             requested_package_id: requested_package.id,
             repo_hash_id: repo_hash.id,
+            tree: package_tree.to_json()?,
         };
 
         database_connection.transaction::<_, Error, _>(|conn| {
@@ -83,4 +91,16 @@ impl Submit {
             .context("Loading submit")
             .map_err(Error::from)
     }
+
+    /// The additional environment variables the submit was started with
+    pub fn env(&self, database_connection: &mut PgConnection) -> Result<Vec<EnvVar>> {
+        use crate::schema;
+
+        schema::submit_envs::table
+            .inner_join(schema::envvars::table)
+            .filter(schema::submit_envs::submit_id.eq(self.id))
+            .select(schema::envvars::all_columns)
+            .load::<EnvVar>(database_connection)
+            .map_err(Error::from)
+    }
 }
@@ -0,0 +1,54 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::db::models::EnvVar;
+use crate::db::models::Submit;
+use crate::schema::submit_envs;
+
+#[derive(Identifiable, Queryable, Associations)]
+#[diesel(belongs_to(Submit))]
+#[diesel(belongs_to(EnvVar, foreign_key = env_id))]
+#[diesel(table_name = submit_envs)]
+pub struct SubmitEnv {
+    pub id: i32,
+    pub submit_id: i32,
+    pub env_id: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = submit_envs)]
+struct NewSubmitEnv {
+    pub submit_id: i32,
+    pub env_id: i32,
+}
+
+impl SubmitEnv {
+    pub fn create(
+        database_connection: &mut PgConnection,
+        submit: &Submit,
+        env: &EnvVar,
+    ) -> Result<()> {
+        let new_submitenv = NewSubmitEnv {
+            submit_id: submit.id,
+            env_id: env.id,
+        };
+
+        diesel::insert_into(submit_envs::table)
+            .values(&new_submitenv)
+            // the submit already has the variable if the staging store is re-used
+            .on_conflict_do_nothing()
+            .execute(database_connection)?;
+        Ok(())
+    }
+}
@@ -8,6 +8,8 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::HashMap;
+
 use getset::Getters;
 use typed_builder::TypedBuilder;
 
@@ -21,6 +23,8 @@ pub struct EndpointConfiguration {
     #[getset(get = "pub")]
     endpoint: crate::config::Endpoint,
 
+    /// The images that have to be available on the endpoint (or are pulled, see
+    /// `image_pull_policy`), the setup fails if one is missing
     #[getset(get = "pub")]
     #[builder(default)]
     required_images: Vec<ImageName>,
@@ -32,4 +36,24 @@ pub struct EndpointConfiguration {
     #[getset(get = "pub")]
     #[builder(default)]
     required_docker_api_versions: Option<Vec<String>>,
+
+    /// Whether the required images are pulled
+    #[getset(get = "pub")]
+    #[builder(default)]
+    image_pull_policy: crate::config::ImagePullPolicy,
+
+    /// The credentials for pulling the required images, keyed by the registry host
+    #[getset(get = "pub")]
+    #[builder(default)]
+    registry_auth: HashMap<String, crate::config::RegistryAuth>,
+
+    /// The platform the required images must be for, the native platform of the endpoint if not set
+    #[getset(get = "pub")]
+    #[builder(default)]
+    platform: Option<crate::util::docker::Platform>,
+
+    /// How often idempotent Docker API calls are retried
+    #[getset(get = "pub")]
+    #[builder(default)]
+    api_retries: u32,
 }
@@ -8,6 +8,7 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -24,13 +25,16 @@ use result_inspect::ResultInspect;
 use shiplift::Container;
 use shiplift::Docker;
 use shiplift::ExecContainerOptions;
+use shiplift::RmContainerOptions;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::RwLock;
 use tokio_stream::StreamExt;
-use tracing::{debug, trace};
+use tracing::{debug, info, trace, warn};
 use typed_builder::TypedBuilder;
 
 use crate::config::EndpointName;
+use crate::config::EndpointType;
+use crate::config::ImagePullPolicy;
 use crate::endpoint::EndpointConfiguration;
 use crate::filestore::path::ArtifactPath;
 use crate::filestore::ReleaseStore;
@@ -39,9 +43,13 @@ use crate::job::JobResource;
 use crate::job::RunnableJob;
 use crate::log::buffer_stream_to_line_stream;
 use crate::log::LogItem;
+use crate::package::PackageName;
+use crate::package::PackageVersion;
 use crate::package::Script;
+use crate::util::docker::with_retries;
 use crate::util::docker::ContainerHash;
 use crate::util::docker::ImageName;
+use crate::util::docker::Platform;
 
 #[derive(Getters, CopyGetters, TypedBuilder)]
 pub struct Endpoint {
@@ -60,8 +68,22 @@ pub struct Endpoint {
     #[getset(get = "pub")]
     uri: String,
 
+    endpoint_type: EndpointType,
+
+    /// The platform the images are pulled and the containers are created for, the native
+    /// platform of the endpoint if not set
+    #[builder(default)]
+    platform: Option<Platform>,
+
     #[builder(default)]
     running_jobs: std::sync::atomic::AtomicUsize,
+
+    #[getset(get = "pub")]
+    container_slots: ContainerSlots,
+
+    /// How often idempotent Docker API calls are retried
+    #[builder(default)]
+    api_retries: u32,
 }
 
 impl Debug for Endpoint {
@@ -72,20 +94,29 @@ impl Debug for Endpoint {
 
 impl Endpoint {
     pub(super) async fn setup(epc: EndpointConfiguration) -> Result<Self> {
-        let ep =
-            Endpoint::setup_endpoint(epc.endpoint_name(), epc.endpoint()).with_context(|| {
-                anyhow!(
-                    "Setting up endpoint: {} -> {}",
-                    epc.endpoint_name(),
-                    epc.endpoint().uri()
-                )
-            })?;
+        let ep = Endpoint::setup_endpoint(
+            epc.endpoint_name(),
+            epc.endpoint(),
+            *epc.api_retries(),
+            epc.platform().clone(),
+        )
+        .with_context(|| {
+            anyhow!(
+                "Setting up endpoint: {} -> {}",
+                epc.endpoint_name(),
+                epc.endpoint().uri()
+            )
+        })?;
 
         let versions_compat =
             Endpoint::check_version_compat(epc.required_docker_versions().as_ref(), &ep);
         let api_versions_compat =
             Endpoint::check_api_version_compat(epc.required_docker_api_versions().as_ref(), &ep);
-        let imgs_avail = Endpoint::check_images_available(epc.required_images().as_ref(), &ep);
+        let imgs_avail = Endpoint::images_to_pull(
+            epc.required_images().as_ref(),
+            *epc.image_pull_policy(),
+            &ep,
+        );
 
         let (versions_compat, api_versions_compat, imgs_avail) = {
             let timeout = std::time::Duration::from_secs(epc.endpoint().timeout().unwrap_or(10));
@@ -95,34 +126,63 @@ impl Endpoint {
             tokio::join!(versions_compat, api_versions_compat, imgs_avail)
         };
 
-        let _ = versions_compat.with_context(|| {
-            anyhow!(
-                "Checking version compatibility for {} -> {}",
-                epc.endpoint_name(),
-                epc.endpoint().uri()
-            )
-        })?;
-        let _ = api_versions_compat.with_context(|| {
-            anyhow!(
-                "Checking API version compatibility for {} -> {}",
-                epc.endpoint_name(),
-                epc.endpoint().uri()
-            )
-        })?;
-        let _ = imgs_avail.with_context(|| {
-            anyhow!(
-                "Checking for available images on {} -> {}",
-                epc.endpoint_name(),
-                epc.endpoint().uri()
-            )
-        })?;
+        versions_compat
+            .map_err(Error::from)
+            .and_then(|r| r)
+            .with_context(|| {
+                anyhow!(
+                    "Checking version compatibility for {} -> {}",
+                    epc.endpoint_name(),
+                    epc.endpoint().uri()
+                )
+            })?;
+        api_versions_compat
+            .map_err(Error::from)
+            .and_then(|r| r)
+            .with_context(|| {
+                anyhow!(
+                    "Checking API version compatibility for {} -> {}",
+                    epc.endpoint_name(),
+                    epc.endpoint().uri()
+                )
+            })?;
+        let imgs_to_pull = imgs_avail
+            .map_err(Error::from)
+            .and_then(|r| r)
+            .with_context(|| {
+                anyhow!(
+                    "Checking for available images on {} -> {}",
+                    epc.endpoint_name(),
+                    epc.endpoint().uri()
+                )
+            })?;
+
+        // Pulling can take a while, so it is not subject to the timeout
+        for img in imgs_to_pull {
+            let auth = crate::config::registry_auth_for(epc.registry_auth(), &img)
+                .map(|(registry, auth)| auth.docker_auth(registry))
+                .transpose()
+                .with_context(|| anyhow!("Resolving the registry credentials for '{}'", img))?;
+            ep.pull_image(&img, auth).await?;
+        }
+
+        if let Some(platform) = ep.platform.as_ref() {
+            for img in epc.required_images() {
+                ep.check_image_platform(img, platform).await?;
+            }
+        }
 
         Ok(ep)
     }
 
-    fn setup_endpoint(ep_name: &EndpointName, ep: &crate::config::Endpoint) -> Result<Endpoint> {
+    fn setup_endpoint(
+        ep_name: &EndpointName,
+        ep: &crate::config::Endpoint,
+        api_retries: u32,
+        platform: Option<Platform>,
+    ) -> Result<Endpoint> {
         match ep.endpoint_type() {
-            crate::config::EndpointType::Http => shiplift::Uri::from_str(ep.uri())
+            EndpointType::Http => shiplift::Uri::from_str(ep.uri())
                 .map(shiplift::Docker::host)
                 .with_context(|| anyhow!("Connecting to {}", ep.uri()))
                 .map_err(Error::from)
@@ -130,18 +190,26 @@ impl Endpoint {
                     Endpoint::builder()
                         .name(ep_name.clone())
                         .uri(ep.uri().clone())
+                        .endpoint_type(EndpointType::Http)
                         .docker(docker)
                         .num_max_jobs(ep.maxjobs())
                         .network_mode(ep.network_mode().clone())
+                        .container_slots(ContainerSlots::new(ep.max_containers()))
+                        .api_retries(api_retries)
+                        .platform(platform)
                         .build()
                 }),
 
-            crate::config::EndpointType::Socket => Ok({
+            EndpointType::Socket => Ok({
                 Endpoint::builder()
                     .name(ep_name.clone())
                     .uri(ep.uri().clone())
+                    .endpoint_type(EndpointType::Socket)
                     .num_max_jobs(ep.maxjobs())
                     .network_mode(ep.network_mode().clone())
+                    .container_slots(ContainerSlots::new(ep.max_containers()))
+                    .api_retries(api_retries)
+                    .platform(platform)
                     .docker(shiplift::Docker::unix(ep.uri()))
                     .build()
             }),
@@ -152,22 +220,11 @@ impl Endpoint {
         match req {
             None => Ok(()),
             Some(v) => {
-                let avail = ep
-                    .docker()
-                    .version()
+                let avail = with_retries(ep.api_retries, || ep.docker().version())
                     .await
                     .with_context(|| anyhow!("Getting version of endpoint: {}", ep.name))?;
 
-                if !v.contains(&avail.version) {
-                    Err(anyhow!(
-                        "Incompatible Docker version on endpoint {}: Expected: {}, Available: [{}]",
-                        ep.name(),
-                        avail.version,
-                        v.join(", ")
-                    ))
-                } else {
-                    Ok(())
-                }
+                check_version_allowed(ep.name(), "Docker version", &avail.version, v)
             }
         }
     }
@@ -176,86 +233,175 @@ impl Endpoint {
         match req {
             None => Ok(()),
             Some(v) => {
-                let avail = ep
-                    .docker()
-                    .version()
+                let avail = with_retries(ep.api_retries, || ep.docker().version())
                     .await
                     .with_context(|| anyhow!("Getting API version of endpoint: {}", ep.name))?;
 
-                if !v.contains(&avail.api_version) {
-                    Err(anyhow!("Incompatible Docker API version on endpoint {}: Exepected: {}, Available: [{}]",
-                            ep.name(), avail.api_version, v.join(", ")))
-                } else {
-                    Ok(())
-                }
+                check_version_allowed(ep.name(), "Docker API version", &avail.api_version, v)
             }
         }
     }
 
-    async fn check_images_available(imgs: &[ImageName], ep: &Endpoint) -> Result<()> {
+    /// The images that have to be pulled on the endpoint, according to the pull policy
+    async fn images_to_pull(
+        imgs: &[ImageName],
+        policy: ImagePullPolicy,
+        ep: &Endpoint,
+    ) -> Result<Vec<ImageName>> {
         use shiplift::ImageListOptions;
 
+        if imgs.is_empty() {
+            return Ok(vec![]);
+        }
         trace!("Checking availability of images: {:?}", imgs);
-        let available_names = ep
-            .docker()
-            .images()
-            .list(&ImageListOptions::builder().all().build())
-            .await
-            .with_context(|| anyhow!("Listing images on endpoint: {}", ep.name))?
-            .into_iter()
-            .flat_map(|image_rep| {
-                image_rep
-                    .repo_tags
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(ImageName::from)
-            })
-            .collect::<Vec<ImageName>>();
+        let list_options = ImageListOptions::builder().all().build();
+        let available_names = with_retries(ep.api_retries, || async {
+            ep.docker().images().list(&list_options).await
+        })
+        .await
+        .with_context(|| anyhow!("Listing images on endpoint: {}", ep.name))?
+        .into_iter()
+        .flat_map(|image_rep| {
+            image_rep
+                .repo_tags
+                .unwrap_or_default()
+                .into_iter()
+                .map(ImageName::from)
+        })
+        .collect::<Vec<ImageName>>();
 
         trace!("Available images = {:?}", available_names);
 
-        imgs.iter()
-            .map(|img| {
-                if !available_names.contains(img) {
-                    Err(anyhow!(
-                        "Image '{}' missing from endpoint '{}'",
-                        img.as_ref(),
-                        ep.name
-                    ))
-                } else {
-                    Ok(())
-                }
+        let mut to_pull = vec![];
+        for img in imgs {
+            if image_needs_pull(policy, available_names.contains(img), img, &ep.name)? {
+                to_pull.push(img.clone());
+            }
+        }
+        Ok(to_pull)
+    }
+
+    /// Check that the image on the endpoint is for the platform
+    ///
+    /// The containers are created from the images on the endpoint, so this ensures that the builds
+    /// run on the platform.
+    async fn check_image_platform(&self, img: &ImageName, platform: &Platform) -> Result<()> {
+        let details = crate::util::docker::with_retries(self.api_retries, || async {
+            self.docker.images().get(img.as_ref()).inspect().await
+        })
+        .await
+        .with_context(|| anyhow!("Inspecting image '{}' on endpoint '{}'", img, self.name))?;
+
+        if platform.matches(&details.os, &details.architecture) {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Pull the image for the platform with '--pull always' or 'docker pull --platform {} {}' on the endpoint",
+                platform,
+                img
+            ))
+            .context(anyhow!(
+                "Image '{}' on endpoint '{}' is for platform {}/{}, not {}",
+                img,
+                self.name,
+                details.os,
+                details.architecture,
+                platform
+            ))
+        }
+    }
+
+    /// Pull an image for the platform on the endpoint, logging in to its registry with `auth` if
+    /// set
+    pub async fn pull_image(
+        &self,
+        img: &ImageName,
+        auth: Option<shiplift::RegistryAuth>,
+    ) -> Result<()> {
+        info!("Pulling image '{}' on endpoint '{}'", img, self.name);
+        let platform = self.platform.as_ref().map(Platform::to_string);
+        let query = std::iter::once(("fromImage", img.as_ref()))
+            .chain(platform.as_deref().map(|platform| ("platform", platform)))
+            .collect::<Vec<_>>();
+        let headers = auth
+            .map(|auth| ("X-Registry-Auth", auth.serialize()))
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        // Pulling an image again only fetches the layers that are still missing
+        let progress = with_retries(self.api_retries, || {
+            self.post_raw("/images/create", &query, &headers, None)
+        })
+        .await
+        .with_context(|| anyhow!("Pulling image '{}' on endpoint '{}'", img, self.name))?;
+
+        for line in String::from_utf8_lossy(&progress).lines() {
+            trace!("Pulling {}: {}", img, line);
+        }
+        match pull_error(&progress) {
+            None => Ok(()),
+            Some(e) => Err(anyhow!(e))
+                .with_context(|| anyhow!("Pulling image '{}' on endpoint '{}'", img, self.name)),
+        }
+    }
+
+    /// Send a POST request to the Docker API of the endpoint and return the response body
+    ///
+    /// This is for the calls that shiplift has no option for (e.g. the `platform` of an image
+    /// pull). Failures are returned as shiplift errors, so that they can be retried with
+    /// `with_retries` like all other calls.
+    async fn post_raw(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: Option<String>,
+    ) -> std::result::Result<hyper::body::Bytes, shiplift::Error> {
+        let mut request =
+            hyper::Request::post(api_uri(&self.endpoint_type, &self.uri, path, query)?);
+        if body.is_some() {
+            request = request.header(hyper::header::CONTENT_TYPE, "application/json");
+        }
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        let request = request.body(body.map(hyper::Body::from).unwrap_or_default())?;
+
+        let response = match self.endpoint_type {
+            EndpointType::Http => hyper::Client::new().request(request).await?,
+            EndpointType::Socket => {
+                hyper::Client::builder()
+                    .build::<_, hyper::Body>(hyperlocal::UnixConnector)
+                    .request(request)
+                    .await?
+            }
+        };
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(shiplift::Error::Fault {
+                code: status,
+                message: api_error_message(&body),
             })
-            .collect::<Result<Vec<_>>>()
-            .map(|_| ())
+        }
     }
 
     pub async fn prepare_container(
         &self,
         job: &RunnableJob,
+        labels: &HashMap<&'static str, String>,
         staging_store: Arc<RwLock<StagingStore>>,
         release_stores: Vec<Arc<ReleaseStore>>,
     ) -> Result<PreparedContainer<'_>> {
-        PreparedContainer::new(self, job, staging_store, release_stores).await
+        PreparedContainer::new(self, job, labels, staging_store, release_stores).await
     }
 
     pub fn running_jobs(&self) -> usize {
         self.running_jobs.load(std::sync::atomic::Ordering::Relaxed)
     }
 
-    /// Super non-scientific utilization calculation for the endpoint
-    pub fn utilization(&self) -> f64 {
-        let max_jobs = self.num_max_jobs() as f64;
-        let run_jobs = self.running_jobs() as f64;
-        trace!(
-            "utilization of {}: 100.0 / {} * {}",
-            self.name(),
-            max_jobs,
-            run_jobs
-        );
-        100.0 / max_jobs * run_jobs
-    }
-
     /// Ping the endpoint (once)
     pub async fn ping(&self) -> Result<String> {
         self.docker.ping().await.map_err(Error::from)
@@ -270,16 +416,32 @@ impl Endpoint {
     }
 
     pub async fn container_stats(&self) -> Result<Vec<ContainerStat>> {
+        let list_options = shiplift::builder::ContainerListOptions::builder()
+            .all()
+            .build();
+        with_retries(self.api_retries, || async {
+            self.docker.containers().list(&list_options).await
+        })
+        .await
+        .map_err(Error::from)
+        .map(|containers| containers.into_iter().map(ContainerStat::from).collect())
+    }
+
+    /// The number of running containers, including the ones that were not started by butido
+    pub async fn number_of_running_containers(&self) -> Result<usize> {
+        self.container_stats()
+            .await
+            .map(|stats| stats.iter().filter(|stat| stat.state == "running").count())
+    }
+
+    pub async fn remove_image(&self, id: &str) -> Result<()> {
         self.docker
-            .containers()
-            .list({
-                &shiplift::builder::ContainerListOptions::builder()
-                    .all()
-                    .build()
-            })
+            .images()
+            .get(id)
+            .delete()
             .await
-            .map_err(Error::from)
-            .map(|containers| containers.into_iter().map(ContainerStat::from).collect())
+            .map(|_| ())
+            .with_context(|| anyhow!("Removing image {} on '{}'", id, self.name))
     }
 
     pub async fn has_container_with_id(&self, id: &str) -> Result<bool> {
@@ -309,12 +471,13 @@ impl Endpoint {
             listopts.all();
         }
 
-        self.docker
-            .images()
-            .list(&listopts.build())
-            .await
-            .map_err(Error::from)
-            .map(|v| v.into_iter().map(Image::from))
+        let listopts = listopts.build();
+        with_retries(self.api_retries, || async {
+            self.docker.images().list(&listopts).await
+        })
+        .await
+        .map_err(Error::from)
+        .map(|v| v.into_iter().map(Image::from))
     }
 }
 
@@ -354,6 +517,72 @@ impl From<shiplift::rep::Info> for EndpointStats {
     }
 }
 
+/// Limits the number of build containers on an endpoint
+#[derive(Clone, Debug, Default)]
+pub struct ContainerSlots {
+    semaphore: Option<Arc<tokio::sync::Semaphore>>,
+
+    /// Notified when a container slot or a job slot of the endpoint is freed
+    freed: Arc<tokio::sync::Notify>,
+}
+
+/// An occupied container slot of an endpoint, the slot is freed when this is dropped
+#[derive(Debug)]
+pub struct ContainerSlot {
+    permit: Option<tokio::sync::OwnedSemaphorePermit>,
+    freed: Arc<tokio::sync::Notify>,
+}
+
+impl ContainerSlots {
+    /// Slots for at most `max` containers, or unlimited ones if `max` is not set
+    pub fn new(max: Option<usize>) -> Self {
+        ContainerSlots {
+            semaphore: max.map(|max| Arc::new(tokio::sync::Semaphore::new(max))),
+            freed: Arc::new(tokio::sync::Notify::new()),
+        }
+    }
+
+    /// Whether a slot is free at the moment
+    pub fn is_free(&self) -> bool {
+        self.semaphore
+            .as_ref()
+            .map(|semaphore| semaphore.available_permits() > 0)
+            .unwrap_or(true)
+    }
+
+    /// Occupy a slot without waiting, returns `None` if all slots are in use
+    pub fn try_acquire(&self) -> Option<ContainerSlot> {
+        let permit = match self.semaphore.as_ref() {
+            Some(semaphore) => Some(semaphore.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        Some(ContainerSlot {
+            permit,
+            freed: self.freed.clone(),
+        })
+    }
+
+    /// Wait until a container slot or a job slot of the endpoint is freed
+    ///
+    /// Only slots that are freed after the returned future was enabled (see
+    /// `tokio::sync::futures::Notified::enable()`) or first polled are noticed.
+    pub fn freed(&self) -> tokio::sync::futures::Notified<'_> {
+        self.freed.notified()
+    }
+
+    fn notify_freed(&self) {
+        self.freed.notify_waiters();
+    }
+}
+
+impl Drop for ContainerSlot {
+    fn drop(&mut self) {
+        // The permit has to be returned before the waiting jobs are woken up
+        drop(self.permit.take());
+        self.freed.notify_waiters();
+    }
+}
+
 /// Helper type to store stats about a container
 pub struct ContainerStat {
     pub created: chrono::DateTime<chrono::Utc>,
@@ -362,6 +591,7 @@ pub struct ContainerStat {
     pub image_id: String,
     pub state: String,
     pub status: String,
+    pub labels: HashMap<String, String>,
 }
 
 impl From<shiplift::rep::Container> for ContainerStat {
@@ -373,6 +603,7 @@ impl From<shiplift::rep::Container> for ContainerStat {
             image_id: cont.image_id,
             state: cont.state,
             status: cont.status,
+            labels: cont.labels,
         }
     }
 }
@@ -387,6 +618,10 @@ pub struct Image {
 
     #[getset(get = "pub")]
     tags: Option<Vec<String>>,
+
+    /// The size of the image including the layers it shares with other images
+    #[getset(get = "pub")]
+    size: u64,
 }
 
 impl From<shiplift::rep::Image> for Image {
@@ -395,6 +630,19 @@ impl From<shiplift::rep::Image> for Image {
             created: img.created,
             id: img.id,
             tags: img.repo_tags,
+            size: img.virtual_size,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Image {
+    pub fn new(created: chrono::DateTime<chrono::Utc>, id: &str, tags: &[&str]) -> Self {
+        Image {
+            created,
+            id: String::from(id),
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            size: 0,
         }
     }
 }
@@ -418,6 +666,7 @@ impl Drop for EndpointHandle {
             .running_jobs
             .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
         trace!("Endpoint {} has one job less: {}", self.0.name(), res - 1);
+        self.0.container_slots.notify_freed();
     }
 }
 
@@ -429,6 +678,182 @@ impl std::ops::Deref for EndpointHandle {
     }
 }
 
+/// Whether an image has to be pulled on an endpoint, according to the pull policy
+///
+/// Errors if the image is missing and must not be pulled.
+/// The URI of a call to the Docker API of an endpoint
+///
+/// Like shiplift, only the scheme and the authority of the URI of an HTTP endpoint are used.
+fn api_uri(
+    endpoint_type: &EndpointType,
+    endpoint_uri: &str,
+    path: &str,
+    query: &[(&str, &str)],
+) -> std::result::Result<hyper::Uri, shiplift::Error> {
+    let path_and_query = format!(
+        "{}?{}",
+        path,
+        url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(query)
+            .finish()
+    );
+    match endpoint_type {
+        EndpointType::Socket => Ok(hyperlocal::Uri::new(endpoint_uri, &path_and_query).into()),
+        EndpointType::Http => {
+            let endpoint_uri = hyper::Uri::from_str(endpoint_uri)?;
+            let mut uri = hyper::Uri::builder();
+            if let Some(scheme) = endpoint_uri.scheme() {
+                uri = uri.scheme(scheme.clone());
+            }
+            if let Some(authority) = endpoint_uri.authority() {
+                uri = uri.authority(authority.clone());
+            }
+            Ok(uri.path_and_query(path_and_query).build()?)
+        }
+    }
+}
+
+/// The message of an error response of the Docker API, the whole body if it has no message
+fn api_error_message(body: &[u8]) -> String {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|error| error.get("message")?.as_str().map(String::from))
+        .unwrap_or_else(|| String::from_utf8_lossy(body).into_owned())
+}
+
+/// The first error in the progress of an image pull
+///
+/// Docker reports errors that happen during a pull (e.g. if there is no image for the platform)
+/// in the progress, after the pull was started successfully.
+fn pull_error(progress: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(progress).lines().find_map(|line| {
+        serde_json::from_str::<serde_json::Value>(line)
+            .ok()?
+            .get("error")?
+            .as_str()
+            .map(String::from)
+    })
+}
+
+fn image_needs_pull(
+    policy: ImagePullPolicy,
+    present: bool,
+    img: &ImageName,
+    endpoint_name: &EndpointName,
+) -> Result<bool> {
+    match (policy, present) {
+        (ImagePullPolicy::Always, _) => Ok(true),
+        (ImagePullPolicy::Missing, present) => Ok(!present),
+        (ImagePullPolicy::Never, true) => Ok(false),
+        (ImagePullPolicy::Never, false) => Err(anyhow!(
+            "Image '{}' missing from endpoint '{}'",
+            img.as_ref(),
+            endpoint_name
+        ))
+        .context("Use 'build --pull missing' to pull missing images"),
+    }
+}
+
+/// Check whether the `available` version of an endpoint is one of the `allowed` versions
+fn check_version_allowed(
+    endpoint_name: &EndpointName,
+    what: &str,
+    available: &str,
+    allowed: &[String],
+) -> Result<()> {
+    if allowed.iter().any(|v| v == available) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Incompatible {} on endpoint {}: {} is not one of the allowed versions [{}]",
+            what,
+            endpoint_name,
+            available,
+            allowed.join(", ")
+        ))
+        .context(
+            "Use 'build --skip-version-check' or 'docker.check_versions = false' to skip the check",
+        )
+    }
+}
+
+/// The name of the build container of a job
+///
+/// The tag (e.g. the git hash of the repository) is appended if it is set.
+fn container_name(
+    package: &PackageName,
+    version: &PackageVersion,
+    job_uuid: &uuid::Uuid,
+    tag: Option<&str>,
+) -> String {
+    let name = format!(
+        "butido-{package}-{version}-{id}",
+        package = package.as_ref(),
+        version = version.as_ref(),
+        id = job_uuid
+    );
+
+    match tag {
+        Some(tag) => format!("{name}-{tag}"),
+        None => name,
+    }
+}
+
+/// The label of a build container that contains the name of the package
+pub const LABEL_PACKAGE: &str = "butido.package";
+
+/// The label of a build container that contains the version of the package
+pub const LABEL_VERSION: &str = "butido.version";
+
+/// The label of a build container that contains the UUID of the submit
+pub const LABEL_SUBMIT: &str = "butido.submit";
+
+/// The label of a build container that contains the UUID of the job
+pub const LABEL_JOB: &str = "butido.job";
+
+/// The labels that are set on the build container of a job
+///
+/// They can be used to find out which package, submit, and job a container belongs to, e.g. via
+/// `docker inspect`.
+pub fn container_labels(
+    package: &PackageName,
+    version: &PackageVersion,
+    submit_uuid: &uuid::Uuid,
+    job_uuid: &uuid::Uuid,
+) -> HashMap<&'static str, String> {
+    HashMap::from([
+        (LABEL_PACKAGE, package.to_string()),
+        (LABEL_VERSION, version.to_string()),
+        (LABEL_SUBMIT, submit_uuid.to_string()),
+        (LABEL_JOB, job_uuid.to_string()),
+    ])
+}
+
+/// What happens to the build container of a job after the job ran
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ContainerCleanup {
+    /// Remove all containers
+    Always,
+
+    /// Remove the containers of successful jobs, keep the ones of failed jobs for debugging
+    #[default]
+    KeepFailed,
+
+    /// Keep all containers
+    Never,
+}
+
+impl ContainerCleanup {
+    /// Whether the container of a job should be removed
+    pub fn removes(self, job_succeeded: bool) -> bool {
+        match self {
+            ContainerCleanup::Always => true,
+            ContainerCleanup::KeepFailed => job_succeeded,
+            ContainerCleanup::Never => false,
+        }
+    }
+}
+
 #[derive(Getters)]
 pub struct PreparedContainer<'a> {
     endpoint: &'a Endpoint,
@@ -442,11 +867,12 @@ impl<'a> PreparedContainer<'a> {
     async fn new(
         endpoint: &'a Endpoint,
         job: &RunnableJob,
+        labels: &HashMap<&'static str, String>,
         staging_store: Arc<RwLock<StagingStore>>,
         release_stores: Vec<Arc<ReleaseStore>>,
     ) -> Result<PreparedContainer<'a>> {
         let script = job.script().clone();
-        let create_info = Self::build_container(endpoint, job).await?;
+        let create_info = Self::build_container(endpoint, job, labels).await?;
         let container = endpoint.docker.containers().get(&create_info.id);
 
         let (cpysrc, cpypch, cpyart, cpyscr) = tokio::join!(
@@ -500,6 +926,7 @@ impl<'a> PreparedContainer<'a> {
     async fn build_container(
         endpoint: &Endpoint,
         job: &RunnableJob,
+        labels: &HashMap<&'static str, String>,
     ) -> Result<shiplift::rep::ContainerCreateInfo> {
         let envs = job
             .environment()
@@ -507,19 +934,26 @@ impl<'a> PreparedContainer<'a> {
             .collect::<Vec<_>>();
         trace!("Job resources: Environment variables = {:?}", envs);
 
+        let labels = labels
+            .iter()
+            .map(|(k, v)| (*k, v.as_str()))
+            .collect::<HashMap<&str, &str>>();
+        trace!("Container labels = {:?}", labels);
+
         let builder_opts = {
             let mut builder_opts = shiplift::ContainerOptions::builder(job.image().as_ref());
-            let container_name = format!(
-                "butido-{package}-{version}-{id}",
-                package = job.package().name().as_ref(),
-                version = job.package().version().as_ref(),
-                id = job.uuid()
+            let container_name = container_name(
+                job.package().name(),
+                job.package().version(),
+                job.uuid(),
+                job.container_tag().as_deref(),
             );
             trace!("container name = {}", container_name);
             builder_opts.name(&container_name);
             builder_opts.env(envs.iter().map(AsRef::as_ref).collect::<Vec<&str>>());
             builder_opts.cmd(vec!["/bin/bash"]); // we start the container with /bin/bash, but exec() the script in it later
             builder_opts.attach_stdin(true); // we have to attach, otherwise bash exits
+            builder_opts.labels(&labels);
 
             if let Some(network_mode) = endpoint.network_mode().as_ref() {
                 builder_opts.network_mode(network_mode);
@@ -529,18 +963,30 @@ impl<'a> PreparedContainer<'a> {
         };
         trace!("Builder options = {:?}", builder_opts);
 
-        let create_info = endpoint
-            .docker
-            .containers()
-            .create(&builder_opts)
-            .await
-            .with_context(|| {
-                anyhow!(
-                    "Creating container with builder options = {:?}",
-                    builder_opts
-                )
-            })
-            .with_context(|| anyhow!("Creating container on '{}'", endpoint.name))?;
+        let body = builder_opts.serialize()?;
+        let platform = endpoint.platform.as_ref().map(Platform::to_string);
+        let query = builder_opts
+            .name
+            .as_deref()
+            .map(|name| ("name", name))
+            .into_iter()
+            .chain(platform.as_deref().map(|platform| ("platform", platform)))
+            .collect::<Vec<_>>();
+        let create_info = with_retries(endpoint.api_retries, || async {
+            let response = endpoint
+                .post_raw("/containers/create", &query, &[], Some(body.clone()))
+                .await?;
+            serde_json::from_slice::<shiplift::rep::ContainerCreateInfo>(&response)
+                .map_err(shiplift::Error::from)
+        })
+        .await
+        .with_context(|| {
+            anyhow!(
+                "Creating container with builder options = {:?}",
+                builder_opts
+            )
+        })
+        .with_context(|| anyhow!("Creating container on '{}'", endpoint.name))?;
         trace!("Create info = {:?}", create_info);
         Ok(create_info)
     }
@@ -946,6 +1392,7 @@ impl<'a> ExecutedContainer<'a> {
     pub async fn finalize(
         self,
         staging_store: Arc<RwLock<StagingStore>>,
+        cleanup: ContainerCleanup,
     ) -> Result<FinalizedContainer> {
         let (exit_info, artifacts) = match self.exit_info {
             Some((false, msg)) => {
@@ -991,10 +1438,31 @@ impl<'a> ExecutedContainer<'a> {
             }
         };
 
+        let container_removed = if cleanup.removes(exit_info.is_ok()) {
+            // A container that cannot be removed is reported as kept instead of failing the job
+            self.endpoint
+                .docker
+                .containers()
+                .get(&self.create_info.id)
+                .remove(RmContainerOptions::builder().force(true).build())
+                .await
+                .map(|_| trace!("Removed container {}", self.create_info.id))
+                .map_err(|e| {
+                    warn!(
+                        "Removing container {} on '{}' failed: {}",
+                        self.create_info.id, self.endpoint.name, e
+                    )
+                })
+                .is_ok()
+        } else {
+            false
+        };
+
         Ok({
             FinalizedContainer {
                 artifacts,
                 exit_info,
+                container_removed,
             }
         })
     }
@@ -1004,10 +1472,196 @@ impl<'a> ExecutedContainer<'a> {
 pub struct FinalizedContainer {
     artifacts: Vec<ArtifactPath>,
     exit_info: Result<()>,
+    container_removed: bool,
 }
 
 impl FinalizedContainer {
+    pub fn container_removed(&self) -> bool {
+        self.container_removed
+    }
+
+    pub fn exit_info(&self) -> &Result<()> {
+        &self.exit_info
+    }
+
     pub fn unpack(self) -> (Vec<ArtifactPath>, Result<()>) {
         (self.artifacts, self.exit_info)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_pull_policies() {
+        let img = ImageName::from("debian:bullseye");
+        let endpoint = EndpointName::from(String::from("testhost"));
+        let needs_pull = |policy, present| image_needs_pull(policy, present, &img, &endpoint).ok();
+
+        assert_eq!(needs_pull(ImagePullPolicy::Always, true), Some(true));
+        assert_eq!(needs_pull(ImagePullPolicy::Always, false), Some(true));
+        assert_eq!(needs_pull(ImagePullPolicy::Missing, true), Some(false));
+        assert_eq!(needs_pull(ImagePullPolicy::Missing, false), Some(true));
+        assert_eq!(needs_pull(ImagePullPolicy::Never, true), Some(false));
+        assert_eq!(needs_pull(ImagePullPolicy::Never, false), None);
+    }
+
+    #[test]
+    fn test_api_uri() {
+        let query = [
+            ("fromImage", "debian:bookworm"),
+            ("platform", "linux/arm/v7"),
+        ];
+
+        let uri = api_uri(
+            &EndpointType::Http,
+            "http://0.0.0.0:8095/ignored",
+            "/images/create",
+            &query,
+        )
+        .unwrap();
+        assert_eq!(
+            uri.to_string(),
+            "http://0.0.0.0:8095/images/create?fromImage=debian%3Abookworm&platform=linux%2Farm%2Fv7"
+        );
+
+        let uri = api_uri(
+            &EndpointType::Socket,
+            "/var/run/docker.sock",
+            "/images/create",
+            &query,
+        )
+        .unwrap();
+        assert_eq!(uri.scheme_str(), Some("unix"));
+        assert_eq!(
+            uri.path_and_query().map(|pq| pq.as_str()),
+            Some("/images/create?fromImage=debian%3Abookworm&platform=linux%2Farm%2Fv7")
+        );
+    }
+
+    #[test]
+    fn test_api_responses() {
+        assert_eq!(
+            api_error_message(br#"{"message": "No such image: debian:bookworm"}"#),
+            "No such image: debian:bookworm"
+        );
+        assert_eq!(api_error_message(b"page not found"), "page not found");
+
+        let progress = indoc::indoc!(
+            r#"
+            {"status":"Pulling from library/debian","id":"bookworm"}
+            {"status":"Downloading","progressDetail":{"current":1,"total":2},"id":"abc"}
+        "#
+        );
+        assert_eq!(pull_error(progress.as_bytes()), None);
+        let progress = format!(
+            "{}{}",
+            progress,
+            r#"{"error":"no matching manifest for linux/s390x in the manifest list entries"}"#
+        );
+        assert_eq!(
+            pull_error(progress.as_bytes()).as_deref(),
+            Some("no matching manifest for linux/s390x in the manifest list entries")
+        );
+    }
+
+    #[test]
+    fn test_check_version_allowed() {
+        let endpoint = EndpointName::from(String::from("testhost"));
+        let allowed = vec![String::from("20.10.21"), String::from("24.0.7")];
+
+        assert!(check_version_allowed(&endpoint, "Docker version", "24.0.7", &allowed).is_ok());
+
+        let err = check_version_allowed(&endpoint, "Docker version", "25.0.0", &allowed)
+            .unwrap_err()
+            .root_cause()
+            .to_string();
+        assert_eq!(
+            err,
+            "Incompatible Docker version on endpoint testhost: 25.0.0 is not one of the allowed versions [20.10.21, 24.0.7]"
+        );
+
+        // Versions are compared exactly
+        assert!(check_version_allowed(&endpoint, "Docker version", "24.0", &allowed).is_err());
+        assert!(check_version_allowed(&endpoint, "Docker version", "24.0.7", &[]).is_err());
+    }
+
+    #[test]
+    fn test_container_name_with_git_tag() {
+        let package = PackageName::from(String::from("a"));
+        let version = PackageVersion::from(String::from("1.0"));
+        let uuid = uuid::Uuid::parse_str("6a4c2b5e-3f0d-4a8e-9d3c-1f2e3d4c5b6a").unwrap();
+        let hash = "0123456789abcdef0123456789abcdef01234567";
+
+        assert_eq!(
+            container_name(&package, &version, &uuid, None),
+            "butido-a-1.0-6a4c2b5e-3f0d-4a8e-9d3c-1f2e3d4c5b6a"
+        );
+        assert_eq!(
+            container_name(
+                &package,
+                &version,
+                &uuid,
+                Some(crate::util::git::short_hash(hash))
+            ),
+            "butido-a-1.0-6a4c2b5e-3f0d-4a8e-9d3c-1f2e3d4c5b6a-0123456"
+        );
+    }
+
+    #[test]
+    fn test_container_labels() {
+        let package = PackageName::from(String::from("a"));
+        let version = PackageVersion::from(String::from("1.0"));
+        let submit = uuid::Uuid::parse_str("0c1b2a3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d").unwrap();
+        let job = uuid::Uuid::parse_str("6a4c2b5e-3f0d-4a8e-9d3c-1f2e3d4c5b6a").unwrap();
+
+        let labels = container_labels(&package, &version, &submit, &job);
+        assert_eq!(labels.len(), 4);
+        assert_eq!(labels["butido.package"], "a");
+        assert_eq!(labels["butido.version"], "1.0");
+        assert_eq!(
+            labels["butido.submit"],
+            "0c1b2a3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d"
+        );
+        assert_eq!(labels["butido.job"], "6a4c2b5e-3f0d-4a8e-9d3c-1f2e3d4c5b6a");
+    }
+
+    #[test]
+    fn test_container_slots() {
+        let slots = ContainerSlots::new(Some(1));
+        assert!(slots.is_free());
+        let slot = slots.try_acquire().unwrap();
+        assert!(!slots.is_free());
+        assert!(slots.try_acquire().is_none());
+
+        // The slot is freed when it is dropped
+        drop(slot);
+        assert!(slots.is_free());
+        assert!(slots.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_unlimited_container_slots() {
+        let slots = ContainerSlots::new(None);
+        let (a, b) = (slots.try_acquire(), slots.try_acquire());
+        assert!(a.is_some());
+        assert!(b.is_some());
+        assert!(slots.is_free());
+    }
+
+    #[test]
+    fn test_container_cleanup() {
+        // (policy, removed after success, removed after failure)
+        let expected = [
+            (ContainerCleanup::Always, true, true),
+            (ContainerCleanup::KeepFailed, true, false),
+            (ContainerCleanup::Never, false, false),
+        ];
+        for (cleanup, success, failure) in expected {
+            assert_eq!(cleanup.removes(true), success, "{cleanup:?} after success");
+            assert_eq!(cleanup.removes(false), failure, "{cleanup:?} after failure");
+        }
+        assert_eq!(ContainerCleanup::default(), ContainerCleanup::KeepFailed);
+    }
+}
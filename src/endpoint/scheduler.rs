@@ -8,7 +8,10 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use anyhow::anyhow;
@@ -20,14 +23,16 @@ use diesel::r2d2::ConnectionManager;
 use diesel::r2d2::Pool;
 use diesel::PgConnection;
 use indicatif::ProgressBar;
-use itertools::Itertools;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::RwLock;
-use tracing::trace;
+use tracing::{trace, warn};
 use uuid::Uuid;
 
+use crate::config::EndpointScheduling;
 use crate::db::models as dbmodels;
+use crate::endpoint::ContainerCleanup;
+use crate::endpoint::ContainerSlot;
 use crate::endpoint::Endpoint;
 use crate::endpoint::EndpointConfiguration;
 use crate::endpoint::EndpointHandle;
@@ -46,9 +51,21 @@ pub struct EndpointScheduler {
     release_stores: Vec<Arc<ReleaseStore>>,
     db: Pool<ConnectionManager<PgConnection>>,
     submit: crate::db::models::Submit,
+
+    /// Whether the build containers are labeled with the package, submit, and job
+    container_labels: bool,
+
+    /// What happens to the build containers after the jobs ran
+    container_cleanup: ContainerCleanup,
+
+    scheduling: EndpointScheduling,
+
+    /// The index of the endpoint where round robin scheduling continues
+    round_robin_next: AtomicUsize,
 }
 
 impl EndpointScheduler {
+    #[allow(clippy::too_many_arguments)]
     pub async fn setup(
         endpoints: Vec<EndpointConfiguration>,
         staging_store: Arc<RwLock<StagingStore>>,
@@ -56,8 +73,14 @@ impl EndpointScheduler {
         db: Pool<ConnectionManager<PgConnection>>,
         submit: crate::db::models::Submit,
         log_dir: Option<PathBuf>,
+        container_labels: bool,
+        container_cleanup: ContainerCleanup,
+        scheduling: EndpointScheduling,
     ) -> Result<Self> {
-        let endpoints = crate::endpoint::util::setup_endpoints(endpoints).await?;
+        let mut endpoints = crate::endpoint::util::setup_endpoints(endpoints).await?;
+
+        // The endpoints are set up in parallel, sort them so that the scheduling is deterministic
+        endpoints.sort_by(|a, b| a.name().as_ref().cmp(b.name().as_ref()));
 
         Ok(EndpointScheduler {
             log_dir,
@@ -66,6 +89,10 @@ impl EndpointScheduler {
             release_stores,
             db,
             submit,
+            container_labels,
+            container_cleanup,
+            scheduling,
+            round_robin_next: AtomicUsize::new(0),
         })
     }
 
@@ -73,67 +100,150 @@ impl EndpointScheduler {
     ///
     /// # Warning
     ///
-    /// This function blocks as long as there is no free endpoint available!
+    /// This function blocks as long as there is no endpoint with a free job and container slot
+    /// available!
     pub async fn schedule_job(
         &self,
         job: RunnableJob,
         bar: indicatif::ProgressBar,
     ) -> Result<JobHandle> {
-        let endpoint = self.select_free_endpoint().await?;
+        let (endpoint, container_slot) =
+            select_free_endpoint(&self.endpoints, self.scheduling, &self.round_robin_next).await?;
 
         Ok(JobHandle {
             log_dir: self.log_dir.clone(),
             bar,
             endpoint,
+            container_slot,
             job,
             staging_store: self.staging_store.clone(),
             release_stores: self.release_stores.clone(),
             db: self.db.clone(),
             submit: self.submit.clone(),
+            container_labels: self.container_labels,
+            container_cleanup: self.container_cleanup,
         })
     }
+}
 
-    async fn select_free_endpoint(&self) -> Result<EndpointHandle> {
-        loop {
-            let ep = self
-                .endpoints
-                .iter()
-                .filter(|ep| {
-                    // filter out all running containers where the number of max jobs is reached
-                    let r = ep.running_jobs() < ep.num_max_jobs();
-                    trace!(
-                        "Endpoint {} considered for scheduling job: {}",
-                        ep.name(),
-                        r
-                    );
+/// Wait for an endpoint that can run another job and has a free container slot
+///
+/// The slot is occupied until the returned `ContainerSlot` is dropped, so that jobs are only
+/// scheduled on endpoints where they can create their container right away.
+async fn select_free_endpoint(
+    endpoints: &[Arc<Endpoint>],
+    scheduling: EndpointScheduling,
+    round_robin_next: &AtomicUsize,
+) -> Result<(EndpointHandle, ContainerSlot)> {
+    let is_free =
+        |ep: &Endpoint| ep.running_jobs() < ep.num_max_jobs() && ep.container_slots().is_free();
+
+    if endpoints.is_empty() {
+        return Err(anyhow!("No endpoints to schedule the job on"));
+    }
+
+    loop {
+        // Listen before checking the endpoints, so that a slot that is freed in between is noticed
+        let mut freed = endpoints
+            .iter()
+            .map(|ep| Box::pin(ep.container_slots().freed()))
+            .collect::<Vec<_>>();
+        for freed in freed.iter_mut() {
+            freed.as_mut().enable();
+        }
+
+        // filter out all endpoints where the number of max jobs or containers is reached
+        let free = endpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, ep)| {
+                let r = is_free(ep);
+                trace!(
+                    "Endpoint {} considered for scheduling job: {}",
+                    ep.name(),
                     r
-                })
-                .sorted_by(|ep1, ep2| {
-                    ep1.utilization()
-                        .partial_cmp(&ep2.utilization())
-                        .unwrap_or(std::cmp::Ordering::Equal)
-                })
-                .next();
-
-            if let Some(endpoint) = ep {
-                return Ok(EndpointHandle::new(endpoint.clone()));
-            } else {
-                trace!("No free endpoint found, retry...");
-                tokio::task::yield_now().await
-            }
+                );
+                r
+            })
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        if free.is_empty() {
+            trace!("No free endpoint found, waiting for a free slot...");
+            futures::future::select_all(freed).await;
+            continue;
+        }
+
+        let running_containers = if scheduling == EndpointScheduling::LeastContainers {
+            futures::future::try_join_all(
+                free.iter()
+                    .map(|i| endpoints[*i].number_of_running_containers()),
+            )
+            .await?
+        } else {
+            Vec::new()
+        };
+
+        let next = round_robin_next.load(Ordering::Relaxed);
+        let selected = select_endpoint(scheduling, &free, next, &running_containers);
+
+        // The endpoint might have been taken by another job while the containers were counted
+        let selected = selected
+            .filter(|i| endpoints[*i].running_jobs() < endpoints[*i].num_max_jobs())
+            .and_then(|i| {
+                endpoints[i]
+                    .container_slots()
+                    .try_acquire()
+                    .map(|slot| (i, slot))
+            });
+        if let Some((i, slot)) = selected {
+            round_robin_next.store(i + 1, Ordering::Relaxed);
+            trace!("Selected endpoint {}", endpoints[i].name());
+            return Ok((EndpointHandle::new(endpoints[i].clone()), slot));
         }
     }
 }
 
+/// Select the endpoint for the next job
+///
+/// `free` are the (ascending) indices of the endpoints that can run another job, `next` is the
+/// index where round robin scheduling continues, and `running_containers` are the numbers of
+/// running containers of the `free` endpoints (only required for
+/// `EndpointScheduling::LeastContainers`).
+fn select_endpoint(
+    scheduling: EndpointScheduling,
+    free: &[usize],
+    next: usize,
+    running_containers: &[usize],
+) -> Option<usize> {
+    match scheduling {
+        EndpointScheduling::RoundRobin => free
+            .iter()
+            .find(|i| **i >= next)
+            .or_else(|| free.first())
+            .copied(),
+        EndpointScheduling::LeastContainers => free
+            .iter()
+            .zip(running_containers)
+            .min_by_key(|(_, running)| **running)
+            .map(|(i, _)| *i),
+        EndpointScheduling::FirstAvailable => free.first().copied(),
+    }
+}
+
 pub struct JobHandle {
     log_dir: Option<PathBuf>,
     endpoint: EndpointHandle,
     job: RunnableJob,
     bar: ProgressBar,
+    /// The container slot on the endpoint, occupied until the container is finalized
+    container_slot: ContainerSlot,
     db: Pool<ConnectionManager<PgConnection>>,
     staging_store: Arc<RwLock<StagingStore>>,
     release_stores: Vec<Arc<ReleaseStore>>,
     submit: crate::db::models::Submit,
+    container_labels: bool,
+    container_cleanup: ContainerCleanup,
 }
 
 impl std::fmt::Debug for JobHandle {
@@ -160,10 +270,21 @@ impl JobHandle {
             job_id,
             self.endpoint.name()
         );
+        let labels = if self.container_labels {
+            crate::endpoint::container_labels(
+                self.job.package().name(),
+                self.job.package().version(),
+                &self.submit.uuid,
+                self.job.uuid(),
+            )
+        } else {
+            HashMap::new()
+        };
         let prepared_container = self
             .endpoint
             .prepare_container(
                 &self.job,
+                &labels,
                 self.staging_store.clone(),
                 self.release_stores.clone(),
             )
@@ -179,6 +300,7 @@ impl JobHandle {
                     &package.version,
                     &endpoint_uri,
                     &container_id,
+                    false,
                 )
             })?
             .execute_script(log_sender);
@@ -208,6 +330,7 @@ impl JobHandle {
                     &package.version,
                     &endpoint_uri,
                     &container_id,
+                    false,
                 )
             })?;
 
@@ -237,7 +360,7 @@ impl JobHandle {
         }
 
         let res: crate::endpoint::FinalizedContainer = run_container
-            .finalize(self.staging_store.clone())
+            .finalize(self.staging_store.clone(), self.container_cleanup)
             .await
             .context("Finalizing container")
             .with_context(|| {
@@ -247,10 +370,19 @@ impl JobHandle {
                     &package.version,
                     &endpoint_uri,
                     &container_id,
+                    false,
                 )
             })?;
 
+        drop(self.container_slot);
         trace!("Found result for job {}: {:?}", job_id, res);
+        let container_removed = res.container_removed();
+        if !container_removed && res.exit_info().is_ok() {
+            warn!(
+                "Keeping container {} of job {} on '{}', connect with: docker --host {} exec -it {} /bin/bash",
+                container_id, job_id, endpoint_name, endpoint_uri, container_id
+            );
+        }
         let (paths, res) = res.unpack();
         let res = res
             .with_context(|| anyhow!("Error during running job on '{}'", endpoint_name))
@@ -261,6 +393,7 @@ impl JobHandle {
                     &package.version,
                     &endpoint_uri,
                     &container_id,
+                    container_removed,
                 )
             })
             .map_err(Error::from);
@@ -295,15 +428,27 @@ impl JobHandle {
         package_version: &str,
         endpoint_uri: &str,
         container_id: &str,
+        container_removed: bool,
     ) -> Error {
+        let debug_hint = if container_removed {
+            format!(
+                "The container {container_id} was removed, build without '--cleanup-failed' to keep it for debugging."
+            )
+        } else {
+            format!(
+                "To debug, connect to Docker using:\n\n    {}",
+                format!("docker --host {endpoint_uri} exec -it {container_id} /bin/bash")
+                    .yellow()
+                    .bold()
+            )
+        };
+
         anyhow!(indoc::formatdoc!(
             r#"Error while running job for {package_name} {package_version} with id:
 
             {job_id}
 
-        To debug, connect to Docker using:
-
-            {docker_connect_string}
+        {debug_hint}
 
         or, to use butido to show the log of the job, run:
 
@@ -312,10 +457,6 @@ impl JobHandle {
             job_id = job_id.to_string().red(),
             package_name = package_name.to_string().red(),
             package_version = package_version.to_string().red(),
-            docker_connect_string =
-                format!("docker --host {endpoint_uri} exec -it {container_id} /bin/bash")
-                    .yellow()
-                    .bold(),
         ))
     }
 
@@ -525,3 +666,107 @@ impl<'a> LogReceiver<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_robin(free: &[usize], next: usize) -> Option<usize> {
+        select_endpoint(EndpointScheduling::RoundRobin, free, next, &[])
+    }
+
+    #[test]
+    fn test_round_robin_selection() {
+        // Three endpoints with free slots, the selection wraps around
+        let mut next = 0;
+        let mut selected = vec![];
+        for _ in 0..4 {
+            let i = round_robin(&[0, 1, 2], next).unwrap();
+            selected.push(i);
+            next = i + 1;
+        }
+        assert_eq!(selected, vec![0, 1, 2, 0]);
+
+        // Busy endpoints are skipped
+        assert_eq!(round_robin(&[0, 2], 1), Some(2));
+        assert_eq!(round_robin(&[0], 1), Some(0));
+        assert_eq!(round_robin(&[], 0), None);
+    }
+
+    #[test]
+    fn test_least_containers_selection() {
+        let select = |free: &[usize], running: &[usize]| {
+            select_endpoint(EndpointScheduling::LeastContainers, free, 0, running)
+        };
+
+        assert_eq!(select(&[0, 1, 2], &[3, 1, 2]), Some(1));
+        assert_eq!(select(&[0, 2], &[3, 2]), Some(2));
+
+        // On a tie, the first endpoint is used
+        assert_eq!(select(&[0, 1], &[1, 1]), Some(0));
+        assert_eq!(select(&[], &[]), None);
+    }
+
+    fn endpoint(name: &str, max_containers: Option<usize>) -> Arc<Endpoint> {
+        Arc::new(
+            Endpoint::builder()
+                .name(crate::config::EndpointName::from(name.to_string()))
+                .uri(format!("unix:///run/{name}.sock"))
+                .endpoint_type(crate::config::EndpointType::Socket)
+                .docker(shiplift::Docker::unix(format!("/run/{name}.sock")))
+                .num_max_jobs(2)
+                .network_mode(None)
+                .container_slots(crate::endpoint::ContainerSlots::new(max_containers))
+                .build(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_scheduling_skips_endpoints_without_container_slot() {
+        let endpoints = vec![endpoint("a", Some(1)), endpoint("b", None)];
+        let next = AtomicUsize::new(0);
+        let select = || select_free_endpoint(&endpoints, EndpointScheduling::FirstAvailable, &next);
+
+        let (a, a_slot) = select().await.unwrap();
+        assert_eq!(a.name().as_ref(), "a");
+
+        // "a" could run another job, but all of its container slots are in use
+        let (b, _b_slot) = select().await.unwrap();
+        assert_eq!(b.name().as_ref(), "b");
+        let (b, _b_slot) = select().await.unwrap();
+        assert_eq!(b.name().as_ref(), "b");
+
+        // The slot of "a" is free again when the container of the first job is finalized
+        drop(a_slot);
+        let (a, _a_slot) = select().await.unwrap();
+        assert_eq!(a.name().as_ref(), "a");
+    }
+
+    #[tokio::test]
+    async fn test_scheduling_waits_for_a_free_container_slot() {
+        let endpoints = vec![endpoint("a", Some(1))];
+        let next = AtomicUsize::new(0);
+        let select = || select_free_endpoint(&endpoints, EndpointScheduling::FirstAvailable, &next);
+
+        let (_a, a_slot) = select().await.unwrap();
+        let mut second = Box::pin(select());
+        let waiting = futures::poll!(&mut second).is_pending();
+        let still_waiting = futures::poll!(&mut second).is_pending();
+
+        drop(a_slot);
+        let second = tokio::time::timeout(std::time::Duration::from_secs(5), second).await;
+
+        assert!(waiting);
+        assert!(still_waiting);
+        assert_eq!(second.unwrap().unwrap().0.name().as_ref(), "a");
+    }
+
+    #[test]
+    fn test_first_available_selection() {
+        let select =
+            |free: &[usize]| select_endpoint(EndpointScheduling::FirstAvailable, free, 2, &[]);
+
+        assert_eq!(select(&[0, 1, 2]), Some(0));
+        assert_eq!(select(&[1, 2]), Some(1));
+    }
+}
@@ -0,0 +1,281 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! The common categories of failures and the exit codes of butido
+//!
+//! The errors are usually wrapped in an `anyhow::Error` with more context, `exit_code()` searches
+//! the whole chain of causes. Errors of the dependency resolution are `ResolutionError`s, failed
+//! database queries and Docker API calls are recognized by the error types of diesel and
+//! shiplift.
+//!
+//! | Exit code | Failure                                    |
+//! |-----------|--------------------------------------------|
+//! | 1         | Any other error                            |
+//! | 2         | Invalid command line arguments (by clap)   |
+//! | 3         | Invalid configuration                      |
+//! | 4         | Package not found                          |
+//! | 5         | Dependency not found or version conflict   |
+//! | 6         | Dependency cycle                           |
+//! | 7         | Source missing                             |
+//! | 8         | Source corrupt (hash mismatch)             |
+//! | 9         | Database error                             |
+//! | 10        | Docker error                               |
+
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::package::ResolutionError;
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_CONFIG_INVALID: i32 = 3;
+pub const EXIT_PACKAGE_NOT_FOUND: i32 = 4;
+pub const EXIT_DEPENDENCY_UNRESOLVED: i32 = 5;
+pub const EXIT_DEPENDENCY_CYCLE: i32 = 6;
+pub const EXIT_SOURCE_MISSING: i32 = 7;
+pub const EXIT_SOURCE_CORRUPT: i32 = 8;
+pub const EXIT_DATABASE: i32 = 9;
+pub const EXIT_DOCKER: i32 = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ButidoError {
+    /// The configuration could not be loaded or failed the validation
+    #[error(transparent)]
+    ConfigInvalid(anyhow::Error),
+
+    /// No package in the repository matches the given name (and version)
+    #[error("No package found: {0}")]
+    PackageNotFound(String),
+
+    #[error("Source missing: {}", .0.display())]
+    SourceMissing(PathBuf),
+
+    #[error("Hash mismatch, expected '{expected}', got '{computed}'")]
+    SourceCorrupt { expected: String, computed: String },
+
+    /// The connection to the database could not be established
+    #[error(transparent)]
+    Database(anyhow::Error),
+}
+
+impl ButidoError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ButidoError::ConfigInvalid(_) => EXIT_CONFIG_INVALID,
+            ButidoError::PackageNotFound(_) => EXIT_PACKAGE_NOT_FOUND,
+            ButidoError::SourceMissing(_) => EXIT_SOURCE_MISSING,
+            ButidoError::SourceCorrupt { .. } => EXIT_SOURCE_CORRUPT,
+            ButidoError::Database(_) => EXIT_DATABASE,
+        }
+    }
+}
+
+/// The exit code for the first cause of `error` that belongs to a known category
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    error
+        .chain()
+        .find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<ButidoError>() {
+                Some(e.exit_code())
+            } else if let Some(e) = cause.downcast_ref::<ResolutionError>() {
+                Some(match e {
+                    ResolutionError::NotFound { .. } | ResolutionError::Conflict { .. } => {
+                        EXIT_DEPENDENCY_UNRESOLVED
+                    }
+                    ResolutionError::Cycle { .. } => EXIT_DEPENDENCY_CYCLE,
+                })
+            } else if cause.is::<diesel::result::Error>()
+                || cause.is::<diesel::ConnectionError>()
+                || cause.is::<diesel::r2d2::PoolError>()
+            {
+                Some(EXIT_DATABASE)
+            } else if cause.is::<shiplift::Error>() {
+                Some(EXIT_DOCKER)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(EXIT_FAILURE)
+}
+
+/// How errors are printed, see the global `--short-errors` and `--verbose-errors` flags
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ErrorRendering {
+    /// Only the top-level message, with a hint to rerun with `--verbose-errors`
+    #[default]
+    Short,
+
+    /// All causes, and the backtrace if it was captured (see `RUST_BACKTRACE`)
+    Verbose,
+}
+
+static ERROR_RENDERING: OnceLock<ErrorRendering> = OnceLock::new();
+
+const VERBOSE_ERRORS_HINT: &str = "(Rerun with --verbose-errors to see all causes)";
+
+impl ErrorRendering {
+    /// Use this rendering for all errors of the run, can only be set once
+    pub fn set_global(self) {
+        let _ = ERROR_RENDERING.set(self);
+    }
+
+    pub fn global() -> Self {
+        ERROR_RENDERING.get().copied().unwrap_or_default()
+    }
+
+    /// The messages of `error` that are printed, one per cause
+    pub fn causes(self, error: &anyhow::Error) -> Vec<String> {
+        match self {
+            ErrorRendering::Short => vec![error.to_string()],
+            ErrorRendering::Verbose => error.chain().map(ToString::to_string).collect(),
+        }
+    }
+
+    /// The hint that is printed after the `causes()` of the errors, if any causes were omitted
+    pub fn hint<'a, I>(self, errors: I) -> Option<&'static str>
+    where
+        I: IntoIterator<Item = &'a anyhow::Error>,
+    {
+        let omitted =
+            self == ErrorRendering::Short && errors.into_iter().any(|e| e.chain().nth(1).is_some());
+        omitted.then_some(VERBOSE_ERRORS_HINT)
+    }
+
+    /// Render `error` as the final error of the run
+    pub fn render(self, error: &anyhow::Error) -> String {
+        match self {
+            ErrorRendering::Short => match self.hint([error]) {
+                Some(hint) => format!("Error: {error}\n{hint}"),
+                None => format!("Error: {error}"),
+            },
+
+            // Lists the causes and the backtrace
+            ErrorRendering::Verbose => format!("Error: {error:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use anyhow::Context;
+
+    use super::*;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+    use crate::package::PackageVersionConstraint;
+
+    #[test]
+    fn test_exit_codes() {
+        let not_found = ResolutionError::not_found(
+            pname("a"),
+            pversion("1"),
+            pname("b"),
+            &PackageVersionConstraint::try_from("=2").unwrap(),
+            vec![],
+        );
+        let cases: Vec<(anyhow::Error, i32)> = vec![
+            (anyhow!("something else"), EXIT_FAILURE),
+            (
+                ButidoError::ConfigInvalid(anyhow!("invalid")).into(),
+                EXIT_CONFIG_INVALID,
+            ),
+            (
+                ButidoError::PackageNotFound(String::from("a")).into(),
+                EXIT_PACKAGE_NOT_FOUND,
+            ),
+            (not_found.into(), EXIT_DEPENDENCY_UNRESOLVED),
+            (
+                ResolutionError::Conflict { packages: vec![] }.into(),
+                EXIT_DEPENDENCY_UNRESOLVED,
+            ),
+            (
+                ResolutionError::Cycle { packages: vec![] }.into(),
+                EXIT_DEPENDENCY_CYCLE,
+            ),
+            (
+                ButidoError::SourceMissing(PathBuf::from("/sources/a")).into(),
+                EXIT_SOURCE_MISSING,
+            ),
+            (
+                ButidoError::SourceCorrupt {
+                    expected: String::from("1"),
+                    computed: String::from("2"),
+                }
+                .into(),
+                EXIT_SOURCE_CORRUPT,
+            ),
+            (
+                ButidoError::Database(anyhow!("connection refused")).into(),
+                EXIT_DATABASE,
+            ),
+            (diesel::result::Error::NotFound.into(), EXIT_DATABASE),
+            (shiplift::Error::ConnectionNotUpgraded.into(), EXIT_DOCKER),
+        ];
+
+        for (error, code) in cases {
+            assert_eq!(exit_code(&error), code, "{error:#}");
+
+            // ... also if they are wrapped with more context
+            let error = Err::<(), _>(error).context("Context").unwrap_err();
+            assert_eq!(exit_code(&error), code, "{error:#}");
+        }
+    }
+
+    #[test]
+    fn test_transparent_errors_keep_their_message() {
+        let error = anyhow::Error::from(ButidoError::ConfigInvalid(
+            anyhow!("cause").context("Failed to validate the butido configuration"),
+        ));
+        assert_eq!(
+            format!("{error:#}"),
+            "Failed to validate the butido configuration: cause"
+        );
+    }
+
+    #[test]
+    fn test_error_rendering() {
+        let error = Err::<(), _>(anyhow!("Connection refused"))
+            .context("Downloading https://example.com/a.tar.gz")
+            .context("Downloading the sources of a 1")
+            .unwrap_err();
+
+        let short = ErrorRendering::Short;
+        assert_eq!(short.causes(&error), vec!["Downloading the sources of a 1"]);
+        assert_eq!(short.hint([&error]), Some(VERBOSE_ERRORS_HINT));
+        assert_eq!(
+            short.render(&error),
+            "Error: Downloading the sources of a 1\n\
+            (Rerun with --verbose-errors to see all causes)"
+        );
+
+        let verbose = ErrorRendering::Verbose;
+        assert_eq!(
+            verbose.causes(&error),
+            vec![
+                "Downloading the sources of a 1",
+                "Downloading https://example.com/a.tar.gz",
+                "Connection refused",
+            ]
+        );
+        assert_eq!(verbose.hint([&error]), None);
+        assert!(verbose.render(&error).starts_with(
+            "Error: Downloading the sources of a 1\n\
+            \n\
+            Caused by:\n    \
+            0: Downloading https://example.com/a.tar.gz\n    \
+            1: Connection refused"
+        ));
+
+        // Nothing is omitted from an error without causes
+        let single = anyhow!("Connection refused");
+        assert_eq!(short.hint([&single]), None);
+        assert_eq!(short.render(&single), "Error: Connection refused");
+    }
+}
@@ -147,10 +147,15 @@ pub struct ArtifactPath(PathBuf);
 
 impl ArtifactPath {
     pub fn new(p: PathBuf) -> Result<Self> {
-        if p.is_relative() {
-            Ok(ArtifactPath(p))
-        } else {
+        if !p.is_relative() {
             Err(anyhow!("Path is not relative: {}", p.display()))
+        } else if p
+            .components()
+            .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            Err(anyhow!("Path must not contain '..': {}", p.display()))
+        } else {
+            Ok(ArtifactPath(p))
         }
     }
 
@@ -220,3 +225,16 @@ impl<'a> std::fmt::Display for FullArtifactPathDisplay<'a> {
         write!(fmt, "{}/{}", self.0.display(), self.1.display())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artifact_path_must_stay_in_store() {
+        assert!(ArtifactPath::new(PathBuf::from("a-1.tar.gz")).is_ok());
+        assert!(ArtifactPath::new(PathBuf::from("./sub/a-1.tar.gz")).is_ok());
+        assert!(ArtifactPath::new(PathBuf::from("/a-1.tar.gz")).is_err());
+        assert!(ArtifactPath::new(PathBuf::from("sub/../../a-1.tar.gz")).is_err());
+    }
+}
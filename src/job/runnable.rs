@@ -47,6 +47,10 @@ pub struct RunnableJob {
 
     #[getset(get = "pub")]
     resources: Vec<JobResource>,
+
+    /// Appended to the name of the container the job runs in
+    #[getset(get = "pub")]
+    container_tag: Option<String>,
 }
 
 impl RunnableJob {
@@ -59,6 +63,7 @@ impl RunnableJob {
         config: &Configuration,
         git_author_env: Option<&(EnvironmentVariableName, String)>,
         git_commit_env: Option<&(EnvironmentVariableName, String)>,
+        container_tag: Option<&str>,
         dependencies: Vec<ArtifactPath>,
     ) -> Result<Self> {
         if config.containers().check_env_names() {
@@ -127,6 +132,7 @@ impl RunnableJob {
             image: job.image().clone(),
             resources,
             source_cache: source_cache.clone(),
+            container_tag: container_tag.map(String::from),
 
             script,
         })
@@ -46,6 +46,7 @@
 #[macro_use]
 extern crate diesel;
 
+use std::path::Path;
 use std::path::PathBuf;
 
 use anyhow::anyhow;
@@ -57,7 +58,7 @@ use clap::ArgMatches;
 // TODO: Drop the rust-inspect dependency once we bump the MSRV to 1.76:
 #[rustversion::since(1.76)]
 use result_inspect as _;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 mod cli;
 mod commands;
@@ -65,6 +66,7 @@ mod config;
 mod consts;
 mod db;
 mod endpoint;
+mod error;
 mod filestore;
 mod job;
 mod log;
@@ -77,8 +79,14 @@ mod ui;
 mod util;
 
 use crate::config::*;
+use crate::error::ButidoError;
+use crate::error::ErrorRendering;
 use crate::repository::Repository;
+use crate::util::parallelism::Parallelism;
 use crate::util::progress::ProgressBars;
+use crate::util::progress::ProgressMode;
+use crate::util::timings::Timings;
+use crate::util::timings::TimingsFormat;
 use indoc::concatdoc;
 
 pub const VERSION_LONG: &str = concatdoc! {"
@@ -89,8 +97,7 @@ pub const VERSION_LONG: &str = concatdoc! {"
     Debug Build:          ", env!("VERGEN_CARGO_DEBUG")
 };
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() {
     human_panic::setup_panic!(Metadata {
         name: env!("CARGO_PKG_NAME").into(),
         version: env!("CARGO_PKG_VERSION").into(),
@@ -116,6 +123,42 @@ async fn main() -> Result<()> {
         std::process::exit(0);
     }
 
+    if cli.get_flag("verbose_errors") {
+        ErrorRendering::Verbose.set_global();
+    }
+
+    if cli.get_flag("quiet") {
+        crate::util::summary::set_quiet();
+    }
+
+    if let Some(width) = cli.get_one::<usize>("width") {
+        crate::util::width::set_width_override(*width);
+    }
+
+    let json_diagnostics = cli
+        .get_one::<String>("diagnostics")
+        .is_some_and(|format| format == "json");
+
+    // All errors (including the ones of loading the configuration) are rendered and mapped to
+    // an exit code here
+    if let Err(e) = try_main(cli) {
+        report_error(&e, json_diagnostics);
+        std::process::exit(crate::error::exit_code(&e));
+    }
+}
+
+/// Print the error to stderr, as JSON if requested and it is a dependency resolution error
+fn report_error(e: &Error, json_diagnostics: bool) {
+    let resolution_error = e
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<crate::package::ResolutionError>());
+    match resolution_error.map(serde_json::to_string) {
+        Some(Ok(json)) if json_diagnostics => eprintln!("{json}"),
+        _ => eprintln!("{}", ErrorRendering::global().render(e)),
+    }
+}
+
+fn try_main(cli: ArgMatches) -> Result<()> {
     let repo = git2::Repository::open(PathBuf::from(".")).map_err(|e| match e.code() {
         git2::ErrorCode::NotFound => {
             eprintln!("Butido must be executed in the top-level of the git repository");
@@ -128,6 +171,56 @@ async fn main() -> Result<()> {
         .workdir()
         .ok_or_else(|| anyhow!("Not a repository with working directory. Cannot do my job!"))?;
 
+    let config = load_config(&cli, repo_path).map_err(ButidoError::ConfigInvalid)?;
+
+    let parallelism = Parallelism::new(
+        cli.get_one::<String>("jobs")
+            .map(|s| s.parse::<usize>())
+            .transpose()?, // safe by clap
+        *config.parallelism(),
+    );
+    debug!("Using {} jobs by default", parallelism.jobs());
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(parallelism.jobs())
+        .build_global()
+        .context("Failed to set up the thread pool")?;
+
+    let timings = Timings::new(cli.get_one::<String>("timings").map(|format| {
+        if format == "json" {
+            TimingsFormat::Json
+        } else {
+            TimingsFormat::Text
+        }
+    }));
+    let metrics_file = cli.get_one::<String>("metrics_file").map(PathBuf::from);
+    let timings = if metrics_file.is_some() {
+        timings.with_metrics()
+    } else {
+        timings
+    };
+
+    let result = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(parallelism.jobs())
+        .enable_all()
+        .build()
+        .context("Failed to set up the async runtime")?
+        .block_on(run(cli, repo_path, config, parallelism, &timings));
+
+    // The metrics are also written if the run failed
+    if let Some(path) = metrics_file {
+        if let Err(e) = crate::util::metrics::write_metrics_file(&path, &timings) {
+            warn!("Failed to write the metrics: {:#}", e);
+        }
+    }
+    result
+}
+
+/// Load and validate the configuration of the repository at `repo_path`
+///
+/// The configuration files are merged with the XDG configuration, the profile and the environment
+/// variables.
+fn load_config(cli: &ArgMatches, repo_path: &Path) -> Result<Configuration> {
     let mut config = ::config::Config::default();
     config
         .merge(::config::File::from(repo_path.join("config.toml")).required(true))
@@ -152,6 +245,19 @@ async fn main() -> Result<()> {
         }
     }
 
+    // The profile is applied before the environment variables are merged, so that they still
+    // override all settings
+    let profile = cli
+        .get_one::<String>("profile")
+        .cloned()
+        .or_else(|| std::env::var("BUTIDO_PROFILE").ok())
+        .or_else(|| config.get_str("profile").ok());
+    if let Some(profile) = profile {
+        debug!("Using configuration profile: {}", profile);
+        apply_profile(&mut config, &profile)
+            .context("Failed to apply the configuration profile")?;
+    }
+
     config.merge(::config::Environment::with_prefix("BUTIDO"))?;
 
     // Check the "compatibility" setting before loading (type checking) the configuration so that
@@ -161,25 +267,87 @@ async fn main() -> Result<()> {
 
     let config = config
         .try_into::<NotValidatedConfiguration>()
-        .context("Failed to load (type check) the butido configuration")?
-        .validate()
-        .context("Failed to validate the butido configuration")?;
+        .context("Failed to load (type check) the butido configuration")?;
+    // `doctor` reports missing directories itself, together with the results of its other checks
+    if cli.subcommand_name() == Some("doctor") {
+        config.validate_without_filesystem_checks()
+    } else {
+        config.validate()
+    }
+    .context("Failed to validate the butido configuration")
+}
 
-    let hide_bars = cli.get_flag("hide_bars") || crate::util::stdout_is_pipe();
-    let progressbars = ProgressBars::setup(config.progress_format().clone(), hide_bars);
+async fn run(
+    cli: ArgMatches,
+    repo_path: &Path,
+    config: Configuration,
+    parallelism: Parallelism,
+    timings: &Timings,
+) -> Result<()> {
+    let progress_mode = if cli.get_flag("hide_bars") {
+        ProgressMode::Hidden
+    } else if let Some(mode) = cli.get_one::<String>("progress") {
+        mode.parse::<ProgressMode>()
+            .map_err(|_| anyhow!("Unknown progress mode: {}", mode))?
+    } else if crate::util::stdout_is_pipe() {
+        ProgressMode::Plain
+    } else {
+        ProgressMode::Fancy
+    };
+    let progressbars = ProgressBars::setup(
+        config.progress_format().clone(),
+        config.spinner_format().clone(),
+        progress_mode,
+    );
 
     let load_repo = || -> Result<Repository> {
+        let start = std::time::Instant::now();
         let bar = progressbars.bar()?;
         bar.set_message("Loading repository...");
-        let repo = Repository::load(repo_path, &bar).context("Loading the repository")?;
+        let follow_symlinks = cli.get_flag("follow_symlinks") || *config.repo_follow_symlinks();
+        let repo =
+            Repository::load(repo_path, follow_symlinks, &bar).context("Loading the repository")?;
+        let repo = repo.with_case_insensitive_names(*config.case_insensitive_package_names());
         bar.finish_with_message("Repository loading finished");
+        timings.add_since("Loading the repository", start);
         Ok(repo)
     };
 
     let db_connection_config = crate::db::DbConnectionConfig::parse(&config, &cli)?;
     match cli.subcommand() {
         Some(("generate-completions", matches)) => generate_completions(matches),
-        Some(("db", matches)) => crate::commands::db(db_connection_config, &config, matches)?,
+        Some(("config", matches)) => {
+            crate::commands::config(matches).context("config command failed")?
+        }
+        Some(("schema", _)) => crate::commands::schema().context("schema command failed")?,
+        Some(("themes", matches)) => {
+            crate::commands::themes(matches).context("themes command failed")?
+        }
+        Some(("db", matches)) => match matches.subcommand() {
+            Some(("submit", submit_matches)) if submit_matches.get_flag("rebuild") => {
+                let submit_id = submit_matches
+                    .get_one::<String>("submit")
+                    .map(|s| uuid::Uuid::parse_str(s))
+                    .unwrap() // safe by clap
+                    .context("Parsing submit UUID")?;
+                let pool = db_connection_config.establish_pool()?;
+                let repo = load_repo()?;
+
+                crate::commands::rebuild(
+                    &submit_id,
+                    progressbars,
+                    pool,
+                    &config,
+                    repo,
+                    repo_path,
+                    parallelism,
+                    timings,
+                )
+                .await
+                .context("rebuild of submit failed")?
+            }
+            _ => crate::commands::db(db_connection_config, &config, matches)?,
+        },
         Some(("build", matches)) => {
             let pool = db_connection_config.establish_pool()?;
 
@@ -193,6 +361,8 @@ async fn main() -> Result<()> {
                 &config,
                 repo,
                 repo_path,
+                parallelism,
+                timings,
             )
             .await
             .context("build command failed")?
@@ -242,15 +412,23 @@ async fn main() -> Result<()> {
 
         Some(("source", matches)) => {
             let repo = load_repo()?;
-            crate::commands::source(matches, &config, repo, progressbars)
+            crate::commands::source(matches, &config, repo, progressbars, parallelism, timings)
                 .await
                 .context("source command failed")?
         }
 
         Some(("release", matches)) => {
+            let start = std::time::Instant::now();
             crate::commands::release(db_connection_config, &config, matches)
                 .await
-                .context("release command failed")?
+                .context("release command failed")?;
+            timings.add_since("Releasing", start);
+        }
+
+        Some(("staging", matches)) => {
+            crate::commands::staging(db_connection_config, matches, &config)
+                .await
+                .context("staging command failed")?
         }
 
         Some(("lint", matches)) => {
@@ -260,6 +438,20 @@ async fn main() -> Result<()> {
                 .context("lint command failed")?
         }
 
+        Some(("repo", matches)) => {
+            let repo = load_repo()?;
+            crate::commands::repo(matches, &config, repo)
+                .await
+                .context("repo command failed")?
+        }
+
+        Some(("tree-of", matches)) if matches.contains_id("load_dag") => {
+            let path = matches.get_one::<String>("load_dag").unwrap(); // safe by contains_id()
+            crate::commands::tree_of_dump(Path::new(path), matches, &config)
+                .await
+                .context("tree-of command failed")?
+        }
+
         Some(("tree-of", matches)) => {
             let repo = load_repo()?;
             crate::commands::tree_of(matches, repo, &config)
@@ -275,9 +467,15 @@ async fn main() -> Result<()> {
                 .context("metrics command failed")?
         }
 
+        Some(("doctor", _)) => crate::commands::doctor(db_connection_config, &config)
+            .await
+            .context("doctor command failed")?,
         Some(("endpoint", matches)) => crate::commands::endpoint(matches, &config, progressbars)
             .await
             .context("endpoint command failed")?,
+        Some(("container", matches)) => crate::commands::container(matches, &config)
+            .await
+            .context("container command failed")?,
         Some((other, _)) => {
             error!("Unknown subcommand: {}", other);
             error!("Use --help to find available subcommands");
@@ -290,7 +488,7 @@ async fn main() -> Result<()> {
         }
     }
 
-    Ok(())
+    timings.print()
 }
 
 fn generate_completions(matches: &ArgMatches) {
@@ -312,3 +510,37 @@ fn generate_completions(matches: &ArgMatches) {
         print_completions(shell, &mut cli::cli());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::tests::temp_dir;
+
+    #[test]
+    fn test_invalid_config_exit_code() {
+        let dir = temp_dir();
+        let cli = cli::cli().get_matches_from(["butido", "db", "setup"]);
+
+        let mut errors = vec![];
+        for content in ["this is [not toml", "unknown_setting = 1\n"] {
+            std::fs::write(dir.join("config.toml"), content).unwrap();
+            errors.push(
+                load_config(&cli, &dir)
+                    .map(|_| ())
+                    .map_err(ButidoError::ConfigInvalid)
+                    .map_err(Error::from)
+                    .unwrap_err(),
+            );
+        }
+
+        for e in errors {
+            assert_eq!(
+                crate::error::exit_code(&e),
+                crate::error::EXIT_CONFIG_INVALID
+            );
+            let rendered = ErrorRendering::Short.render(&e);
+            assert!(rendered.starts_with("Error: "), "{rendered}");
+            assert!(!rendered.contains("Caused by"), "{rendered}");
+        }
+    }
+}
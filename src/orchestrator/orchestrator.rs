@@ -37,6 +37,7 @@ use uuid::Uuid;
 
 use crate::config::Configuration;
 use crate::db::models as dbmodels;
+use crate::endpoint::ContainerCleanup;
 use crate::endpoint::EndpointConfiguration;
 use crate::endpoint::EndpointScheduler;
 use crate::filestore::ArtifactPath;
@@ -48,6 +49,8 @@ use crate::job::RunnableJob;
 use crate::orchestrator::util::*;
 use crate::source::SourceCache;
 use crate::util::progress::ProgressBars;
+use crate::util::progress::StageProgress;
+use crate::util::timings::Timings;
 use crate::util::EnvironmentVariableName;
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
@@ -167,6 +170,9 @@ pub struct Orchestrator<'a> {
     config: &'a Configuration,
     repository: Repository,
     database: Pool<ConnectionManager<PgConnection>>,
+    container_tag: Option<String>,
+    timings: Timings,
+    stage: Option<StageProgress>,
 }
 
 #[derive(TypedBuilder)]
@@ -182,6 +188,22 @@ pub struct OrchestratorSetup<'a> {
     log_dir: Option<PathBuf>,
     config: &'a Configuration,
     repository: Repository,
+
+    /// Appended to the names of the build containers
+    #[builder(default)]
+    container_tag: Option<String>,
+
+    /// What happens to the build containers after the jobs ran
+    #[builder(default)]
+    container_cleanup: ContainerCleanup,
+
+    /// Records how long each job took
+    #[builder(default)]
+    timings: Timings,
+
+    /// Reports the fraction of the finished jobs
+    #[builder(default)]
+    stage: Option<StageProgress>,
 }
 
 impl<'a> OrchestratorSetup<'a> {
@@ -193,6 +215,9 @@ impl<'a> OrchestratorSetup<'a> {
             self.database.clone(),
             self.submit.clone(),
             self.log_dir,
+            *self.config.container_labels(),
+            self.container_cleanup,
+            self.config.docker().endpoint_scheduling(),
         )
         .await?;
 
@@ -206,6 +231,9 @@ impl<'a> OrchestratorSetup<'a> {
             config: self.config,
             database: self.database,
             repository: self.repository,
+            container_tag: self.container_tag,
+            timings: self.timings,
+            stage: self.stage,
         })
     }
 }
@@ -267,13 +295,7 @@ impl<'a> Orchestrator<'a> {
     }
 
     async fn run_tree(self) -> Result<(Vec<ArtifactPath>, HashMap<Uuid, Error>)> {
-        let multibar = Arc::new({
-            let mp = indicatif::MultiProgress::new();
-            if self.progress_generator.hide() {
-                mp.set_draw_target(indicatif::ProgressDrawTarget::hidden());
-            }
-            mp
-        });
+        let multibar = Arc::new(self.progress_generator.multi_progress());
 
         let git_author_env = {
             self.config
@@ -332,11 +354,13 @@ impl<'a> Orchestrator<'a> {
                     config: self.config,
                     git_author_env: git_author_env.as_ref(),
                     git_commit_env: git_commit_env.as_ref(),
+                    container_tag: self.container_tag.as_deref(),
                     source_cache: &self.source_cache,
                     scheduler: &self.scheduler,
                     staging_store: self.staging_store.clone(),
                     release_stores: self.release_stores.clone(),
                     database: self.database.clone(),
+                    timings: &self.timings,
                 };
 
                 Ok((
@@ -434,7 +458,7 @@ impl<'a> Orchestrator<'a> {
         //
         // The JobTask::run implementation handles the rest, we just have to wait for all futures
         // to succeed.
-        let running_jobs = jobs
+        let mut running_jobs = jobs
             .into_iter()
             .map(|prep| {
                 trace!("Creating JobTask for = {}", prep.1.jobdef.job.uuid());
@@ -448,9 +472,17 @@ impl<'a> Orchestrator<'a> {
             .inspect(|task| trace!("Running: {}", task.jobdef.job.uuid()))
             .map(|task| task.run())
             .collect::<futures::stream::FuturesUnordered<_>>();
-        debug!("Built {} jobs", running_jobs.len());
-
-        running_jobs.collect::<Result<()>>().await?;
+        let job_count = running_jobs.len();
+        debug!("Built {} jobs", job_count);
+
+        let mut finished_jobs = 0;
+        while let Some(result) = running_jobs.next().await {
+            result?;
+            finished_jobs += 1;
+            if let Some(stage) = self.stage.as_ref() {
+                stage.set_fraction(finished_jobs as f64 / job_count as f64);
+            }
+        }
         trace!("All jobs finished");
         match root_receiver.recv().await {
             None => Err(anyhow!("No result received...")),
@@ -481,11 +513,13 @@ struct TaskPreparation<'a> {
     config: &'a Configuration,
     git_author_env: Option<&'a (EnvironmentVariableName, String)>,
     git_commit_env: Option<&'a (EnvironmentVariableName, String)>,
+    container_tag: Option<&'a str>,
     source_cache: &'a SourceCache,
     scheduler: &'a EndpointScheduler,
     staging_store: Arc<RwLock<StagingStore>>,
     release_stores: Vec<Arc<ReleaseStore>>,
     database: Pool<ConnectionManager<PgConnection>>,
+    timings: &'a Timings,
 }
 
 /// Helper type for executing one job task
@@ -499,11 +533,13 @@ struct JobTask<'a> {
     config: &'a Configuration,
     git_author_env: Option<&'a (EnvironmentVariableName, String)>,
     git_commit_env: Option<&'a (EnvironmentVariableName, String)>,
+    container_tag: Option<&'a str>,
     source_cache: &'a SourceCache,
     scheduler: &'a EndpointScheduler,
     staging_store: Arc<RwLock<StagingStore>>,
     release_stores: Vec<Arc<ReleaseStore>>,
     database: Pool<ConnectionManager<PgConnection>>,
+    timings: &'a Timings,
 
     /// Channel where the dependencies arrive
     receiver: Receiver<JobResult>,
@@ -572,11 +608,13 @@ impl<'a> JobTask<'a> {
             config: prep.config,
             git_author_env: prep.git_author_env,
             git_commit_env: prep.git_commit_env,
+            container_tag: prep.container_tag,
             source_cache: prep.source_cache,
             scheduler: prep.scheduler,
             staging_store: prep.staging_store,
             release_stores: prep.release_stores,
             database: prep.database.clone(),
+            timings: prep.timings,
 
             receiver,
             sender,
@@ -827,6 +865,7 @@ impl<'a> JobTask<'a> {
             self.config,
             self.git_author_env,
             self.git_commit_env,
+            self.container_tag,
             dependency_artifacts,
         )?;
 
@@ -837,15 +876,22 @@ impl<'a> JobTask<'a> {
             self.jobdef.job.package().version()
         ));
         let job_uuid = *self.jobdef.job.uuid();
+        let stage = format!(
+            "Building {} {}",
+            self.jobdef.job.package().name(),
+            self.jobdef.job.package().version()
+        );
+        let start = std::time::Instant::now();
 
         // Schedule the job on the scheduler
-        match self
+        let result = self
             .scheduler
             .schedule_job(runnable, self.bar.clone())
             .await?
             .run()
-            .await?
-        {
+            .await?;
+        self.timings.add_since(stage, start);
+        match result {
             Err(e) => {
                 trace!(
                     "[{}]: Scheduler returned error = {:?}",
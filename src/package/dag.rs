@@ -9,14 +9,19 @@
 //
 
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::io::Result as IoResult;
 use std::io::Write;
+use std::rc::Rc;
 
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
+use colored::Colorize;
 use daggy::Walker;
 use getset::Getters;
 use indicatif::ProgressBar;
@@ -24,14 +29,23 @@ use itertools::Itertools;
 use ptree::Style;
 use ptree::TreeItem;
 use resiter::AndThen;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
 use tracing::trace;
+use tracing::warn;
 
 use crate::package::condition::ConditionCheckable;
 use crate::package::condition::ConditionData;
 use crate::package::dependency::ParseDependency;
+use crate::package::DisplayOptions;
 use crate::package::Package;
+use crate::package::PackageHighlight;
 use crate::package::PackageName;
 use crate::package::PackageVersionConstraint;
+use crate::package::ResolutionError;
+use crate::package::SerializedDag;
+use crate::package::VersionConflict;
 use crate::repository::Repository;
 
 #[derive(Debug, Getters)]
@@ -43,19 +57,49 @@ pub struct Dag {
     root_idx: daggy::NodeIndex,
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DependencyType {
     Build,
     Runtime,
 }
 
+/// How several versions of the same package in one DAG are handled, see
+/// `Dag::check_version_conflicts()`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyConflictPolicy {
+    /// Building the DAG fails
+    Error,
+
+    /// A warning is printed for every conflict, building continues as before this check existed
+    #[default]
+    Warn,
+
+    /// Several versions of a package are allowed
+    Allow,
+}
+
 impl Dag {
+    /// Create a Dag from an already built graph, e.g. one that was loaded from the `DagCache`
+    pub(in crate::package) fn from_parts(
+        dag: daggy::Dag<Package, DependencyType>,
+        root_idx: daggy::NodeIndex,
+    ) -> Self {
+        Dag { dag, root_idx }
+    }
+
     /// Builds the package/dependency DAG for the given package
+    ///
+    /// Fails if a chain of dependencies is longer than `max_depth`. Several versions of the same
+    /// package are handled according to the `conflict_policy`.
     pub fn for_root_package(
         p: Package,
         repo: &Repository,
         progress: Option<&ProgressBar>,
         conditional_data: &ConditionData<'_>, // required for selecting packages with conditional dependencies
+        max_depth: usize,
+        conflict_policy: DependencyConflictPolicy,
     ) -> Result<Self> {
         /// Helper fn to check the dependency condition of a dependency and parse the dependency
         /// into a tuple for further processing
@@ -112,14 +156,33 @@ impl Dag {
 
         /// Main helper function to build the DAG. Recursively resolves a package's dependencies
         /// and adds corresponding nodes to the DAG. The edges are added later in `add_edges()`.
+        ///
+        /// `path` is the chain of packages from the root to `p` (inclusive).
+        /// `constraints` are the combined version constraints of the dependencies that were
+        /// already processed, with the paths to the dependencies that required them.
+        #[allow(clippy::too_many_arguments)]
         fn add_sub_packages<'a>(
             repo: &'a Repository,
             mappings: &mut HashMap<&'a Package, daggy::NodeIndex>,
+            constraints: &mut HashMap<PackageName, (PackageVersionConstraint, Vec<String>)>,
             dag: &mut daggy::Dag<&'a Package, DependencyType>,
             p: &'a Package,
+            path: &mut Vec<&'a Package>,
+            max_depth: usize,
             progress: Option<&ProgressBar>,
             conditional_data: &ConditionData<'_>,
+            conflict_policy: DependencyConflictPolicy,
         ) -> Result<()> {
+            if path.len() > max_depth + 1 {
+                return Err(anyhow!(
+                    "Maximum dependency depth of {} exceeded: {}",
+                    max_depth,
+                    path.iter()
+                        .map(|p| format!("{} {}", p.name(), p.version()))
+                        .join(" -> ")
+                ));
+            }
+
             get_package_dependencies(p, conditional_data)
                 .and_then_ok(|(name, constr, kind)| {
                     trace!(
@@ -130,19 +193,66 @@ impl Dag {
                         constr,
                         kind
                     );
+
+                    // Packages that depend on the same package should agree on its version. If
+                    // they don't, resolution fails with the `Error` policy. Otherwise both
+                    // versions are added and the conflict is reported by
+                    // `check_version_conflicts()`.
+                    let dependency_path = path
+                        .iter()
+                        .map(|p| format!("{} {}", p.name(), p.version()))
+                        .chain(std::iter::once(format!("{name} {constr}")))
+                        .join(" -> ");
+                    let constr = match constraints.get_mut(&name) {
+                        Some((other_constr, paths)) => match other_constr.intersect(&constr) {
+                            Some(intersection) => {
+                                *other_constr = intersection.clone();
+                                paths.push(dependency_path);
+                                intersection
+                            }
+                            None if conflict_policy == DependencyConflictPolicy::Error => {
+                                paths.push(dependency_path);
+                                return Err(ResolutionError::Conflict {
+                                    packages: vec![VersionConflict {
+                                        name: name.clone(),
+                                        paths: paths.clone(),
+                                    }],
+                                }
+                                .into());
+                            }
+                            None => {
+                                trace!(
+                                    "{} {} requires {} {}, but {} was already required",
+                                    p.name(),
+                                    p.version(),
+                                    name,
+                                    constr,
+                                    other_constr
+                                );
+                                constr
+                            }
+                        },
+                        None => {
+                            constraints
+                                .insert(name.clone(), (constr.clone(), vec![dependency_path]));
+                            constr
+                        }
+                    };
+
                     let packs = repo.find_with_version(&name, &constr);
                     trace!(
                         "Found the following matching packages in the repo: {:?}",
                         packs
                     );
                     if packs.is_empty() {
-                        return Err(anyhow!(
-                            "Couldn't find the following dependency of {} {} in the repo: {} {}",
-                            p.name(),
-                            p.version(),
-                            name,
-                            constr
-                        ));
+                        return Err(ResolutionError::not_found(
+                            p.name().clone(),
+                            p.version().clone(),
+                            name.clone(),
+                            &constr,
+                            repo.find_all_versions(&name).into_iter().cloned().collect(),
+                        )
+                        .into());
                     }
 
                     // Check if we already created a DAG node for any of the matching packages and
@@ -167,7 +277,21 @@ impl Dag {
                             mappings.insert(p, idx);
 
                             trace!("Recursing for: {:?}", p);
-                            add_sub_packages(repo, mappings, dag, p, progress, conditional_data)
+                            path.push(p);
+                            let result = add_sub_packages(
+                                repo,
+                                mappings,
+                                constraints,
+                                dag,
+                                p,
+                                path,
+                                max_depth,
+                                progress,
+                                conditional_data,
+                                conflict_policy,
+                            );
+                            path.pop();
+                            result
                         })
                     } else {
                         Ok(())
@@ -176,6 +300,41 @@ impl Dag {
                 .collect::<Result<()>>()
         }
 
+        /// Helper fn to get the cycle that an edge from `from` to `to` would create, as a path
+        /// from `from` back to itself
+        fn cycle(
+            dag: &daggy::Dag<&Package, DependencyType>,
+            from: daggy::NodeIndex,
+            to: daggy::NodeIndex,
+        ) -> Vec<String> {
+            let mut predecessors = HashMap::new();
+            let mut queue = VecDeque::from([to]);
+            while let Some(idx) = queue.pop_front() {
+                if idx == from {
+                    break;
+                }
+                for (_, child) in dag.children(idx).iter(dag) {
+                    if child != to && !predecessors.contains_key(&child) {
+                        predecessors.insert(child, idx);
+                        queue.push_back(child);
+                    }
+                }
+            }
+
+            let mut path = vec![from];
+            let mut idx = from;
+            while let Some(predecessor) = predecessors.get(&idx) {
+                path.push(*predecessor);
+                idx = *predecessor;
+            }
+            path.push(from);
+            path.iter()
+                .rev()
+                .filter_map(|idx| dag.node_weight(*idx))
+                .map(|p| format!("{} {}", p.name(), p.version()))
+                .collect()
+        }
+
         // Helper fn to add the edges to the DAG with all nodes.
         // TODO: It seems easier and more efficient to do this in `add_sub_packages` as well (it
         // makes that function more complex but doing it separately is weird).
@@ -187,15 +346,29 @@ impl Dag {
             for (package, idx) in mappings {
                 get_package_dependencies(package, conditional_data)
                     .and_then_ok(|(dep_name, dep_constr, dep_kind)| {
-                        mappings
+                        let candidates = mappings
                             .iter()
                             .filter(|(pkg, _)| {
                                 *pkg.name() == dep_name && dep_constr.matches(pkg.version())
                             })
+                            .collect::<Vec<_>>();
+                        // Like `Repository::find_with_version()`, prefer the exact version
+                        let has_exact_match = candidates
+                            .iter()
+                            .any(|(pkg, _)| dep_constr.matches_exactly(pkg.version()));
+                        candidates
+                            .into_iter()
+                            .filter(|(pkg, _)| {
+                                !has_exact_match || dep_constr.matches_exactly(pkg.version())
+                            })
                             .try_for_each(|(dep, dep_idx)| {
                                 dag.add_edge(*idx, *dep_idx, dep_kind.clone())
                                     .map(|_| ())
-                                    .map_err(Error::from)
+                                    .map_err(|_| {
+                                        Error::from(ResolutionError::Cycle {
+                                            packages: cycle(dag, *idx, *dep_idx),
+                                        })
+                                    })
                                     .with_context(|| {
                                         anyhow!(
                                             "Failed to add package dependency DAG edge \
@@ -224,22 +397,108 @@ impl Dag {
         add_sub_packages(
             repo,
             &mut mappings,
+            &mut HashMap::new(),
             &mut dag,
             &p,
+            &mut vec![&p],
+            max_depth,
             progress,
             conditional_data,
+            conflict_policy,
         )?;
         trace!("Adding the dependency edges to the DAG for package {:?}", p);
         add_edges(&mappings, &mut dag, conditional_data)?;
         trace!("Finished building the package DAG");
 
-        Ok(Dag {
+        let dag = Dag {
             dag: dag.map(
                 |_, p: &&Package| -> Package { (*p).clone() },
                 |_, e| (*e).clone(),
             ),
             root_idx,
-        })
+        };
+        dag.check_version_conflicts(conflict_policy)?;
+        Ok(dag)
+    }
+
+    /// The packages of which several versions are in the DAG
+    ///
+    /// Returns the name of each such package with a path from the root package to each of its
+    /// versions, ordered by name and version.
+    pub fn version_conflicts(&self) -> Vec<VersionConflict> {
+        // The shortest path from the root to every package, as the predecessor of each package
+        let mut predecessors = HashMap::new();
+        let mut seen = HashSet::from([self.root_idx]);
+        let mut queue = VecDeque::from([self.root_idx]);
+        while let Some(idx) = queue.pop_front() {
+            for (_, child) in self.dag.children(idx).iter(&self.dag) {
+                if seen.insert(child) {
+                    predecessors.insert(child, idx);
+                    queue.push_back(child);
+                }
+            }
+        }
+
+        let path_to = |mut idx| {
+            let mut path = vec![idx];
+            while let Some(predecessor) = predecessors.get(&idx) {
+                path.push(*predecessor);
+                idx = *predecessor;
+            }
+            path.iter()
+                .rev()
+                .filter_map(|idx| self.dag.node_weight(*idx))
+                .map(|p| format!("{} {}", p.name(), p.version()))
+                .join(" -> ")
+        };
+
+        let mut by_name = BTreeMap::<&PackageName, Vec<(&Package, daggy::NodeIndex)>>::new();
+        for idx in self.dag.graph().node_indices() {
+            if let Some(p) = self.dag.node_weight(idx) {
+                by_name.entry(p.name()).or_default().push((p, idx));
+            }
+        }
+
+        by_name
+            .into_iter()
+            .filter(|(_, versions)| versions.len() > 1)
+            .map(|(name, mut versions)| {
+                versions.sort_by_key(|(p, _)| p.version());
+                VersionConflict {
+                    name: name.clone(),
+                    paths: versions.into_iter().map(|(_, idx)| path_to(idx)).collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Report the packages of which several versions are in the DAG according to the `policy`
+    pub fn check_version_conflicts(&self, policy: DependencyConflictPolicy) -> Result<()> {
+        if policy == DependencyConflictPolicy::Allow {
+            return Ok(());
+        }
+
+        let conflicts = self.version_conflicts();
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+
+        match policy {
+            DependencyConflictPolicy::Error => Err(ResolutionError::Conflict {
+                packages: conflicts,
+            }
+            .into()),
+            _ => {
+                conflicts.iter().for_each(|conflict| {
+                    warn!(
+                        "Several versions of {} are required: {}",
+                        conflict.name,
+                        conflict.paths.join(", ")
+                    )
+                });
+                Ok(())
+            }
+        }
     }
 
     /// Get all packages in the tree by reference
@@ -255,13 +514,31 @@ impl Dag {
             .collect()
     }
 
-    pub fn display(&self) -> DagDisplay {
-        DagDisplay(self, self.root_idx, None)
+    pub fn display<'a>(&'a self, opts: &'a DisplayOptions) -> DagDisplay<'a> {
+        DagDisplay(self, self.root_idx, None, opts, Rc::new(HashSet::new()), 0)
+    }
+
+    /// Like `display()`, the packages on the paths to the `highlight`ed packages are colored
+    pub fn display_highlighted<'a>(
+        &'a self,
+        opts: &'a DisplayOptions,
+        highlight: &PackageHighlight,
+    ) -> DagDisplay<'a> {
+        // The positions of the serialized packages are the node indices
+        let highlighted = SerializedDag::from(self).highlighted(highlight);
+        DagDisplay(self, self.root_idx, None, opts, Rc::new(highlighted), 0)
     }
 }
 
 #[derive(Clone)]
-pub struct DagDisplay<'a>(&'a Dag, daggy::NodeIndex, Option<daggy::EdgeIndex>);
+pub struct DagDisplay<'a>(
+    &'a Dag,
+    daggy::NodeIndex,
+    Option<daggy::EdgeIndex>,
+    &'a DisplayOptions,
+    Rc<HashSet<usize>>,
+    usize, // the depth in the tree, to fit the label into the max. width
+);
 
 impl<'a> TreeItem for DagDisplay<'a> {
     type Child = Self;
@@ -291,14 +568,38 @@ impl<'a> TreeItem for DagDisplay<'a> {
             &DependencyType::Build => "*",
             _ => "",
         };
-        write!(f, "{}{} {}", extra_info, p.name(), p.version())
+        let label = format!("{}{}", extra_info, p.display(self.3));
+        let label = match self.3.max_width() {
+            // ptree indents each level of the tree
+            Some(width) => {
+                let indent = self.3.print_config().indent;
+                let width = width.saturating_sub(self.5 * indent);
+                crate::util::width::ellipsize(&label, width).into_owned()
+            }
+            None => label,
+        };
+        if self.4.contains(&self.1.index()) {
+            write!(f, "{}", label.red().bold())
+        } else {
+            write!(f, "{label}")
+        }
     }
 
     fn children(&self) -> Cow<[Self::Child]> {
         let c = self.0.dag.children(self.1);
         Cow::from(
             c.iter(&self.0.dag)
-                .map(|(edge_idx, node_idx)| DagDisplay(self.0, node_idx, Some(edge_idx)))
+                .map(|(edge_idx, node_idx)| {
+                    let depth = self.5 + 1;
+                    DagDisplay(
+                        self.0,
+                        node_idx,
+                        Some(edge_idx),
+                        self.3,
+                        self.4.clone(),
+                        depth,
+                    )
+                })
                 .collect::<Vec<_>>(),
         )
     }
@@ -315,6 +616,7 @@ mod tests {
     use crate::package::tests::package;
     use crate::package::tests::pname;
     use crate::package::tests::pversion;
+    use crate::package::tests::repository;
     use crate::package::Dependencies;
     use crate::package::Dependency;
     use crate::util::docker::ImageName;
@@ -341,7 +643,14 @@ mod tests {
             env: &[],
         };
 
-        let r = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
+        let r = Dag::for_root_package(
+            p1,
+            &repo,
+            Some(&progress),
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        );
 
         assert!(r.is_ok());
     }
@@ -379,7 +688,14 @@ mod tests {
             env: &[],
         };
 
-        let dag = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
+        let dag = Dag::for_root_package(
+            p1,
+            &repo,
+            Some(&progress),
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        );
         assert!(dag.is_ok());
         let dag = dag.unwrap();
         let ps = dag.all_packages();
@@ -390,6 +706,289 @@ mod tests {
         assert!(ps.iter().any(|p| *p.version() == pversion("2")));
     }
 
+    #[test]
+    fn test_display_max_width() {
+        let mut btree = BTreeMap::new();
+        let mut root = package("root-package", "1", "https://rust-lang.org", "123");
+        let dependency = package("long-dependency-name", "2", "https://rust-lang.org", "124");
+        root.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+            String::from("long-dependency-name =2"),
+        )));
+        for p in [&root, &dependency] {
+            btree.insert((p.name().clone(), p.version().clone()), p.clone());
+        }
+        let repo = Repository::from(btree);
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+        let dag = Dag::for_root_package(
+            root,
+            &repo,
+            None,
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap();
+
+        let render = |max_width| {
+            let opts = DisplayOptions::default().with_max_width(max_width);
+            let mut out = Vec::new();
+            ptree::write_tree(&dag.display(&opts), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let line_widths = |tree: &str| tree.lines().map(|l| l.chars().count()).collect::<Vec<_>>();
+
+        let full = render(None);
+        assert_eq!(full, "root-package 1\n└─ long-dependency-name 2\n");
+        assert_eq!(render(Some(100)), full);
+        assert_eq!(render(Some(25)), full);
+        assert_eq!(
+            render(Some(24)),
+            "root-package 1\n└─ long-dependency-name…\n"
+        );
+        assert_eq!(line_widths(&render(Some(10))), vec![10, 10]);
+        assert_eq!(render(Some(3)), "ro…\n└─ \n");
+    }
+
+    #[test]
+    fn test_missing_dependency_lists_available_versions() {
+        let (a, repo) = diamond_repo("=3");
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+
+        let err = Dag::for_root_package(
+            a,
+            &repo,
+            None,
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Couldn't find the following dependency of b 1 in the repo: c =3 \
+             (available versions: 1, 2)"
+        );
+    }
+
+    #[test]
+    fn test_dependency_cycle() {
+        let mut btree = BTreeMap::new();
+        for (name, dependency) in [("a", "b =1"), ("b", "c =1"), ("c", "a =1")] {
+            let mut pack = package(name, "1", "https://rust-lang.org", "123");
+            pack.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+                String::from(dependency),
+            )));
+            btree.insert((pname(name), pversion("1")), pack);
+        }
+        let root = btree[&(pname("a"), pversion("1"))].clone();
+        let repo = Repository::from(btree);
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+
+        let err = Dag::for_root_package(
+            root,
+            &repo,
+            None,
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap_err();
+        let Some(ResolutionError::Cycle { packages }) = err.downcast_ref::<ResolutionError>()
+        else {
+            panic!("Not a cycle: {err:?}");
+        };
+        // The cycle is detected at whichever edge is added last
+        assert_eq!(packages.len(), 4);
+        assert_eq!(packages.first(), packages.last());
+        let mut unique = packages[..3].to_vec();
+        unique.sort();
+        assert_eq!(unique, vec!["a 1", "b 1", "c 1"]);
+    }
+
+    #[test]
+    fn test_max_dependency_depth() {
+        // A linear chain p0 -> p1 -> ... -> p5
+        let mut btree = BTreeMap::new();
+        for i in 0..6 {
+            let mut pack = package(&format!("p{i}"), "1", "https://rust-lang.org", "123");
+            if i < 5 {
+                let d = Dependency::from(format!("p{} =1", i + 1));
+                pack.set_dependencies(Dependencies::with_runtime_dependency(d));
+            }
+            btree.insert((pname(&format!("p{i}")), pversion("1")), pack);
+        }
+        let root = btree[&(pname("p0"), pversion("1"))].clone();
+        let repo = Repository::from(btree);
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+
+        assert!(Dag::for_root_package(
+            root.clone(),
+            &repo,
+            None,
+            &condition_data,
+            5,
+            DependencyConflictPolicy::Error
+        )
+        .is_ok());
+
+        let err = Dag::for_root_package(
+            root,
+            &repo,
+            None,
+            &condition_data,
+            3,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Maximum dependency depth of 3 exceeded: p0 1 -> p1 1 -> p2 1 -> p3 1 -> p4 1"
+        );
+    }
+
+    /// "a" depends on "b" and "c", "b" depends on "c" with `c_constraint`
+    fn diamond_repo(c_constraint: &str) -> (Package, Repository) {
+        let mut btree = BTreeMap::new();
+        let mut a = package("a", "1", "https://rust-lang.org", "123");
+        a.set_dependencies(Dependencies::with_runtime_dependencies(vec![
+            Dependency::from(String::from("b =1")),
+            Dependency::from(String::from("c =1")),
+        ]));
+        let mut b = package("b", "1", "https://rust-lang.org", "124");
+        b.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+            format!("c {c_constraint}"),
+        )));
+        for p in [
+            a.clone(),
+            b,
+            package("c", "1", "https://rust-lang.org", "125"),
+            package("c", "2", "https://rust-lang.org", "126"),
+        ] {
+            btree.insert((p.name().clone(), p.version().clone()), p);
+        }
+        (a, Repository::from(btree))
+    }
+
+    #[test]
+    fn test_dependency_with_compatible_constraints() {
+        let (a, repo) = diamond_repo("=1");
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+
+        let dag = Dag::for_root_package(
+            a,
+            &repo,
+            None,
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap();
+        let mut packages = dag
+            .all_packages()
+            .iter()
+            .map(|p| format!("{} {}", p.name(), p.version()))
+            .collect::<Vec<_>>();
+        packages.sort();
+        assert_eq!(packages, vec!["a 1", "b 1", "c 1"]);
+    }
+
+    #[test]
+    fn test_dependency_with_incompatible_constraints() {
+        let (a, repo) = diamond_repo("=2");
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+        let build =
+            |policy| Dag::for_root_package(a.clone(), &repo, None, &condition_data, 100, policy);
+
+        // Resolution fails as soon as the constraints cannot be satisfied both
+        let err = build(DependencyConflictPolicy::Error).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("Several versions of c are required: a 1 -> b 1 -> c =2, a 1 -> c =1"),
+            "{err}"
+        );
+
+        for policy in [
+            DependencyConflictPolicy::default(),
+            DependencyConflictPolicy::Warn,
+            DependencyConflictPolicy::Allow,
+        ] {
+            let dag = build(policy).unwrap();
+            let conflicts = dag.version_conflicts();
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].name, pname("c"));
+            assert_eq!(conflicts[0].paths, vec!["a 1 -> c 1", "a 1 -> b 1 -> c 2"]);
+        }
+    }
+
+    #[test]
+    fn test_dependency_constraints_are_combined() {
+        // "b" accepts any build of "x 1", "c" narrows it to "1+a", which "d" contradicts
+        let mut a = package("a", "1", "https://rust-lang.org", "123");
+        a.set_dependencies(Dependencies::with_runtime_dependencies(vec![
+            Dependency::from(String::from("b =1")),
+            Dependency::from(String::from("c =1")),
+            Dependency::from(String::from("d =1")),
+        ]));
+        let mut repo = vec![a.clone()];
+        for (name, x_constraint) in [("b", "=1"), ("c", "=1+a"), ("d", "=1+b")] {
+            let mut p = package(name, "1", "https://rust-lang.org", "124");
+            p.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+                format!("x {x_constraint}"),
+            )));
+            repo.push(p);
+        }
+        repo.push(package("x", "1+a", "https://rust-lang.org", "125"));
+        repo.push(package("x", "1+b", "https://rust-lang.org", "126"));
+        let repo = repository(repo);
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+
+        let err = Dag::for_root_package(
+            a,
+            &repo,
+            None,
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap_err();
+        let Some(ResolutionError::Conflict { packages }) = err.downcast_ref::<ResolutionError>()
+        else {
+            panic!("Not a conflict: {err}");
+        };
+        assert_eq!(
+            packages,
+            &vec![VersionConflict {
+                name: pname("x"),
+                paths: vec![
+                    String::from("a 1 -> b 1 -> x =1"),
+                    String::from("a 1 -> c 1 -> x =1+a"),
+                    String::from("a 1 -> d 1 -> x =1+b"),
+                ],
+            }]
+        );
+    }
+
     #[test]
     fn test_add_deep_package_tree() {
         let mut btree = BTreeMap::new();
@@ -473,7 +1072,14 @@ mod tests {
             env: &[],
         };
 
-        let r = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
+        let r = Dag::for_root_package(
+            p1,
+            &repo,
+            Some(&progress),
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        );
         assert!(r.is_ok());
         let r = r.unwrap();
         let ps = r.all_packages();
@@ -623,7 +1229,14 @@ mod tests {
             env: &[],
         };
 
-        let r = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
+        let r = Dag::for_root_package(
+            p1,
+            &repo,
+            Some(&progress),
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        );
         assert!(r.is_ok());
         let r = r.unwrap();
         let ps = r.all_packages();
@@ -735,7 +1348,14 @@ mod tests {
             env: &[],
         };
 
-        let r = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
+        let r = Dag::for_root_package(
+            p1,
+            &repo,
+            Some(&progress),
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        );
         assert!(r.is_ok());
         let r = r.unwrap();
         let ps = r.all_packages();
@@ -794,7 +1414,14 @@ mod tests {
 
         let progress = ProgressBar::hidden();
 
-        let dag = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
+        let dag = Dag::for_root_package(
+            p1,
+            &repo,
+            Some(&progress),
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        );
         assert!(dag.is_ok());
         let dag = dag.unwrap();
         let ps = dag.all_packages();
@@ -834,7 +1461,14 @@ mod tests {
 
         let progress = ProgressBar::hidden();
 
-        let dag = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
+        let dag = Dag::for_root_package(
+            p1,
+            &repo,
+            Some(&progress),
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        );
         assert!(dag.is_ok());
         let dag = dag.unwrap();
         let ps = dag.all_packages();
@@ -864,7 +1498,14 @@ mod tests {
 
         let progress = ProgressBar::hidden();
 
-        let dag = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
+        let dag = Dag::for_root_package(
+            p1,
+            &repo,
+            Some(&progress),
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        );
         assert!(dag.is_ok());
         let dag = dag.unwrap();
         let ps = dag.all_packages();
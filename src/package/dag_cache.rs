@@ -0,0 +1,289 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! A cache for package DAGs
+//!
+//! Building the DAG of a package is repeated for every `tree-of` run. The cache keeps the DAGs
+//! in memory and, if a directory is configured, stores them on disk so that later runs can reuse
+//! them. The entries on disk are only valid for the exact state of the repository they were
+//! built from, entries of other states are removed.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use sha2::Digest;
+use tracing::trace;
+use tracing::warn;
+
+use crate::package::condition::ConditionData;
+use crate::package::Dag;
+use crate::package::DependencyConflictPolicy;
+use crate::package::Package;
+use crate::package::SerializedDag;
+use crate::repository::Repository;
+
+pub struct DagCache {
+    /// The directory the DAGs of the current repository state are stored in, if any
+    dir: Option<PathBuf>,
+
+    /// A hash of all packages of the repository, empty if the DAGs are only cached in memory
+    repository_fingerprint: String,
+
+    memory: HashMap<String, Dag>,
+}
+
+impl DagCache {
+    /// Create a cache for the DAGs of the packages in `repo`
+    ///
+    /// If `dir` is set, the DAGs are stored in a subdirectory of `dir` for the current state of
+    /// the repository. The subdirectories of other states of the repository are removed.
+    pub fn new(repo: &Repository, dir: Option<&Path>) -> Result<Self> {
+        let Some(dir) = dir else {
+            return Ok(DagCache {
+                dir: None,
+                repository_fingerprint: String::new(),
+                memory: HashMap::new(),
+            });
+        };
+
+        let repository_fingerprint = repository_fingerprint(repo)?;
+        remove_outdated_entries(dir, &repository_fingerprint)?;
+        let dir = dir.join(&repository_fingerprint);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| anyhow!("Creating DAG cache directory {}", dir.display()))?;
+
+        Ok(DagCache {
+            dir: Some(dir),
+            repository_fingerprint,
+            memory: HashMap::new(),
+        })
+    }
+
+    /// The key of the DAG of `package` with the `condition_data`
+    pub fn key(&self, package: &Package, condition_data: &ConditionData<'_>) -> Result<String> {
+        let mut env = condition_data
+            .env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect::<Vec<_>>();
+        env.sort();
+
+        let key = serde_json::json!({
+            "repository": self.repository_fingerprint,
+            "package": package.name(),
+            "version": package.version(),
+            "image": condition_data.image_name.map(ToString::to_string),
+            "env": env,
+        });
+        Ok(sha256_hex(serde_json::to_string(&key)?.as_bytes()))
+    }
+
+    /// Get the DAG of `package` from the cache, or build it if it is not cached yet
+    ///
+    /// See `Dag::for_root_package()` for `max_depth` and `conflict_policy`. The policy is also
+    /// applied to DAGs that are loaded from the cache.
+    pub fn get_or_build(
+        &mut self,
+        package: &Package,
+        repo: &Repository,
+        condition_data: &ConditionData<'_>,
+        max_depth: usize,
+        conflict_policy: DependencyConflictPolicy,
+    ) -> Result<&Dag> {
+        let key = self.key(package, condition_data)?;
+        if !self.memory.contains_key(&key) {
+            let cached = self.load(&key, repo).unwrap_or_else(|e| {
+                warn!("Ignoring DAG cache entry {}: {:#}", key, e);
+                None
+            });
+            let dag = match cached {
+                Some(dag) => {
+                    dag.check_version_conflicts(conflict_policy)?;
+                    dag
+                }
+                None => {
+                    let dag = Dag::for_root_package(
+                        package.clone(),
+                        repo,
+                        None,
+                        condition_data,
+                        max_depth,
+                        conflict_policy,
+                    )?;
+                    if let Err(e) = self.store(&key, &dag) {
+                        warn!("Failed to store DAG in the cache: {:#}", e);
+                    }
+                    dag
+                }
+            };
+            self.memory.insert(key.clone(), dag);
+        }
+        Ok(&self.memory[&key])
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{key}.json")))
+    }
+
+    fn load(&self, key: &str, repo: &Repository) -> Result<Option<Dag>> {
+        let Some(path) = self.path(key).filter(|path| path.is_file()) else {
+            return Ok(None);
+        };
+
+        trace!("Loading cached DAG from {}", path.display());
+        std::fs::read_to_string(&path)
+            .map_err(Error::from)
+            .and_then(|content| serde_json::from_str(&content).map_err(Error::from))
+            .and_then(SerializedDag::from_json)
+            .and_then(|serialized| serialized.into_dag(repo))
+            .with_context(|| anyhow!("Loading {}", path.display()))
+            .map(Some)
+    }
+
+    fn store(&self, key: &str, dag: &Dag) -> Result<()> {
+        let Some(path) = self.path(key) else {
+            return Ok(());
+        };
+
+        trace!("Storing DAG in {}", path.display());
+        let content = SerializedDag::from(dag).to_json()?.to_string();
+        std::fs::write(&path, content).with_context(|| anyhow!("Writing {}", path.display()))
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", sha2::Sha256::digest(data))
+}
+
+/// A hash of all package definitions of the repository
+///
+/// The packages are hashed in their serialized form (with sorted keys), so every change of a
+/// package changes the fingerprint.
+fn repository_fingerprint(repo: &Repository) -> Result<String> {
+    let mut hasher = sha2::Sha256::new();
+    for package in repo.packages() {
+        hasher.update(serde_json::to_value(package)?.to_string().as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Remove the cached DAGs of all repository states except the current one
+///
+/// Only directories that are named like a fingerprint are removed, in case `dir` also contains
+/// other files.
+fn remove_outdated_entries(dir: &Path, current: &str) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| anyhow!("Reading DAG cache directory {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let is_fingerprint = path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+            n != current && n.len() == 64 && n.chars().all(|c| c.is_ascii_hexdigit())
+        });
+        if is_fingerprint && path.is_dir() {
+            trace!("Removing outdated DAG cache {}", path.display());
+            std::fs::remove_dir_all(&path)
+                .with_context(|| anyhow!("Removing outdated DAG cache {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::tests::ab_repo;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+    use crate::package::tests::temp_dir;
+    use crate::util::docker::ImageName;
+    use crate::util::EnvironmentVariableName;
+
+    /// A repository where "a" depends on "b", `b_hash` is the source hash of "b"
+    fn names(dag: &Dag) -> Vec<String> {
+        let mut names = dag
+            .all_packages()
+            .iter()
+            .map(|p| format!("{} {}", p.name(), p.version()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_key() {
+        let (a, repo) = ab_repo("124");
+        let image = ImageName::from("debian:bullseye");
+        let env = [
+            (EnvironmentVariableName::from("A"), String::from("1")),
+            (EnvironmentVariableName::from("B"), String::from("2")),
+        ];
+        let reversed_env = [env[1].clone(), env[0].clone()];
+        let data = |image_name, env| ConditionData { image_name, env };
+
+        let cache = DagCache::new(&repo, None).unwrap();
+        let key = cache.key(&a, &data(Some(&image), &env)).unwrap();
+
+        assert_eq!(key, cache.key(&a, &data(Some(&image), &env)).unwrap());
+        assert_eq!(
+            key,
+            cache.key(&a, &data(Some(&image), &reversed_env)).unwrap()
+        );
+        assert_ne!(key, cache.key(&a, &data(None, &env)).unwrap());
+        assert_ne!(key, cache.key(&a, &data(Some(&image), &env[..1])).unwrap());
+
+        let b = repo.find(&pname("b"), &pversion("2"))[0];
+        assert_ne!(key, cache.key(b, &data(Some(&image), &env)).unwrap());
+    }
+
+    #[test]
+    fn test_cache_is_invalidated_on_repository_change() {
+        let dir = temp_dir();
+        let data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+
+        let (a, repo) = ab_repo("124");
+        let mut cache = DagCache::new(&repo, Some(&dir)).unwrap();
+        let built = names(
+            cache
+                .get_or_build(&a, &repo, &data, 100, DependencyConflictPolicy::Error)
+                .unwrap(),
+        );
+        let key = cache.key(&a, &data).unwrap();
+
+        // A new process with the same repository loads the DAG from disk
+        let cache = DagCache::new(&repo, Some(&dir)).unwrap();
+        let loaded = cache.load(&key, &repo).unwrap().map(|dag| names(&dag));
+
+        // Changing a package changes the key and removes the outdated entries
+        let (changed_a, changed_repo) = ab_repo("125");
+        let changed_cache = DagCache::new(&changed_repo, Some(&dir)).unwrap();
+        let changed_key = changed_cache.key(&changed_a, &data).unwrap();
+        let outdated = cache.load(&key, &repo).unwrap();
+        let entries = std::fs::read_dir(&dir).unwrap().count();
+
+        assert_eq!(built, vec!["a 1", "b 2"]);
+        assert_eq!(loaded, Some(built));
+        assert_ne!(key, changed_key);
+        assert!(outdated.is_none());
+        assert_eq!(entries, 1);
+    }
+}
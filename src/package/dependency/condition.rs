@@ -12,6 +12,7 @@ use std::collections::BTreeMap;
 
 use anyhow::Result;
 use getset::Getters;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -27,7 +28,9 @@ use crate::util::EnvironmentVariableName;
 /// build image is used.
 /// All these settings are optional, of course.
 ///
-#[derive(Serialize, Deserialize, Getters, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(
+    Serialize, Deserialize, Getters, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, JsonSchema,
+)]
 pub struct Condition {
     #[serde(rename = "has_env", skip_serializing_if = "Option::is_none")]
     #[getset(get = "pub")]
@@ -146,7 +149,9 @@ impl Condition {
 
 /// Helper type for supporting Vec<T> and T in value
 /// position of Condition
-#[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(
+    Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
 #[serde(untagged)]
 pub enum OneOrMore<T: Sized> {
     One(T),
@@ -70,7 +70,7 @@ pub(in crate::package::dependency) fn parse_package_dependency_string_into_name_
             s
         ))
     })?;
-    Ok((PackageName::from(name), v))
+    Ok((PackageName::try_new(name)?, v))
 }
 
 #[cfg(test)]
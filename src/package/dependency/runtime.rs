@@ -9,6 +9,7 @@
 //
 
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -19,7 +20,9 @@ use crate::package::PackageName;
 use crate::package::PackageVersionConstraint;
 
 /// A dependency that is packaged and is required during runtime
-#[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(
+    Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, JsonSchema,
+)]
 #[serde(untagged)]
 pub enum Dependency {
     Simple(String),
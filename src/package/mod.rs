@@ -32,5 +32,14 @@ pub use source::*;
 mod dag;
 pub use dag::*;
 
+mod dag_cache;
+pub use dag_cache::*;
+
+mod resolution_error;
+pub use resolution_error::*;
+
+mod serialized_dag;
+pub use serialized_dag::*;
+
 mod version;
 pub use version::*;
@@ -10,7 +10,11 @@
 
 use std::ops::Deref;
 
+use anyhow::anyhow;
+use anyhow::Result;
+
 use pom::parser::Parser as PomParser;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -25,6 +29,7 @@ use serde::Serialize;
     PartialEq,
     Ord,
     PartialOrd,
+    JsonSchema,
 )]
 #[serde(transparent)]
 #[display("{0}")]
@@ -43,6 +48,7 @@ impl AsRef<str> for PackageName {
     }
 }
 
+#[cfg(test)]
 impl From<String> for PackageName {
     fn from(s: String) -> Self {
         PackageName(s)
@@ -50,10 +56,52 @@ impl From<String> for PackageName {
 }
 
 impl PackageName {
+    /// Create a package name, if the name is valid
+    ///
+    /// A package name must start with a letter and may only contain letters, digits, "-", "_",
+    /// and "." (the same characters that are accepted in dependency specifications), because
+    /// package names are used in paths and container names.
+    pub fn try_new(name: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        let mut chars = name.chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+        if valid {
+            Ok(PackageName(name))
+        } else {
+            Err(anyhow!(
+                "Invalid package name '{}': A package name must start with a letter and may only contain letters, digits, '-', '_', and '.'",
+                name
+            ))
+        }
+    }
+
     pub fn parser<'a>() -> PomParser<'a, u8, Self> {
         use crate::util::parser::*;
         (letters() + ((letters() | numbers()).repeat(0..)))
             .collect()
-            .convert(|b| String::from_utf8(b.to_vec()).map(Self::from))
+            .convert(|b| String::from_utf8(b.to_vec()).map(PackageName))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_package_names() {
+        for name in ["a", "abc", "gcc-12", "lib_foo", "python3.11", "X11"] {
+            assert_eq!(PackageName::try_new(name).unwrap().as_ref(), name);
+        }
+    }
+
+    #[test]
+    fn test_invalid_package_names() {
+        for name in [
+            "", "1abc", "-abc", ".hidden", "a b", "a/b", "../a", "a:b", "a=1", "paket-ä",
+        ] {
+            assert!(PackageName::try_new(name).is_err(), "{name} is valid");
+        }
     }
 }
@@ -11,7 +11,10 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+use anyhow::anyhow;
+use anyhow::Result;
 use getset::Getters;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -20,10 +23,12 @@ use crate::package::name::*;
 use crate::package::source::*;
 use crate::package::version::*;
 use crate::package::{Phase, PhaseName};
+use crate::source::SourceCache;
+use crate::source::SourceEntry;
 use crate::util::docker::ImageName;
 use crate::util::EnvironmentVariableName;
 
-#[derive(Clone, Serialize, Deserialize, Getters)]
+#[derive(Clone, Serialize, Deserialize, Getters, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Package {
     #[getset(get = "pub")]
@@ -128,6 +133,127 @@ impl std::fmt::Debug for Package {
     }
 }
 
+impl Package {
+    /// Whether all sources of the package are in the `source_cache`
+    pub fn all_sources_present(&self, source_cache: &SourceCache) -> bool {
+        self.missing_sources(source_cache).is_empty()
+    }
+
+    /// The sources of the package that are not in the `source_cache`
+    pub fn missing_sources(&self, source_cache: &SourceCache) -> Vec<SourceEntry> {
+        source_cache
+            .sources_for(self)
+            .into_iter()
+            .filter(|source| !source.exists())
+            .collect()
+    }
+
+    /// Render the package as a single line, as configured by the `opts`
+    pub fn display(&self, opts: &DisplayOptions) -> String {
+        LABEL_PLACEHOLDER_RE
+            .replace_all(&opts.label, |caps: &regex::Captures| match &caps[1] {
+                "name" => self.name.to_string(),
+                "version" => self.version.to_string(),
+                "sources" => self.sources.len().to_string(),
+                "build_dependencies" => self.dependencies.build.len().to_string(),
+                "runtime_dependencies" => self.dependencies.runtime.len().to_string(),
+                "dependencies" => {
+                    (self.dependencies.build.len() + self.dependencies.runtime.len()).to_string()
+                }
+                "hash" => self
+                    .sources
+                    .iter()
+                    .min_by_key(|(name, _)| *name)
+                    .map(|(_, source)| source.hash().value().to_string().chars().take(8).collect())
+                    .unwrap_or_default(),
+                _ => caps[0].to_string(), // unreachable, placeholders are validated
+            })
+            .into_owned()
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref LABEL_PLACEHOLDER_RE: regex::Regex = regex::Regex::new(r"\{([a-z_]*)\}").unwrap();
+}
+
+/// Options for rendering a package with `Package::display()`
+#[derive(Clone, Debug)]
+pub struct DisplayOptions {
+    label: String,
+
+    /// The width a line of a tree has to fit in, see `crate::util::width::output_width()`
+    max_width: Option<usize>,
+
+    /// Draw trees with ASCII characters only, see `crate::ui::tree_print_config()`
+    ascii: bool,
+}
+
+impl DisplayOptions {
+    /// The placeholders that can be used in a label template
+    ///
+    /// `hash` is the first 8 characters of the hash of the first source (by source name).
+    pub const PLACEHOLDERS: [&'static str; 7] = [
+        "name",
+        "version",
+        "sources",
+        "build_dependencies",
+        "runtime_dependencies",
+        "dependencies",
+        "hash",
+    ];
+
+    /// Use a label template like "{name} {version} ({sources} sources)"
+    pub fn with_label(label: String) -> Result<Self> {
+        if let Some(unknown) = LABEL_PLACEHOLDER_RE
+            .captures_iter(&label)
+            .map(|caps| caps[1].to_string())
+            .find(|p| !Self::PLACEHOLDERS.contains(&p.as_str()))
+        {
+            return Err(anyhow!(
+                "Unknown placeholder '{{{}}}' in label, available: {}",
+                unknown,
+                Self::PLACEHOLDERS.join(", ")
+            ));
+        }
+
+        Ok(DisplayOptions {
+            label,
+            max_width: None,
+            ascii: false,
+        })
+    }
+
+    /// Shorten the labels in trees, so that the lines are at most `max_width` characters wide
+    pub fn with_max_width(mut self, max_width: Option<usize>) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    pub fn max_width(&self) -> Option<usize> {
+        self.max_width
+    }
+
+    pub fn with_ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
+    }
+
+    /// The ptree configuration to write trees of packages with
+    pub fn print_config(&self) -> ptree::PrintConfig {
+        crate::ui::tree_print_config(self.ascii)
+    }
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        DisplayOptions {
+            label: String::from("{name} {version}"),
+            max_width: None,
+            ascii: false,
+        }
+    }
+}
+
 /// Helper type for printing debug information about a package with much more details than the
 /// Debug impl for Package provides.
 #[cfg(debug_assertions)]
@@ -243,7 +369,7 @@ impl Ord for Package {
 
 impl Eq for Package {}
 
-#[derive(Clone, Debug, Serialize, Deserialize, Getters)]
+#[derive(Clone, Debug, Serialize, Deserialize, Getters, JsonSchema)]
 pub struct Dependencies {
     #[getset(get = "pub")]
     build: Vec<BuildDependency>,
@@ -271,16 +397,30 @@ impl Dependencies {
             runtime: runtime_dependencies,
         }
     }
+
+    pub fn with_build_dependencies(build_dependencies: Vec<BuildDependency>) -> Self {
+        Dependencies {
+            build: build_dependencies,
+            runtime: vec![],
+        }
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
     use super::*;
+    use crate::package::condition::ConditionData;
+    use crate::package::Dag;
     use crate::package::Dependencies;
+    use crate::package::DependencyConflictPolicy;
     use crate::package::HashType;
     use crate::package::HashValue;
     use crate::package::Source;
     use crate::package::SourceHash;
+    use crate::repository::Repository;
     use url::Url;
 
     /// helper function for quick object construction
@@ -311,4 +451,152 @@ pub mod tests {
         let dependencies = Dependencies::empty();
         Package::new(name, version, version_is_semver, sources, dependencies)
     }
+
+    /// A repository with the packages
+    pub fn repository<I: IntoIterator<Item = Package>>(packages: I) -> Repository {
+        Repository::from(
+            packages
+                .into_iter()
+                .map(|p| ((p.name().clone(), p.version().clone()), p))
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+
+    /// A repository where "a 1" depends on "b =2" at runtime, `b_hash` is the source hash of "b"
+    ///
+    /// Returns the package "a" as well.
+    pub fn ab_repo(b_hash: &str) -> (Package, Repository) {
+        let mut a = package("a", "1", "https://example.com/a.tar.gz", "1");
+        a.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+            String::from("b =2"),
+        )));
+        let b = package("b", "2", "https://example.com/b.tar.gz", b_hash);
+        (a.clone(), repository([a, b]))
+    }
+
+    /// The DAG of the package `p` from the `repo`, without image and environment
+    pub fn dag_of(p: Package, repo: &Repository) -> Dag {
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+        };
+        Dag::for_root_package(
+            p,
+            repo,
+            None,
+            &condition_data,
+            100,
+            DependencyConflictPolicy::Error,
+        )
+        .unwrap()
+    }
+
+    /// A new directory in the temporary directory, it is removed with its content when it is
+    /// dropped (also if the test fails)
+    pub fn temp_dir() -> TempDir {
+        let path = std::env::temp_dir().join(format!("butido-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    /// See `temp_dir()`
+    #[derive(Debug)]
+    pub struct TempDir(PathBuf);
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_all_sources_present() {
+        let cache_root = temp_dir();
+        let source_cache = SourceCache::new(cache_root.to_path_buf());
+        let mut sources = package("a", "1", "https://example.com/a.tar.gz", "123").sources;
+        let patches = sources["src"].clone();
+        sources.insert(String::from("patches"), patches);
+        let pkg = Package::new(
+            pname("a"),
+            pversion("1"),
+            false,
+            sources,
+            Dependencies::empty(),
+        );
+
+        let all_missing = pkg.missing_sources(&source_cache).len();
+        let entries = source_cache.sources_for(&pkg);
+        std::fs::create_dir_all(entries[0].path().parent().unwrap()).unwrap();
+        std::fs::write(entries[0].path(), b"butido").unwrap();
+        let some_missing = (
+            pkg.all_sources_present(&source_cache),
+            pkg.missing_sources(&source_cache)
+                .iter()
+                .map(|source| source.path())
+                .collect::<Vec<_>>(),
+        );
+        std::fs::write(entries[1].path(), b"butido").unwrap();
+        let all_present = pkg.all_sources_present(&source_cache);
+
+        assert_eq!(all_missing, 2);
+        assert_eq!(some_missing, (false, vec![entries[1].path()]));
+        assert!(all_present);
+    }
+
+    #[test]
+    fn test_display_default_label() {
+        let p = package(
+            "a",
+            "1.0",
+            "https://example.com/a.tar.gz",
+            "0123456789abcdef",
+        );
+        assert_eq!(p.display(&DisplayOptions::default()), "a 1.0");
+    }
+
+    #[test]
+    fn test_display_label_templates() {
+        let mut p = package(
+            "a",
+            "1.0",
+            "https://example.com/a.tar.gz",
+            "0123456789abcdef",
+        );
+        p.set_dependencies(Dependencies::with_runtime_dependencies(vec![
+            Dependency::from(String::from("b =1")),
+            Dependency::from(String::from("c =2")),
+        ]));
+
+        let opts = DisplayOptions::with_label(String::from(
+            "{name}@{version} ({sources} sources, {dependencies} deps)",
+        ))
+        .unwrap();
+        assert_eq!(p.display(&opts), "a@1.0 (1 sources, 2 deps)");
+
+        let opts = DisplayOptions::with_label(String::from(
+            "{name} [{hash}] b={build_dependencies} r={runtime_dependencies}",
+        ))
+        .unwrap();
+        assert_eq!(p.display(&opts), "a [01234567] b=0 r=2");
+    }
+
+    #[test]
+    fn test_display_label_unknown_placeholder() {
+        let err = DisplayOptions::with_label(String::from("{name} {nmae}")).unwrap_err();
+        assert!(err.to_string().starts_with("Unknown placeholder '{nmae}'"));
+    }
 }
@@ -10,10 +10,11 @@
 
 use std::path::PathBuf;
 
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash, JsonSchema)]
 #[serde(transparent)]
 pub struct PhaseName(String);
 
@@ -30,7 +31,7 @@ impl From<String> for PhaseName {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, JsonSchema)]
 pub enum Phase {
     #[serde(rename = "path")]
     Path(PathBuf),
@@ -0,0 +1,176 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! The errors of the dependency resolution, i.e. of building a package DAG
+//!
+//! The errors are formatted as text by default. With `--diagnostics json` they are written to
+//! stderr as JSON, tagged with the kind of the error.
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::package::PackageName;
+use crate::package::PackageVersion;
+use crate::package::PackageVersionConstraint;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename_all = "kebab-case")]
+pub enum ResolutionError {
+    /// No package in the repository matches a dependency of a package
+    NotFound {
+        package: PackageName,
+        version: PackageVersion,
+        dependency: PackageName,
+        constraint: String,
+        available_versions: Vec<PackageVersion>,
+    },
+
+    /// Several versions of the same packages are in the DAG, see `Dag::version_conflicts()`
+    Conflict { packages: Vec<VersionConflict> },
+
+    /// The dependencies of the packages form a cycle, the first package is repeated at the end
+    Cycle { packages: Vec<String> },
+}
+
+/// The paths from the root package to each version of a package
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct VersionConflict {
+    pub name: PackageName,
+    pub paths: Vec<String>,
+}
+
+impl ResolutionError {
+    pub fn not_found(
+        package: PackageName,
+        version: PackageVersion,
+        dependency: PackageName,
+        constraint: &PackageVersionConstraint,
+        available_versions: Vec<PackageVersion>,
+    ) -> Self {
+        ResolutionError::NotFound {
+            package,
+            version,
+            dependency,
+            constraint: constraint.to_string(),
+            available_versions,
+        }
+    }
+}
+
+impl std::fmt::Display for ResolutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolutionError::NotFound {
+                package,
+                version,
+                dependency,
+                constraint,
+                available_versions,
+            } => {
+                write!(
+                    f,
+                    "Couldn't find the following dependency of {package} {version} in the repo: \
+                    {dependency} {constraint} "
+                )?;
+                if available_versions.is_empty() {
+                    write!(f, "(there is no package named {dependency})")
+                } else {
+                    write!(
+                        f,
+                        "(available versions: {})",
+                        available_versions.iter().join(", ")
+                    )
+                }
+            }
+            ResolutionError::Conflict { packages } => {
+                write!(
+                    f,
+                    "Conflicting dependency versions (see the dependency_conflict_policy setting):"
+                )?;
+                packages.iter().try_for_each(|conflict| {
+                    write!(
+                        f,
+                        "\nSeveral versions of {} are required: {}",
+                        conflict.name,
+                        conflict.paths.join(", ")
+                    )
+                })
+            }
+            ResolutionError::Cycle { packages } => {
+                write!(f, "Dependency cycle: {}", packages.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResolutionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+
+    #[test]
+    fn test_json_shapes() {
+        let not_found = ResolutionError::not_found(
+            pname("b"),
+            pversion("1"),
+            pname("c"),
+            &PackageVersionConstraint::try_from("=2.5").unwrap(),
+            vec![pversion("2.4"), pversion("2.6")],
+        );
+        assert_eq!(
+            serde_json::to_value(&not_found).unwrap(),
+            serde_json::json!({
+                "error": "not-found",
+                "package": "b",
+                "version": "1",
+                "dependency": "c",
+                "constraint": "=2.5",
+                "available_versions": ["2.4", "2.6"],
+            })
+        );
+
+        let conflict = ResolutionError::Conflict {
+            packages: vec![VersionConflict {
+                name: pname("c"),
+                paths: vec![
+                    String::from("a 1 -> c 1"),
+                    String::from("a 1 -> b 1 -> c 2"),
+                ],
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(&conflict).unwrap(),
+            serde_json::json!({
+                "error": "conflict",
+                "packages": [
+                    { "name": "c", "paths": ["a 1 -> c 1", "a 1 -> b 1 -> c 2"] },
+                ],
+            })
+        );
+
+        let cycle = ResolutionError::Cycle {
+            packages: vec![
+                String::from("a 1"),
+                String::from("b 1"),
+                String::from("a 1"),
+            ],
+        };
+        assert_eq!(
+            serde_json::to_value(&cycle).unwrap(),
+            serde_json::json!({
+                "error": "cycle",
+                "packages": ["a 1", "b 1", "a 1"],
+            })
+        );
+    }
+}
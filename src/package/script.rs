@@ -21,6 +21,7 @@ use handlebars::{
     Context, Handlebars, Helper, HelperDef, HelperResult, JsonRender, Output, PathAndJson,
     RenderContext, RenderErrorReason,
 };
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 use syntect::easy::HighlightLines;
@@ -34,7 +35,7 @@ use crate::package::Package;
 use crate::package::Phase;
 use crate::package::PhaseName;
 
-#[derive(parse_display::Display, Serialize, Deserialize, Clone, Debug)]
+#[derive(parse_display::Display, Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(transparent)]
 #[display("{0}")]
 pub struct Script(String);
@@ -99,6 +100,11 @@ impl Script {
     }
 }
 
+/// The names of all themes that can be used to highlight scripts
+pub fn highlight_theme_names() -> Vec<String> {
+    ThemeSet::load_defaults().themes.into_keys().collect()
+}
+
 #[derive(Debug)]
 pub struct HighlightedScript<'a> {
     script: &'a Script,
@@ -0,0 +1,676 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! The on-disk/database format of a package DAG
+//!
+//! The format is versioned, so that a DAG that was stored by one version of butido can be read
+//! by later versions. Packages are referenced by name and version, the package definitions have
+//! to be taken from the repository when the DAG is reconstructed.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use colored::Colorize;
+use ptree::Style;
+use ptree::TreeItem;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::package::Dag;
+use crate::package::DependencyType;
+use crate::package::PackageName;
+use crate::package::PackageVersion;
+use crate::repository::Repository;
+
+/// The version of the format that is written by this version of butido
+///
+/// Must be increased on every incompatible change of `SerializedDag`.
+pub const DAG_FORMAT_VERSION: u32 = 1;
+
+/// A package DAG in the versioned serialization format
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SerializedDag {
+    format_version: u32,
+
+    /// The packages (nodes) of the DAG, referenced by their position in the other fields
+    packages: Vec<SerializedPackage>,
+
+    dependencies: Vec<SerializedDependency>,
+
+    /// The position of the root package in `packages`
+    root: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+struct SerializedPackage {
+    name: PackageName,
+    version: PackageVersion,
+}
+
+/// A dependency (edge) of the package at position `from` on the package at position `to`
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+struct SerializedDependency {
+    from: usize,
+    to: usize,
+
+    #[serde(rename = "type")]
+    dependency_type: DependencyType,
+}
+
+/// A package that is highlighted together with all paths to it, see `tree-of --highlight`
+#[derive(Clone, Debug)]
+pub struct PackageHighlight {
+    name: PackageName,
+
+    /// All versions are highlighted if not set
+    version: Option<PackageVersion>,
+}
+
+impl PackageHighlight {
+    /// Parse "NAME" or "NAME VERSION"
+    pub fn parse(s: &str) -> Result<Self> {
+        let (name, version) = match s.trim().split_once(' ') {
+            Some((name, version)) => (name, Some(PackageVersion::from(version.trim().to_owned()))),
+            None => (s.trim(), None),
+        };
+        Ok(PackageHighlight {
+            name: PackageName::try_new(name)?,
+            version,
+        })
+    }
+
+    fn matches(&self, name: &PackageName, version: &PackageVersion) -> bool {
+        self.name == *name && self.version.as_ref().map_or(true, |v| v == version)
+    }
+}
+
+impl From<&Dag> for SerializedDag {
+    fn from(dag: &Dag) -> Self {
+        SerializedDag {
+            format_version: DAG_FORMAT_VERSION,
+            packages: dag
+                .dag()
+                .graph()
+                .node_indices()
+                .filter_map(|idx| dag.dag().node_weight(idx))
+                .map(|p| SerializedPackage {
+                    name: p.name().clone(),
+                    version: p.version().clone(),
+                })
+                .collect(),
+            dependencies: dag
+                .dag()
+                .raw_edges()
+                .iter()
+                .map(|e| SerializedDependency {
+                    from: e.source().index(),
+                    to: e.target().index(),
+                    dependency_type: e.weight.clone(),
+                })
+                .collect(),
+            root: dag.root_idx().index(),
+        }
+    }
+}
+
+impl SerializedDag {
+    pub fn to_json(&self) -> Result<serde_json::Value> {
+        serde_json::to_value(self).context("Serializing the package DAG")
+    }
+
+    /// Parse a serialized DAG, fails if it was written in an unsupported format version
+    pub fn from_json(value: serde_json::Value) -> Result<Self> {
+        let format_version = value
+            .get("format_version")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or_else(|| anyhow!("Serialized package DAG has no format version"))?;
+        if format_version != u64::from(DAG_FORMAT_VERSION) {
+            return Err(anyhow!(
+                "Unsupported format version {} of the serialized package DAG, expected version {}",
+                format_version,
+                DAG_FORMAT_VERSION
+            ));
+        }
+
+        serde_json::from_value(value).context("Parsing the serialized package DAG")
+    }
+
+    /// The positions of the packages that match `highlight` and of all packages that depend on
+    /// them (directly or transitively), i.e. of all packages on the paths from the root to them
+    pub fn highlighted(&self, highlight: &PackageHighlight) -> HashSet<usize> {
+        let mut highlighted = self
+            .packages
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| highlight.matches(&p.name, &p.version))
+            .map(|(idx, _)| idx)
+            .collect::<HashSet<_>>();
+        loop {
+            let before = highlighted.len();
+            for d in self.dependencies.iter() {
+                if highlighted.contains(&d.to) {
+                    highlighted.insert(d.from);
+                }
+            }
+            if highlighted.len() == before {
+                return highlighted;
+            }
+        }
+    }
+
+    /// The DAG in the Graphviz dot format, the edges are labeled with the dependency type
+    ///
+    /// The packages and dependencies on the paths to the `highlight`ed packages are drawn in red.
+    pub fn to_dot(&self, highlight: Option<&PackageHighlight>) -> String {
+        const HIGHLIGHT: &str = ", color=\"red\", fontcolor=\"red\", penwidth=2";
+        let highlighted = highlight.map(|h| self.highlighted(h)).unwrap_or_default();
+        let style = |idx: usize| {
+            if highlighted.contains(&idx) {
+                HIGHLIGHT
+            } else {
+                ""
+            }
+        };
+        let quote = |s: String| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::new();
+        // Writing to a String cannot fail
+        let _ = writeln!(
+            dot,
+            "digraph {} {{",
+            quote(
+                self.packages
+                    .get(self.root)
+                    .map(|p| format!("{} {}", p.name, p.version))
+                    .unwrap_or_default()
+            )
+        );
+        for (idx, package) in self.packages.iter().enumerate() {
+            let _ = writeln!(
+                dot,
+                "    {} [label={}{}];",
+                idx,
+                quote(format!("{} {}", package.name, package.version)),
+                style(idx)
+            );
+        }
+        for dependency in self.dependencies.iter() {
+            let label = match dependency.dependency_type {
+                DependencyType::Build => "build",
+                DependencyType::Runtime => "runtime",
+            };
+            let _ = writeln!(
+                dot,
+                "    {} -> {} [label={}{}];",
+                dependency.from,
+                dependency.to,
+                quote(label.to_string()),
+                style(dependency.to)
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Write the `dags` to the file at `path`, e.g. for the `--dump-dag` flags
+    ///
+    /// The DAGs are written in the Graphviz dot format (one graph per DAG) if the file name ends
+    /// with ".dot" or ".gv", as a JSON array of serialized DAGs otherwise. The `highlight` is only
+    /// drawn in the dot format.
+    pub fn write_dump(
+        path: &Path,
+        dags: &[SerializedDag],
+        highlight: Option<&PackageHighlight>,
+    ) -> Result<()> {
+        let is_dot = path
+            .extension()
+            .is_some_and(|ext| ext == "dot" || ext == "gv");
+        let content = if is_dot {
+            dags.iter()
+                .map(|dag| dag.to_dot(highlight))
+                .collect::<String>()
+        } else {
+            serde_json::to_string_pretty(dags).context("Serializing the package DAGs")?
+        };
+        std::fs::write(path, content)
+            .with_context(|| anyhow!("Writing the package DAGs to {}", path.display()))
+    }
+
+    /// Read the DAGs from a JSON file that was written by `write_dump()`
+    ///
+    /// The file may also contain a single serialized DAG instead of an array. The format version of
+    /// each DAG is checked, see `from_json()`.
+    pub fn read_dump(path: &Path) -> Result<Vec<SerializedDag>> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| anyhow!("Reading the package DAGs from {}", path.display()))?;
+        let value = serde_json::from_str::<serde_json::Value>(&content)
+            .with_context(|| anyhow!("Parsing {}", path.display()))?;
+        match value {
+            serde_json::Value::Array(values) => values
+                .into_iter()
+                .map(SerializedDag::from_json)
+                .collect::<Result<Vec<_>>>(),
+            value => SerializedDag::from_json(value).map(|dag| vec![dag]),
+        }
+        .with_context(|| anyhow!("Loading the package DAGs from {}", path.display()))
+    }
+
+    /// Display the DAG as a tree like `Dag::display()` with the default label, without the
+    /// packages from the repository
+    pub fn display(&self, highlight: Option<&PackageHighlight>) -> SerializedDagDisplay<'_> {
+        let highlighted = highlight.map(|h| self.highlighted(h)).unwrap_or_default();
+        SerializedDagDisplay(self, self.root, None, Rc::new(highlighted))
+    }
+
+    /// The packages as a tree, starting with the root package and each package followed by its
+    /// (indented) dependencies
+    ///
+    /// Returns the depth of each package in the tree. Packages that are a dependency of several
+    /// packages are listed below each of them.
+    pub fn tree(&self) -> Vec<(usize, &PackageName, &PackageVersion)> {
+        fn walk<'a>(
+            dag: &'a SerializedDag,
+            idx: usize,
+            depth: usize,
+            tree: &mut Vec<(usize, &'a PackageName, &'a PackageVersion)>,
+        ) {
+            let Some(package) = dag.packages.get(idx) else {
+                return;
+            };
+            tree.push((depth, &package.name, &package.version));
+
+            let mut dependencies = dag
+                .dependencies
+                .iter()
+                .filter(|d| d.from == idx)
+                .map(|d| d.to)
+                .collect::<Vec<_>>();
+            dependencies.sort_by_key(|to| dag.packages.get(*to).map(|p| (&p.name, &p.version)));
+            for to in dependencies {
+                walk(dag, to, depth + 1, tree);
+            }
+        }
+
+        let mut tree = Vec::new();
+        walk(self, self.root, 0, &mut tree);
+        tree
+    }
+
+    /// The packages that have to be built again if only the packages for which `is_built`
+    /// returns true can be reused
+    ///
+    /// A package that was built has to be built again if any of its dependencies is built again.
+    pub fn packages_to_rebuild<F>(&self, is_built: F) -> Vec<(&PackageName, &PackageVersion)>
+    where
+        F: Fn(&PackageName, &PackageVersion) -> bool,
+    {
+        fn needs_rebuild<F>(
+            dag: &SerializedDag,
+            idx: usize,
+            is_built: &F,
+            memo: &mut Vec<Option<bool>>,
+        ) -> bool
+        where
+            F: Fn(&PackageName, &PackageVersion) -> bool,
+        {
+            if let Some(needs) = memo[idx] {
+                return needs;
+            }
+
+            let package = &dag.packages[idx];
+            let needs = !is_built(&package.name, &package.version)
+                || dag
+                    .dependencies
+                    .iter()
+                    .filter(|d| d.from == idx && d.to < dag.packages.len())
+                    .any(|d| needs_rebuild(dag, d.to, is_built, memo));
+            memo[idx] = Some(needs);
+            needs
+        }
+
+        let mut memo = vec![None; self.packages.len()];
+        (0..self.packages.len())
+            .filter(|idx| needs_rebuild(self, *idx, &is_built, &mut memo))
+            .map(|idx| (&self.packages[idx].name, &self.packages[idx].version))
+            .collect()
+    }
+
+    /// Reconstruct the DAG with the packages from `repo`
+    pub fn into_dag(self, repo: &Repository) -> Result<Dag> {
+        let mut dag = daggy::Dag::new();
+        for p in self.packages {
+            let package = repo
+                .find(&p.name, &p.version)
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("Package {} {} not in the repository", p.name, p.version))?;
+            dag.add_node(package.clone());
+        }
+
+        for d in self.dependencies {
+            if d.from >= dag.node_count() || d.to >= dag.node_count() {
+                return Err(anyhow!(
+                    "Dependency between unknown packages in the package DAG"
+                ));
+            }
+            dag.add_edge(
+                daggy::NodeIndex::new(d.from),
+                daggy::NodeIndex::new(d.to),
+                d.dependency_type,
+            )
+            .map_err(|_| anyhow!("Serialized package DAG contains a cycle"))?;
+        }
+
+        if self.root >= dag.node_count() {
+            return Err(anyhow!("Serialized package DAG has no root package"));
+        }
+        Ok(Dag::from_parts(dag, daggy::NodeIndex::new(self.root)))
+    }
+}
+
+/// The package at the index, the type of the dependency on it (none for the root package) and the
+/// highlighted packages
+#[derive(Clone)]
+pub struct SerializedDagDisplay<'a>(
+    &'a SerializedDag,
+    usize,
+    Option<&'a DependencyType>,
+    Rc<HashSet<usize>>,
+);
+
+impl<'a> TreeItem for SerializedDagDisplay<'a> {
+    type Child = Self;
+
+    fn write_self<W: std::io::Write>(&self, f: &mut W, _: &Style) -> std::io::Result<()> {
+        let p =
+            self.0.packages.get(self.1).ok_or_else(|| {
+                std::io::Error::other(anyhow!("Error finding package: {}", self.1))
+            })?;
+        // Build time dependencies are marked with a star, like in `DagDisplay`
+        let extra_info = match self.2 {
+            Some(DependencyType::Build) => "*",
+            _ => "",
+        };
+        let label = format!("{}{} {}", extra_info, p.name, p.version);
+        if self.3.contains(&self.1) {
+            write!(f, "{}", label.red().bold())
+        } else {
+            write!(f, "{label}")
+        }
+    }
+
+    fn children(&self) -> Cow<'_, [Self::Child]> {
+        // The same order as the children of the reconstructed `Dag`, the last added edge first
+        Cow::from(
+            self.0
+                .dependencies
+                .iter()
+                .rev()
+                .filter(|d| d.from == self.1 && d.to < self.0.packages.len())
+                .map(|d| {
+                    SerializedDagDisplay(self.0, d.to, Some(&d.dependency_type), self.3.clone())
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::package::tests::dag_of;
+    use crate::package::tests::package;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+    use crate::package::tests::repository;
+    use crate::package::tests::temp_dir;
+    use crate::package::BuildDependency;
+    use crate::package::Dependencies;
+    use crate::package::Dependency;
+
+    /// "a" depends on "b" and "c", "b" depends on "c" at build time
+    fn abc_dag() -> (Dag, Repository) {
+        let mut a = package("a", "1", "https://example.com/a.tar.gz", "1");
+        let mut b = package("b", "2", "https://example.com/b.tar.gz", "2");
+        let c = package("c", "3", "https://example.com/c.tar.gz", "3");
+        a.set_dependencies(Dependencies::with_runtime_dependencies(vec![
+            Dependency::from(String::from("b =2")),
+            Dependency::from(String::from("c =3")),
+        ]));
+        b.set_dependencies(Dependencies::with_build_dependencies(vec![
+            BuildDependency::Simple(String::from("c =3")),
+        ]));
+
+        let repo = repository([a.clone(), b, c]);
+        (dag_of(a, &repo), repo)
+    }
+
+    fn edges(dag: &Dag) -> Vec<(String, String, DependencyType)> {
+        let name = |idx| dag.dag().node_weight(idx).unwrap().name().to_string();
+        let mut edges = dag
+            .dag()
+            .raw_edges()
+            .iter()
+            .map(|e| (name(e.source()), name(e.target()), e.weight.clone()))
+            .collect::<Vec<_>>();
+        edges.sort_by_key(|(from, to, _)| (from.clone(), to.clone()));
+        edges
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (dag, repo) = abc_dag();
+
+        let json = SerializedDag::from(&dag).to_json().unwrap();
+        assert_eq!(json["format_version"], 1);
+        let restored = SerializedDag::from_json(json)
+            .unwrap()
+            .into_dag(&repo)
+            .unwrap();
+
+        assert_eq!(
+            restored
+                .dag()
+                .node_weight(*restored.root_idx())
+                .unwrap()
+                .name(),
+            &pname("a")
+        );
+        assert_eq!(edges(&restored), edges(&dag));
+        assert_eq!(
+            edges(&restored),
+            vec![
+                (
+                    String::from("a"),
+                    String::from("b"),
+                    DependencyType::Runtime
+                ),
+                (
+                    String::from("a"),
+                    String::from("c"),
+                    DependencyType::Runtime
+                ),
+                (String::from("b"), String::from("c"), DependencyType::Build),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dump_round_trip() {
+        let (dag, repo) = abc_dag();
+        let dir = temp_dir();
+        let path = dir.join("dag.json");
+
+        SerializedDag::write_dump(&path, &[SerializedDag::from(&dag)], None).unwrap();
+        let dumped: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(dumped.len(), 1);
+        let restored = SerializedDag::from_json(dumped[0].clone())
+            .unwrap()
+            .into_dag(&repo)
+            .unwrap();
+        assert_eq!(edges(&restored), edges(&dag));
+        assert_eq!(
+            SerializedDag::from(&restored),
+            SerializedDag::from(&dag),
+            "the restored DAG must serialize to the dumped DAG"
+        );
+
+        let path = dir.join("dag.dot");
+        SerializedDag::write_dump(&path, &[SerializedDag::from(&dag)], None).unwrap();
+        let dot = std::fs::read_to_string(&path).unwrap();
+        assert!(dot.starts_with("digraph \"a 1\" {\n"));
+        assert!(dot.contains("[label=\"b 2\"];"));
+        assert!(dot.contains("[label=\"build\"];"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_loaded_dump_renders_like_the_original() {
+        let (dag, _) = abc_dag();
+        let dir = temp_dir();
+        let path = dir.join("dag.json");
+        SerializedDag::write_dump(&path, &[SerializedDag::from(&dag)], None).unwrap();
+
+        let loaded = SerializedDag::read_dump(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+
+        let render = |item: &dyn Fn(&mut Vec<u8>) -> std::io::Result<()>| {
+            let mut out = Vec::new();
+            item(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let options = crate::package::DisplayOptions::default();
+        let original = render(&|out| ptree::write_tree(&dag.display(&options), out));
+        let rendered = render(&|out| ptree::write_tree(&loaded[0].display(None), out));
+        assert_eq!(rendered, original);
+        assert!(rendered.contains("*c 3"));
+
+        // A single DAG, e.g. the tree of a submit, with an unsupported format version
+        let mut json = SerializedDag::from(&dag).to_json().unwrap();
+        std::fs::write(&path, json.to_string()).unwrap();
+        assert_eq!(SerializedDag::read_dump(&path).unwrap().len(), 1);
+        json["format_version"] = serde_json::json!(2);
+        std::fs::write(&path, json.to_string()).unwrap();
+        let err = SerializedDag::read_dump(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("Unsupported format version 2"));
+    }
+
+    #[test]
+    fn test_highlight() {
+        let (dag, _) = abc_dag();
+        let serialized = SerializedDag::from(&dag);
+        let names = |highlighted: HashSet<usize>| {
+            let mut names = highlighted
+                .into_iter()
+                .map(|idx| serialized.packages[idx].name.to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let highlight = |s: &str| PackageHighlight::parse(s).unwrap();
+
+        assert_eq!(
+            names(serialized.highlighted(&highlight("b"))),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            names(serialized.highlighted(&highlight("c 3"))),
+            vec!["a", "b", "c"]
+        );
+        assert!(serialized.highlighted(&highlight("c 4")).is_empty());
+        assert!(PackageHighlight::parse("1b").is_err());
+
+        let idx = |name: &str| {
+            serialized
+                .packages
+                .iter()
+                .position(|p| p.name.as_ref() == name)
+                .unwrap()
+        };
+        let (a, b, c) = (idx("a"), idx("b"), idx("c"));
+        let dot = serialized.to_dot(Some(&highlight("b")));
+        let marked = ", color=\"red\", fontcolor=\"red\", penwidth=2];";
+        assert!(dot.contains(&format!("    {a} [label=\"a 1\"{marked}\n")));
+        assert!(dot.contains(&format!("    {b} [label=\"b 2\"{marked}\n")));
+        assert!(dot.contains(&format!("    {c} [label=\"c 3\"];\n")));
+        assert!(dot.contains(&format!("    {a} -> {b} [label=\"runtime\"{marked}\n")));
+        assert!(dot.contains(&format!("    {a} -> {c} [label=\"runtime\"];\n")));
+        assert!(dot.contains(&format!("    {b} -> {c} [label=\"build\"];\n")));
+        assert!(!serialized.to_dot(None).contains("color"));
+    }
+
+    #[test]
+    fn test_tree() {
+        let (dag, _) = abc_dag();
+        let tree = SerializedDag::from(&dag)
+            .tree()
+            .into_iter()
+            .map(|(depth, name, version)| format!("{depth} {name} {version}"))
+            .collect::<Vec<_>>();
+        assert_eq!(tree, vec!["0 a 1", "1 b 2", "2 c 3", "1 c 3"]);
+    }
+
+    #[test]
+    fn test_packages_to_rebuild() {
+        let (dag, _) = abc_dag();
+        let serialized = SerializedDag::from(&dag);
+        let rebuild = |built: &[&str]| {
+            let mut names = serialized
+                .packages_to_rebuild(|name, _| built.contains(&name.as_ref()))
+                .into_iter()
+                .map(|(name, _)| name.to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        assert!(rebuild(&["a", "b", "c"]).is_empty());
+        // "a" and "b" depend on "c"
+        assert_eq!(rebuild(&["a", "b"]), vec!["a", "b", "c"]);
+        assert_eq!(rebuild(&["a", "c"]), vec!["a", "b"]);
+        assert_eq!(rebuild(&["b", "c"]), vec!["a"]);
+        assert_eq!(rebuild(&[]), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_unknown_format_version_is_rejected() {
+        let (dag, _) = abc_dag();
+        let mut json = SerializedDag::from(&dag).to_json().unwrap();
+        json["format_version"] = serde_json::json!(2);
+        json["something_new"] = serde_json::json!({});
+
+        let err = SerializedDag::from_json(json).unwrap_err();
+        assert!(err.to_string().contains("Unsupported format version 2"));
+        assert!(SerializedDag::from_json(serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_package_missing_in_repository() {
+        let (dag, _) = abc_dag();
+        let mut btree = BTreeMap::new();
+        btree.insert(
+            (pname("a"), pversion("1")),
+            package("a", "1", "https://example.com/a.tar.gz", "1"),
+        );
+
+        let err = SerializedDag::from(&dag)
+            .into_dag(&Repository::from(btree))
+            .unwrap_err();
+        assert!(err.to_string().contains("not in the repository"));
+    }
+}
@@ -8,16 +8,16 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
-use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use getset::Getters;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 use tracing::trace;
 use url::Url;
 
-#[derive(Clone, Debug, Serialize, Deserialize, Getters)]
+#[derive(Clone, Debug, Serialize, Deserialize, Getters, JsonSchema)]
 pub struct Source {
     #[getset(get = "pub")]
     url: Url,
@@ -25,6 +25,17 @@ pub struct Source {
     hash: SourceHash,
     #[getset(get = "pub")]
     download_manually: bool,
+
+    /// How to obtain the source if it has to be downloaded manually, e.g. where to accept a
+    /// license
+    #[serde(default)]
+    #[getset(get = "pub")]
+    manual_note: Option<String>,
+
+    /// Whether `hash` is the hash of the downloaded file or of its decompressed content
+    #[serde(default)]
+    #[getset(get = "pub")]
+    hash_of: HashOf,
 }
 
 impl Source {
@@ -34,11 +45,81 @@ impl Source {
             url,
             hash,
             download_manually: false,
+            manual_note: None,
+            hash_of: HashOf::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_download_manually(&mut self, download_manually: bool) {
+        self.download_manually = download_manually;
+    }
+}
+
+/// What the hash of a source is computed of
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HashOf {
+    /// The downloaded file
+    #[default]
+    Archive,
+
+    /// The decompressed content of the downloaded file, see `Compression`
+    Decompressed,
+}
+
+/// The compression of a source file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression from the file extension of the URL, if it is compressed
+    pub fn from_url(url: &Url) -> Option<Self> {
+        let file_name = url.path().rsplit('/').next()?;
+        let (_, extension) = file_name.rsplit_once('.')?;
+        match extension {
+            "gz" | "tgz" => Some(Compression::Gzip),
+            "xz" | "txz" => Some(Compression::Xz),
+            "zst" | "tzst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Decompress `reader` while it is read
+    ///
+    /// Like `gzip -dc` (and `xz`, `zstd`), files with multiple concatenated members are
+    /// decompressed completely.
+    pub fn decoder<'a, R>(self, reader: R) -> Box<dyn tokio::io::AsyncRead + Unpin + Send + 'a>
+    where
+        R: tokio::io::AsyncBufRead + Unpin + Send + 'a,
+    {
+        use async_compression::tokio::bufread::{GzipDecoder, XzDecoder, ZstdDecoder};
+
+        match self {
+            Compression::Gzip => {
+                let mut decoder = GzipDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }
+            Compression::Xz => {
+                let mut decoder = XzDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }
+            Compression::Zstd => {
+                let mut decoder = ZstdDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Getters)]
+#[derive(Clone, Debug, Serialize, Deserialize, Getters, JsonSchema)]
 pub struct SourceHash {
     #[serde(rename = "type")]
     #[getset(get = "pub")]
@@ -50,26 +131,65 @@ pub struct SourceHash {
 }
 
 impl SourceHash {
-    pub async fn matches_hash_of<R: tokio::io::AsyncRead + Unpin>(&self, reader: R) -> Result<()> {
+    /// Hash the contents of `reader` with the hash type of this SourceHash
+    ///
+    /// If `hash_limit` is set, a permit of it is held while a block of the content is hashed.
+    pub async fn compute_hash_of<R: tokio::io::AsyncRead + Unpin>(
+        &self,
+        reader: R,
+        hash_limit: Option<&tokio::sync::Semaphore>,
+    ) -> Result<HashValue> {
         trace!("Hashing buffer with: {:?}", self.hashtype);
-        let h = self
-            .hashtype
-            .hash_from_reader(reader)
-            .await
-            .context("Hashing failed")?;
+        let h = HashType::hash_all_from_reader(
+            std::slice::from_ref(&self.hashtype),
+            reader,
+            hash_limit,
+        )
+        .await
+        .map(|mut hashes| hashes.remove(0))
+        .context("Hashing failed")?;
         trace!("Hashing buffer with: {} finished", self.hashtype);
+        Ok(h)
+    }
 
-        if h == self.value {
-            trace!("Hash matches expected hash");
-            Ok(())
-        } else {
-            trace!("Hash mismatch expected hash");
-            Err(anyhow!(
-                "Hash mismatch, expected '{}', got '{}'",
-                self.value,
-                h
-            ))
+    /// Hash `data` (e.g. a memory-mapped file) with the hash type of this SourceHash
+    ///
+    /// The data is hashed in blocks of `HASH_BLOCK_SIZE` bytes on the blocking thread pool, so
+    /// that reading it (i.e. page faults of a mapping) doesn't block the runtime. `progress` is
+    /// called with the size of every hashed block.
+    /// If `hash_limit` is set, a permit of it is held while a block is hashed.
+    pub async fn compute_hash_of_mapped<D, F>(
+        &self,
+        data: std::sync::Arc<D>,
+        hash_limit: Option<&tokio::sync::Semaphore>,
+        mut progress: F,
+    ) -> Result<HashValue>
+    where
+        D: AsRef<[u8]> + Send + Sync + 'static,
+        F: FnMut(u64),
+    {
+        trace!("Hashing mapped data with: {:?}", self.hashtype);
+        let len = (*data).as_ref().len();
+        let mut hasher = Hasher::new(&self.hashtype);
+        let mut start = 0;
+        while start < len {
+            let end = std::cmp::min(start + HASH_BLOCK_SIZE, len);
+            let _permit = match hash_limit {
+                Some(limit) => Some(limit.acquire().await?),
+                None => None,
+            };
+            let data = data.clone();
+            hasher = tokio::task::spawn_blocking(move || {
+                hasher.update(&(*data).as_ref()[start..end]);
+                hasher
+            })
+            .await
+            .context("Hashing failed")?;
+            progress((end - start) as u64);
+            start = end;
         }
+        trace!("Hashing mapped data with: {} finished", self.hashtype);
+        Ok(hasher.finalize())
     }
 
     #[cfg(test)]
@@ -78,7 +198,10 @@ impl SourceHash {
     }
 }
 
-#[derive(parse_display::Display, Clone, Debug, Serialize, Deserialize)]
+/// The number of bytes that are read and hashed at once
+const HASH_BLOCK_SIZE: usize = 1024 * 1024;
+
+#[derive(parse_display::Display, Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub enum HashType {
     #[serde(rename = "sha1")]
     #[display("sha1")]
@@ -94,82 +217,135 @@ pub enum HashType {
 }
 
 impl HashType {
-    async fn hash_from_reader<R: tokio::io::AsyncRead + Unpin>(
+    pub(crate) async fn hash_from_reader<R: tokio::io::AsyncRead + Unpin>(
         &self,
-        mut reader: R,
+        reader: R,
     ) -> Result<HashValue> {
+        HashType::hash_all_from_reader(std::slice::from_ref(self), reader, None)
+            .await
+            .map(|mut hashes| hashes.remove(0))
+    }
+
+    /// Hash the contents of `reader` with all `hashtypes` at once
+    ///
+    /// The reader is only read once, in blocks of `HASH_BLOCK_SIZE` bytes. Every block is passed
+    /// to all hashers on the blocking thread pool, so that hashing doesn't block the runtime. The
+    /// hashes are returned in the order of `hashtypes`.
+    /// If `hash_limit` is set, a permit of it is held while a block is hashed, but not while the
+    /// next block is read.
+    pub(crate) async fn hash_all_from_reader<R: tokio::io::AsyncRead + Unpin>(
+        hashtypes: &[HashType],
+        mut reader: R,
+        hash_limit: Option<&tokio::sync::Semaphore>,
+    ) -> Result<Vec<HashValue>> {
         use tokio::io::AsyncReadExt;
 
-        let mut buffer = [0; 1024];
+        trace!("Hashing buffer with: {:?}", hashtypes);
+        let mut hashers = hashtypes.iter().map(Hasher::new).collect::<Vec<_>>();
+        let mut buffer = vec![0; HASH_BLOCK_SIZE];
+        loop {
+            // Fill the buffer, a single read may return only a few bytes
+            let mut count = 0;
+            while count < buffer.len() {
+                match reader
+                    .read(&mut buffer[count..])
+                    .await
+                    .context("Reading buffer failed")?
+                {
+                    0 => break,
+                    n => count += n,
+                }
+            }
 
-        match self {
-            HashType::Sha1 => {
-                use sha1::Digest;
+            if count == 0 {
+                trace!("ready");
+                break;
+            }
+
+            let _permit = match hash_limit {
+                Some(limit) => Some(limit.acquire().await?),
+                None => None,
+            };
+            (hashers, buffer) = tokio::task::spawn_blocking(move || {
+                for hasher in hashers.iter_mut() {
+                    hasher.update(&buffer[..count]);
+                }
+                (hashers, buffer)
+            })
+            .await
+            .context("Hashing buffer failed")?;
+        }
 
-                trace!("SHA1 hashing buffer");
-                let mut m = sha1::Sha1::new();
-                loop {
-                    let count = reader
-                        .read(&mut buffer)
-                        .await
-                        .context("Reading buffer failed")?;
+        let hashes = hashers.into_iter().map(Hasher::finalize).collect();
+        trace!("Hashes = {:?}", hashes);
+        Ok(hashes)
+    }
+}
 
-                    if count == 0 {
-                        trace!("ready");
-                        break;
-                    }
+/// The state of the hash computation of one HashType
+enum Hasher {
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+}
 
-                    m.update(&buffer[..count]);
-                }
-                Ok(HashValue(format!("{:x}", m.finalize())))
+impl Hasher {
+    fn new(hashtype: &HashType) -> Self {
+        match hashtype {
+            HashType::Sha1 => {
+                use sha1::Digest;
+                Hasher::Sha1(sha1::Sha1::new())
             }
             HashType::Sha256 => {
                 use sha2::Digest;
-
-                trace!("SHA256 hashing buffer");
-                let mut m = sha2::Sha256::new();
-                loop {
-                    let count = reader
-                        .read(&mut buffer)
-                        .await
-                        .context("Reading buffer failed")?;
-
-                    if count == 0 {
-                        trace!("ready");
-                        break;
-                    }
-
-                    m.update(&buffer[..count]);
-                }
-                let h = format!("{:x}", m.finalize());
-                trace!("Hash = {:?}", h);
-                Ok(HashValue(h))
+                Hasher::Sha256(sha2::Sha256::new())
             }
             HashType::Sha512 => {
                 use sha2::Digest;
+                Hasher::Sha512(sha2::Sha512::new())
+            }
+        }
+    }
 
-                trace!("SHA512 hashing buffer");
-                let mut m = sha2::Sha512::new();
-                loop {
-                    let count = reader
-                        .read(&mut buffer)
-                        .await
-                        .context("Reading buffer failed")?;
-
-                    if count == 0 {
-                        trace!("ready");
-                        break;
-                    }
-
-                    m.update(&buffer[..count]);
-                }
-                Ok(HashValue(String::from_utf8(m.finalize()[..].to_vec())?))
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(m) => {
+                use sha1::Digest;
+                m.update(data)
+            }
+            Hasher::Sha256(m) => {
+                use sha2::Digest;
+                m.update(data)
+            }
+            Hasher::Sha512(m) => {
+                use sha2::Digest;
+                m.update(data)
             }
         }
     }
+
+    fn finalize(self) -> HashValue {
+        let h = match self {
+            Hasher::Sha1(m) => {
+                use sha1::Digest;
+                format!("{:x}", m.finalize())
+            }
+            Hasher::Sha256(m) => {
+                use sha2::Digest;
+                format!("{:x}", m.finalize())
+            }
+            Hasher::Sha512(m) => {
+                use sha2::Digest;
+                format!("{:x}", m.finalize())
+            }
+        };
+        HashValue(h)
+    }
 }
 
-#[derive(parse_display::Display, Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
+#[derive(
+    parse_display::Display, Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq, JsonSchema,
+)]
 #[serde(transparent)]
 #[display("{0}")]
 pub struct HashValue(String);
@@ -180,3 +356,159 @@ impl From<String> for HashValue {
         HashValue(s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn hash_of(hashtype: HashType, content: &[u8]) -> HashValue {
+        SourceHash::new(hashtype, HashValue::from(String::new()))
+            .compute_hash_of(content, None)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_compute_hash_of_known_fixtures() {
+        assert_eq!(
+            hash_of(HashType::Sha1, b"butido").await,
+            HashValue::from(String::from("03f904e2a4708dfd115cf6665838244d0fda06c9"))
+        );
+        assert_eq!(
+            hash_of(HashType::Sha256, b"butido").await,
+            HashValue::from(String::from(
+                "d6876ab39ca1ec87f2ead7df2450c720523718e5dd85ed7f1bb7c03b8b0a9430"
+            ))
+        );
+        assert_eq!(
+            hash_of(HashType::Sha512, b"butido").await,
+            HashValue::from(String::from(
+                "8931738ca5b593af5d26b2b1e973faceb643d8110a96126d3fe626cbf73e9cd1\
+                 62f650530796c5561f19800b835571c4f3422015f7bf3e88ae6b0aa70be1eea6"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_compression_from_url() {
+        let compression = |url: &str| Compression::from_url(&Url::parse(url).unwrap());
+        assert_eq!(
+            compression("https://example.com/a-1.tar.gz"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            compression("https://example.com/a-1.tgz"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            compression("https://example.com/a-1.tar.xz"),
+            Some(Compression::Xz)
+        );
+        assert_eq!(
+            compression("https://example.com/a-1.tar.zst?download=1"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(compression("https://example.com/a-1.tar"), None);
+        assert_eq!(compression("https://example.com/gz/a"), None);
+    }
+
+    #[tokio::test]
+    async fn test_decoders() {
+        use async_compression::tokio::write::{GzipEncoder, XzEncoder, ZstdEncoder};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        async fn compress<W: tokio::io::AsyncWrite + Unpin>(mut encoder: W, content: &[u8]) -> W {
+            encoder.write_all(content).await.unwrap();
+            encoder.shutdown().await.unwrap();
+            encoder
+        }
+
+        // Two members each, which are decompressed like one
+        let mut gzip = compress(GzipEncoder::new(vec![]), b"but")
+            .await
+            .into_inner();
+        gzip.extend(
+            compress(GzipEncoder::new(vec![]), b"ido")
+                .await
+                .into_inner(),
+        );
+        let mut xz = compress(XzEncoder::new(vec![]), b"but").await.into_inner();
+        xz.extend(compress(XzEncoder::new(vec![]), b"ido").await.into_inner());
+        let mut zstd = compress(ZstdEncoder::new(vec![]), b"but")
+            .await
+            .into_inner();
+        zstd.extend(
+            compress(ZstdEncoder::new(vec![]), b"ido")
+                .await
+                .into_inner(),
+        );
+
+        for (compression, compressed) in [
+            (Compression::Gzip, gzip),
+            (Compression::Xz, xz),
+            (Compression::Zstd, zstd),
+        ] {
+            let mut content = String::new();
+            compression
+                .decoder(compressed.as_slice())
+                .read_to_string(&mut content)
+                .await
+                .unwrap();
+            assert_eq!(content, "butido", "{compression:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hash_of_several_blocks() {
+        use sha2::Digest;
+
+        // Three blocks
+        let content = b"butido".repeat(HASH_BLOCK_SIZE / 2);
+        let limit = tokio::sync::Semaphore::new(1);
+        let hash = HashType::hash_all_from_reader(&[HashType::Sha256], &content[..], Some(&limit))
+            .await
+            .unwrap();
+
+        let expected = format!("{:x}", sha2::Sha256::digest(&content));
+        assert_eq!(hash, vec![HashValue::from(expected)]);
+    }
+
+    #[tokio::test]
+    async fn test_mapped_and_read_hashes_match() {
+        // Three blocks, the last one is not full
+        let content = std::sync::Arc::new(b"butido".repeat(HASH_BLOCK_SIZE / 3 + 1));
+        let limit = tokio::sync::Semaphore::new(1);
+        for hashtype in [HashType::Sha1, HashType::Sha256, HashType::Sha512] {
+            let source_hash = SourceHash::new(hashtype.clone(), HashValue::from(String::new()));
+            let mut blocks = vec![];
+            let mapped = source_hash
+                .compute_hash_of_mapped(content.clone(), Some(&limit), |n| blocks.push(n))
+                .await
+                .unwrap();
+
+            assert_eq!(mapped, hash_of(hashtype, &content).await);
+            assert_eq!(
+                blocks,
+                vec![HASH_BLOCK_SIZE as u64, HASH_BLOCK_SIZE as u64, 4]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hash_all_in_one_read() {
+        let hashes = HashType::hash_all_from_reader(
+            &[HashType::Sha256, HashType::Sha512],
+            &b"butido"[..],
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            hashes,
+            vec![
+                hash_of(HashType::Sha256, b"butido").await,
+                hash_of(HashType::Sha512, b"butido").await,
+            ]
+        );
+    }
+}
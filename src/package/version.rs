@@ -15,6 +15,7 @@ use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 use pom::parser::Parser as PomParser;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -38,10 +39,36 @@ impl PackageVersionConstraint {
             })
     }
 
+    /// Whether the version satisfies the constraint
+    ///
+    /// The build metadata of `v` (see `PackageVersion::build_metadata()`) is ignored if the
+    /// constraint has none, e.g. "=1.2.0" matches "1.2.0+build5".
     pub fn matches(&self, v: &PackageVersion) -> bool {
+        self.matches_exactly(v)
+            || (self.version.build_metadata().is_none() && self.version.0 == v.without_build())
+    }
+
+    /// Whether the version is exactly the required one, including the build metadata
+    pub fn matches_exactly(&self, v: &PackageVersion) -> bool {
         self.version == *v
     }
 
+    /// The constraint that is satisfied by the versions that satisfy both constraints
+    ///
+    /// Returns `None` if no version can satisfy both. As only `=` constraints are supported, two
+    /// constraints are only compatible if they require the same version (or one of them only
+    /// requires the version without build metadata, see `matches()`).
+    pub fn intersect(&self, other: &PackageVersionConstraint) -> Option<PackageVersionConstraint> {
+        if self.constraint != other.constraint {
+            return None;
+        }
+        match (self.matches(&other.version), other.matches(&self.version)) {
+            (true, false) => Some(other.clone()),
+            (_, true) => Some(self.clone()),
+            (false, false) => None,
+        }
+    }
+
     #[cfg(test)]
     pub fn from_version(constraint: String, version: PackageVersion) -> Self {
         PackageVersionConstraint {
@@ -77,22 +104,30 @@ impl std::fmt::Display for PackageVersionConstraint {
     }
 }
 
+/// The version of a package
+///
+/// Versions are ordered semantically, see `PackageVersion::semantic_cmp()`.
 #[derive(
-    parse_display::Display,
-    Serialize,
-    Deserialize,
-    Clone,
-    Debug,
-    Hash,
-    Eq,
-    PartialEq,
-    Ord,
-    PartialOrd,
+    parse_display::Display, Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq, JsonSchema,
 )]
 #[serde(transparent)]
 #[display("{0}")]
 pub struct PackageVersion(String);
 
+impl Ord for PackageVersion {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // Versions that only differ in their build metadata are ordered by it, to be consistent
+        // with Eq
+        self.semantic_cmp(other).then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for PackageVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Deref for PackageVersion {
     type Target = String;
     fn deref(&self) -> &Self::Target {
@@ -113,8 +148,103 @@ impl From<String> for PackageVersion {
 }
 
 impl PackageVersion {
+    /// The build metadata, i.e. everything after the first "+" (like in "1.2.0+build5")
+    pub fn build_metadata(&self) -> Option<&str> {
+        self.0.split_once('+').map(|(_, build)| build)
+    }
+
+    /// The version without the build metadata
+    fn without_build(&self) -> &str {
+        self.0
+            .split_once('+')
+            .map_or(&self.0, |(version, _)| version)
+    }
+
+    /// The major and minor version of the release, e.g. "1.2" for "1.2.3" or "1.2.0-rc1"
+    ///
+    /// This is everything before the second "." of the version without pre-release and build
+    /// metadata (or all of it).
+    pub fn major_minor(&self) -> &str {
+        let (release, _) = self.pre_release();
+        match release.match_indices('.').nth(1) {
+            Some((idx, _)) => &release[..idx],
+            None => release,
+        }
+    }
+
+    /// The version without the build metadata split into the release and the pre-release
+    ///
+    /// The pre-release is the part after the first "-" if it starts with a letter (like in
+    /// "1.2.0-rc1"). Parts that start with a digit are no pre-releases (like the package revision
+    /// in "1.2.0-2"), so that other version schemes still work.
+    fn pre_release(&self) -> (&str, Option<&str>) {
+        let version = self.without_build();
+        match version.split_once('-') {
+            Some((release, pre)) if pre.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                (release, Some(pre))
+            }
+            _ => (version, None),
+        }
+    }
+
+    /// Compare the versions semantically, e.g. "1.10" is newer than "1.9"
+    ///
+    /// The versions are split into runs of digits and runs of other characters. Runs of digits
+    /// are compared by their numeric value, all other runs are compared lexicographically.
+    ///
+    /// Like in semver, a pre-release is older than its release (e.g. "1.2.0-rc1" is older than
+    /// "1.2.0") and the build metadata is ignored (e.g. "1.2.0+build5" and "1.2.0" are equal).
+    pub fn semantic_cmp(&self, other: &PackageVersion) -> std::cmp::Ordering {
+        fn runs(s: &str) -> Vec<&str> {
+            let mut runs = Vec::new();
+            let mut start = 0;
+            for (i, c) in s.char_indices().skip(1) {
+                let prev_is_digit = s[..i].ends_with(|p: char| p.is_ascii_digit());
+                if prev_is_digit != c.is_ascii_digit() {
+                    runs.push(&s[start..i]);
+                    start = i;
+                }
+            }
+            if !s.is_empty() {
+                runs.push(&s[start..]);
+            }
+            runs
+        }
+
+        fn cmp_runs(a: &str, b: &str) -> std::cmp::Ordering {
+            let is_number = |s: &str| s.starts_with(|c: char| c.is_ascii_digit());
+            if is_number(a) && is_number(b) {
+                // Compare by length first, so that arbitrarily long numbers cannot overflow
+                let a = a.trim_start_matches('0');
+                let b = b.trim_start_matches('0');
+                a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+            } else {
+                a.cmp(b)
+            }
+        }
+
+        fn cmp_all_runs(a: &str, b: &str) -> std::cmp::Ordering {
+            let (a, b) = (runs(a), runs(b));
+            a.iter()
+                .zip(b.iter())
+                .map(|(a, b)| cmp_runs(a, b))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len()))
+        }
+
+        let ((release_a, pre_a), (release_b, pre_b)) = (self.pre_release(), other.pre_release());
+        cmp_all_runs(release_a, release_b)
+            .then_with(|| match (pre_a, pre_b) {
+                (None, None) => std::cmp::Ordering::Equal,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (Some(a), Some(b)) => cmp_all_runs(a, b),
+            })
+            .then_with(|| self.without_build().cmp(other.without_build()))
+    }
+
     fn parser<'a>() -> PomParser<'a, u8, Self> {
-        (numbers() + ((dash() | under() | dot() | letters() | numbers()).repeat(0..)))
+        (numbers() + ((dash() | under() | dot() | plus() | letters() | numbers()).repeat(0..)))
             .collect()
             .convert(|b| String::from_utf8(b.to_vec()).map(Self::from))
     }
@@ -177,6 +307,106 @@ mod tests {
         assert_eq!(c.version, PackageVersion::from(String::from("1.0.17asejg")));
     }
 
+    #[test]
+    fn test_semantic_cmp() {
+        let mut versions = [
+            "1.10",
+            "1.9",
+            "1.9.1",
+            "10",
+            "2",
+            "1.0.17asejg",
+            "1.0.17",
+            "01.9",
+        ]
+        .into_iter()
+        .map(|v| PackageVersion::from(String::from(v)))
+        .collect::<Vec<_>>();
+        versions.sort_by(PackageVersion::semantic_cmp);
+        assert_eq!(
+            versions.iter().map(|v| v.as_str()).collect::<Vec<_>>(),
+            vec![
+                "1.0.17",
+                "1.0.17asejg",
+                "01.9",
+                "1.9",
+                "1.9.1",
+                "1.10",
+                "2",
+                "10"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pre_release_and_build_metadata() {
+        use std::cmp::Ordering::*;
+
+        let v = |s: &str| PackageVersion::from(String::from(s));
+        for (a, b, ordering) in [
+            ("1.2.0-rc1", "1.2.0", Less),
+            ("1.2.0-rc1", "1.2.0-rc2", Less),
+            ("1.2.0-rc2", "1.2.0-rc10", Less),
+            ("1.2.0-alpha", "1.2.0-beta", Less),
+            ("1.2.0-rc1", "1.1.9", Greater),
+            ("1.2.0-rc1", "1.2.1-alpha", Less),
+            ("1.2.0+build5", "1.2.0", Equal),
+            ("1.2.0+build5", "1.2.0+build6", Equal),
+            ("1.2.0-rc1+build5", "1.2.0-rc1", Equal),
+            ("1.2.0+build5", "1.2.1", Less),
+            // Parts after a dash that start with a digit are no pre-releases
+            ("1.2.0-1", "1.2.0", Greater),
+            ("1.2.0-2", "1.2.0-10", Less),
+        ] {
+            assert_eq!(v(a).semantic_cmp(&v(b)), ordering, "{a} <=> {b}");
+            assert_eq!(v(b).semantic_cmp(&v(a)), ordering.reverse(), "{b} <=> {a}");
+        }
+
+        assert_eq!(v("1.2.3").major_minor(), "1.2");
+        assert_eq!(v("1.2.0-rc1+build5").major_minor(), "1.2");
+        assert_eq!(v("1.2").major_minor(), "1.2");
+        assert_eq!(v("10").major_minor(), "10");
+
+        // Consistent with Eq
+        assert_eq!(v("1.2.0+build5").cmp(&v("1.2.0")), Greater);
+        assert_eq!(v("1.2.0-rc1").cmp(&v("1.2.0")), Less);
+
+        let c = |s: &str| PackageVersionConstraint::try_from(s).unwrap();
+        assert!(c("=1.2.0").matches(&v("1.2.0+build5")));
+        assert!(c("=1.2.0+build5").matches(&v("1.2.0+build5")));
+        assert!(!c("=1.2.0+build5").matches(&v("1.2.0+build6")));
+        assert!(!c("=1.2.0+build5").matches(&v("1.2.0")));
+        assert!(!c("=1.2.0").matches(&v("1.2.0-rc1")));
+        assert!(c("=1.2.0-rc1").matches(&v("1.2.0-rc1")));
+
+        assert_eq!(
+            c("=1.2.0").intersect(&c("=1.2.0+build5")),
+            Some(c("=1.2.0+build5"))
+        );
+        assert_eq!(
+            c("=1.2.0+build5").intersect(&c("=1.2.0")),
+            Some(c("=1.2.0+build5"))
+        );
+        assert_eq!(c("=1.2.0+build5").intersect(&c("=1.2.0+build6")), None);
+    }
+
+    #[test]
+    fn test_intersect_compatible_constraints() {
+        let a = PackageVersionConstraint::try_from("=1.0.17").unwrap();
+        let b = PackageVersionConstraint::try_from("=1.0.17").unwrap();
+        let intersection = a.intersect(&b).unwrap();
+        assert_eq!(intersection, a);
+        assert!(intersection.matches(&PackageVersion::from(String::from("1.0.17"))));
+    }
+
+    #[test]
+    fn test_intersect_incompatible_constraints() {
+        let a = PackageVersionConstraint::try_from("=1.0.17").unwrap();
+        let b = PackageVersionConstraint::try_from("=1.0.18").unwrap();
+        assert!(a.intersect(&b).is_none());
+        assert!(b.intersect(&a).is_none());
+    }
+
     #[test]
     fn test_parse_version_5() {
         let s = "=1-0B17-beta1247_commit_12653hasd";
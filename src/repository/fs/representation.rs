@@ -18,16 +18,23 @@ use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+use ignore::Match;
 use resiter::AndThen;
 use resiter::Filter;
 use resiter::Map;
 use tracing::trace;
+use tracing::warn;
 use walkdir::DirEntry;
 use walkdir::WalkDir;
 
 use crate::repository::fs::element::Element;
 use crate::repository::fs::path::PathComponent;
 
+/// The name of the files that exclude paths from being loaded
+const IGNORE_FILE_NAME: &str = ".butidoignore";
+
 /// A type representing the filesystem
 ///
 /// This type can be used to load pkg.toml files from the filesystem. As soon as this object is
@@ -51,7 +58,11 @@ pub struct FileSystemRepresentation {
 
 impl FileSystemRepresentation {
     /// Load the FileSystemRepresentation object starting at `root`.
-    pub fn load(root: PathBuf) -> Result<Self> {
+    ///
+    /// If `follow_symlinks` is set, symlinks to directories are followed as well. Symlinks that
+    /// point to one of their own parent directories are skipped, so cycles do not end in an
+    /// infinite loop.
+    pub fn load(root: PathBuf, follow_symlinks: bool) -> Result<Self> {
         let mut fsr = FileSystemRepresentation {
             root: root.clone(),
             elements: HashMap::new(),
@@ -74,12 +85,36 @@ impl FileSystemRepresentation {
             root.display()
         );
         trace!("Loading with a maximum of {} files open", max_files_open);
-        WalkDir::new(root)
-            .follow_links(false)
+        let mut ignore_files = IgnoreFiles::default();
+        let mut ignore_error = None;
+        WalkDir::new(&root)
+            .follow_links(follow_symlinks)
             .max_open(max_files_open)
             .same_file_system(true)
             .into_iter()
-            .filter_entry(|e| !is_hidden(e) && (is_pkgtoml(e) || is_dir(e)))
+            .filter_entry(|e| {
+                !is_hidden(e)
+                    && (is_pkgtoml(e) || is_dir(e))
+                    && match ignore_files.is_ignored(&root, e.path(), is_dir(e)) {
+                        Ok(ignored) => {
+                            if ignored {
+                                trace!("Ignoring: {}", e.path().display());
+                            }
+                            !ignored
+                        }
+                        Err(err) => {
+                            ignore_error.get_or_insert(err);
+                            false
+                        }
+                    }
+            })
+            .filter(|res| match res {
+                Err(e) if e.loop_ancestor().is_some() => {
+                    warn!("Skipping symlink cycle: {}", e);
+                    false
+                }
+                _ => true,
+            })
             .filter_ok(is_pkgtoml)
             .inspect(|el| trace!("Loading: {:?}", el))
             .map_err(Error::from)
@@ -117,6 +152,9 @@ impl FileSystemRepresentation {
             })
             .collect::<Result<Vec<_>>>()?;
 
+        if let Some(e) = ignore_error {
+            return Err(e);
+        }
         Ok(fsr)
     }
 
@@ -247,6 +285,57 @@ fn is_hidden(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
+/// The `.butidoignore` files of the repository, which are loaded when their directory is visited
+///
+/// The files use the gitignore syntax and their patterns are relative to the directory of the
+/// file.
+#[derive(Default)]
+struct IgnoreFiles(HashMap<PathBuf, Option<Gitignore>>);
+
+impl IgnoreFiles {
+    /// Whether a `.butidoignore` file in `root` or one of its subdirectories excludes `path`
+    ///
+    /// The file that is closest to `path` takes precedence, so it can re-include paths that are
+    /// excluded by the files above it (with `!`).
+    fn is_ignored(&mut self, root: &Path, path: &Path, is_dir: bool) -> Result<bool> {
+        for dir in path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(root))
+        {
+            match self.for_dir(dir)?.map(|gi| gi.matched(path, is_dir)) {
+                Some(Match::Ignore(_)) => return Ok(true),
+                Some(Match::Whitelist(_)) => return Ok(false),
+                Some(Match::None) | None => {}
+            }
+        }
+        Ok(false)
+    }
+
+    fn for_dir(&mut self, dir: &Path) -> Result<Option<&Gitignore>> {
+        if !self.0.contains_key(dir) {
+            let file = dir.join(IGNORE_FILE_NAME);
+            let gitignore = if file.is_file() {
+                trace!("Loading {}", file.display());
+                let mut builder = GitignoreBuilder::new(dir);
+                if let Some(e) = builder.add(&file) {
+                    return Err(Error::from(e))
+                        .with_context(|| anyhow!("Loading {}", file.display()));
+                }
+                Some(
+                    builder
+                        .build()
+                        .with_context(|| anyhow!("Loading {}", file.display()))?,
+                )
+            } else {
+                None
+            };
+            self.0.insert(dir.to_path_buf(), gitignore);
+        }
+        Ok(self.0[dir].as_ref())
+    }
+}
+
 /// Helper to check whether a DirEntry points to a directory
 fn is_dir(entry: &DirEntry) -> bool {
     trace!("Check {:?} is directory", entry);
@@ -274,6 +363,7 @@ fn load_file(path: &Path) -> Result<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::package::tests::temp_dir;
 
     fn dir(name: &str, hm: Vec<(PathComponent, Element)>) -> (PathComponent, Element) {
         (
@@ -500,7 +590,7 @@ mod tests {
             String::from(pb(repo_relative_path).to_string_lossy())
         }
 
-        let fsr = FileSystemRepresentation::load(pb(""))?;
+        let fsr = FileSystemRepresentation::load(pb(""), false)?;
 
         // Test the leaf file logic:
         assert!(!fsr.is_leaf_file(&pb("pkg.toml")).unwrap());
@@ -549,4 +639,51 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_symlinked_package_directories() {
+        let tmp = temp_dir();
+        let root = tmp.join("repo");
+        let shared = tmp.join("shared").join("a");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(root.join("pkg.toml"), "root").unwrap();
+        std::fs::write(shared.join("pkg.toml"), "a").unwrap();
+        std::os::unix::fs::symlink(&shared, root.join("a")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("cycle")).unwrap();
+
+        let without = FileSystemRepresentation::load(root.to_path_buf(), false);
+        let with = FileSystemRepresentation::load(root.to_path_buf(), true);
+
+        let mut files = without.unwrap().files().clone();
+        files.sort();
+        assert_eq!(files, vec![root.join("pkg.toml")]);
+
+        let with = with.unwrap();
+        let mut files = with.files().clone();
+        files.sort();
+        assert_eq!(files, vec![root.join("a/pkg.toml"), root.join("pkg.toml")]);
+        assert!(with.is_leaf_file(&root.join("a/pkg.toml")).unwrap());
+    }
+
+    #[test]
+    fn test_butidoignore() {
+        let root = temp_dir();
+        for dir in ["a", "docs/b", "tools/c", "tools/d"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("pkg.toml"), dir).unwrap();
+        }
+        std::fs::write(root.join(".butidoignore"), "/docs\ntools/*\n").unwrap();
+        // Patterns are relative to the directory of the file
+        std::fs::write(root.join("tools/.butidoignore"), "!/d\n").unwrap();
+
+        let fsr = FileSystemRepresentation::load(root.to_path_buf(), false);
+
+        let mut files = fsr.unwrap().files().clone();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![root.join("a/pkg.toml"), root.join("tools/d/pkg.toml")]
+        );
+    }
 }
@@ -12,4 +12,7 @@
 mod repository;
 pub use repository::*;
 
+mod package_filter;
+pub use package_filter::PackageFilter;
+
 mod fs;
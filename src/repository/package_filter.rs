@@ -0,0 +1,247 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::anyhow;
+use anyhow::Result;
+use regex::Regex;
+
+use crate::package::Package;
+use crate::package::PackageName;
+use crate::package::PackageVersionConstraint;
+
+/// A selection of packages from the repository
+///
+/// Packages are either selected by name and (optionally) version, or by a regex that is matched
+/// against the package name. Without any criteria, all packages are selected.
+///
+/// Packages where the name matches one of the `exclude` regexes are never selected, even if they
+/// match the other criteria.
+#[derive(Debug, Default)]
+pub struct PackageFilter {
+    name: Option<PackageName>,
+    version: Option<PackageVersionConstraint>,
+    regex: Option<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl PackageFilter {
+    pub fn new(
+        name: Option<PackageName>,
+        version: Option<PackageVersionConstraint>,
+        regex: Option<Regex>,
+    ) -> Result<Self> {
+        if regex.is_some() && (name.is_some() || version.is_some()) {
+            return Err(anyhow!(
+                "Packages can either be selected by name and (optionally) version, or by regex, not both"
+            ));
+        }
+
+        if name.is_none() && version.is_some() {
+            return Err(anyhow!(
+                "Packages cannot be selected by version without a name"
+            ));
+        }
+
+        Ok(PackageFilter {
+            name,
+            version,
+            regex,
+            exclude: Vec::new(),
+        })
+    }
+
+    /// Remove the packages where the name matches any of the `exclude` regexes from the selection
+    pub fn with_exclude(mut self, exclude: Vec<Regex>) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    /// Whether the filter selects all packages
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.version.is_none()
+            && self.regex.is_none()
+            && self.exclude.is_empty()
+    }
+
+    pub fn matches(&self, package: &Package) -> bool {
+        self.matches_with_case(package, false)
+    }
+
+    /// Like `matches()`, but the name (not the regex) is optionally compared case-insensitively
+    pub fn matches_with_case(&self, package: &Package, case_insensitive: bool) -> bool {
+        let name_matches = self
+            .name
+            .as_ref()
+            .map(|name| names_match(package.name(), name, case_insensitive))
+            .unwrap_or(true);
+        let version_matches = self
+            .version
+            .as_ref()
+            .map(|version| version.matches(package.version()))
+            .unwrap_or(true);
+        let regex_matches = self
+            .regex
+            .as_ref()
+            .map(|regex| regex.is_match(package.name()))
+            .unwrap_or(true);
+        let excluded = self
+            .exclude
+            .iter()
+            .any(|regex| regex.is_match(package.name()));
+
+        name_matches && version_matches && regex_matches && !excluded
+    }
+
+    pub fn name(&self) -> Option<&PackageName> {
+        self.name.as_ref()
+    }
+}
+
+/// Compare two package names, optionally ignoring the (ASCII) case
+pub(in crate::repository) fn names_match(
+    a: &PackageName,
+    b: &PackageName,
+    case_insensitive: bool,
+) -> bool {
+    if case_insensitive {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+impl std::fmt::Display for PackageFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (
+            self.name.as_ref(),
+            self.version.as_ref(),
+            self.regex.as_ref(),
+        ) {
+            (Some(name), Some(version), _) => write!(f, "{name} {version}"),
+            (Some(name), None, _) => write!(f, "{name}"),
+            (None, _, Some(regex)) => write!(f, "{regex} regex"),
+            (None, _, None) => write!(f, "all packages"),
+        }?;
+
+        if !self.exclude.is_empty() {
+            write!(f, " excluding {}", itertools::join(&self.exclude, ", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::package::tests::package;
+    use crate::package::tests::pname;
+
+    fn packages() -> Vec<Package> {
+        vec![
+            package("a", "1", "https://rust-lang.org", "123"),
+            package("a", "2", "https://rust-lang.org", "124"),
+            package("ab", "1", "https://rust-lang.org", "125"),
+            package("b", "1", "https://rust-lang.org", "126"),
+        ]
+    }
+
+    fn selected(filter: &PackageFilter) -> Vec<String> {
+        packages()
+            .iter()
+            .filter(|p| filter.matches(p))
+            .map(|p| format!("{} {}", p.name(), p.version()))
+            .collect()
+    }
+
+    fn constraint(s: &str) -> PackageVersionConstraint {
+        PackageVersionConstraint::try_from(String::from(s)).unwrap()
+    }
+
+    #[test]
+    fn test_no_criteria_matches_all() {
+        let filter = PackageFilter::new(None, None, None).unwrap();
+        assert!(filter.is_empty());
+        assert_eq!(selected(&filter), vec!["a 1", "a 2", "ab 1", "b 1"]);
+    }
+
+    #[test]
+    fn test_name() {
+        let filter = PackageFilter::new(Some(pname("a")), None, None).unwrap();
+        assert_eq!(selected(&filter), vec!["a 1", "a 2"]);
+    }
+
+    #[test]
+    fn test_name_and_version() {
+        let filter = PackageFilter::new(Some(pname("a")), Some(constraint("=2")), None).unwrap();
+        assert_eq!(selected(&filter), vec!["a 2"]);
+    }
+
+    #[test]
+    fn test_regex() {
+        let regex = Regex::new("^a").unwrap();
+        let filter = PackageFilter::new(None, None, Some(regex)).unwrap();
+        assert_eq!(selected(&filter), vec!["a 1", "a 2", "ab 1"]);
+    }
+
+    #[test]
+    fn test_exclude() {
+        let exclude = vec![Regex::new("^ab$").unwrap()];
+        let filter = PackageFilter::new(None, None, None)
+            .unwrap()
+            .with_exclude(exclude);
+        assert!(!filter.is_empty());
+        assert_eq!(selected(&filter), vec!["a 1", "a 2", "b 1"]);
+        assert_eq!(filter.to_string(), "all packages excluding ^ab$");
+    }
+
+    #[test]
+    fn test_exclude_wins_over_include() {
+        let regex = Regex::new("^a").unwrap();
+        let exclude = vec![Regex::new("b").unwrap(), Regex::new("^x").unwrap()];
+        let filter = PackageFilter::new(None, None, Some(regex))
+            .unwrap()
+            .with_exclude(exclude);
+        assert_eq!(selected(&filter), vec!["a 1", "a 2"]);
+
+        let exclude = vec![Regex::new("^a$").unwrap()];
+        let filter = PackageFilter::new(Some(pname("a")), None, None)
+            .unwrap()
+            .with_exclude(exclude);
+        assert!(selected(&filter).is_empty());
+    }
+
+    #[test]
+    fn test_name_case_insensitive() {
+        let filter = PackageFilter::new(Some(pname("A")), None, None).unwrap();
+        assert!(selected(&filter).is_empty());
+
+        let selected = packages()
+            .iter()
+            .filter(|p| filter.matches_with_case(p, true))
+            .map(|p| format!("{} {}", p.name(), p.version()))
+            .collect::<Vec<_>>();
+        assert_eq!(selected, vec!["a 1", "a 2"]);
+    }
+
+    #[test]
+    fn test_version_without_name_is_an_error() {
+        assert!(PackageFilter::new(None, Some(constraint("=1")), None).is_err());
+    }
+
+    #[test]
+    fn test_name_and_regex_is_an_error() {
+        let regex = Regex::new("^a").unwrap();
+        assert!(PackageFilter::new(Some(pname("a")), None, Some(regex.clone())).is_err());
+        assert!(PackageFilter::new(Some(pname("a")), Some(constraint("=1")), Some(regex)).is_err());
+    }
+}
@@ -21,36 +21,111 @@ use resiter::FilterMap;
 use resiter::Map;
 use tracing::trace;
 
+use crate::error::ButidoError;
 use crate::package::Package;
 use crate::package::PackageName;
 use crate::package::PackageVersion;
 use crate::package::PackageVersionConstraint;
+use crate::repository::package_filter::names_match;
+use crate::repository::PackageFilter;
 
 /// A repository represents a collection of packages
 pub struct Repository {
     inner: BTreeMap<(PackageName, PackageVersion), Package>,
+
+    /// The (leaf) pkg.toml file each package was loaded from
+    paths: BTreeMap<(PackageName, PackageVersion), PathBuf>,
+
+    /// Whether package names are compared case-insensitively, see
+    /// `Repository::with_case_insensitive_names()`
+    case_insensitive_names: bool,
 }
 
 #[cfg(test)]
 impl From<BTreeMap<(PackageName, PackageVersion), Package>> for Repository {
     fn from(inner: BTreeMap<(PackageName, PackageVersion), Package>) -> Self {
-        Repository { inner }
+        Repository {
+            inner,
+            paths: BTreeMap::new(),
+            case_insensitive_names: false,
+        }
     }
 }
 
 impl Repository {
-    fn new(inner: BTreeMap<(PackageName, PackageVersion), Package>) -> Self {
-        Repository { inner }
+    fn new(
+        inner: BTreeMap<(PackageName, PackageVersion), Package>,
+        paths: BTreeMap<(PackageName, PackageVersion), PathBuf>,
+    ) -> Self {
+        Repository {
+            inner,
+            paths,
+            case_insensitive_names: false,
+        }
+    }
+
+    /// Look up the packages by name case-insensitively, e.g. "OpenSSL" finds "openssl"
+    pub fn with_case_insensitive_names(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive_names = case_insensitive;
+        self
+    }
+
+    /// Whether two package names are the same, case-insensitively if configured
+    pub fn names_match(&self, a: &PackageName, b: &PackageName) -> bool {
+        names_match(a, b, self.case_insensitive_names)
+    }
+
+    /// Up to three package names that are similar to `name`, if there is no package with exactly
+    /// that name
+    ///
+    /// The names are ordered by their (case-insensitive) Levenshtein distance to `name`, which
+    /// must be at most a third of the length of `name` (but at least one).
+    pub fn suggest_names(&self, name: &PackageName) -> Vec<&PackageName> {
+        if self.inner.keys().any(|(n, _)| n == name) {
+            return Vec::new();
+        }
+
+        let lowercase = name.to_lowercase();
+        let max_distance = (lowercase.chars().count() / 3).max(1);
+        let mut names = self
+            .inner
+            .keys()
+            .map(|(n, _)| n)
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .map(|n| (strsim::levenshtein(&lowercase, &n.to_lowercase()), n))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect::<Vec<_>>();
+        names.sort();
+        names.into_iter().take(3).map(|(_, n)| n).collect()
+    }
+
+    /// The error for a failed lookup of the package `name` (described by `what`), with
+    /// suggestions of similar package names
+    pub fn package_not_found(&self, name: &PackageName, what: &str) -> ButidoError {
+        let suggestions = self.suggest_names(name);
+        if suggestions.is_empty() {
+            ButidoError::PackageNotFound(what.to_string())
+        } else {
+            ButidoError::PackageNotFound(format!(
+                "{what} (did you mean {}?)",
+                itertools::join(suggestions, ", ")
+            ))
+        }
     }
 
-    pub fn load(path: &Path, progress: &indicatif::ProgressBar) -> Result<Self> {
+    pub fn load(
+        path: &Path,
+        follow_symlinks: bool,
+        progress: &indicatif::ProgressBar,
+    ) -> Result<Self> {
         use crate::repository::fs::FileSystemRepresentation;
         use config::Config;
         use rayon::iter::IntoParallelRefIterator;
         use rayon::iter::ParallelIterator;
 
         trace!("Loading files from filesystem");
-        let fsr = FileSystemRepresentation::load(path.to_path_buf())?;
+        let fsr = FileSystemRepresentation::load(path.to_path_buf(), follow_symlinks)?;
 
         // Helper function to extract the `patches` array from a package config/definition:
         fn get_patches(config: &Config) -> Result<Vec<PathBuf>> {
@@ -146,10 +221,19 @@ impl Repository {
                     })
                     .and_then(|c| c.try_into::<Package>().map_err(Error::from)
                         .with_context(|| anyhow!("Could not load package configuration: {}", path.display())))
-                    .map(|pkg| ((pkg.name().clone(), pkg.version().clone()), pkg))
+                    .and_then(|pkg| validate_package(&pkg)
+                        .with_context(|| anyhow!("Invalid package configuration: {}", path.display()))
+                        .map(|_| pkg))
+                    .map(|pkg| ((pkg.name().clone(), pkg.version().clone()), pkg, path.clone()))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(|packages| {
+                let (inner, paths) = packages
+                    .into_iter()
+                    .map(|(key, pkg, path)| ((key.clone(), pkg), (key, path)))
+                    .unzip();
+                Repository::new(inner, paths)
             })
-            .collect::<Result<BTreeMap<_, _>>>()
-            .map(Repository::new)
     }
 
     pub fn find_by_name<'a>(&'a self, name: &PackageName) -> Vec<&'a Package> {
@@ -157,8 +241,9 @@ impl Repository {
         self.inner
             .iter()
             .filter(|((n, _), _)| {
-                trace!("{} == {} -> {}", name, n, name == n);
-                name == n
+                let matches = self.names_match(name, n);
+                trace!("{} == {} -> {}", name, n, matches);
+                matches
             })
             .map(|(_, pack)| pack)
             .collect()
@@ -167,19 +252,40 @@ impl Repository {
     pub fn find<'a>(&'a self, name: &PackageName, version: &PackageVersion) -> Vec<&'a Package> {
         self.inner
             .iter()
-            .filter(|((n, v), _)| n == name && v == version)
+            .filter(|((n, v), _)| self.names_match(n, name) && v == version)
             .map(|(_, p)| p)
             .collect()
     }
 
+    /// All versions of the package `name` in the repository, in ascending order
+    pub fn find_all_versions<'a>(&'a self, name: &PackageName) -> Vec<&'a PackageVersion> {
+        self.inner
+            .keys()
+            .filter(|(n, _)| self.names_match(n, name))
+            .map(|(_, v)| v)
+            .collect()
+    }
+
+    /// The packages `name` that satisfy the version constraint
+    ///
+    /// If a package has exactly the required version, only the exact matches are returned, so
+    /// that "=1.2.0" selects "1.2.0" and not also "1.2.0+build5". The versions with build metadata
+    /// are only used if there is no exact match (see `PackageVersionConstraint::matches()`).
     pub fn find_with_version<'a>(
         &'a self,
         name: &PackageName,
         vc: &PackageVersionConstraint,
     ) -> Vec<&'a Package> {
-        self.inner
+        let matching = self
+            .inner
             .iter()
-            .filter(|((n, v), _)| n == name && vc.matches(v))
+            .filter(|((n, v), _)| self.names_match(n, name) && vc.matches(v))
+            .collect::<Vec<_>>();
+        let has_exact_match = matching.iter().any(|((_, v), _)| vc.matches_exactly(v));
+
+        matching
+            .into_iter()
+            .filter(|((_, v), _)| !has_exact_match || vc.matches_exactly(v))
             .map(|(_, p)| p)
             .collect()
     }
@@ -187,6 +293,32 @@ impl Repository {
     pub fn packages(&self) -> impl Iterator<Item = &Package> {
         self.inner.values()
     }
+
+    /// The packages that are selected by the filter
+    pub fn packages_matching<'a>(
+        &'a self,
+        filter: &'a PackageFilter,
+    ) -> impl Iterator<Item = &'a Package> {
+        self.packages()
+            .filter(move |p| filter.matches_with_case(p, self.case_insensitive_names))
+    }
+
+    /// The (leaf) pkg.toml file a package was loaded from
+    pub fn path_of(&self, package: &Package) -> Option<&Path> {
+        self.paths
+            .get(&(package.name().clone(), package.version().clone()))
+            .map(PathBuf::as_path)
+    }
+}
+
+/// Check the names of a package that end up in paths (e.g. in the source cache)
+fn validate_package(pkg: &Package) -> Result<()> {
+    PackageName::try_new(pkg.name().as_str())?;
+    crate::util::check_path_component(pkg.version().as_ref())
+        .with_context(|| anyhow!("Invalid package version"))?;
+    pkg.sources().keys().try_for_each(|name| {
+        crate::util::check_path_component(name).with_context(|| anyhow!("Invalid source name"))
+    })
 }
 
 #[cfg(test)]
@@ -195,6 +327,7 @@ pub mod tests {
     use crate::package::tests::package;
     use crate::package::tests::pname;
     use crate::package::tests::pversion;
+    use crate::package::tests::temp_dir;
 
     #[test]
     fn test_finding_by_name() {
@@ -218,6 +351,83 @@ pub mod tests {
         assert!(!p.version_is_semver());
     }
 
+    fn openssl_repo() -> Repository {
+        let mut btree = BTreeMap::new();
+        for (name, vers) in [("openssl", "1"), ("openssl", "3"), ("zlib", "1")] {
+            let pack = package(name, vers, "https://rust-lang.org", "123");
+            btree.insert((pname(name), pversion(vers)), pack);
+        }
+        Repository::from(btree)
+    }
+
+    #[test]
+    fn test_case_insensitive_names() {
+        let constraint = PackageVersionConstraint::try_from(String::from("=3")).unwrap();
+        let repo = openssl_repo();
+        assert!(repo
+            .find_with_version(&pname("OpenSSL"), &constraint)
+            .is_empty());
+        assert!(repo.find_by_name(&pname("OpenSSL")).is_empty());
+        assert!(!repo.names_match(&pname("OpenSSL"), &pname("openssl")));
+
+        let repo = repo.with_case_insensitive_names(true);
+        assert!(repo.names_match(&pname("OpenSSL"), &pname("openssl")));
+        let ps = repo.find_with_version(&pname("OpenSSL"), &constraint);
+        assert_eq!(ps.len(), 1);
+        assert_eq!(*ps[0].name(), pname("openssl"));
+        assert_eq!(*ps[0].version(), pversion("3"));
+        assert_eq!(repo.find_by_name(&pname("OPENSSL")).len(), 2);
+        assert_eq!(
+            repo.find_all_versions(&pname("Openssl")),
+            vec![&pversion("1"), &pversion("3")]
+        );
+
+        let filter = PackageFilter::new(Some(pname("ZLIB")), None, None).unwrap();
+        assert_eq!(repo.packages_matching(&filter).count(), 1);
+    }
+
+    #[test]
+    fn test_suggest_names() {
+        let repo = openssl_repo();
+        assert_eq!(
+            repo.suggest_names(&pname("OpenSSL")),
+            vec![&pname("openssl")]
+        );
+        assert_eq!(
+            repo.suggest_names(&pname("opnessl")),
+            vec![&pname("openssl")]
+        );
+        assert_eq!(repo.suggest_names(&pname("zlb")), vec![&pname("zlib")]);
+        assert!(repo.suggest_names(&pname("openssl")).is_empty());
+        assert!(repo.suggest_names(&pname("libressl")).is_empty());
+        assert!(repo.suggest_names(&pname("kubernetes")).is_empty());
+
+        assert_eq!(
+            repo.package_not_found(&pname("OpenSSL"), "OpenSSL =3")
+                .to_string(),
+            "No package found: OpenSSL =3 (did you mean openssl?)"
+        );
+        assert_eq!(
+            repo.package_not_found(&pname("libressl"), "libressl")
+                .to_string(),
+            "No package found: libressl"
+        );
+    }
+
+    #[test]
+    fn test_suggest_at_most_three_names() {
+        let mut btree = BTreeMap::new();
+        for name in ["liba", "libb", "libc", "libd", "libxyz"] {
+            let pack = package(name, "1", "https://rust-lang.org", "123");
+            btree.insert((pname(name), pversion("1")), pack);
+        }
+        let repo = Repository::from(btree);
+        assert_eq!(
+            repo.suggest_names(&pname("lib")),
+            vec![&pname("liba"), &pname("libb"), &pname("libc")]
+        );
+    }
+
     #[test]
     fn test_find() {
         let mut btree = BTreeMap::new();
@@ -282,6 +492,37 @@ pub mod tests {
         assert!(!p.version_is_semver());
     }
 
+    #[test]
+    fn test_find_with_version_prefers_exact_match() {
+        let mut btree = BTreeMap::new();
+        for vers in ["1.2.0", "1.2.0+build5", "1.2.0+build6"] {
+            let pack = package("a", vers, "https://rust-lang.org", "123");
+            btree.insert((pname("a"), pversion(vers)), pack);
+        }
+        let repo = Repository::from(btree);
+        let versions = |constraint: &str| {
+            let constraint =
+                PackageVersionConstraint::from_version(String::from("="), pversion(constraint));
+            repo.find_with_version(&pname("a"), &constraint)
+                .into_iter()
+                .map(|p| p.version().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(versions("1.2.0"), vec!["1.2.0"]);
+        assert_eq!(versions("1.2.0+build5"), vec!["1.2.0+build5"]);
+
+        let mut btree = BTreeMap::new();
+        for vers in ["1.2.0+build5", "1.2.0+build6"] {
+            let pack = package("a", vers, "https://rust-lang.org", "123");
+            btree.insert((pname("a"), pversion(vers)), pack);
+        }
+        let repo = Repository::from(btree);
+        let constraint =
+            PackageVersionConstraint::from_version(String::from("="), pversion("1.2.0"));
+        assert_eq!(repo.find_with_version(&pname("a"), &constraint).len(), 2);
+    }
+
     #[test]
     fn test_load_example_pkg_repo() -> Result<()> {
         use crate::package::Package;
@@ -302,6 +543,7 @@ pub mod tests {
 
         let repo = Repository::load(
             &PathBuf::from("examples/packages/repo/"),
+            false,
             &indicatif::ProgressBar::hidden(),
         )?;
 
@@ -347,4 +589,34 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_load_rejects_invalid_package_names() {
+        let root = temp_dir();
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::copy("examples/packages/repo/pkg.toml", root.join("pkg.toml")).unwrap();
+        std::fs::write(
+            root.join("a").join("pkg.toml"),
+            indoc::indoc!(
+                r#"
+                name = "a/../b"
+                version = "1"
+
+                [sources.src]
+                url = "https://example.com"
+                hash.hash = "e5fa44f2b31c1fb553b6021e7360d07d5d91ff5e"
+            "#
+            ),
+        )
+        .unwrap();
+
+        let result = Repository::load(&root, false, &indicatif::ProgressBar::hidden());
+
+        let err = match result {
+            Ok(_) => panic!("Loading a package with an invalid name succeeded"),
+            Err(e) => format!("{e:#}"),
+        };
+        assert!(err.contains("a/pkg.toml"), "{err}");
+        assert!(err.contains("Invalid package name 'a/../b'"), "{err}");
+    }
 }
@@ -78,6 +78,7 @@ table! {
         artifact_id -> Int4,
         release_date -> Timestamptz,
         release_store_id -> Int4,
+        tag -> Nullable<Varchar>,
     }
 }
 
@@ -97,6 +98,7 @@ table! {
         requested_image_id -> Int4,
         requested_package_id -> Int4,
         repo_hash_id -> Int4,
+        tree -> Nullable<Jsonb>,
     }
 }
 
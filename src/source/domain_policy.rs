@@ -0,0 +1,131 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::anyhow;
+use anyhow::Result;
+use url::Url;
+
+use crate::config::Configuration;
+
+/// Decides whether a source URL may be used, based on the host it points to
+///
+/// A domain matches a host if the host is the domain itself or a subdomain of it. Denied domains
+/// take precedence over allowed domains. If no allowed domains are configured, all hosts that are
+/// not denied are allowed.
+#[derive(Debug)]
+pub struct SourceDomainPolicy<'a> {
+    allowed: Option<&'a [String]>,
+    denied: Option<&'a [String]>,
+}
+
+impl<'a> SourceDomainPolicy<'a> {
+    pub fn new(allowed: Option<&'a [String]>, denied: Option<&'a [String]>) -> Self {
+        SourceDomainPolicy { allowed, denied }
+    }
+
+    pub fn from_config(config: &'a Configuration) -> Self {
+        Self::new(
+            config.allowed_source_domains().as_deref(),
+            config.denied_source_domains().as_deref(),
+        )
+    }
+
+    /// Check the host of the URL against the policy
+    pub fn check(&self, url: &Url) -> Result<()> {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None if self.allowed.is_some() => {
+                return Err(anyhow!(
+                "Source URL '{}' has no host, but only sources from allowed domains are permitted",
+                url
+            ))
+            }
+            None => return Ok(()),
+        };
+
+        if let Some(domain) = self
+            .denied
+            .and_then(|denied| denied.iter().find(|d| host_matches(&host, d)))
+        {
+            return Err(anyhow!(
+                "Host '{}' of source URL '{}' is denied by 'denied_source_domains' (matches '{}')",
+                host,
+                url,
+                domain
+            ));
+        }
+
+        match self.allowed {
+            Some(allowed) if !allowed.iter().any(|d| host_matches(&host, d)) => Err(anyhow!(
+                "Host '{}' of source URL '{}' is not in 'allowed_source_domains'",
+                host,
+                url
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn host_matches(host: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.').to_lowercase();
+    host == domain
+        || host
+            .strip_suffix(&domain)
+            .map(|prefix| prefix.ends_with('.'))
+            .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_allowed_host_passes() {
+        let allowed = vec![String::from("example.com")];
+        let policy = SourceDomainPolicy::new(Some(&allowed), None);
+
+        assert!(policy.check(&url("https://example.com/src.tar.gz")).is_ok());
+        assert!(policy
+            .check(&url("https://download.example.com/src.tar.gz"))
+            .is_ok());
+        assert!(policy
+            .check(&url("https://notexample.com/src.tar.gz"))
+            .is_err());
+        assert!(policy.check(&url("file:///tmp/src.tar.gz")).is_err());
+    }
+
+    #[test]
+    fn test_denied_host_is_rejected() {
+        let allowed = vec![String::from("example.com")];
+        let denied = vec![String::from("evil.example.com")];
+        let policy = SourceDomainPolicy::new(Some(&allowed), Some(&denied));
+
+        let err = policy
+            .check(&url("https://mirror.evil.example.com/src.tar.gz"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Host 'mirror.evil.example.com' of source URL 'https://mirror.evil.example.com/src.tar.gz' \
+             is denied by 'denied_source_domains' (matches 'evil.example.com')"
+        );
+        assert!(policy.check(&url("https://example.com/src.tar.gz")).is_ok());
+    }
+
+    #[test]
+    fn test_unrestricted_policy_allows_everything() {
+        let policy = SourceDomainPolicy::new(None, None);
+        assert!(policy.check(&url("https://example.org/src.tar.gz")).is_ok());
+        assert!(policy.check(&url("file:///tmp/src.tar.gz")).is_ok());
+    }
+}
@@ -8,57 +8,271 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::HashSet;
+use std::path::Path;
 use std::path::PathBuf;
 
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
+use getset::Getters;
+use indicatif::ProgressBar;
+use schemars::JsonSchema;
+use serde::Deserialize;
 use tracing::trace;
+use tracing::warn;
 use url::Url;
 
+use crate::config::Configuration;
+use crate::error::ButidoError;
+use crate::package::Compression;
+use crate::package::HashOf;
+use crate::package::HashValue;
 use crate::package::Package;
 use crate::package::PackageName;
 use crate::package::PackageVersion;
 use crate::package::Source;
+use crate::util::progress::ProgressReader;
+
+mod domain_policy;
+pub use domain_policy::SourceDomainPolicy;
+
+mod netrc;
+pub use netrc::*;
+
+/// How the source files are stored in the source cache
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheLayout {
+    /// Each source is stored as `{name}-{version}/{source_name}.source`
+    #[default]
+    Package,
+
+    /// Each source is stored once by its hash as `cas/{hash[0:2]}/{hash}`, the paths of the
+    /// package layout are symlinks to these files
+    ///
+    /// Sources with a hash of their decompressed content are stored like in the package layout,
+    /// because archives with different compressions have the same hash then.
+    Cas,
+}
 
 #[derive(Clone, Debug)]
 pub struct SourceCache {
     root: PathBuf,
+    layout: CacheLayout,
 }
 
 impl SourceCache {
     pub fn new(root: PathBuf) -> Self {
-        SourceCache { root }
+        SourceCache {
+            root,
+            layout: CacheLayout::default(),
+        }
+    }
+
+    /// The source cache with the root and layout from the configuration
+    pub fn from_config(config: &Configuration) -> Self {
+        SourceCache::new(config.source_cache_root().clone())
+            .with_layout(*config.source_cache_layout())
+    }
+
+    pub fn with_layout(mut self, layout: CacheLayout) -> Self {
+        self.layout = layout;
+        self
     }
 
     pub fn sources_for(&self, p: &Package) -> Vec<SourceEntry> {
-        SourceEntry::for_package(self.root.clone(), p)
+        SourceEntry::for_package(self.root.clone(), self.layout, p)
+    }
+
+    /// The source files in the cache directory of the package that are not one of its sources
+    ///
+    /// Such files are left behind if a source of the package is renamed or removed.
+    pub fn orphaned_files(&self, p: &Package) -> Result<Vec<PathBuf>> {
+        let dir = source_file_directory(&self.root, p.name(), p.version());
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let expected = self
+            .sources_for(p)
+            .iter()
+            .map(SourceEntry::path)
+            .collect::<Vec<_>>();
+        let mut orphans = std::fs::read_dir(&dir)
+            .with_context(|| anyhow!("Reading directory {}", dir.display()))?
+            .map(|entry| entry.map(|e| e.path()).map_err(Error::from))
+            .filter(|path| {
+                path.as_ref().map_or(true, |path| {
+                    path.extension().is_some_and(|ext| ext == "source")
+                        && path.is_file()
+                        && !expected.contains(path)
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| anyhow!("Reading directory {}", dir.display()))?;
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    /// The files in the content-addressed store that no source file in the cache links to
+    ///
+    /// Such files are left behind if the sources that linked to them were removed or their hash
+    /// changed (see `CacheLayout::Cas`).
+    pub fn orphaned_stored_files(&self) -> Result<Vec<PathBuf>> {
+        let store = self.root.join("cas");
+        if !store.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let linked = self.linked_stored_files()?;
+        let mut orphans = vec![];
+        for dir in std::fs::read_dir(&store)
+            .with_context(|| anyhow!("Reading directory {}", store.display()))?
+        {
+            let dir = dir?.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(&dir)
+                .with_context(|| anyhow!("Reading directory {}", dir.display()))?
+            {
+                let path = entry?.path();
+                if path.is_file() && !linked.contains(&path.canonicalize()?) {
+                    orphans.push(path);
+                }
+            }
+        }
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    /// The (canonical) paths of the files in the content-addressed store that the source files in
+    /// the cache link to
+    fn linked_stored_files(&self) -> Result<HashSet<PathBuf>> {
+        let mut linked = HashSet::new();
+        if !self.root.is_dir() {
+            return Ok(linked);
+        }
+
+        let store = self.root.join("cas");
+        for dir in std::fs::read_dir(&self.root)
+            .with_context(|| anyhow!("Reading directory {}", self.root.display()))?
+        {
+            let dir = dir?.path();
+            if !dir.is_dir() || dir == store {
+                continue;
+            }
+            for entry in std::fs::read_dir(&dir)
+                .with_context(|| anyhow!("Reading directory {}", dir.display()))?
+            {
+                let path = entry?.path();
+                // Dangling links don't keep a file alive
+                if let Some(target) = path
+                    .is_symlink()
+                    .then(|| path.canonicalize().ok())
+                    .flatten()
+                {
+                    linked.insert(target);
+                }
+            }
+        }
+        Ok(linked)
     }
 }
 
-#[derive(Debug)]
+fn source_file_directory(root: &Path, name: &PackageName, version: &PackageVersion) -> PathBuf {
+    root.join(format!("{name}-{version}"))
+}
+
+/// The outcome of hashing a source file
+#[derive(Clone, Debug, Getters)]
+pub struct HashVerification {
+    #[getset(get = "pub")]
+    expected: HashValue,
+
+    #[getset(get = "pub")]
+    computed: HashValue,
+}
+
+impl HashVerification {
+    pub fn matches(&self) -> bool {
+        self.expected == self.computed
+    }
+
+    /// Turn a hash mismatch into an error
+    pub fn as_result(&self) -> Result<()> {
+        if self.matches() {
+            trace!("Hash matches expected hash");
+            Ok(())
+        } else {
+            trace!("Hash mismatch expected hash");
+            Err(ButidoError::SourceCorrupt {
+                expected: self.expected.to_string(),
+                computed: self.computed.to_string(),
+            }
+            .into())
+        }
+    }
+}
+
+/// Source files larger than this (in bytes) are memory-mapped for hashing, if enabled
+pub const HASH_MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Limits for hashing several source files concurrently
+///
+/// Reading a file and hashing its contents are limited separately, e.g. to read only a few files
+/// from a slow network filesystem at a time while all CPUs are used for hashing. A file is read
+/// from opening it until its end, a block of up to 1 MiB of it is hashed while its hash is
+/// updated.
+#[derive(Debug, Default)]
+pub struct HashLimits {
+    read: Option<tokio::sync::Semaphore>,
+    hash: Option<tokio::sync::Semaphore>,
+}
+
+impl HashLimits {
+    /// Limit the number of files that are read and of blocks that are hashed at the same time
+    pub fn new(read: Option<usize>, hash: Option<usize>) -> Self {
+        HashLimits {
+            read: read.map(tokio::sync::Semaphore::new),
+            hash: hash.map(tokio::sync::Semaphore::new),
+        }
+    }
+}
+
+#[derive(Debug, Getters)]
 pub struct SourceEntry {
     cache_root: PathBuf,
+    layout: CacheLayout,
+
+    #[getset(get = "pub")]
     package_name: PackageName,
+
+    #[getset(get = "pub")]
     package_version: PackageVersion,
+
+    #[getset(get = "pub")]
     package_source_name: String,
+
+    #[getset(get = "pub")]
     package_source: Source,
 }
 
 impl SourceEntry {
     fn source_file_directory(&self) -> PathBuf {
-        self.cache_root
-            .join(format!("{}-{}", self.package_name, self.package_version))
+        source_file_directory(&self.cache_root, &self.package_name, &self.package_version)
     }
 
-    fn for_package(cache_root: PathBuf, package: &Package) -> Vec<Self> {
+    fn for_package(cache_root: PathBuf, layout: CacheLayout, package: &Package) -> Vec<Self> {
         package
             .sources()
             .clone()
             .into_iter()
             .map(|(source_name, source)| SourceEntry {
                 cache_root: cache_root.clone(),
+                layout,
                 package_name: package.name().clone(),
                 package_version: package.version().clone(),
                 package_source_name: source_name,
@@ -67,12 +281,107 @@ impl SourceEntry {
             .collect()
     }
 
+    /// Make sure that `path()` is inside of the cache root
+    ///
+    /// The names are checked when the repository is loaded, but they are checked again before
+    /// files are created or removed.
+    fn check_path(&self) -> Result<()> {
+        crate::util::check_path_component(&self.package_source_name)
+            .and_then(|_| {
+                crate::util::check_path_component(&format!(
+                    "{}-{}",
+                    self.package_name, self.package_version
+                ))
+            })
+            .and_then(|_| match self.cas_path() {
+                Some(_) => crate::util::check_path_component(
+                    &self.package_source.hash().value().to_string(),
+                ),
+                None => Ok(()),
+            })
+            .with_context(|| {
+                anyhow!(
+                    "Invalid source '{}' of package {} {}",
+                    self.package_source_name,
+                    self.package_name,
+                    self.package_version
+                )
+            })
+    }
+
     pub fn path(&self) -> PathBuf {
         self.source_file_directory().join({
             (self.package_source_name.as_ref() as &std::path::Path).with_extension("source")
         })
     }
 
+    /// The file in the content-addressed store that `path()` links to, if the source is stored
+    /// there (see `CacheLayout::Cas`)
+    pub fn cas_path(&self) -> Option<PathBuf> {
+        if self.layout != CacheLayout::Cas || *self.package_source.hash_of() == HashOf::Decompressed
+        {
+            return None;
+        }
+
+        let hash = self.package_source.hash().value().to_string();
+        let prefix = hash.get(..2)?;
+        Some(self.cache_root.join("cas").join(prefix).join(&hash))
+    }
+
+    /// Whether the source file exists in the cache
+    pub fn exists(&self) -> bool {
+        self.path().exists()
+    }
+
+    /// Link `path()` to the file in the content-addressed store, if that file exists already
+    ///
+    /// Returns whether the source was linked, i.e. whether it doesn't have to be downloaded.
+    pub async fn link_stored(&self) -> Result<bool> {
+        match self.cas_path() {
+            Some(cas_path) if cas_path.is_file() => {
+                self.check_path()?;
+                self.link(&cas_path).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Create the symlink from `path()` to `cas_path`
+    ///
+    /// An existing file or symlink at `path()` is replaced atomically.
+    async fn link(&self, cas_path: &Path) -> Result<()> {
+        let p = self.path();
+        let dir = self.source_file_directory();
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| anyhow!("Creating directory {}", dir.display()))?;
+        let tmp = self.tmp_path();
+        if tokio::fs::symlink_metadata(&tmp).await.is_ok() {
+            tokio::fs::remove_file(&tmp)
+                .await
+                .with_context(|| anyhow!("Removing {}", tmp.display()))?;
+        }
+
+        // Relative to the package directory, so that the cache can be moved
+        let target = cas_path
+            .strip_prefix(&self.cache_root)
+            .map(|rel| Path::new("..").join(rel))
+            .unwrap_or_else(|_| cas_path.to_path_buf());
+        trace!("Linking {} to {}", p.display(), target.display());
+        tokio::fs::symlink(&target, &tmp)
+            .await
+            .with_context(|| anyhow!("Linking {} to {}", tmp.display(), target.display()))?;
+        tokio::fs::rename(&tmp, &p)
+            .await
+            .with_context(|| anyhow!("Moving {} to {}", tmp.display(), p.display()))
+    }
+
+    /// The path next to `path()` where a file is prepared before it is moved to `path()`
+    pub fn tmp_path(&self) -> PathBuf {
+        self.path().with_extension("source.tmp")
+    }
+
     pub fn url(&self) -> &Url {
         self.package_source.url()
     }
@@ -81,15 +390,96 @@ impl SourceEntry {
         *self.package_source.download_manually()
     }
 
+    /// Remove the source file
+    ///
+    /// With the content-addressed layout, the stored file is also removed if no other source
+    /// links to it anymore.
     pub async fn remove_file(&self) -> Result<()> {
+        self.check_path()?;
         let p = self.path();
         tokio::fs::remove_file(&p).await?;
+        if let Some(cas_path) = self.cas_path().filter(|p| p.is_file()) {
+            let linked = SourceCache::new(self.cache_root.clone())
+                .with_layout(self.layout)
+                .linked_stored_files()?;
+            if !linked.contains(&cas_path.canonicalize()?) {
+                tokio::fs::remove_file(&cas_path).await?;
+            }
+        }
         Ok(())
     }
 
-    pub async fn verify_hash(&self) -> Result<()> {
+    /// The size of the source file in bytes
+    pub async fn size(&self) -> Result<u64> {
+        let p = self.path();
+        tokio::fs::metadata(&p)
+            .await
+            .map(|meta| meta.len())
+            .with_context(|| anyhow!("Getting metadata of {}", p.display()))
+    }
+
+    /// Hash the source file and compare the result with the expected hash
+    ///
+    /// `bars` are advanced by the number of bytes hashed.
+    /// An error is only returned if the file couldn't be hashed, a mismatch is reported via the
+    /// returned `HashVerification`.
+    /// If `use_mmap` is set, files larger than `HASH_MMAP_THRESHOLD` are memory-mapped for hashing.
+    /// The file is only read and hashed within the `limits`.
+    pub async fn verify_hash_detailed(
+        &self,
+        bars: Vec<ProgressBar>,
+        use_mmap: bool,
+        limits: &HashLimits,
+    ) -> Result<HashVerification> {
+        let computed = self
+            .compute_hash(bars, use_mmap.then_some(HASH_MMAP_THRESHOLD), limits)
+            .await?;
+        Ok(HashVerification {
+            expected: self.package_source.hash().value().clone(),
+            computed,
+        })
+    }
+
+    /// Hash the source file, memory-mapped if it is larger than `mmap_threshold` (if set)
+    async fn compute_hash(
+        &self,
+        bars: Vec<ProgressBar>,
+        mmap_threshold: Option<u64>,
+        limits: &HashLimits,
+    ) -> Result<HashValue> {
         let p = self.path();
-        trace!("Verifying : {}", p.display());
+        let _read_permit = match limits.read.as_ref() {
+            Some(limit) => Some(limit.acquire().await?),
+            None => None,
+        };
+        trace!("Hashing : {}", p.display());
+
+        if *self.package_source.hash_of() == HashOf::Decompressed {
+            return self.compute_decompressed_hash(&p, bars, limits).await;
+        }
+
+        let mmap = match mmap_threshold.map(|threshold| self.mmap(threshold)) {
+            Some(Ok(mmap)) => mmap,
+            Some(Err(e)) => {
+                warn!(
+                    "Memory-mapping {} failed, using buffered reads: {:#}",
+                    p.display(),
+                    e
+                );
+                None
+            }
+            None => None,
+        };
+        if let Some(mmap) = mmap {
+            trace!("Hashing memory-mapped file: {}", p.display());
+            return self
+                .package_source
+                .hash()
+                .compute_hash_of_mapped(std::sync::Arc::new(mmap), limits.hash.as_ref(), |n| {
+                    bars.iter().for_each(|bar| bar.inc(n))
+                })
+                .await;
+        }
 
         let reader = tokio::fs::OpenOptions::new()
             .create(false)
@@ -98,14 +488,83 @@ impl SourceEntry {
             .open(&p)
             .await
             .map(tokio::io::BufReader::new)
+            .map(|reader| ProgressReader::new(reader, bars))
             .context("Opening file failed")?;
 
         trace!("Reader constructed for path: {}", p.display());
-        self.package_source.hash().matches_hash_of(reader).await
+        self.package_source
+            .hash()
+            .compute_hash_of(reader, limits.hash.as_ref())
+            .await
     }
 
+    /// Memory-map the source file if it is larger than `threshold`
+    ///
+    /// Files that are being replaced (i.e. whose `tmp_path()` exists, because they are downloaded
+    /// again) are not mapped.
+    #[allow(unsafe_code)]
+    fn mmap(&self, threshold: u64) -> Result<Option<memmap2::Mmap>> {
+        if self.tmp_path().exists() {
+            trace!(
+                "Not memory-mapping {}, it is being replaced",
+                self.path().display()
+            );
+            return Ok(None);
+        }
+
+        let file = std::fs::File::open(self.path())?;
+        if file.metadata()?.len() <= threshold {
+            return Ok(None);
+        }
+
+        // SAFETY: A mapped file must not be truncated while it is mapped. butido never modifies a
+        // file in the source cache: new contents are written to `tmp_path()` and renamed over the
+        // file by `persist()` and files are removed by unlinking them, which both leave the mapped
+        // file intact.
+        unsafe { memmap2::Mmap::map(&file) }
+            .map(Some)
+            .map_err(Error::from)
+    }
+
+    /// Hash the decompressed content of the source file
+    ///
+    /// The file is decompressed on the fly with the decoder of its `Compression`, `bars` are
+    /// advanced by the compressed bytes that are read.
+    async fn compute_decompressed_hash(
+        &self,
+        p: &Path,
+        bars: Vec<ProgressBar>,
+        limits: &HashLimits,
+    ) -> Result<HashValue> {
+        let compression = Compression::from_url(self.url()).ok_or_else(|| {
+            anyhow!(
+                "Cannot hash the decompressed content of {}, unknown compression of {}",
+                p.display(),
+                self.url()
+            )
+        })?;
+        let reader = tokio::fs::File::open(p)
+            .await
+            .map(|file| ProgressReader::new(file, bars))
+            .map(tokio::io::BufReader::new)
+            .with_context(|| anyhow!("Opening {}", p.display()))?;
+
+        trace!("Hashing decompressed content of: {}", p.display());
+        self.package_source
+            .hash()
+            .compute_hash_of(compression.decoder(reader), limits.hash.as_ref())
+            .await
+            .with_context(|| anyhow!("Decompressing {} failed", p.display()))
+    }
+
+    /// Create the file that the source is written to
+    ///
+    /// The file is created at `tmp_path()` and only moved to its place in the cache by
+    /// `persist()`, so that an interrupted download never leaves a truncated source behind (which
+    /// would be shared by all sources with the same hash in the content-addressed layout).
     pub async fn create(&self) -> Result<tokio::fs::File> {
-        let p = self.path();
+        self.check_path()?;
+        let p = self.tmp_path();
         trace!("Creating source file: {}", p.display());
 
         if !self.cache_root.is_dir() {
@@ -133,13 +592,347 @@ impl SourceEntry {
             }
         }
 
+        // A file that is left from an interrupted download is overwritten
         trace!("Creating file now: {}", p.display());
         tokio::fs::OpenOptions::new()
-            .create_new(true)
+            .create(true)
+            .truncate(true)
             .write(true)
             .open(&p)
             .await
             .with_context(|| anyhow!("Creating file: {}", p.display()))
-            .map_err(Error::from)
+    }
+
+    /// Move the file that was written after `create()` to its place in the cache
+    ///
+    /// With the content-addressed layout, the file is moved to `cas_path()` (replacing a stored
+    /// file with the same hash) and `path()` is linked to it.
+    pub async fn persist(&self) -> Result<()> {
+        self.check_path()?;
+        let tmp = self.tmp_path();
+        let cas_path = self.cas_path();
+        let file_path = match cas_path.as_ref() {
+            Some(cas_path) => {
+                if let Some(dir) = cas_path.parent() {
+                    tokio::fs::create_dir_all(dir)
+                        .await
+                        .with_context(|| anyhow!("Creating directory {}", dir.display()))?;
+                }
+                cas_path.clone()
+            }
+            None => self.path(),
+        };
+
+        tokio::fs::rename(&tmp, &file_path)
+            .await
+            .with_context(|| anyhow!("Moving {} to {}", tmp.display(), file_path.display()))?;
+        match cas_path {
+            Some(cas_path) => self.link(&cas_path).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::tests::package;
+    use crate::package::tests::temp_dir;
+
+    #[tokio::test]
+    async fn test_verify_hash_detailed_returns_computed_hash() {
+        let cache_root = temp_dir();
+        let fixture_hash = "03f904e2a4708dfd115cf6665838244d0fda06c9"; // sha1 of "butido"
+
+        let matching = package("a", "1", "https://example.com/a.tar.gz", fixture_hash);
+        let mismatching = package("b", "1", "https://example.com/b.tar.gz", "0000");
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        let sources = sc
+            .sources_for(&matching)
+            .into_iter()
+            .chain(sc.sources_for(&mismatching))
+            .collect::<Vec<_>>();
+        for source in sources.iter() {
+            std::fs::create_dir_all(source.path().parent().unwrap()).unwrap();
+            std::fs::write(source.path(), b"butido").unwrap();
+        }
+
+        let mut verifications = vec![];
+        for source in sources.iter() {
+            verifications.push(
+                source
+                    .verify_hash_detailed(vec![], false, &HashLimits::default())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let expected = HashValue::from(String::from(fixture_hash));
+        assert_eq!(*verifications[0].computed(), expected);
+        assert!(verifications[0].matches());
+        assert!(verifications[0].as_result().is_ok());
+
+        assert_eq!(*verifications[1].computed(), expected);
+        assert_eq!(
+            *verifications[1].expected(),
+            HashValue::from(String::from("0000"))
+        );
+        assert!(!verifications[1].matches());
+        assert!(verifications[1].as_result().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hash_of_decompressed_content() {
+        // `printf butido | gzip -n`
+        const GZIP_FIXTURE: &[u8] = &[
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x4b, 0x2a, 0x2d, 0xc9,
+            0x4c, 0xc9, 0x07, 0x00, 0x43, 0x75, 0x0b, 0xba, 0x06, 0x00, 0x00, 0x00,
+        ];
+        let cache_root = temp_dir();
+        let pkg = package("a", "1", "https://example.com/a.txt.gz", "0000");
+        let entry = |url: &str, hash_of: &str| SourceEntry {
+            cache_root: cache_root.to_path_buf(),
+            layout: CacheLayout::Package,
+            package_name: pkg.name().clone(),
+            package_version: pkg.version().clone(),
+            package_source_name: String::from("src"),
+            // sha1 of "butido"
+            package_source: toml::from_str(&format!(
+                r#"
+                url = "{url}"
+                download_manually = false
+                hash_of = "{hash_of}"
+                hash = {{ type = "sha1", hash = "03f904e2a4708dfd115cf6665838244d0fda06c9" }}
+                "#
+            ))
+            .unwrap(),
+        };
+        let decompressed = entry("https://example.com/a.txt.gz", "decompressed");
+        let corrupt = entry("https://example.com/a.txt.gz", "decompressed");
+        let archive = entry("https://example.com/a.txt.gz", "archive");
+        let unknown_compression = entry("https://example.com/a.txt", "decompressed");
+        std::fs::create_dir_all(decompressed.path().parent().unwrap()).unwrap();
+        std::fs::write(decompressed.path(), GZIP_FIXTURE).unwrap();
+
+        let limits = HashLimits::default();
+        let decompressed = decompressed
+            .verify_hash_detailed(vec![], false, &limits)
+            .await;
+        let archive = archive.verify_hash_detailed(vec![], false, &limits).await;
+        let unknown_compression = unknown_compression
+            .verify_hash_detailed(vec![], false, &limits)
+            .await;
+
+        assert!(decompressed.unwrap().matches());
+        assert!(!archive.unwrap().matches());
+        assert!(unknown_compression.is_err());
+
+        // Corrupt files are reported
+        std::fs::write(corrupt.path(), "butido").unwrap();
+        let corrupt = corrupt.verify_hash_detailed(vec![], false, &limits).await;
+        let err = format!("{:#}", corrupt.unwrap_err());
+        assert!(err.contains("Decompressing"), "{err}");
+        assert!(err.contains("gzip header"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_source_names_must_not_escape_the_cache() {
+        let root = temp_dir();
+        let cache_root = root.join("cache");
+
+        let pkg = package("a", "1", "https://example.com/a.tar.gz", "0000");
+        let entry = SourceEntry {
+            cache_root: cache_root.to_path_buf(),
+            layout: CacheLayout::Package,
+            package_name: pkg.name().clone(),
+            package_version: pkg.version().clone(),
+            package_source_name: String::from("../../escape"),
+            package_source: pkg.sources()["src"].clone(),
+        };
+        let created = entry.create().await;
+        let escaped = root.join("escape.source").exists();
+
+        assert!(created.is_err());
+        assert!(!escaped);
+    }
+
+    #[test]
+    fn test_orphaned_files() {
+        let cache_root = temp_dir();
+        let sc = SourceCache::new(cache_root.to_path_buf());
+        let pkg = package("a", "1", "https://example.com/a.tar.gz", "0000");
+        assert!(sc.orphaned_files(&pkg).unwrap().is_empty());
+
+        // The source was renamed from "old" to "src" in the pkg.toml
+        let current = sc.sources_for(&pkg)[0].path();
+        let dir = current.parent().unwrap();
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(&current, b"butido").unwrap();
+        std::fs::write(dir.join("old.source"), b"butido").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"butido").unwrap();
+
+        // The file name does not depend on the hash, so a hash bump does not leave an orphan
+        let bumped = package("a", "1", "https://example.com/a.tar.gz", "1111");
+        let orphans = sc.orphaned_files(&pkg).unwrap();
+        let orphans_after_bump = sc.orphaned_files(&bumped).unwrap();
+
+        assert_eq!(orphans, vec![dir.join("old.source")]);
+        assert_eq!(orphans_after_bump, orphans);
+    }
+
+    #[tokio::test]
+    async fn test_mmap_and_buffered_hashes_match() {
+        let cache_root = temp_dir();
+        let pkg = package("a", "1", "https://example.com/a.tar.gz", "0000");
+        let source = SourceCache::new(cache_root.to_path_buf())
+            .sources_for(&pkg)
+            .remove(0);
+        std::fs::create_dir_all(source.path().parent().unwrap()).unwrap();
+        std::fs::write(source.path(), "butido".repeat(1000)).unwrap();
+
+        let limits = HashLimits::default();
+        let bar = ProgressBar::hidden();
+        let buffered = source.compute_hash(vec![], None, &limits).await.unwrap();
+        let mapped = source
+            .compute_hash(vec![bar.clone()], Some(0), &limits)
+            .await
+            .unwrap();
+        assert_eq!(buffered, mapped);
+        assert_eq!(bar.position(), 6000);
+
+        assert!(source.mmap(0).unwrap().is_some());
+        assert!(source.mmap(6000).unwrap().is_none());
+
+        // A file that is being downloaded again is not mapped
+        std::fs::write(source.tmp_path(), "butido").unwrap();
+        assert!(source.mmap(0).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_hash_limits_are_respected() {
+        let cache_root = temp_dir();
+        let pkg = package("a", "1", "https://example.com/a.tar.gz", "0000");
+        let source = SourceCache::new(cache_root.to_path_buf())
+            .sources_for(&pkg)
+            .remove(0);
+        std::fs::create_dir_all(source.path().parent().unwrap()).unwrap();
+        std::fs::write(source.path(), "butido".repeat(1000)).unwrap();
+        let limits = HashLimits::new(Some(1), Some(1));
+
+        let unlimited = source.compute_hash(vec![], None, &limits).await.unwrap();
+
+        let read_permit = limits.read.as_ref().unwrap().acquire().await.unwrap();
+        let mut while_reading = Box::pin(source.compute_hash(vec![], None, &limits));
+        let blocked_by_read = futures::poll!(&mut while_reading).is_pending();
+        drop(read_permit);
+        let after_read = while_reading.await.unwrap();
+
+        let hash_permit = limits.hash.as_ref().unwrap().acquire().await.unwrap();
+        let mut while_hashing = Box::pin(source.compute_hash(vec![], None, &limits));
+        let blocked_by_hash = futures::poll!(&mut while_hashing).is_pending();
+        drop(hash_permit);
+        let after_hash = while_hashing.await.unwrap();
+
+        assert!(blocked_by_read);
+        assert!(blocked_by_hash);
+        assert_eq!(after_read, unlimited);
+        assert_eq!(after_hash, unlimited);
+    }
+
+    #[test]
+    fn test_cas_paths() {
+        let cache_root = PathBuf::from("/cache");
+        let pkg = package("a", "1", "https://example.com/a.tar.gz", "abcdef");
+        let source = |layout| {
+            SourceCache::new(cache_root.to_path_buf())
+                .with_layout(layout)
+                .sources_for(&pkg)
+                .remove(0)
+        };
+
+        let package_layout = source(CacheLayout::Package);
+        assert_eq!(
+            package_layout.path(),
+            PathBuf::from("/cache/a-1/src.source")
+        );
+        assert_eq!(package_layout.cas_path(), None);
+
+        let cas = source(CacheLayout::Cas);
+        assert_eq!(cas.path(), PathBuf::from("/cache/a-1/src.source"));
+        assert_eq!(cas.cas_path(), Some(PathBuf::from("/cache/cas/ab/abcdef")));
+
+        let mut decompressed = source(CacheLayout::Cas);
+        decompressed.package_source = toml::from_str(
+            r#"
+            url = "https://example.com/a.tar.gz"
+            download_manually = false
+            hash_of = "decompressed"
+            hash = { type = "sha1", hash = "abcdef" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(decompressed.cas_path(), None);
+    }
+
+    #[tokio::test]
+    async fn test_cas_stores_identical_sources_once() {
+        use tokio::io::AsyncWriteExt;
+
+        let cache_root = temp_dir();
+        let fixture_hash = "03f904e2a4708dfd115cf6665838244d0fda06c9"; // sha1 of "butido"
+        let sc = SourceCache::new(cache_root.to_path_buf()).with_layout(CacheLayout::Cas);
+        let a1 = package("a", "1", "https://example.com/a-1.tar.gz", fixture_hash);
+        let a2 = package("a", "2", "https://example.com/a-2.tar.gz", fixture_hash);
+        let first = sc.sources_for(&a1).remove(0);
+        let second = sc.sources_for(&a2).remove(0);
+
+        assert!(!first.link_stored().await.unwrap());
+        let mut file = first.create().await.unwrap();
+        file.write_all(b"butido").await.unwrap();
+        file.flush().await.unwrap();
+        assert!(!first.exists());
+        first.persist().await.unwrap();
+        assert!(first.exists());
+
+        assert!(!second.exists());
+        assert!(second.link_stored().await.unwrap());
+        assert!(second.exists());
+
+        let cas_path = first.cas_path().unwrap();
+        assert_eq!(second.cas_path().as_ref(), Some(&cas_path));
+        assert_eq!(
+            std::fs::read_dir(cas_path.parent().unwrap())
+                .unwrap()
+                .count(),
+            1
+        );
+        for source in [&first, &second] {
+            assert!(source.path().is_symlink());
+            assert_eq!(
+                source.path().canonicalize().unwrap(),
+                cas_path.canonicalize().unwrap()
+            );
+            let verification = source
+                .verify_hash_detailed(vec![], false, &HashLimits::default())
+                .await
+                .unwrap();
+            assert!(verification.matches());
+        }
+
+        assert!(sc.orphaned_stored_files().unwrap().is_empty());
+
+        // The stored file is kept as long as a source links to it
+        second.remove_file().await.unwrap();
+        assert!(!second.exists());
+        assert!(first.exists());
+        assert!(sc.orphaned_stored_files().unwrap().is_empty());
+
+        first.remove_file().await.unwrap();
+        assert!(!cas_path.exists());
+        assert!(!first.exists());
+
+        // A stored file that is left behind, e.g. after the hash of a source was bumped
+        std::fs::write(&cas_path, b"butido").unwrap();
+        assert_eq!(sc.orphaned_stored_files().unwrap(), vec![cas_path]);
     }
 }
@@ -0,0 +1,222 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use tracing::debug;
+
+/// The credentials of a machine in a netrc file
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct NetrcEntry {
+    login: String,
+    password: String,
+}
+
+/// Never print the password
+impl std::fmt::Debug for NetrcEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NetrcEntry")
+            .field("login", &self.login)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+impl NetrcEntry {
+    /// Add the credentials as HTTP basic authentication to the request
+    pub fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request.basic_auth(&self.login, Some(&self.password))
+    }
+}
+
+/// The contents of a netrc file (usually `~/.netrc`)
+#[derive(Debug, Default)]
+pub struct Netrc {
+    machines: HashMap<String, NetrcEntry>,
+    default: Option<NetrcEntry>,
+}
+
+impl Netrc {
+    /// Load the netrc file from `$NETRC` or `~/.netrc`
+    ///
+    /// Returns `None` if the file does not exist.
+    pub fn load() -> Result<Option<Self>> {
+        let path = match std::env::var_os("NETRC") {
+            Some(path) => PathBuf::from(path),
+            None => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".netrc"),
+                None => return Ok(None),
+            },
+        };
+
+        if !path.exists() {
+            debug!("No netrc file at {}", path.display());
+            return Ok(None);
+        }
+
+        std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Netrc::parse(&content))
+            .with_context(|| anyhow!("Loading netrc file {}", path.display()))
+            .map(Some)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut netrc = Netrc::default();
+
+        // The machine the tokens currently belong to, None for "default"
+        let mut current: Option<(Option<String>, NetrcEntry)> = None;
+        let mut lines = content.lines();
+        while let Some(line) = lines.next() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+            while let Some(token) = tokens.next() {
+                let mut value = |name: &str| {
+                    tokens
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing value for '{}' in netrc file", name))
+                };
+
+                match token {
+                    "machine" => {
+                        let machine = value("machine")?.to_lowercase();
+                        netrc.insert(current.replace((Some(machine), NetrcEntry::default())));
+                    }
+                    "default" => {
+                        netrc.insert(current.replace((None, NetrcEntry::default())));
+                    }
+                    "login" | "password" | "account" => {
+                        let value = value(token)?.to_string();
+                        let (_, entry) = current.as_mut().ok_or_else(|| {
+                            anyhow!("'{}' without 'machine' in netrc file", token)
+                        })?;
+                        match token {
+                            "login" => entry.login = value,
+                            "password" => entry.password = value,
+                            _ => {} // not used for HTTP
+                        }
+                    }
+                    "macdef" => {
+                        // Macro definitions end with an empty line
+                        for line in lines.by_ref() {
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        break;
+                    }
+                    other => return Err(anyhow!("Unknown token in netrc file: {}", other)),
+                }
+            }
+        }
+        netrc.insert(current);
+
+        Ok(netrc)
+    }
+
+    fn insert(&mut self, entry: Option<(Option<String>, NetrcEntry)>) {
+        match entry {
+            Some((Some(machine), entry)) => {
+                // Like other netrc implementations, the first entry for a machine wins
+                self.machines.entry(machine).or_insert(entry);
+            }
+            Some((None, entry)) => self.default = Some(entry),
+            None => {}
+        }
+    }
+
+    /// The credentials for a host, falling back to the "default" entry
+    pub fn credentials_for(&self, host: &str) -> Option<&NetrcEntry> {
+        self.machines
+            .get(&host.to_lowercase())
+            .or(self.default.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(login: &str, password: &str) -> NetrcEntry {
+        NetrcEntry {
+            login: String::from(login),
+            password: String::from(password),
+        }
+    }
+
+    #[test]
+    fn test_host_matching() {
+        let netrc = Netrc::parse(indoc::indoc!(
+            r#"
+            # Artifact servers
+            machine artifacts.example.com login butido password secret
+            machine Private.Example.com
+                login other
+                account ignored
+                password hidden
+        "#
+        ))
+        .unwrap();
+
+        assert_eq!(
+            netrc.credentials_for("artifacts.example.com"),
+            Some(&entry("butido", "secret"))
+        );
+        assert_eq!(
+            netrc.credentials_for("private.example.COM"),
+            Some(&entry("other", "hidden"))
+        );
+        assert_eq!(netrc.credentials_for("sub.artifacts.example.com"), None);
+        assert_eq!(netrc.credentials_for("example.com"), None);
+    }
+
+    #[test]
+    fn test_default_entry() {
+        let netrc = Netrc::parse(indoc::indoc!(
+            r#"
+            machine artifacts.example.com login butido password secret
+            macdef init
+            cd /pub
+            get file
+
+            default login anonymous password guest
+        "#
+        ))
+        .unwrap();
+
+        assert_eq!(
+            netrc.credentials_for("artifacts.example.com"),
+            Some(&entry("butido", "secret"))
+        );
+        assert_eq!(
+            netrc.credentials_for("example.com"),
+            Some(&entry("anonymous", "guest"))
+        );
+    }
+
+    #[test]
+    fn test_invalid_netrc() {
+        assert!(Netrc::parse("login butido").is_err());
+        assert!(Netrc::parse("machine").is_err());
+        assert!(Netrc::parse("machine a.example.com user butido").is_err());
+    }
+
+    #[test]
+    fn test_debug_masks_password() {
+        assert!(!format!("{:?}", entry("butido", "secret")).contains("secret"));
+    }
+}
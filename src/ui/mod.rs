@@ -23,6 +23,25 @@ use crate::package::Script;
 mod package;
 pub use crate::ui::package::*;
 
+/// The ptree configuration of the tree outputs
+///
+/// With `ascii` (see the `--ascii` flags and the `ascii_trees` setting), the branches are drawn
+/// with "|", "+--" and "`--" instead of box-drawing characters.
+pub fn tree_print_config(ascii: bool) -> ptree::PrintConfig {
+    let mut config = ptree::PrintConfig::from_env();
+    if ascii {
+        config.indent = 4;
+        config.characters = ptree::IndentChars {
+            down_and_right: String::from("+"),
+            down: String::from("|"),
+            turn_right: String::from("`"),
+            right: String::from("-"),
+            empty: String::from(" "),
+        };
+    }
+    config
+}
+
 pub fn script_to_printable(
     script: &Script,
     highlight: bool,
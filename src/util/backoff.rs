@@ -0,0 +1,119 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Exponential backoff for retrying operations that failed with a temporary error, see
+//! `crate::util::retry`
+
+use std::time::Duration;
+
+use getset::CopyGetters;
+
+/// The delays between the attempts of an operation
+///
+/// The first retry is delayed by `base_delay`, the delay is doubled for every further retry but
+/// never exceeds `max_delay`.
+#[derive(Clone, Debug, CopyGetters, typed_builder::TypedBuilder)]
+pub struct Backoff {
+    base_delay: Duration,
+
+    #[builder(default = Duration::MAX)]
+    max_delay: Duration,
+
+    /// How often the operation is retried, i.e. it is attempted up to `retries + 1` times
+    #[getset(get_copy = "pub")]
+    retries: u32,
+
+    /// Whether every delay is randomized between half of the delay and the full delay, so that
+    /// several clients do not retry at the same time
+    #[builder(default)]
+    jitter: bool,
+}
+
+impl Backoff {
+    /// The delays before each retry
+    pub fn delays(&self) -> impl Iterator<Item = Duration> {
+        let max_delay = self.max_delay;
+        let jitter = self.jitter;
+        std::iter::successors(Some(self.base_delay.min(max_delay)), move |delay| {
+            Some(delay.saturating_mul(2).min(max_delay))
+        })
+        .take(self.retries as usize)
+        .map(move |delay| {
+            if jitter {
+                let half = delay / 2;
+                half + (delay - half).mul_f64(fastrand::f64())
+            } else {
+                delay
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delays() {
+        let backoff = Backoff::builder()
+            .base_delay(Duration::from_millis(100))
+            .retries(4)
+            .build();
+        assert_eq!(
+            backoff.delays().collect::<Vec<_>>(),
+            [100, 200, 400, 800].map(Duration::from_millis)
+        );
+
+        let backoff = Backoff::builder()
+            .base_delay(Duration::ZERO)
+            .retries(0)
+            .build();
+        assert_eq!(backoff.delays().count(), 0);
+    }
+
+    #[test]
+    fn test_delays_are_capped() {
+        let backoff = Backoff::builder()
+            .base_delay(Duration::from_secs(1))
+            .max_delay(Duration::from_secs(5))
+            .retries(5)
+            .build();
+        assert_eq!(
+            backoff.delays().collect::<Vec<_>>(),
+            [1, 2, 4, 5, 5].map(Duration::from_secs)
+        );
+
+        // Doubling must not overflow
+        let backoff = Backoff::builder()
+            .base_delay(Duration::MAX)
+            .retries(3)
+            .build();
+        assert_eq!(backoff.delays().last(), Some(Duration::MAX));
+    }
+
+    #[test]
+    fn test_delays_with_jitter() {
+        let backoff = Backoff::builder()
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(1000))
+            .retries(8)
+            .jitter(true)
+            .build();
+        for _ in 0..100 {
+            let delays = backoff.delays().collect::<Vec<_>>();
+            assert_eq!(delays.len(), 8);
+            let expected = [100, 200, 400, 800, 1000, 1000, 1000, 1000];
+            for (delay, expected) in delays.into_iter().zip(expected) {
+                let expected = Duration::from_millis(expected);
+                assert!(delay >= expected / 2 && delay <= expected, "{delay:?}");
+            }
+        }
+    }
+}
@@ -12,10 +12,14 @@ use std::collections::HashMap;
 
 use anyhow::anyhow;
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 use tracing::warn;
 
+use crate::util::backoff::Backoff;
+use crate::util::retry::retry_async;
+
 #[derive(
     parse_display::Display,
     Serialize,
@@ -27,6 +31,7 @@ use tracing::warn;
     PartialEq,
     Ord,
     PartialOrd,
+    JsonSchema,
 )]
 #[display("{0}")]
 pub struct ImageName(String);
@@ -44,13 +49,26 @@ impl From<&str> for ImageName {
     }
 }
 
+impl ImageName {
+    /// The host of the registry the image is pulled from
+    ///
+    /// Like Docker, the first component of the name is only a registry host if it contains a "."
+    /// or a ":" or is "localhost", otherwise the image is from Docker Hub ("docker.io").
+    pub fn registry_host(&self) -> &str {
+        match self.0.split_once('/') {
+            Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => host,
+            _ => "docker.io",
+        }
+    }
+}
+
 impl AsRef<str> for ImageName {
     fn as_ref(&self) -> &str {
         self.0.as_ref()
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
 pub struct ContainerImage {
     pub name: ImageName,
     pub short_name: ImageName,
@@ -108,3 +126,214 @@ impl AsRef<str> for ContainerHash {
         self.0.as_ref()
     }
 }
+
+/// A platform of container images, in the form "os/arch" or "os/arch/variant" (e.g. "linux/amd64"
+/// or "linux/arm/v7")
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Platform {
+    os: String,
+    architecture: String,
+    variant: Option<String>,
+}
+
+impl Platform {
+    /// Whether an image for the `os` and `architecture` (as reported by Docker) is for the platform
+    ///
+    /// Docker does not report the variant of an image, so it is not compared.
+    pub fn matches(&self, os: &str, architecture: &str) -> bool {
+        self.os == os && self.architecture == architecture
+    }
+}
+
+impl std::str::FromStr for Platform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let is_valid = |part: &str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        };
+
+        let parts = s.split('/').collect::<Vec<_>>();
+        match parts.as_slice() {
+            [os, architecture] | [os, architecture, _]
+                if parts.iter().all(|part| is_valid(part)) =>
+            {
+                Ok(Platform {
+                    os: os.to_string(),
+                    architecture: architecture.to_string(),
+                    variant: parts.get(2).map(|variant| variant.to_string()),
+                })
+            }
+            _ => Err(anyhow!(
+                "Invalid platform '{}', expected 'os/arch' or 'os/arch/variant' (e.g. 'linux/amd64')",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = self.variant.as_ref() {
+            write!(f, "/{variant}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The delay before the first retry of a Docker API call, it is doubled for every further retry
+const RETRY_BASE_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Whether a failed Docker API call may succeed if it is retried
+///
+/// Transport errors (e.g. connection resets) and server errors (5xx) are temporary, all other
+/// errors (e.g. "404 Not Found" or a malformed response) are not.
+pub fn is_retryable(err: &shiplift::Error) -> bool {
+    match err {
+        shiplift::Error::Hyper(_) | shiplift::Error::IO(_) => true,
+        shiplift::Error::Fault { code, .. } => code.is_server_error(),
+        _ => false,
+    }
+}
+
+/// Run a Docker API call and retry it up to `retries` times if it fails with a retryable error
+///
+/// Only use this for idempotent calls (e.g. listing or inspecting objects), never for calls with
+/// side effects like executing something in a container.
+pub async fn with_retries<T, F, Fut>(
+    retries: u32,
+    call: F,
+) -> std::result::Result<T, shiplift::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = std::result::Result<T, shiplift::Error>>,
+{
+    with_retries_and_delay(retries, RETRY_BASE_DELAY, call).await
+}
+
+async fn with_retries_and_delay<T, F, Fut>(
+    retries: u32,
+    base_delay: std::time::Duration,
+    call: F,
+) -> std::result::Result<T, shiplift::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = std::result::Result<T, shiplift::Error>>,
+{
+    let policy = Backoff::builder()
+        .base_delay(base_delay)
+        .retries(retries)
+        .build();
+    retry_async(call, &policy, is_retryable).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering;
+
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    #[test]
+    fn test_platform_parsing() {
+        for valid in [
+            "linux/amd64",
+            "linux/arm64",
+            "linux/arm/v7",
+            "windows/amd64",
+        ] {
+            let platform = valid.parse::<Platform>().unwrap();
+            assert_eq!(platform.to_string(), valid);
+        }
+
+        let platform = "linux/arm/v7".parse::<Platform>().unwrap();
+        assert!(platform.matches("linux", "arm"));
+        assert!(!platform.matches("linux", "arm64"));
+
+        for invalid in [
+            "",
+            "linux",
+            "amd64",
+            "linux/",
+            "/amd64",
+            "linux/amd64/",
+            "linux/arm/v7/extra",
+            "Linux/AMD64",
+            "linux amd64",
+            "linux/x86-64",
+        ] {
+            assert!(invalid.parse::<Platform>().is_err(), "{invalid} is valid");
+        }
+    }
+
+    fn fault(code: StatusCode) -> shiplift::Error {
+        shiplift::Error::Fault {
+            code,
+            message: String::from("fault"),
+        }
+    }
+
+    fn connection_reset() -> shiplift::Error {
+        shiplift::Error::IO(std::io::Error::from(std::io::ErrorKind::ConnectionReset))
+    }
+
+    #[test]
+    fn test_retry_classification() {
+        assert!(is_retryable(&connection_reset()));
+        assert!(is_retryable(&fault(StatusCode::INTERNAL_SERVER_ERROR)));
+        assert!(is_retryable(&fault(StatusCode::SERVICE_UNAVAILABLE)));
+
+        assert!(!is_retryable(&fault(StatusCode::NOT_FOUND)));
+        assert!(!is_retryable(&fault(StatusCode::CONFLICT)));
+        assert!(!is_retryable(&shiplift::Error::InvalidResponse(
+            String::from("garbage")
+        )));
+        assert!(!is_retryable(&shiplift::Error::ConnectionNotUpgraded));
+    }
+
+    #[tokio::test]
+    async fn test_with_retries() {
+        let calls = AtomicU32::new(0);
+        let call = |fail_times: u32, err: fn() -> shiplift::Error| {
+            calls.store(0, Ordering::SeqCst);
+            let calls = &calls;
+            move || async move {
+                if calls.fetch_add(1, Ordering::SeqCst) < fail_times {
+                    Err(err())
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        let no_delay = std::time::Duration::ZERO;
+
+        // Temporary errors are retried
+        assert!(
+            with_retries_and_delay(2, no_delay, call(2, connection_reset))
+                .await
+                .is_ok()
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // ... but only as often as configured
+        assert!(
+            with_retries_and_delay(1, no_delay, call(2, connection_reset))
+                .await
+                .is_err()
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Genuine API errors are not retried
+        let not_found = || fault(StatusCode::NOT_FOUND);
+        assert!(with_retries_and_delay(2, no_delay, call(1, not_found))
+            .await
+            .is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -18,6 +18,7 @@ use crate::package::Package;
 use crate::package::PackageName;
 use crate::package::PackageVersionConstraint;
 use crate::package::ParseDependency;
+use crate::repository::Repository;
 
 /// Helper function to build a package filter based on some flags and the package version
 pub fn build_package_filter_by_dependency_name(
@@ -68,10 +69,14 @@ pub fn build_package_filter_by_dependency_name(
     filter_build_dep.or(filter_rt_dep)
 }
 
-pub fn build_package_filter_by_name(name: PackageName) -> impl filters::filter::Filter<Package> {
+/// Filter the packages of the `repo` by name, see `Repository::names_match()`
+pub fn build_package_filter_by_name(
+    repo: &Repository,
+    name: PackageName,
+) -> impl filters::filter::Filter<Package> + '_ {
     move |p: &Package| {
         trace!("Checking {:?} -> name == {}", p, name);
-        *p.name() == name
+        repo.names_match(p.name(), &name)
     }
 }
 
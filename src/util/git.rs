@@ -14,6 +14,11 @@ use anyhow::Result;
 use git2::Repository;
 use tracing::trace;
 
+/// The abbreviated form of a commit hash, as used by `git log --oneline`
+pub fn short_hash(hash: &str) -> &str {
+    hash.get(..7).unwrap_or(hash)
+}
+
 pub fn get_repo_head_commit_hash(r: &Repository) -> Result<String> {
     let s = r
         .head()
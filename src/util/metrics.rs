@@ -0,0 +1,191 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Metrics of a butido run in the Prometheus text format, written with the global
+//! `--metrics-file` flag
+//!
+//! The metrics are written once when the run finished, e.g. for the textfile collector of the
+//! node exporter.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::util::timings::Timings;
+
+/// The events of a run that are counted
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Counter {
+    Builds,
+    BuildFailures,
+    SourceBytesDownloaded,
+    SourceVerificationFailures,
+}
+
+impl Counter {
+    const ALL: [Counter; 4] = [
+        Counter::Builds,
+        Counter::BuildFailures,
+        Counter::SourceBytesDownloaded,
+        Counter::SourceVerificationFailures,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Counter::Builds => "butido_builds_total",
+            Counter::BuildFailures => "butido_build_failures_total",
+            Counter::SourceBytesDownloaded => "butido_source_downloaded_bytes_total",
+            Counter::SourceVerificationFailures => "butido_source_verification_failures_total",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Counter::Builds => "Number of builds that were run",
+            Counter::BuildFailures => "Number of builds with failed jobs",
+            Counter::SourceBytesDownloaded => "Number of bytes of downloaded sources",
+            Counter::SourceVerificationFailures => "Number of sources that failed the verification",
+        }
+    }
+}
+
+const STAGE_DURATION: &str = "butido_stage_duration_seconds";
+
+/// Escape a label value, see the Prometheus exposition format
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The counters and stage durations of `timings` in the Prometheus text format
+pub fn to_prometheus_text(timings: &Timings) -> String {
+    let mut text = String::new();
+    for counter in Counter::ALL {
+        // Writing to a String cannot fail
+        let _ = writeln!(text, "# HELP {} {}", counter.name(), counter.help());
+        let _ = writeln!(text, "# TYPE {} counter", counter.name());
+        let _ = writeln!(text, "{} {}", counter.name(), timings.counter(counter));
+    }
+
+    let _ = writeln!(
+        text,
+        "# HELP {STAGE_DURATION} How long the stages of the run took"
+    );
+    let _ = writeln!(text, "# TYPE {STAGE_DURATION} gauge");
+    for (stage, duration) in timings.stages() {
+        let _ = writeln!(
+            text,
+            "{}{{stage=\"{}\"}} {}",
+            STAGE_DURATION,
+            escape_label_value(&stage),
+            duration.as_secs_f64()
+        );
+    }
+    text
+}
+
+/// Write the metrics to `path`
+///
+/// The file is replaced atomically, so a scraper never reads a partially written file.
+pub fn write_metrics_file(path: &Path, timings: &Timings) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Not a file: {}", path.display()))?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    std::fs::write(&tmp_path, to_prometheus_text(timings))
+        .with_context(|| anyhow!("Writing {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| anyhow!("Moving {} to {}", tmp_path.display(), path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::util::timings::TimingsFormat;
+
+    /// Check that `text` is valid according to the Prometheus text exposition format
+    ///
+    /// Returns the samples by metric name and labels.
+    fn parse_exposition_format(text: &str) -> HashMap<String, f64> {
+        let metric_name = r"[a-zA-Z_:][a-zA-Z0-9_:]*";
+        let label_value = r#""(?:[^"\\\n]|\\[\\"n])*""#;
+        let help = regex::Regex::new(&format!(r"^# HELP ({metric_name}) .*$")).unwrap();
+        let typ = regex::Regex::new(&format!(
+            r"^# TYPE ({metric_name}) (counter|gauge|histogram|summary|untyped)$"
+        ))
+        .unwrap();
+        let sample = regex::Regex::new(&format!(
+            r"^({metric_name})(\{{[a-zA-Z_][a-zA-Z0-9_]*={label_value}(?:,[a-zA-Z_][a-zA-Z0-9_]*={label_value})*\}})? (\S+)$"
+        ))
+        .unwrap();
+
+        assert!(text.ends_with('\n'), "missing final line feed");
+        let mut typed = Vec::new();
+        let mut samples = HashMap::new();
+        for line in text.lines() {
+            if let Some(captures) = typ.captures(line) {
+                let name = captures[1].to_string();
+                assert!(!typed.contains(&name), "second TYPE for {name}");
+                typed.push(name);
+            } else if help.is_match(line) {
+                continue;
+            } else if let Some(captures) = sample.captures(line) {
+                let name = captures[1].to_string();
+                assert_eq!(typed.last(), Some(&name), "sample before TYPE: {line}");
+                let value = captures[3].parse::<f64>().expect(line);
+                let key = format!("{}{}", name, captures.get(2).map_or("", |m| m.as_str()));
+                assert!(samples.insert(key, value).is_none(), "duplicate: {line}");
+            } else {
+                panic!("invalid line: {line}");
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn test_prometheus_text_format() {
+        let timings = Timings::new(Some(TimingsFormat::Text));
+        timings.count(Counter::Builds, 1);
+        timings.count(Counter::SourceBytesDownloaded, 1024);
+        timings.add("Loading the repository", Duration::from_millis(250));
+        timings.add("Building \"a\" 1\\2", Duration::from_secs(2));
+
+        let samples = parse_exposition_format(&to_prometheus_text(&timings));
+        assert_eq!(samples["butido_builds_total"], 1.0);
+        assert_eq!(samples["butido_build_failures_total"], 0.0);
+        assert_eq!(samples["butido_source_downloaded_bytes_total"], 1024.0);
+        assert_eq!(samples["butido_source_verification_failures_total"], 0.0);
+        assert_eq!(
+            samples[r#"butido_stage_duration_seconds{stage="Loading the repository"}"#],
+            0.25
+        );
+        assert_eq!(
+            samples[r#"butido_stage_duration_seconds{stage="Building \"a\" 1\\2"}"#],
+            2.0
+        );
+    }
+
+    #[test]
+    fn test_prometheus_text_without_stages() {
+        let samples = parse_exposition_format(&to_prometheus_text(&Timings::new(None)));
+        assert_eq!(samples.len(), Counter::ALL.len());
+    }
+}
@@ -10,6 +10,9 @@
 
 use std::io::IsTerminal;
 
+use anyhow::anyhow;
+use anyhow::Result;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -24,6 +27,7 @@ use serde::Serialize;
     PartialEq,
     Ord,
     PartialOrd,
+    JsonSchema,
 )]
 #[serde(transparent)]
 #[display("{0}")]
@@ -41,13 +45,51 @@ impl AsRef<str> for EnvironmentVariableName {
     }
 }
 
+pub mod backoff;
 pub mod docker;
 pub mod env;
 pub mod filters;
 pub mod git;
+pub mod metrics;
+pub mod notify;
+pub mod parallelism;
 pub mod parser;
 pub mod progress;
+pub mod retry;
+pub mod summary;
+pub mod timings;
+pub mod width;
+
+/// Check that a name from a package definition can be used as a single component of a path
+///
+/// Such names are joined to directories (e.g. the source cache), so they must not contain path
+/// separators and must not be "." or "..", otherwise the resulting path could be outside of the
+/// directory.
+pub fn check_path_component(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        Err(anyhow!("'{}' is not allowed as a name", name))
+    } else if name.contains(['/', '\\', '\0']) {
+        Err(anyhow!("'{}' must not contain path separators", name))
+    } else {
+        Ok(())
+    }
+}
 
 pub fn stdout_is_pipe() -> bool {
     !std::io::stdout().is_terminal()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_path_component() {
+        for valid in ["src", "a-1.tar.gz", "..tar", "a..b"] {
+            assert!(check_path_component(valid).is_ok(), "{valid} is invalid");
+        }
+        for invalid in ["", ".", "..", "../escape", "a/b", "/abs", "a\\b", "a\0b"] {
+            assert!(check_path_component(invalid).is_err(), "{invalid} is valid");
+        }
+    }
+}
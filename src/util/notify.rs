@@ -0,0 +1,197 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Notifications about finished builds, see the `--notify-command` and `--notify-webhook` flags
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+use tracing::debug;
+use tracing::warn;
+use uuid::Uuid;
+
+/// How long posting to the webhook may take, so that an unresponsive server doesn't block butido
+const WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// The overall result of a build
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildStatus {
+    Success,
+    Failure,
+}
+
+impl BuildStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BuildStatus::Success => "success",
+            BuildStatus::Failure => "failure",
+        }
+    }
+}
+
+/// The result of a single package of a build
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PackageResult {
+    Success,
+    Failure,
+
+    /// No job ran for the package, because an artifact was reused or the build stopped before
+    NotRun,
+}
+
+#[derive(Debug, Serialize)]
+struct PackageNotification {
+    name: String,
+    version: String,
+    result: PackageResult,
+}
+
+/// The notification about a finished build
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    submit: Uuid,
+    status: BuildStatus,
+    packages: Vec<PackageNotification>,
+}
+
+impl Notification {
+    /// Create the notification for the `packages` (name and version) of a submit
+    ///
+    /// `jobs` are the package name, version and success of all jobs of the submit. A package is
+    /// successful if any of its jobs succeeded.
+    pub fn new<I>(
+        submit: Uuid,
+        status: BuildStatus,
+        packages: I,
+        jobs: &[(String, String, Option<bool>)],
+    ) -> Self
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let packages = packages
+            .into_iter()
+            .map(|(name, version)| {
+                let results = jobs
+                    .iter()
+                    .filter(|(n, v, _)| *n == name && *v == version)
+                    .map(|(_, _, success)| *success)
+                    .collect::<Vec<_>>();
+                let result = if results.is_empty() {
+                    PackageResult::NotRun
+                } else if results.contains(&Some(true)) {
+                    PackageResult::Success
+                } else {
+                    PackageResult::Failure
+                };
+                PackageNotification {
+                    name,
+                    version,
+                    result,
+                }
+            })
+            .collect();
+
+        Notification {
+            submit,
+            status,
+            packages,
+        }
+    }
+
+    /// Run `command` and post to `webhook`, failures are only reported as warnings
+    pub async fn send(&self, command: Option<&str>, webhook: Option<&url::Url>) {
+        if let Some(command) = command {
+            if let Err(e) = self.run_command(command).await {
+                warn!("Notification command failed: {:#}", e);
+            }
+        }
+
+        if let Some(webhook) = webhook {
+            if let Err(e) = self.post(webhook).await {
+                warn!("Notification webhook failed: {:#}", e);
+            }
+        }
+    }
+
+    async fn run_command(&self, command: &str) -> Result<()> {
+        debug!("Running notification command: {}", command);
+        let status = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("BUTIDO_SUBMIT", self.submit.to_string())
+            .env("BUTIDO_STATUS", self.status.as_str())
+            .env("BUTIDO_NOTIFICATION", serde_json::to_string(self)?)
+            .status()
+            .await
+            .with_context(|| anyhow!("Running {}", command))?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(anyhow!("{} exited with {}", command, status))
+        }
+    }
+
+    async fn post(&self, webhook: &url::Url) -> Result<()> {
+        debug!("Posting notification to {}", webhook);
+        reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .context("Building the HTTP client")?
+            .post(webhook.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(self)?)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| anyhow!("Posting to {}", webhook))
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification_payload() {
+        let submit = Uuid::nil();
+        let packages = [("a", "1"), ("b", "2"), ("c", "3"), ("d", "4")]
+            .into_iter()
+            .map(|(name, version)| (name.to_string(), version.to_string()));
+        let job =
+            |name: &str, version: &str, success| (name.to_string(), version.to_string(), success);
+        let jobs = [
+            job("a", "1", Some(false)),
+            job("b", "2", Some(false)),
+            job("b", "2", Some(true)),
+            job("c", "3", None),
+            job("a", "2", Some(true)),
+        ];
+
+        let notification = Notification::new(submit, BuildStatus::Failure, packages, &jobs);
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({
+                "submit": "00000000-0000-0000-0000-000000000000",
+                "status": "failure",
+                "packages": [
+                    { "name": "a", "version": "1", "result": "failure" },
+                    { "name": "b", "version": "2", "result": "success" },
+                    { "name": "c", "version": "3", "result": "failure" },
+                    { "name": "d", "version": "4", "result": "not-run" },
+                ],
+            })
+        );
+    }
+}
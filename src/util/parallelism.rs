@@ -0,0 +1,97 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Concurrency settings that are shared by all subsystems that do work in parallel
+//!
+//! The effective number of jobs is resolved with the following precedence (highest first):
+//!
+//! 1. A command specific flag (e.g. a flag of the `source verify` subcommand)
+//! 2. The global `--jobs` CLI flag
+//! 3. The `parallelism` configuration setting
+//! 4. The number of available CPUs
+
+use std::num::NonZeroUsize;
+
+/// The parallelism that is used by default when neither the CLI nor the configuration set it
+fn available_cpus() -> usize {
+    std::thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Parallelism {
+    /// The value of the global `--jobs` CLI flag, if passed
+    cli_jobs: Option<usize>,
+
+    /// The value of the `parallelism` configuration setting, if set
+    config_jobs: Option<usize>,
+
+    /// The fallback if nothing was configured
+    available_cpus: usize,
+}
+
+impl Parallelism {
+    pub fn new(cli_jobs: Option<usize>, config_jobs: Option<usize>) -> Self {
+        Self::with_available_cpus(cli_jobs, config_jobs, available_cpus())
+    }
+
+    fn with_available_cpus(
+        cli_jobs: Option<usize>,
+        config_jobs: Option<usize>,
+        available_cpus: usize,
+    ) -> Self {
+        Parallelism {
+            cli_jobs,
+            config_jobs,
+            available_cpus,
+        }
+    }
+
+    /// The number of jobs that was explicitly requested by the user (via CLI or configuration)
+    ///
+    /// Subsystems that are not CPU bound (e.g. downloads) can use this to fall back to their own
+    /// default instead of the number of CPUs.
+    pub fn explicit(&self, command_flag: Option<usize>) -> Option<usize> {
+        command_flag.or(self.cli_jobs).or(self.config_jobs)
+    }
+
+    /// Resolve the number of jobs, optionally overridden by a command specific flag
+    pub fn resolve(&self, command_flag: Option<usize>) -> usize {
+        self.explicit(command_flag).unwrap_or(self.available_cpus)
+    }
+
+    /// The number of jobs if there is no command specific override
+    pub fn jobs(&self) -> usize {
+        self.resolve(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution_precedence() {
+        let p = Parallelism::with_available_cpus(None, None, 8);
+        assert_eq!(p.jobs(), 8);
+        assert_eq!(p.explicit(None), None);
+        assert_eq!(p.resolve(Some(2)), 2);
+
+        let p = Parallelism::with_available_cpus(None, Some(4), 8);
+        assert_eq!(p.jobs(), 4);
+        assert_eq!(p.resolve(Some(2)), 2);
+
+        let p = Parallelism::with_available_cpus(Some(3), Some(4), 8);
+        assert_eq!(p.jobs(), 3);
+        assert_eq!(p.explicit(None), Some(3));
+        assert_eq!(p.resolve(Some(2)), 2);
+    }
+}
@@ -31,6 +31,10 @@ pub fn dot<'a>() -> PomParser<'a, u8, Vec<u8>> {
     sym(b'.').map(|b| vec![b])
 }
 
+pub fn plus<'a>() -> PomParser<'a, u8, Vec<u8>> {
+    sym(b'+').map(|b| vec![b])
+}
+
 pub fn equal<'a>() -> PomParser<'a, u8, Vec<u8>> {
     sym(b'=').map(|b| vec![b])
 }
@@ -8,29 +8,559 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
 use getset::CopyGetters;
 use indicatif::*;
+use tokio::io::AsyncRead;
+use tokio::io::ReadBuf;
+
+/// The template of the bars in the plain mode, the configured templates are meant for terminals
+const PLAIN_BAR_TEMPLATE: &str = "[{elapsed_precise}] {msg}: {pos}/{len}";
+const PLAIN_SPINNER_TEMPLATE: &str = "[{elapsed_precise}] {msg}";
+
+/// The minimal time between two progress lines in the plain mode
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How the progress is shown, see the `--progress` flag
+#[derive(parse_display::Display, parse_display::FromStr, Clone, Copy, Debug, Eq, PartialEq)]
+#[display(style = "lowercase")]
+pub enum ProgressMode {
+    /// Progress bars that are redrawn in place, for terminals
+    Fancy,
+
+    /// Plain text progress lines without control sequences, e.g. for CI logs
+    Plain,
+
+    Hidden,
+}
+
+impl ProgressMode {
+    /// The values that are accepted by the `--progress` flag
+    pub const VALUES: [&'static str; 3] = ["fancy", "plain", "hidden"];
+}
 
 #[derive(Clone, Debug, CopyGetters)]
 pub struct ProgressBars {
     bar_template: String,
+    spinner_template: String,
 
     #[getset(get_copy = "pub")]
-    hide: bool,
+    mode: ProgressMode,
 }
 
 impl ProgressBars {
-    pub fn setup(bar_template: String, hide: bool) -> Self {
-        ProgressBars { bar_template, hide }
+    pub fn setup(bar_template: String, spinner_template: String, mode: ProgressMode) -> Self {
+        ProgressBars {
+            bar_template,
+            spinner_template,
+            mode,
+        }
     }
 
     pub fn bar(&self) -> anyhow::Result<ProgressBar> {
-        if self.hide {
-            Ok(ProgressBar::hidden())
+        match self.mode {
+            ProgressMode::Fancy => {
+                let b = ProgressBar::new(1);
+                b.set_style(ProgressStyle::default_bar().template(&self.bar_template)?);
+                Ok(b)
+            }
+            ProgressMode::Plain => {
+                let b = ProgressBar::with_draw_target(Some(1), plain_draw_target());
+                b.set_style(ProgressStyle::default_bar().template(PLAIN_BAR_TEMPLATE)?);
+                Ok(b)
+            }
+            ProgressMode::Hidden => Ok(ProgressBar::hidden()),
+        }
+    }
+
+    pub fn spinner(&self) -> anyhow::Result<ProgressBar> {
+        match self.mode {
+            ProgressMode::Fancy => {
+                let b = ProgressBar::new_spinner();
+                b.set_style(ProgressStyle::default_spinner().template(&self.spinner_template)?);
+                Ok(b)
+            }
+            ProgressMode::Plain => {
+                let b = ProgressBar::with_draw_target(None, plain_draw_target());
+                b.set_style(ProgressStyle::default_spinner().template(PLAIN_SPINNER_TEMPLATE)?);
+                Ok(b)
+            }
+            ProgressMode::Hidden => Ok(ProgressBar::hidden()),
+        }
+    }
+
+    /// A `MultiProgress` for the bars of this generator, the bars take over its draw target
+    pub fn multi_progress(&self) -> MultiProgress {
+        match self.mode {
+            ProgressMode::Fancy => MultiProgress::new(),
+            ProgressMode::Plain => MultiProgress::with_draw_target(plain_draw_target()),
+            ProgressMode::Hidden => MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        }
+    }
+
+    /// A bar that shows the overall percentage of an operation with several stages
+    pub fn staged(&self, message: &'static str) -> anyhow::Result<StagedProgress> {
+        let bar = self.bar()?;
+        bar.set_message(message);
+        Ok(StagedProgress::new(bar))
+    }
+}
+
+fn plain_draw_target() -> ProgressDrawTarget {
+    let term = PlainTerm::new(std::io::stderr(), PLAIN_INTERVAL);
+    ProgressDrawTarget::term_like_with_hz(Box::new(term), 4)
+}
+
+/// A "terminal" that prints the lines of the progress bars as plain text lines
+///
+/// The cursor movements of indicatif are ignored, a drawn frame is printed as lines without
+/// control sequences. Lines that did not change since the last printed frame are not printed
+/// again and frames are printed at most once per interval (the last frame is printed when the bar
+/// is dropped).
+struct PlainTerm<W: Write + Send> {
+    interval: Duration,
+    state: Mutex<PlainTermState<W>>,
+}
+
+struct PlainTermState<W> {
+    out: W,
+
+    /// The line that is currently written and the lines of the current frame
+    line: String,
+    frame: Vec<String>,
+
+    /// The last complete frame, if it was not printed yet
+    pending: Option<Vec<String>>,
+
+    printed: Vec<String>,
+    last_print: Option<Instant>,
+}
+
+impl<W: Write + Send> PlainTerm<W> {
+    fn new(out: W, interval: Duration) -> Self {
+        PlainTerm {
+            interval,
+            state: Mutex::new(PlainTermState {
+                out,
+                line: String::new(),
+                frame: Vec::new(),
+                pending: None,
+                printed: Vec::new(),
+                last_print: None,
+            }),
+        }
+    }
+}
+
+impl<W: Write> PlainTermState<W> {
+    fn end_line(&mut self) {
+        let line = strip_control_sequences(&self.line);
+        let line = line.trim_end();
+        if !line.is_empty() {
+            self.frame.push(line.to_string());
+        }
+        self.line.clear();
+    }
+
+    fn print_pending(&mut self) -> std::io::Result<()> {
+        let Some(frame) = self.pending.take() else {
+            return Ok(());
+        };
+        for line in frame.iter().filter(|line| !self.printed.contains(line)) {
+            writeln!(self.out, "{line}")?;
+        }
+        self.printed = frame;
+        self.last_print = Some(Instant::now());
+        self.out.flush()
+    }
+}
+
+impl<W: Write + Send> std::fmt::Debug for PlainTerm<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PlainTerm")
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W: Write + Send> TermLike for PlainTerm<W> {
+    fn width(&self) -> u16 {
+        200
+    }
+
+    fn height(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn move_cursor_up(&self, _: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_down(&self, _: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_right(&self, _: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_left(&self, _: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&self, s: &str) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.line.push_str(s);
+        state.end_line();
+        Ok(())
+    }
+
+    fn write_str(&self, s: &str) -> std::io::Result<()> {
+        self.state.lock().unwrap().line.push_str(s);
+        Ok(())
+    }
+
+    fn clear_line(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.end_line();
+        state.pending = Some(std::mem::take(&mut state.frame));
+        if state
+            .last_print
+            .map_or(true, |last| last.elapsed() >= self.interval)
+        {
+            state.print_pending()
         } else {
-            let b = ProgressBar::new(1);
-            b.set_style(ProgressStyle::default_bar().template(&self.bar_template)?);
-            Ok(b)
+            Ok(())
+        }
+    }
+}
+
+impl<W: Write + Send> Drop for PlainTerm<W> {
+    fn drop(&mut self) {
+        if let Ok(state) = self.state.get_mut() {
+            let _ = state.print_pending();
         }
     }
 }
+
+/// Remove the ANSI escape sequences (like colors) and carriage returns from `s`
+fn strip_control_sequences(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                // A CSI sequence ends with a character in the range '@' to '~'
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\r' => {}
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// The overall progress of an operation with several stages, in percent
+///
+/// Every stage contributes its weight divided by the sum of the weights of all stages to the
+/// overall progress. All stages have to be added before the first one reports progress.
+/// If the operation fails (the bar is dropped before all stages are finished), the bar is cleared.
+#[derive(Clone, Debug)]
+pub struct StagedProgress {
+    bar: ProgressBar,
+
+    /// The weight and the completed fraction (0 to 1) of each stage
+    stages: Arc<Mutex<Vec<(u64, f64)>>>,
+}
+
+/// A stage of a `StagedProgress`
+#[derive(Clone, Debug)]
+pub struct StageProgress {
+    overall: StagedProgress,
+    index: usize,
+}
+
+impl StagedProgress {
+    pub fn new(bar: ProgressBar) -> Self {
+        bar.set_length(100);
+        StagedProgress {
+            bar: bar.with_finish(ProgressFinish::AndClear),
+            stages: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn add_stage(&self, weight: u64) -> StageProgress {
+        let mut stages = self.stages.lock().unwrap();
+        stages.push((weight, 0.0));
+        StageProgress {
+            overall: self.clone(),
+            index: stages.len() - 1,
+        }
+    }
+
+    fn set_fraction(&self, index: usize, fraction: f64) {
+        let percentage = {
+            let mut stages = self.stages.lock().unwrap();
+            stages[index].1 = fraction.clamp(0.0, 1.0);
+            overall_percentage(&stages)
+        };
+        self.bar.set_position(percentage);
+        if percentage == 100 {
+            self.bar.finish();
+        }
+    }
+}
+
+impl StageProgress {
+    /// Report that `fraction` (0 to 1) of the stage is completed
+    pub fn set_fraction(&self, fraction: f64) {
+        self.overall.set_fraction(self.index, fraction)
+    }
+
+    /// Report the completed fraction of the `bar` as the completed fraction of the stage
+    pub fn follow(&self, bar: &ProgressBar) {
+        match bar.length() {
+            Some(length) if length > 0 => self.set_fraction(bar.position() as f64 / length as f64),
+            _ => {}
+        }
+    }
+
+    pub fn finish(&self) {
+        self.set_fraction(1.0)
+    }
+}
+
+/// The overall progress in percent, 100 only if all stages are finished
+fn overall_percentage(stages: &[(u64, f64)]) -> u64 {
+    let total_weight = stages.iter().map(|(weight, _)| weight).sum::<u64>();
+    if total_weight == 0 {
+        return if stages.iter().all(|(_, fraction)| *fraction >= 1.0) {
+            100
+        } else {
+            0
+        };
+    }
+
+    let completed = stages
+        .iter()
+        .map(|(weight, fraction)| *weight as f64 * fraction)
+        .sum::<f64>();
+    (completed * 100.0 / total_weight as f64).floor() as u64
+}
+
+/// A wrapper around an `AsyncRead` that advances progress bars by the number of bytes read
+pub struct ProgressReader<R> {
+    inner: R,
+    bars: Vec<ProgressBar>,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, bars: Vec<ProgressBar>) -> Self {
+        ProgressReader { inner, bars }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = (buf.filled().len() - filled_before) as u64;
+            self.bars.iter().for_each(|bar| bar.inc(read));
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+    use tokio::io::AsyncReadExt;
+
+    /// A writer whose content can be read while a `PlainTerm` owns it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn content(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn plain_bar(out: &SharedBuffer, interval: Duration) -> ProgressBar {
+        let term = PlainTerm::new(out.clone(), interval);
+        let bar =
+            ProgressBar::with_draw_target(Some(10), ProgressDrawTarget::term_like(Box::new(term)));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{msg:.green} {pos}/{len}")
+                .unwrap(),
+        );
+        bar
+    }
+
+    #[test]
+    fn test_plain_progress_lines() {
+        let out = SharedBuffer::default();
+        let bar = plain_bar(&out, Duration::ZERO);
+        bar.set_message("\x1b[1mverified\x1b[0m sources");
+        for _ in 0..3 {
+            bar.inc(1);
+        }
+        bar.finish();
+        drop(bar);
+
+        let content = out.content();
+        assert!(content.ends_with('\n'));
+        assert!(!content.contains('\r'));
+        assert!(!content.contains('\x1b'));
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.first(), Some(&"verified sources 0/10"));
+        assert!(lines.contains(&"verified sources 3/10"));
+        assert_eq!(lines.last(), Some(&"verified sources 10/10"));
+
+        // Lines are only printed once
+        assert_eq!(lines.iter().unique().count(), lines.len());
+    }
+
+    #[test]
+    fn test_plain_progress_interval() {
+        let out = SharedBuffer::default();
+        let bar = plain_bar(&out, Duration::from_secs(3600));
+        bar.set_message("verified sources");
+        for _ in 0..5 {
+            bar.inc(1);
+        }
+        assert_eq!(out.content(), "verified sources 0/10\n");
+
+        // The last frame is printed when the bar is dropped
+        bar.finish();
+        drop(bar);
+        assert_eq!(
+            out.content(),
+            "verified sources 0/10\nverified sources 10/10\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_control_sequences() {
+        assert_eq!(strip_control_sequences("plain"), "plain");
+        assert_eq!(
+            strip_control_sequences("\x1b[32mok\x1b[0m 1/2\r\x1b[2K"),
+            "ok 1/2"
+        );
+    }
+
+    #[test]
+    fn test_staged_progress_weighting() {
+        let overall = StagedProgress::new(ProgressBar::hidden());
+        let download = overall.add_stage(1);
+        let verify = overall.add_stage(1);
+        let build = overall.add_stage(6);
+        assert_eq!(overall.bar.position(), 0);
+
+        download.set_fraction(0.5);
+        assert_eq!(overall.bar.position(), 6); // 0.5 / 8 = 6.25%
+        download.finish();
+        assert_eq!(overall.bar.position(), 12); // 1 / 8 = 12.5%
+        verify.finish();
+        assert_eq!(overall.bar.position(), 25);
+        build.set_fraction(0.5);
+        assert_eq!(overall.bar.position(), 62); // 5 / 8 = 62.5%
+        build.set_fraction(0.999);
+        assert_eq!(overall.bar.position(), 99);
+        assert!(!overall.bar.is_finished());
+        build.finish();
+        assert_eq!(overall.bar.position(), 100);
+        assert!(overall.bar.is_finished());
+
+        // Fractions are clamped, a stage without weight doesn't count
+        let overall = StagedProgress::new(ProgressBar::hidden());
+        let first = overall.add_stage(3);
+        let empty = overall.add_stage(0);
+        first.set_fraction(2.0);
+        assert_eq!(overall.bar.position(), 100);
+        empty.set_fraction(-1.0);
+        assert_eq!(overall.bar.position(), 100);
+
+        assert_eq!(overall_percentage(&[]), 100);
+        assert_eq!(overall_percentage(&[(0, 0.0)]), 0);
+    }
+
+    #[test]
+    fn test_stage_follows_bar() {
+        let overall = StagedProgress::new(ProgressBar::hidden());
+        let stage = overall.add_stage(1);
+        let bar = ProgressBar::hidden();
+
+        // Without a length, nothing is known about the progress
+        stage.follow(&bar);
+        assert_eq!(overall.bar.position(), 0);
+
+        bar.set_length(4);
+        bar.inc(1);
+        stage.follow(&bar);
+        assert_eq!(overall.bar.position(), 25);
+        bar.inc(3);
+        stage.follow(&bar);
+        assert!(overall.bar.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_progress_reader_counts_bytes() {
+        let file_bar = ProgressBar::hidden();
+        let overall_bar = ProgressBar::hidden();
+        overall_bar.inc(10);
+
+        let data = vec![42u8; 4000];
+        let mut reader =
+            ProgressReader::new(&data[..], vec![file_bar.clone(), overall_bar.clone()]);
+
+        let mut buffer = [0; 1024];
+        let mut content = vec![];
+        loop {
+            let count = reader.read(&mut buffer).await.unwrap();
+            if count == 0 {
+                break;
+            }
+            content.extend_from_slice(&buffer[..count]);
+        }
+
+        assert_eq!(content, data);
+        assert_eq!(file_bar.position(), 4000);
+        assert_eq!(overall_bar.position(), 4010);
+    }
+}
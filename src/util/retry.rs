@@ -0,0 +1,125 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Retrying of async operations
+//!
+//! Which errors are worth a retry depends on the operation, so every caller classifies its errors
+//! itself, e.g. `crate::util::docker::is_retryable()` for Docker API calls.
+
+use tracing::debug;
+
+use crate::util::backoff::Backoff;
+
+/// Run `op` and retry it after the next delay of `policy` as long as it fails with an error for
+/// which `is_retryable` returns true
+///
+/// Returns the first success, the first error that is not retryable or the last error if all
+/// retries failed.
+pub async fn retry_async<F, Fut, T, E, R>(
+    mut op: F,
+    policy: &Backoff,
+    is_retryable: R,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+    R: Fn(&E) -> bool,
+    E: std::fmt::Display,
+{
+    let mut delays = policy.delays().enumerate();
+    loop {
+        match op().await {
+            Err(e) if is_retryable(&e) => {
+                let Some((attempt, delay)) = delays.next() else {
+                    return Err(e);
+                };
+                debug!(
+                    "Attempt {}/{} failed, retrying in {:?}: {}",
+                    attempt + 1,
+                    policy.retries() + 1,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum TestError {
+        Temporary,
+        Permanent,
+    }
+
+    impl std::fmt::Display for TestError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    fn is_temporary(e: &TestError) -> bool {
+        *e == TestError::Temporary
+    }
+
+    /// Retry an operation that fails with the `errors` one after another and then succeeds
+    ///
+    /// Returns the result and the number of attempts.
+    async fn run(errors: &[TestError], retries: u32) -> (Result<(), &TestError>, u32) {
+        let attempts = AtomicU32::new(0);
+        let policy = Backoff::builder()
+            .base_delay(Duration::ZERO)
+            .retries(retries)
+            .build();
+        let result = retry_async(
+            || async {
+                match errors.get(attempts.fetch_add(1, Ordering::SeqCst) as usize) {
+                    Some(e) => Err(e),
+                    None => Ok(()),
+                }
+            },
+            &policy,
+            |e| is_temporary(e),
+        )
+        .await;
+        (result, attempts.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_succeeds_on_third_try() {
+        let errors = [TestError::Temporary, TestError::Temporary];
+        assert_eq!(run(&errors, 5).await, (Ok(()), 3));
+    }
+
+    #[tokio::test]
+    async fn test_fails_permanently() {
+        let errors = [
+            TestError::Temporary,
+            TestError::Temporary,
+            TestError::Temporary,
+        ];
+        assert_eq!(run(&errors, 2).await, (Err(&TestError::Temporary), 3));
+    }
+
+    #[tokio::test]
+    async fn test_non_retryable_error() {
+        let errors = [TestError::Permanent];
+        assert_eq!(run(&errors, 5).await, (Err(&TestError::Permanent), 1));
+    }
+}
@@ -0,0 +1,80 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! The summary line that some commands print to stderr when they are done
+//!
+//! The line has the same format for all commands and output formats, so that scripts can grep
+//! it, e.g. `butido: verify: total=1200 ok=1190 corrupt=0 missing=3 failed=7`.
+
+use std::sync::OnceLock;
+
+static QUIET: OnceLock<bool> = OnceLock::new();
+
+/// Don't print summary lines (see the global `--quiet` flag), can only be set once
+pub fn set_quiet() {
+    let _ = QUIET.set(true);
+}
+
+/// The counts of the results of a command, in the order they were added
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SummaryLine {
+    command: &'static str,
+    counts: Vec<(&'static str, usize)>,
+}
+
+impl SummaryLine {
+    pub fn new(command: &'static str) -> Self {
+        SummaryLine {
+            command,
+            counts: Vec::new(),
+        }
+    }
+
+    pub fn count(mut self, name: &'static str, count: usize) -> Self {
+        self.counts.push((name, count));
+        self
+    }
+
+    /// Print the line to stderr, unless `--quiet` was passed
+    pub fn print(&self) {
+        if !QUIET.get().copied().unwrap_or(false) {
+            eprintln!("{self}");
+        }
+    }
+}
+
+impl std::fmt::Display for SummaryLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "butido: {}:", self.command)?;
+        self.counts
+            .iter()
+            .try_for_each(|(name, count)| write!(f, " {name}={count}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_line() {
+        let line = SummaryLine::new("verify")
+            .count("total", 1200)
+            .count("ok", 1190)
+            .count("failed", 7)
+            .count("missing", 3);
+        assert_eq!(
+            line.to_string(),
+            "butido: verify: total=1200 ok=1190 failed=7 missing=3"
+        );
+
+        assert_eq!(SummaryLine::new("lint").to_string(), "butido: lint:");
+    }
+}
@@ -0,0 +1,180 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Durations of the stages of a butido run, reported with the global `--timings` flag
+//!
+//! The counters for the metrics file (`--metrics-file`) are recorded here as well.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Error;
+use anyhow::Result;
+
+use crate::util::metrics::Counter;
+
+/// The format the timings are printed in
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TimingsFormat {
+    Text,
+    Json,
+}
+
+/// Records how long the stages of a run took
+///
+/// Cloned objects record into the same list, so it can be passed to concurrent tasks.
+/// If timings are disabled, nothing is recorded.
+#[derive(Clone, Debug, Default)]
+pub struct Timings {
+    format: Option<TimingsFormat>,
+
+    /// Whether anything is recorded, also set if the timings are not printed but written to the
+    /// metrics file
+    enabled: bool,
+
+    /// The stages in the order they were first recorded
+    stages: Arc<Mutex<Vec<(String, Duration)>>>,
+
+    counters: Arc<Mutex<BTreeMap<Counter, u64>>>,
+}
+
+impl Timings {
+    pub fn new(format: Option<TimingsFormat>) -> Self {
+        Timings {
+            format,
+            enabled: format.is_some(),
+            stages: Arc::new(Mutex::new(Vec::new())),
+            counters: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Record the timings and counters even if the timings are not printed
+    pub fn with_metrics(mut self) -> Self {
+        self.enabled = true;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Add `duration` to the duration of `stage`
+    pub fn add(&self, stage: impl Into<String>, duration: Duration) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let stage = stage.into();
+        let mut stages = self.stages.lock().unwrap(); // only poisoned if another thread panicked
+        match stages.iter_mut().find(|(name, _)| *name == stage) {
+            Some((_, total)) => *total += duration,
+            None => stages.push((stage, duration)),
+        }
+    }
+
+    /// Add the time since `start` to the duration of `stage`
+    pub fn add_since(&self, stage: impl Into<String>, start: Instant) {
+        self.add(stage, start.elapsed())
+    }
+
+    pub fn stages(&self) -> Vec<(String, Duration)> {
+        self.stages.lock().unwrap().clone()
+    }
+
+    /// Add `n` to `counter`
+    pub fn count(&self, counter: Counter, n: u64) {
+        if self.is_enabled() {
+            *self.counters.lock().unwrap().entry(counter).or_default() += n;
+        }
+    }
+
+    pub fn counter(&self, counter: Counter) -> u64 {
+        self.counters
+            .lock()
+            .unwrap()
+            .get(&counter)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Print the recorded durations to stderr, if enabled
+    pub fn print(&self) -> Result<()> {
+        let stages = self.stages();
+        let mut out = std::io::stderr().lock();
+        match self.format {
+            None => Ok(()),
+            Some(TimingsFormat::Text) => {
+                let width = stages.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+                writeln!(out, "Timings:")?;
+                for (name, duration) in stages {
+                    writeln!(out, "  {name:<width$}  {:>10.3}s", duration.as_secs_f64())?;
+                }
+                Ok(())
+            }
+            Some(TimingsFormat::Json) => {
+                let stages = stages
+                    .into_iter()
+                    .map(|(name, duration)| {
+                        serde_json::json!({
+                            "stage": name,
+                            "seconds": duration.as_secs_f64(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                serde_json::to_writer_pretty(&mut out, &stages)?;
+                writeln!(out).map_err(Error::from)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durations_accumulate_per_stage() {
+        let timings = Timings::new(Some(TimingsFormat::Text));
+        timings.add("Loading the repository", Duration::from_millis(300));
+        timings.clone().add("Building a 1", Duration::from_secs(2));
+        timings.add("Building a 1", Duration::from_secs(1));
+        timings.add("Verifying sources", Duration::from_millis(5));
+
+        assert_eq!(
+            timings.stages(),
+            vec![
+                (
+                    String::from("Loading the repository"),
+                    Duration::from_millis(300)
+                ),
+                (String::from("Building a 1"), Duration::from_secs(3)),
+                (String::from("Verifying sources"), Duration::from_millis(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disabled_timings_record_nothing() {
+        let timings = Timings::new(None);
+        timings.add("Loading the repository", Duration::from_secs(1));
+        timings.count(Counter::Builds, 1);
+        assert!(timings.stages().is_empty());
+        assert_eq!(timings.counter(Counter::Builds), 0);
+
+        let timings = Timings::new(None).with_metrics();
+        timings.count(Counter::Builds, 1);
+        timings.clone().count(Counter::Builds, 2);
+        assert_eq!(timings.counter(Counter::Builds), 3);
+    }
+}
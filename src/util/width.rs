@@ -0,0 +1,78 @@
+//
+// Copyright (c) 2020-2022 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! The width of the tree and table outputs, see the global `--width` flag
+//!
+//! The outputs are only fitted to the width if stdout is a terminal, so scripts that read the
+//! output always get the full labels.
+
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use crate::util::stdout_is_pipe;
+
+/// The width that is used if the terminal width cannot be detected
+const DEFAULT_WIDTH: usize = 80;
+
+const ELLIPSIS: char = '…';
+
+static WIDTH_OVERRIDE: OnceLock<usize> = OnceLock::new();
+
+/// Use `width` instead of the width of the terminal, can only be set once
+pub fn set_width_override(width: usize) {
+    let _ = WIDTH_OVERRIDE.set(width);
+}
+
+/// The width the output has to fit in, none if stdout is a pipe (nothing is truncated then)
+pub fn output_width() -> Option<usize> {
+    if stdout_is_pipe() {
+        return None;
+    }
+
+    let width = WIDTH_OVERRIDE.get().copied().unwrap_or_else(|| {
+        terminal_size::terminal_size()
+            .map(|(terminal_size::Width(w), _)| w as usize)
+            .unwrap_or(DEFAULT_WIDTH)
+    });
+    Some(width)
+}
+
+/// Shorten `s` to at most `width` characters, the last one is an ellipsis if `s` was shortened
+pub fn ellipsize(s: &str, width: usize) -> Cow<'_, str> {
+    if s.chars().count() <= width {
+        return Cow::Borrowed(s);
+    }
+
+    match width.checked_sub(1) {
+        Some(keep) => Cow::Owned(s.chars().take(keep).chain(Some(ELLIPSIS)).collect()),
+        None => Cow::Borrowed(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ellipsize() {
+        let label = "libfoo-bar 1.2.3";
+        assert_eq!(ellipsize(label, 100), label);
+        assert_eq!(ellipsize(label, 16), label);
+        assert_eq!(ellipsize(label, 15), "libfoo-bar 1.2…");
+        assert_eq!(ellipsize(label, 5), "libf…");
+        assert_eq!(ellipsize(label, 1), "…");
+        assert_eq!(ellipsize(label, 0), "");
+        assert_eq!(ellipsize("", 0), "");
+
+        // Characters are counted, not bytes
+        assert_eq!(ellipsize("äöü-paket 1", 11), "äöü-paket 1");
+        assert_eq!(ellipsize("äöü-paket 1", 4), "äöü…");
+    }
+}